@@ -0,0 +1,127 @@
+//! An actor behind a mailbox with timeouts, and a machine whose actions are futures
+//!
+//! Run with `cargo run --example async_actor --features unstable`
+
+use state_machine::unstable::{AsyncBuilder, ConcurrencyLimit};
+use state_machine::{Event, EventBus, ManualClock, Result, State, StateMachineBuilder};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::thread;
+use std::time::Duration;
+
+/// Completes after a delay measured by another thread, like a download waiting for the network
+struct Delay {
+    duration: Duration,
+    done: Arc<AtomicBool>,
+    started: bool,
+}
+
+impl Delay {
+    fn new(duration: Duration) -> Self {
+        Self {
+            duration,
+            done: Arc::new(AtomicBool::new(false)),
+            started: false,
+        }
+    }
+}
+
+impl Future for Delay {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<()> {
+        if self.done.load(Ordering::SeqCst) {
+            return Poll::Ready(());
+        }
+        if !self.started {
+            self.started = true;
+            let (done, waker, duration) =
+                (self.done.clone(), context.waker().clone(), self.duration);
+            thread::spawn(move || {
+                thread::sleep(duration);
+                done.store(true, Ordering::SeqCst);
+                waker.wake();
+            });
+        }
+        Poll::Pending
+    }
+}
+
+/// A worker owned by a bus: clients post requests to its mailbox and wait for the reply
+fn actor() -> Result<()> {
+    let (idle, working, timed_out) = (
+        State::new("idle"),
+        State::new("working"),
+        State::new("timed_out"),
+    );
+    let (start, timeout) = (Event::new("start"), Event::new("timeout"));
+    let clock = Arc::new(ManualClock::default());
+    let mut bus: EventBus = EventBus::new();
+    bus.register(
+        StateMachineBuilder::new("worker", &idle)
+            .add_event(idle.clone(), start.clone(), working.clone(), None)
+            .add_event(working.clone(), timeout.clone(), timed_out, None)
+            .time_box(&working, Duration::from_secs(30), vec![timeout])
+            .with_clock(clock.clone())
+            .build(),
+    );
+
+    let sender = bus.sender();
+    let client = thread::spawn(move || {
+        let reply = sender.request("worker", start);
+        reply.recv().expect("the bus replies")
+    });
+    // the bus runs on this thread, it is the only one touching the machine
+    while !client.is_finished() {
+        bus.step()?;
+        thread::yield_now();
+    }
+    match client.join().expect("the client does not panic") {
+        Ok(record) => println!("client got {:?}", record.map(|record| record.to)),
+        Err(e) => println!("client got an error: {e}"),
+    }
+
+    clock.advance(Duration::from_secs(31));
+    let worker = bus.machine("worker").expect("the worker is registered");
+    worker.fire_due()?;
+    println!(
+        "after the time box, the worker is {}",
+        worker.current_state()
+    );
+    Ok(())
+}
+
+/// Downloads awaited outside of the state lock, sharing a limit of two at a time across machines
+fn downloads() -> Result<()> {
+    let (idle, downloading) = (State::new("idle"), State::new("downloading"));
+    let download = Event::new("download");
+    let limit = ConcurrencyLimit::new(2);
+    for name in ["mirror-a", "mirror-b", "mirror-c"] {
+        let machine = AsyncBuilder::new(name, &idle)
+            .add_event(
+                idle.clone(),
+                download.clone(),
+                downloading.clone(),
+                Some(Box::new(|| {
+                    Box::pin(async {
+                        Delay::new(Duration::from_millis(20)).await;
+                        Ok(())
+                    })
+                })),
+            )
+            .limit_state(&downloading, &limit)
+            .build();
+        // synchronous code drives the machine without an executor
+        machine.blocking_event(&download)?;
+        println!("{name}: downloaded, {} slot(s) free", limit.available());
+    }
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    actor()?;
+    downloads()
+}
@@ -0,0 +1,66 @@
+//! An order workflow surviving a restart: snapshots, stores, deadlines, clocks and the audit export
+//!
+//! Run with `cargo run --example order_workflow`
+
+use state_machine::{
+    Clock, Event, ManualClock, MemoryStore, Result, State, StateMachine, StateMachineBuilder, Store,
+};
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Build the order machine, the same definition before and after a restart
+fn order(clock: Arc<ManualClock>) -> StateMachine {
+    let (new, awaiting_payment, paid, shipped, expired) = (
+        State::new("new"),
+        State::new("awaiting_payment"),
+        State::new("paid"),
+        State::new("shipped"),
+        State::new("expired"),
+    );
+    StateMachineBuilder::new("order-42", &new)
+        .add_event(new, Event::new("place"), awaiting_payment.clone(), None)
+        .add_event(
+            awaiting_payment.clone(),
+            Event::new("pay"),
+            paid.clone(),
+            None,
+        )
+        .add_event(awaiting_payment, Event::new("expire"), expired, None)
+        .add_event(paid, Event::new("ship"), shipped, None)
+        .with_clock(clock)
+        .with_history(32)
+        .build()
+}
+
+fn main() -> Result<()> {
+    let clock = Arc::new(ManualClock::default());
+    let store = MemoryStore::new();
+
+    let machine = order(clock.clone());
+    machine.event(&Event::new("place"))?;
+    machine.schedule_at(
+        clock.now() + Duration::from_secs(24 * 3600),
+        Event::new("expire"),
+    );
+    store.save(&42, &machine.snapshot())?;
+    println!("saved in {}", machine.current_state());
+    drop(machine);
+
+    // the process restarts, the order comes back with its payment deadline
+    let machine = order(clock.clone());
+    let snapshot = store.load(&42)?.expect("the order was saved");
+    machine.restore(&snapshot)?;
+    println!(
+        "restored in {} with {} deadline(s)",
+        machine.current_state(),
+        machine.deadlines().len()
+    );
+
+    clock.advance(Duration::from_secs(25 * 3600));
+    let fired = machine.fire_due()?;
+    println!("{fired} deadline(s) fired, now {}", machine.current_state());
+
+    machine.export_audit(io::stdout())?;
+    Ok(())
+}
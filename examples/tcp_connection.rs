@@ -0,0 +1,87 @@
+//! A TCP connection: substates, failure policies, the error state and typed errors
+//!
+//! Run with `cargo run --example tcp_connection --features unstable`
+
+use state_machine::{Event, FailurePolicy, Result, State, StateMachineBuilder, StateMachineError};
+use std::cell::Cell;
+use std::io;
+use std::rc::Rc;
+
+fn main() -> Result<()> {
+    let (closed, syn_sent, established, idle, transferring, dead) = (
+        State::new("closed"),
+        State::new("syn_sent"),
+        State::new("established"),
+        State::new("idle"),
+        State::new("transferring"),
+        State::new("dead"),
+    );
+    let (connect, ack, send, sent, reset) = (
+        Event::new("connect"),
+        Event::new("ack"),
+        Event::new("send"),
+        Event::new("sent"),
+        Event::new("reset"),
+    );
+    // the peer refuses the first handshake
+    let attempts = Rc::new(Cell::new(0));
+    let handshakes = attempts.clone();
+
+    let connection = StateMachineBuilder::new("connection", &closed)
+        .add_event(
+            closed.clone(),
+            connect.clone(),
+            syn_sent.clone(),
+            Some(Box::new(move || {
+                handshakes.set(handshakes.get() + 1);
+                if handshakes.get() == 1 {
+                    return Err(io::Error::from(io::ErrorKind::ConnectionRefused).into());
+                }
+                Ok(())
+            })),
+        )
+        .add_event(syn_sent.clone(), ack.clone(), idle.clone(), None)
+        .add_event(idle.clone(), send.clone(), transferring.clone(), None)
+        .add_event(transferring.clone(), sent.clone(), idle.clone(), None)
+        // a reset closes the connection from any of its substates
+        .add_event(established.clone(), reset.clone(), closed.clone(), None)
+        .with_parent(&idle, &established)
+        .with_parent(&transferring, &established)
+        .on_action_error(FailurePolicy::RollbackOnError)
+        .error_state(&dead, 3)
+        .with_history(32)
+        .build();
+
+    if let Err(e) = connection.event(&connect) {
+        println!(
+            "handshake failed: {e}, still {}",
+            connection.current_state()
+        );
+    }
+    connection.event(&connect)?;
+    connection.event(&ack)?;
+    connection.event(&send)?;
+    println!(
+        "in {}, established: {}",
+        connection.current_state(),
+        connection.is_in(&established)
+    );
+    connection.event(&reset)?;
+
+    match connection.event(&sent) {
+        Err(e) => match e.downcast_ref::<StateMachineError>() {
+            Some(StateMachineError::NoTransition { state, event, .. }) => {
+                println!("{event} is not handled in {state}");
+            }
+            _ => return Err(e),
+        },
+        Ok(()) => unreachable!("a closed connection sends nothing"),
+    }
+
+    println!(
+        "{} records, {} failure(s) in a row before {dead}",
+        connection.history().len(),
+        connection.consecutive_failures()
+    );
+    Ok(())
+}
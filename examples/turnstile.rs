@@ -0,0 +1,72 @@
+//! A coin-operated turnstile: transitions, guards, observers, history and diagrams
+//!
+//! Run with `cargo run --example turnstile`
+
+use state_machine::prelude::*;
+use std::cell::Cell;
+use std::rc::Rc;
+
+/// Prints every transition, like a metrics or audit hook would record it
+struct Printer;
+
+impl Observer for Printer {
+    fn on_transition(&self, transition: &TransitionInfo) {
+        println!(
+            "{}: {} -{}-> {}",
+            transition.machine, transition.from, transition.event, transition.to
+        );
+    }
+}
+
+fn main() -> Result<()> {
+    let (locked, unlocked, broken) = (
+        State::new("locked"),
+        State::new("unlocked"),
+        State::new("broken"),
+    );
+    let (coin, push, kick) = (Event::new("coin"), Event::new("push"), Event::new("kick"));
+    let passages = Rc::new(Cell::new(0));
+    let counter = passages.clone();
+    let in_service = Rc::new(Cell::new(true));
+    let service = in_service.clone();
+
+    let turnstile = StateMachineBuilder::new("turnstile", &locked)
+        .add_event(locked.clone(), coin.clone(), unlocked.clone(), None)
+        .with_guard("in service", move || service.get())
+        .add_event(
+            unlocked.clone(),
+            push.clone(),
+            locked.clone(),
+            Some(Box::new(move || {
+                counter.set(counter.get() + 1);
+                Ok(())
+            })),
+        )
+        .add_any_state_event(kick.clone(), broken.clone(), None)
+        .add_observer(Box::new(Printer))
+        .with_history(16)
+        .record_guard_rejections()
+        .build();
+
+    for event in [&coin, &push, &coin, &push] {
+        turnstile.event(event)?;
+    }
+    in_service.set(false);
+    if let Err(e) = turnstile.event(&coin) {
+        println!("refused: {e}");
+    }
+    turnstile.event(&kick)?;
+    println!(
+        "{} passages, now {}",
+        passages.get(),
+        turnstile.current_state()
+    );
+    println!("{} records in the history", turnstile.history().len());
+
+    print_diagram(&turnstile);
+    Ok(())
+}
+
+fn print_diagram(turnstile: &StateMachine) {
+    println!("{}", turnstile.to_dot());
+}
@@ -0,0 +1,9 @@
+//! Checking definitions and traces without running the machine: validation, explanations,
+//! diagrams, replays and temporal properties
+
+pub use crate::{
+    assert_trace, diff_traces, replay_many, step, step_with_guards, trace_to_mermaid, After,
+    Aligned, Assertions, Candidate, Checker, Choice, Conflict, Determinize, Diagram, Difference,
+    Divergence, Explanation, Minimization, Nondeterminism, Pattern, Property, Replay, Scan,
+    ScanPolicy, States, Step, TemporalViolation, TraceDiff, Validation, Verdict,
+};
@@ -0,0 +1,179 @@
+//! Transitions that need the approval of several principals before they fire
+
+use crate::dedup::Dispatch;
+use crate::trace::debug;
+use crate::{Error, Event, Outcome, State, StateMachine, StateMachineBuilder, StateMachineError};
+use std::collections::{BTreeSet, HashMap};
+
+/// The principals that approved the events of the current state so far
+#[derive(Default, Clone)]
+pub(crate) struct Approvals {
+    pending: HashMap<Event, BTreeSet<String>>,
+}
+
+impl<Err> StateMachine<Err> {
+    /// Collect the approval of the sender of an event, if its transition needs approvals
+    /// # Returns
+    /// `true` if the event can fire: it needs no approvals or this one completes them
+    /// # Errors
+    /// If the event needs approvals and the envelope has no principal
+    /// # Panics
+    /// If the lock is poisoned
+    pub(crate) fn approve(&self, state: &State, dispatch: Dispatch) -> Result<bool, Error> {
+        let event = dispatch.event;
+        let mut candidates = self.table.candidates(state, event);
+        if candidates.clone().all(|t| t.approvals.is_none()) {
+            return Ok(true);
+        }
+        // the approvals of the transition that would fire
+        let Some(required) = candidates.find(|t| t.allowed()).and_then(|t| t.approvals) else {
+            return Ok(true);
+        };
+        let Some(principal) = dispatch.principal() else {
+            return Err(Error::from(StateMachineError::MissingPrincipal {
+                machine: self.name.clone(),
+                event: event.clone(),
+                approvals: required,
+            }));
+        };
+        let mut approvals = self.approvals.lock().expect("failed to get lock");
+        let approvers = approvals.pending.entry(event.clone()).or_default();
+        approvers.insert(principal.clone());
+        if approvers.len() < required {
+            debug!(
+                "{}: {principal} approved {event}, {} of {required}",
+                self.log_name(),
+                approvers.len()
+            );
+            drop(approvals);
+            self.record_not_taken(
+                state,
+                dispatch,
+                Outcome::AwaitingApproval(principal.clone()),
+            );
+            return Ok(false);
+        }
+        approvals.pending.remove(event);
+        Ok(true)
+    }
+
+    /// Forget the approvals collected in the state the machine leaves
+    /// # Returns
+    /// The forgotten approvals, to restore them if the transition is rolled back
+    /// # Panics
+    /// If the lock is poisoned
+    pub(crate) fn clear_approvals(&self) -> Approvals {
+        std::mem::take(&mut *self.approvals.lock().expect("failed to get lock"))
+    }
+
+    /// Get the principals that approved an event in the current state so far, in alphabetical order
+    /// # Panics
+    /// If the lock is poisoned
+    pub fn approvals(&self, event: &Event) -> Vec<String> {
+        self.approvals
+            .lock()
+            .expect("failed to get lock")
+            .pending
+            .get(event)
+            .map(|approvers| approvers.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+impl<Err> StateMachineBuilder<Err> {
+    #[must_use]
+    /// Fire the last added transition only once `count` distinct principals sent its event, see [`Envelope::with_principal`]
+    ///
+    /// The approvals are collected while the machine stays in the state, they are forgotten when it takes any transition
+    /// or is put in another state, e.g. by a reset or a restore.
+    /// The approvals needed are the ones of the first candidate whose guard passes, so the guards of the event
+    /// are checked once more before its transition fires.
+    /// The events completing no approval are recorded in the history with [`Outcome::AwaitingApproval`].
+    /// # Panics
+    /// If no transition was added yet
+    pub fn with_approvals(mut self, count: usize) -> Self {
+        self.last_transition("with_approvals").approvals = Some(count.max(1));
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Delivery, Envelope};
+    use std::cell::Cell;
+    use std::rc::Rc;
+    #[cfg(feature = "tracing")]
+    use tracing_test::traced_test;
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_approvals() {
+        let (proposed, approved) = (State::new("proposed"), State::new("approved"));
+        let (approve, amend) = (Event::new("approve"), Event::new("amend"));
+        let machine = StateMachineBuilder::new("change", &proposed)
+            .add_event(proposed.clone(), approve.clone(), approved.clone(), None)
+            .with_approvals(2)
+            .add_event(proposed.clone(), amend.clone(), proposed.clone(), None)
+            .with_history(10)
+            .build();
+        let send = |principal: &str| {
+            machine.deliver(&Envelope::new("", approve.clone()).with_principal(principal))
+        };
+
+        assert!(machine.event(&approve).is_err());
+        assert_eq!(send("alice").unwrap(), Delivery::Handled);
+        assert_eq!(send("alice").unwrap(), Delivery::Handled);
+        assert_eq!(machine.current_state(), proposed);
+        assert_eq!(machine.approvals(&approve), vec!["alice".to_string()]);
+        assert_eq!(
+            machine.last_transition(),
+            None,
+            "pending approvals are not transitions"
+        );
+        assert_eq!(
+            machine.history()[1].outcome,
+            Outcome::AwaitingApproval("alice".to_string())
+        );
+
+        // amending the change asks for new approvals
+        machine.event(&amend).unwrap();
+        assert!(machine.approvals(&approve).is_empty());
+        send("bob").unwrap();
+        send("alice").unwrap();
+        assert_eq!(machine.current_state(), approved);
+    }
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_guarded_approvals() {
+        let (proposed, approved) = (State::new("proposed"), State::new("approved"));
+        let approve = Event::new("approve");
+        let large = Rc::new(Cell::new(true));
+        let large_clone = large.clone();
+        let machine = StateMachineBuilder::new("change", &proposed)
+            .add_event(proposed.clone(), approve.clone(), approved.clone(), None)
+            .with_approvals(2)
+            .with_guard("large change", move || large_clone.get())
+            .add_event(proposed.clone(), approve.clone(), approved.clone(), None)
+            .entry_point("review", proposed.clone())
+            .build();
+        let send = |principal: &str| {
+            machine.deliver(&Envelope::new("", approve.clone()).with_principal(principal))
+        };
+
+        send("alice").unwrap();
+        assert_eq!(machine.approvals(&approve), vec!["alice".to_string()]);
+        // forcing the state drops the approvals collected so far
+        machine.start_as("review").unwrap();
+        assert!(machine.approvals(&approve).is_empty());
+        send("alice").unwrap();
+        machine.restore(&machine.snapshot()).unwrap();
+        assert!(machine.approvals(&approve).is_empty());
+
+        // small changes take the unguarded transition, without approvals
+        large.set(false);
+        send("alice").unwrap();
+        assert_eq!(machine.current_state(), approved);
+    }
+}
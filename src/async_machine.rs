@@ -0,0 +1,435 @@
+//! Machines whose actions are futures, awaited outside of the state lock
+
+use crate::limit::ConcurrencyLimit;
+use crate::trace::error;
+use crate::{Error, Event, State, StateMachine, StateMachineBuilder};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, Thread};
+
+/// The future of an async action
+pub type ActionFuture<Err = Error> = Pin<Box<dyn Future<Output = Result<(), Err>>>>;
+
+/// An action returning a future, e.g. `Box::new(|| Box::pin(async { upload().await }))`
+pub type AsyncAction<Err = Error> = Box<dyn Fn() -> ActionFuture<Err>>;
+
+/// The future created by the action of the transition fired by [`AsyncStateMachine::event`],
+/// with the state it entered, waiting to be awaited
+struct Slot<Err> {
+    /// set while `event` dispatches, the actions of transitions fired otherwise are not awaited
+    armed: bool,
+    future: Option<(State, ActionFuture<Err>)>,
+}
+
+type Pending<Err> = Rc<RefCell<Slot<Err>>>;
+
+/// Wakes the thread blocked on a future, see [`AsyncStateMachine::blocking_event`]
+struct Unpark(Thread);
+
+impl Wake for Unpark {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Poll a future on the current thread, parking it until the future is woken
+fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = std::pin::pin!(future);
+    let waker = Waker::from(Arc::new(Unpark(thread::current())));
+    let mut context = Context::from_waker(&waker);
+    loop {
+        match future.as_mut().poll(&mut context) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+/// Builds an [`AsyncStateMachine`], see [`StateMachineBuilder`] for the options
+pub struct AsyncBuilder<Err = Error> {
+    builder: StateMachineBuilder<Err>,
+    pending: Pending<Err>,
+    limits: HashMap<State, ConcurrencyLimit>,
+}
+
+impl AsyncBuilder {
+    #[must_use]
+    pub fn new(name: impl Into<String>, initial_state: &State) -> Self {
+        Self::with_error_type(name, initial_state)
+    }
+}
+
+impl<Err: 'static> AsyncBuilder<Err> {
+    /// Create a builder for an async machine whose actions return `Err`, see [`StateMachineBuilder::with_error_type`]
+    #[must_use]
+    pub fn with_error_type(name: impl Into<String>, initial_state: &State) -> Self {
+        Self {
+            builder: StateMachineBuilder::with_error_type(name, initial_state),
+            pending: Rc::new(RefCell::new(Slot {
+                armed: false,
+                future: None,
+            })),
+            limits: HashMap::new(),
+        }
+    }
+
+    /// Wrap an async action into an action creating its future, futures do nothing until awaited
+    fn defer(
+        &self,
+        new_state: &State,
+        action: Option<AsyncAction<Err>>,
+    ) -> Option<crate::Action<Err>> {
+        let pending = self.pending.clone();
+        let new_state = new_state.clone();
+        action.map(|action| -> crate::Action<Err> {
+            Box::new(move || {
+                let mut slot = pending.borrow_mut();
+                if slot.armed {
+                    slot.future = Some((new_state.clone(), action()));
+                } else {
+                    error!("async action entering {new_state} not awaited, its transition was not fired by AsyncStateMachine::event");
+                }
+                Ok(())
+            })
+        })
+    }
+
+    #[must_use]
+    /// See [`StateMachineBuilder::add_event`]
+    pub fn add_event(
+        mut self,
+        old_state: State,
+        event: Event,
+        new_state: State,
+        action: Option<AsyncAction<Err>>,
+    ) -> Self {
+        let action = self.defer(&new_state, action);
+        self.builder = self.builder.add_event(old_state, event, new_state, action);
+        self
+    }
+
+    #[must_use]
+    /// See [`StateMachineBuilder::add_any_state_event`]
+    pub fn add_any_state_event(
+        mut self,
+        event: Event,
+        new_state: State,
+        action: Option<AsyncAction<Err>>,
+    ) -> Self {
+        let action = self.defer(&new_state, action);
+        self.builder = self.builder.add_any_state_event(event, new_state, action);
+        self
+    }
+
+    #[must_use]
+    /// Run the actions of the transitions entering `state` only when `limit` has a free slot
+    ///
+    /// Share the limit between the machines of a workflow, so that a burst of instances
+    /// entering an `exporting` state does not launch all their uploads at once.
+    pub fn limit_state(mut self, state: &State, limit: &ConcurrencyLimit) -> Self {
+        self.limits.insert(state.clone(), limit.clone());
+        self
+    }
+
+    #[must_use]
+    /// Set the other options of the machine on the synchronous builder, e.g. guards or the history
+    pub fn configure(
+        mut self,
+        configure: impl FnOnce(StateMachineBuilder<Err>) -> StateMachineBuilder<Err>,
+    ) -> Self {
+        self.builder = configure(self.builder);
+        self
+    }
+
+    #[must_use]
+    pub fn build(self) -> AsyncStateMachine<Err> {
+        AsyncStateMachine {
+            machine: self.builder.build(),
+            pending: self.pending,
+            limits: self.limits,
+        }
+    }
+}
+
+/// A state machine whose actions are awaited after the transition, without holding the state lock
+///
+/// The transition is taken and recorded when the event is handled, its action runs when the returned future is awaited.
+/// Other events can be handled while an action is pending, e.g. a `cancel` during an upload.
+/// Only the transitions fired by [`AsyncStateMachine::event`] run their action: the actions of transitions
+/// fired through [`AsyncStateMachine::machine`], e.g. by `fire_due`, an escalation or the bus, are not awaited.
+/// Like [`StateMachine`], it is neither `Send` nor `Sync` and runs on a local executor.
+pub struct AsyncStateMachine<Err = Error> {
+    machine: StateMachine<Err>,
+    pending: Pending<Err>,
+    limits: HashMap<State, ConcurrencyLimit>,
+}
+
+impl<Err> AsyncStateMachine<Err>
+where
+    Err: From<Error> + fmt::Display + 'static,
+{
+    /// Handle an event, then await the action of its transition
+    /// The action first waits for a slot if the state it enters is limited, see [`AsyncBuilder::limit_state`].
+    /// An event deferred by a throttle or waiting for approvals completes at once, its action is not awaited.
+    ///
+    /// A failed action counts towards the error state and is reported by [`StateMachine::health`],
+    /// but the transition was already taken and recorded as succeeded when the event was handled:
+    /// it is not rolled back, its record in the history is not updated and the observers are not notified.
+    /// # Errors
+    /// As [`StateMachine::event`], the error of the action is returned once it completes
+    pub async fn event(&self, event: &Event) -> Result<(), Err> {
+        let failures = self.machine.consecutive_failures();
+        self.pending.borrow_mut().armed = true;
+        let result = self.machine.event(event);
+        let action = {
+            let mut slot = self.pending.borrow_mut();
+            slot.armed = false;
+            slot.future.take()
+        };
+        result?;
+        let Some((state, action)) = action else {
+            return Ok(());
+        };
+        // the action has not run yet, it counts once awaited
+        self.machine.set_consecutive_failures(failures);
+        let _permit = match self.limits.get(&state) {
+            Some(limit) => Some(limit.acquire().await),
+            None => None,
+        };
+        match action.await {
+            Ok(()) => {
+                self.machine.action_succeeded();
+                Ok(())
+            }
+            Err(e) => {
+                let message = format!("async action of {event} failed: {e}");
+                self.machine.set_last_error(message.clone());
+                if let Ok(mut state) = self.machine.state.write() {
+                    self.machine.action_failed(&mut state, &message);
+                }
+                Err(crate::error::action_failed(e))
+            }
+        }
+    }
+
+    /// Handle an event and wait for its action on the current thread, for synchronous callers
+    ///
+    /// Meant for code that is not async yet, it must not be called from an async task:
+    /// the thread is blocked until the action completes, so the action must be woken by another thread,
+    /// e.g. an I/O reactor, and not by a task of the same executor.
+    /// # Errors
+    /// As [`AsyncStateMachine::event`]
+    pub fn blocking_event(&self, event: &Event) -> Result<(), Err> {
+        block_on(self.event(event))
+    }
+
+    /// Get the synchronous machine, for the other operations
+    pub fn machine(&self) -> &StateMachine<Err> {
+        &self.machine
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+    #[cfg(feature = "tracing")]
+    use tracing_test::traced_test;
+
+    /// Yields once before completing, like an action waiting for I/O
+    struct YieldOnce(bool);
+
+    impl Future for YieldOnce {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, _: &mut Context) -> Poll<()> {
+            if std::mem::replace(&mut self.0, true) {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        }
+    }
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_async_actions() {
+        let (idle, uploading, cancelled) = (
+            State::new("idle"),
+            State::new("uploading"),
+            State::new("cancelled"),
+        );
+        let (upload, cancel) = (Event::new("upload"), Event::new("cancel"));
+        let uploaded = Rc::new(Cell::new(false));
+        let uploaded_clone = uploaded.clone();
+        let machine = AsyncBuilder::new("uploader", &idle)
+            .add_event(
+                idle.clone(),
+                upload.clone(),
+                uploading.clone(),
+                Some(Box::new(move || {
+                    let uploaded = uploaded_clone.clone();
+                    Box::pin(async move {
+                        YieldOnce(false).await;
+                        uploaded.set(true);
+                        Ok(())
+                    })
+                })),
+            )
+            .add_event(uploading.clone(), cancel.clone(), cancelled.clone(), None)
+            .build();
+
+        let mut context = Context::from_waker(Waker::noop());
+        let mut uploading_future = Box::pin(machine.event(&upload));
+        assert!(uploading_future.as_mut().poll(&mut context).is_pending());
+        assert_eq!(machine.machine().current_state(), uploading);
+        assert!(!uploaded.get());
+
+        // the state lock is free while the upload is pending
+        let mut cancelling = Box::pin(machine.event(&cancel));
+        assert!(cancelling.as_mut().poll(&mut context).is_ready());
+        assert_eq!(machine.machine().current_state(), cancelled);
+
+        assert!(uploading_future.as_mut().poll(&mut context).is_ready());
+        assert!(uploaded.get());
+    }
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_concurrency_limit() {
+        let (idle, exporting) = (State::new("idle"), State::new("exporting"));
+        let export = Event::new("export");
+        let limit = ConcurrencyLimit::new(1);
+        let exported = Rc::new(Cell::new(0));
+        let machines: Vec<_> = (0..2)
+            .map(|_| {
+                let exported = exported.clone();
+                AsyncBuilder::new("exporter", &idle)
+                    .add_event(
+                        idle.clone(),
+                        export.clone(),
+                        exporting.clone(),
+                        Some(Box::new(move || {
+                            let exported = exported.clone();
+                            Box::pin(async move {
+                                YieldOnce(false).await;
+                                exported.set(exported.get() + 1);
+                                Ok(())
+                            })
+                        })),
+                    )
+                    .limit_state(&exporting, &limit)
+                    .build()
+            })
+            .collect();
+
+        let mut context = Context::from_waker(Waker::noop());
+        let mut first = Box::pin(machines[0].event(&export));
+        let mut second = Box::pin(machines[1].event(&export));
+        assert!(first.as_mut().poll(&mut context).is_pending());
+        assert!(second.as_mut().poll(&mut context).is_pending());
+        // both machines entered the state, only the first action runs
+        assert_eq!(machines[1].machine().current_state(), exporting);
+        assert_eq!(limit.available(), 0);
+
+        assert!(first.as_mut().poll(&mut context).is_ready());
+        assert_eq!(exported.get(), 1);
+        assert_eq!(limit.available(), 1);
+        assert!(second.as_mut().poll(&mut context).is_pending());
+        assert!(second.as_mut().poll(&mut context).is_ready());
+        assert_eq!(exported.get(), 2);
+        assert_eq!(limit.available(), 1);
+    }
+
+    /// Completes once another thread set its flag, like an action waiting for a reply
+    struct Flagged(Arc<AtomicBool>);
+
+    impl Future for Flagged {
+        type Output = ();
+
+        fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<()> {
+            if self.0.load(Ordering::SeqCst) {
+                return Poll::Ready(());
+            }
+            let (flag, waker) = (self.0.clone(), context.waker().clone());
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(10));
+                flag.store(true, Ordering::SeqCst);
+                waker.wake();
+            });
+            Poll::Pending
+        }
+    }
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_blocking_event() {
+        let (idle, sending) = (State::new("idle"), State::new("sending"));
+        let send = Event::new("send");
+        let machine = AsyncBuilder::new("sender", &idle)
+            .add_event(
+                idle.clone(),
+                send.clone(),
+                sending.clone(),
+                Some(Box::new(|| {
+                    let flag = Arc::new(AtomicBool::new(false));
+                    Box::pin(async move {
+                        Flagged(flag).await;
+                        Err(crate::error::message("no reply".to_string()))
+                    })
+                })),
+            )
+            .build();
+
+        let error = machine.blocking_event(&send).unwrap_err();
+        assert_eq!(error.to_string(), "no reply");
+        assert_eq!(machine.machine().current_state(), sending);
+        assert!(machine.blocking_event(&send).is_err());
+    }
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_actions_of_other_dispatches() {
+        let (idle, sending, failed) = (
+            State::new("idle"),
+            State::new("sending"),
+            State::new("failed"),
+        );
+        let (send, ack) = (Event::new("send"), Event::new("ack"));
+        let machine = AsyncBuilder::new("sender", &idle)
+            .add_event(
+                idle.clone(),
+                send.clone(),
+                sending.clone(),
+                Some(Box::new(|| {
+                    Box::pin(async { Err(crate::error::message("no reply".to_string())) })
+                })),
+            )
+            .add_event(sending.clone(), ack.clone(), idle.clone(), None)
+            .configure(|builder| builder.error_state(&failed, 2))
+            .build();
+
+        // fired by the synchronous machine, its action is not picked up by the next event
+        machine.machine().event(&send).unwrap();
+        machine.blocking_event(&ack).unwrap();
+        assert_eq!(machine.machine().consecutive_failures(), 0);
+
+        let error = machine.blocking_event(&send).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<crate::StateMachineError>(),
+            Some(crate::StateMachineError::ActionFailed(_))
+        ));
+        assert_eq!(machine.machine().consecutive_failures(), 1);
+        machine.blocking_event(&ack).unwrap();
+        assert!(machine.blocking_event(&send).is_err());
+        assert_eq!(machine.machine().current_state(), failed);
+    }
+}
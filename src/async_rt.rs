@@ -0,0 +1,7 @@
+//! Async machines, whose actions are futures awaited outside of the state lock, and their concurrency limits
+//!
+//! Enabled by the `unstable` feature and exempt from semver, like [`unstable`](crate::unstable).
+
+pub use crate::unstable::{
+    ActionFuture, AsyncAction, AsyncBuilder, AsyncStateMachine, ConcurrencyLimit,
+};
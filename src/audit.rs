@@ -0,0 +1,220 @@
+use crate::{json, Outcome, StateMachine, TransitionRecord};
+use std::io::{self, Write};
+
+/// Identifier of the audit document format, bumped on incompatible changes
+pub const AUDIT_SCHEMA: &str = "state-machine.audit.v1";
+
+impl<Err> StateMachine<Err> {
+    /// Export the transition history as an audit document
+    ///
+    /// The document is a JSON object, with one record per line:
+    /// ```text
+    /// {
+    /// "schema": "state-machine.audit.v1",
+    /// "machine": "<name of the machine>",
+    /// "exported_at": "<RFC 3339 UTC timestamp>",
+    /// "current_state": "<state>",
+    /// "records": [
+    /// {"seq": 1, "timestamp": "<RFC 3339>", "from": "<state>", "event": "<event>", "to": "<state>",
+    ///  "duration_us": 12, "outcome": "ok" | "action_failed" | "action_panicked" | "guard_rejected" | "duplicate"
+    ///  | "unauthorized" | "awaiting_approval" | "escalation_skipped" | "rolled_back",
+    ///  "error": null | "<message>", "guard": null | "<guard name>", "cause": null | "<machine>#<seq>",
+    ///  "envelope": null | "<envelope id>", "principal": null | "<principal>"}
+    /// ]
+    /// }
+    /// ```
+    /// `seq` numbers all records of the machine, so gaps show where records were dropped from the history.
+    /// Records with a `guard_rejected` outcome are transitions that were not taken, `to` is their target.
+    /// `cause` is the record of the transition that generated the event, it correlates the records of several machines.
+    /// `envelope` and `principal` are the id and the sender of the envelope that delivered the event, if any.
+    /// Records with a `duplicate` outcome are ignored deliveries of an envelope, see [`StateMachine::deliver`].
+    /// Records with an `unauthorized` outcome are events refused by the authorizer, `error` is the reason.
+    /// Records with an `awaiting_approval` outcome are approvals that did not complete their transition yet,
+    /// `principal` is the approver.
+    /// Records with an `escalation_skipped` outcome are unhandled escalation events, `error` is the reason.
+    /// Records with a `rolled_back` outcome are transitions undone because their action failed, `error` is its message.
+    /// # Arguments
+    /// * `writer` - where to write the document, records are streamed one by one while the history is locked
+    /// # Errors
+    /// If writing fails
+    /// # Panics
+    /// If the lock is poisoned
+    pub fn export_audit(&self, mut writer: impl Write) -> io::Result<()> {
+        writeln!(writer, "{{")?;
+        writeln!(writer, "\"schema\": {},", json::string(AUDIT_SCHEMA))?;
+        writeln!(writer, "\"machine\": {},", json::string(&self.name))?;
+        writeln!(
+            writer,
+            "\"exported_at\": {},",
+            json::string(&json::timestamp(self.clock.now()))
+        )?;
+        writeln!(
+            writer,
+            "\"current_state\": {},",
+            json::string(&self.current_state().to_string())
+        )?;
+        writeln!(writer, "\"records\": [")?;
+        if let Some(ref history) = self.history {
+            let mut history = history.lock().expect("failed to get lock");
+            history.prune(self.clock.now());
+            for (i, record) in history.records().enumerate() {
+                if i > 0 {
+                    writeln!(writer, ",")?;
+                }
+                write_record(&mut writer, record)?;
+            }
+        }
+        writeln!(writer, "\n]")?;
+        writeln!(writer, "}}")
+    }
+
+    /// Export the transition history as a JSON audit document, see [`StateMachine::export_audit`]
+    /// # Panics
+    /// If the lock is poisoned
+    pub fn history_as_json(&self) -> String {
+        let mut out = Vec::new();
+        self.export_audit(&mut out)
+            .expect("writing to a Vec never fails");
+        String::from_utf8(out).expect("the export is valid UTF-8")
+    }
+}
+
+fn write_record(writer: &mut impl Write, record: &TransitionRecord) -> io::Result<()> {
+    let (outcome, error, guard) = outcome_fields(&record.outcome);
+    let envelope = match record.outcome {
+        Outcome::Duplicate(ref id) => Some(id),
+        _ => record.envelope.as_ref(),
+    };
+    let principal = match record.outcome {
+        Outcome::AwaitingApproval(ref principal) => Some(principal),
+        _ => record.principal.as_ref(),
+    };
+    let optional =
+        |value: Option<&String>| value.map_or_else(|| "null".to_string(), |v| json::string(v));
+    write!(
+        writer,
+        "{{\"seq\": {}, \"timestamp\": {}, \"from\": {}, \"event\": {}, \"to\": {}, \"duration_us\": {}, \"outcome\": \"{outcome}\", \"error\": {error}, \"guard\": {guard}, \"cause\": {}, \"envelope\": {}, \"principal\": {}}}",
+        record.seq,
+        json::string(&json::timestamp(record.timestamp)),
+        json::string(&record.from.to_string()),
+        json::string(&record.event.to_string()),
+        json::string(&record.to.to_string()),
+        record.duration.as_micros(),
+        optional(record.cause.as_ref().map(ToString::to_string).as_ref()),
+        optional(envelope),
+        optional(principal),
+    )
+}
+
+/// The `outcome`, `error` and `guard` fields of a record, as JSON values
+pub(crate) fn outcome_fields(outcome: &Outcome) -> (&'static str, String, String) {
+    let null = || "null".to_string();
+    match outcome {
+        Outcome::Ok => ("ok", null(), null()),
+        Outcome::ActionFailed(e) => ("action_failed", json::string(e), null()),
+        Outcome::ActionPanicked(e) => ("action_panicked", json::string(e), null()),
+        Outcome::GuardRejected(g) => ("guard_rejected", null(), json::string(g)),
+        Outcome::Duplicate(_) => ("duplicate", null(), null()),
+        Outcome::Unauthorized(r) => ("unauthorized", json::string(r), null()),
+        Outcome::AwaitingApproval(_) => ("awaiting_approval", null(), null()),
+        Outcome::EscalationSkipped(e) => ("escalation_skipped", json::string(e), null()),
+        Outcome::RolledBack(e) => ("rolled_back", json::string(e), null()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Cause, Envelope, Event, State, StateMachineBuilder};
+    #[cfg(feature = "tracing")]
+    use tracing_test::traced_test;
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_history_as_json() {
+        let initial = State::new("initial");
+        let second = State::new("sec\"ond");
+        let e1 = Event::new("e1");
+        let e2 = Event::new("e2");
+        let machine = StateMachineBuilder::new("test", &initial)
+            .add_event(initial.clone(), e1.clone(), second.clone(), None)
+            .add_event(
+                second.clone(),
+                e2.clone(),
+                initial.clone(),
+                Some(Box::new(|| {
+                    Err(crate::error::message("action failed".to_string()))
+                })),
+            )
+            .with_history(10)
+            .build();
+        machine.event(&e1).unwrap();
+        assert!(machine.event(&e2).is_err());
+
+        let json = machine.history_as_json();
+        let lines: Vec<&str> = json.lines().collect();
+        assert_eq!(lines[1], "\"schema\": \"state-machine.audit.v1\",");
+        assert_eq!(lines[2], "\"machine\": \"test\",");
+        assert_eq!(lines[4], "\"current_state\": \"initial\",");
+        assert!(lines[6].starts_with("{\"seq\": 1, \"timestamp\": \""));
+        assert!(
+            lines[6].contains("\"from\": \"initial\", \"event\": \"e1\", \"to\": \"sec\\\"ond\"")
+        );
+        assert!(lines[6].ends_with(
+            "\"outcome\": \"ok\", \"error\": null, \"guard\": null, \"cause\": null, \"envelope\": null, \"principal\": null},"
+        ));
+        assert!(lines[7].starts_with("{\"seq\": 2,"));
+        assert!(lines[7].contains(
+            "\"outcome\": \"action_failed\", \"error\": \"action failed\", \"guard\": null,"
+        ));
+        assert_eq!(lines[8..], ["]", "}"]);
+    }
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_audit_envelopes() {
+        let (draft, published) = (State::new("draft"), State::new("published"));
+        let (review, publish) = (Event::new("review"), Event::new("publish"));
+        let machine = StateMachineBuilder::new("post", &draft)
+            .add_event(draft.clone(), review.clone(), draft.clone(), None)
+            .add_event(draft.clone(), publish.clone(), published, None)
+            .with_approvals(2)
+            .dedup_window(10)
+            .with_history(10)
+            .build();
+        let cause = Cause {
+            machine: "editor".to_string(),
+            seq: 7,
+        };
+        let reviewed = Envelope::new("m1", review.clone())
+            .with_principal("carol")
+            .with_cause(cause);
+        machine.deliver(&reviewed).unwrap();
+        machine.deliver(&reviewed).unwrap();
+        machine
+            .deliver(&Envelope::new("m2", publish).with_principal("alice"))
+            .unwrap();
+
+        let json = machine.history_as_json();
+        let lines: Vec<&str> = json.lines().collect();
+        assert!(lines[6].ends_with(
+            "\"cause\": \"editor#7\", \"envelope\": \"m1\", \"principal\": \"carol\"},"
+        ));
+        assert!(lines[7].contains("\"outcome\": \"duplicate\""));
+        assert!(lines[7].contains("\"envelope\": \"m1\""));
+        assert!(lines[8].contains("\"outcome\": \"awaiting_approval\""));
+        assert!(lines[8].ends_with("\"principal\": \"alice\"}"));
+    }
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_history_disabled() {
+        let initial = State::new("initial");
+        let e1 = Event::new("e1");
+        let machine = StateMachineBuilder::new("test", &initial)
+            .add_event(initial.clone(), e1.clone(), initial.clone(), None)
+            .build();
+        machine.event(&e1).unwrap();
+        assert!(machine.history().is_empty());
+        assert!(machine.history_as_json().contains("\"records\": [\n\n]"));
+    }
+}
@@ -0,0 +1,145 @@
+use crate::dedup::Dispatch;
+use crate::{Envelope, Event, Outcome, State, StateMachine, StateMachineBuilder};
+use std::fmt;
+
+/// An event refused by the [`Authorizer`] of a machine
+///
+/// Returned by `event()` and `deliver()` in a [`StateMachineError::Unauthorized`](crate::StateMachineError::Unauthorized)
+/// converted to the error type of the machine, it can be recovered by downcasting.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Unauthorized {
+    /// who sent the event, if known
+    pub principal: Option<String>,
+    pub event: Event,
+    /// the state in which the event was refused
+    pub state: State,
+    /// why the event was refused
+    pub reason: String,
+}
+
+impl Unauthorized {
+    /// Refuse the event of an envelope in a state
+    pub fn new(envelope: &Envelope, state: &State, reason: impl Into<String>) -> Self {
+        Self {
+            principal: envelope.principal.clone(),
+            event: envelope.event.clone(),
+            state: state.clone(),
+            reason: reason.into(),
+        }
+    }
+}
+
+impl fmt::Display for Unauthorized {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "unauthorized event {} in state {}",
+            self.event, self.state
+        )?;
+        if let Some(ref principal) = self.principal {
+            write!(f, " for {principal}")?;
+        }
+        write!(f, ": {}", self.reason)
+    }
+}
+
+impl std::error::Error for Unauthorized {}
+
+/// Decides whether an event may be handled, before any transition is looked up
+///
+/// Events passed to `event()` are authorized as an envelope without id nor principal.
+pub trait Authorizer {
+    /// Check if the event of an envelope may be handled in a state
+    /// # Errors
+    /// If the event is refused
+    fn authorize(&self, envelope: &Envelope, state: &State) -> Result<(), Unauthorized>;
+}
+
+impl<F> Authorizer for F
+where
+    F: Fn(&Envelope, &State) -> Result<(), Unauthorized>,
+{
+    fn authorize(&self, envelope: &Envelope, state: &State) -> Result<(), Unauthorized> {
+        self(envelope, state)
+    }
+}
+
+impl<Err> StateMachine<Err> {
+    /// Ask the authorizer, if any, recording refused events in the history
+    pub(crate) fn authorize(&self, dispatch: Dispatch, state: &State) -> Result<(), Unauthorized> {
+        let Some(ref authorizer) = self.authorizer else {
+            return Ok(());
+        };
+        authorizer
+            .authorize(&dispatch.to_envelope(), state)
+            .inspect_err(|refused| {
+                crate::trace::error!("{}: {refused}", self.log_name());
+                self.record_not_taken(
+                    state,
+                    dispatch,
+                    Outcome::Unauthorized(refused.reason.clone()),
+                );
+            })
+    }
+}
+
+impl<Err> StateMachineBuilder<Err> {
+    #[must_use]
+    /// Check every event with `authorizer` before handling it, see [`Authorizer`]
+    pub fn with_authorizer(mut self, authorizer: Box<dyn Authorizer>) -> Self {
+        self.authorizer = Some(authorizer);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Delivery, Error};
+    #[cfg(feature = "tracing")]
+    use tracing_test::traced_test;
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_authorizer() {
+        let running = State::new("running");
+        let stopped = State::new("stopped");
+        let stop = Event::new("stop");
+        let operators_only = |envelope: &Envelope, state: &State| {
+            if envelope.principal.as_deref() == Some("operator") {
+                Ok(())
+            } else {
+                Err(Unauthorized::new(envelope, state, "operator role required"))
+            }
+        };
+        let machine = StateMachineBuilder::new("pump", &running)
+            .add_event(running.clone(), stop.clone(), stopped.clone(), None)
+            .with_authorizer(Box::new(operators_only))
+            .with_history(10)
+            .build();
+
+        let error: Error = machine.event(&stop).unwrap_err();
+        let Some(crate::StateMachineError::Unauthorized(refused)) = error.downcast_ref() else {
+            panic!("not refused: {error}");
+        };
+        assert_eq!(refused.reason, "operator role required");
+        let guest = Envelope::new("m1", stop.clone()).with_principal("guest");
+        assert_eq!(
+            machine.deliver(&guest).unwrap_err().to_string(),
+            "unauthorized event stop in state running for guest: operator role required"
+        );
+        assert_eq!(machine.current_state(), running);
+
+        let operator = Envelope::new("m2", stop).with_principal("operator");
+        assert_eq!(machine.deliver(&operator).unwrap(), Delivery::Handled);
+        let outcomes: Vec<Outcome> = machine.history().into_iter().map(|r| r.outcome).collect();
+        assert_eq!(
+            outcomes,
+            vec![
+                Outcome::Unauthorized("operator role required".to_string()),
+                Outcome::Unauthorized("operator role required".to_string()),
+                Outcome::Ok,
+            ]
+        );
+    }
+}
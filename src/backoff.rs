@@ -0,0 +1,157 @@
+use crate::{Event, StateMachine};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How much randomness is added to a [`Backoff`] delay
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Jitter {
+    /// the exact exponential delay
+    None,
+    /// a random delay between zero and the exponential delay
+    #[default]
+    Full,
+    /// half the exponential delay, plus a random delay up to the other half
+    Equal,
+}
+
+/// Exponential backoff with jitter and a cap, for the time-based retries of the crate
+///
+/// The jitter is derived from a seed and the attempt number, so the same backoff
+/// always gives the same delays: use a different seed per instance to spread retries,
+/// and a fixed seed in tests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Backoff {
+    base: Duration,
+    factor: u32,
+    max: Duration,
+    jitter: Jitter,
+    seed: u64,
+}
+
+impl Backoff {
+    /// Create a backoff doubling from `base`, with full jitter, without cap, seeded by the system time
+    pub fn exponential(base: Duration) -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.subsec_nanos().into());
+        Self {
+            base,
+            factor: 2,
+            max: Duration::MAX,
+            jitter: Jitter::default(),
+            seed,
+        }
+    }
+
+    #[must_use]
+    /// Multiply the delay by `factor` after every attempt
+    pub fn factor(mut self, factor: u32) -> Self {
+        self.factor = factor;
+        self
+    }
+
+    #[must_use]
+    /// Never wait longer than `max`, before jitter
+    pub fn max(mut self, max: Duration) -> Self {
+        self.max = max;
+        self
+    }
+
+    #[must_use]
+    /// Choose the jitter, the default is [`Jitter::Full`]
+    pub fn jitter(mut self, jitter: Jitter) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    #[must_use]
+    /// Derive the jitter from `seed`
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// The delay before a retry
+    /// # Arguments
+    /// * `attempt` - the number of the retry, starting at 0
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let delay = self
+            .factor
+            .checked_pow(attempt)
+            .and_then(|factor| self.base.checked_mul(factor))
+            .map_or(self.max, |delay| delay.min(self.max));
+        let random = splitmix64(self.seed ^ u64::from(attempt)) as f64 / u64::MAX as f64;
+        match self.jitter {
+            Jitter::None => delay,
+            Jitter::Full => delay.mul_f64(random),
+            Jitter::Equal => delay / 2 + (delay / 2).mul_f64(random),
+        }
+    }
+}
+
+/// Mix the bits of a number, see <https://prng.di.unimi.it/splitmix64.c>
+pub(crate) fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+impl<Err> StateMachine<Err> {
+    /// Schedule a retry of `event` after the backoff delay of `attempt`, see [`StateMachine::schedule_at`]
+    /// # Returns
+    /// When the retry is due
+    pub fn schedule_retry(&self, backoff: &Backoff, attempt: u32, event: Event) -> SystemTime {
+        let at = self.clock.now() + backoff.delay(attempt);
+        self.schedule_at(at, event);
+        at
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ManualClock, State, StateMachineBuilder};
+    use std::sync::Arc;
+    #[cfg(feature = "tracing")]
+    use tracing_test::traced_test;
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_backoff_delays() {
+        let secs = Duration::from_secs;
+        let exact = Backoff::exponential(secs(1))
+            .max(secs(10))
+            .jitter(Jitter::None);
+        let delays: Vec<Duration> = (0..6).map(|attempt| exact.delay(attempt)).collect();
+        assert_eq!(
+            delays,
+            vec![secs(1), secs(2), secs(4), secs(8), secs(10), secs(10)]
+        );
+        assert_eq!(exact.delay(u32::MAX), secs(10));
+
+        let full = exact.jitter(Jitter::Full).seed(7);
+        let equal = exact.jitter(Jitter::Equal).seed(7);
+        for attempt in 0..6 {
+            assert!(full.delay(attempt) <= exact.delay(attempt));
+            assert!(equal.delay(attempt) >= exact.delay(attempt) / 2);
+            assert_eq!(full.delay(attempt), full.delay(attempt));
+        }
+        assert_ne!(full.delay(3), full.seed(8).delay(3));
+    }
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_schedule_retry() {
+        let idle = State::new("idle");
+        let clock = Arc::new(ManualClock::default());
+        let machine = StateMachineBuilder::new("client", &idle)
+            .add_event(idle.clone(), Event::new("connect"), idle, None)
+            .with_clock(clock.clone())
+            .build();
+        let backoff = Backoff::exponential(Duration::from_secs(1)).jitter(Jitter::None);
+        let at = machine.schedule_retry(&backoff, 2, Event::new("connect"));
+        assert_eq!(at, SystemTime::UNIX_EPOCH + Duration::from_secs(4));
+        clock.advance(Duration::from_secs(4));
+        assert_eq!(machine.fire_due().unwrap(), 1);
+    }
+}
@@ -0,0 +1,10 @@
+//! Building machines and the policies they are built with, from code, definitions or templates
+
+#[cfg(feature = "uuid-v7")]
+pub use crate::UuidV7;
+pub use crate::{
+    default_id_generator, Authorizer, Backoff, ChaosConfig, ContextBuilder, CounterIds, Definition,
+    FailurePolicy, FeatureGate, Format, IdGenerator, InvalidEntryPoint, Jitter, Overflow, Param,
+    Params, Scope, StateMachineBuilder, Template, Throttle, TransitionDef, TypedBuilder,
+    Unauthorized, FAILED,
+};
@@ -0,0 +1,885 @@
+use crate::forward::Target;
+use crate::trace::{debug, error};
+use crate::{
+    Cause, Clock, Error, Event, Forward, Health, Result, ShutdownReport, State, StateMachine,
+    StateMachineError, SystemClock, TransitionRecord,
+};
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::fmt;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread::{self, ThreadId};
+use std::time::{Duration, Instant, SystemTime};
+
+/// An event waiting for its machine
+struct Queued {
+    machine: String,
+    event: Event,
+    /// when the event stops being relevant
+    expires: Option<SystemTime>,
+    /// the transition that generated the event
+    cause: Option<Cause>,
+    /// posted by an action of the event being delivered, its cause is set after the delivery
+    from_delivery: bool,
+    /// where to send the result of the delivery, see [`BusSender::request`]
+    reply: Option<Sender<Reply>>,
+}
+
+/// The result of an event posted with [`BusSender::request`], once the bus delivered it
+///
+/// The transition recorded for the event, `None` if the machine recorded none (e.g. a deferred event),
+/// or the error of the machine, see [`StateMachineError::of`].
+pub type Reply = std::result::Result<Option<TransitionRecord>, StateMachineError>;
+
+/// The queue shared by the bus and its senders
+struct Mailbox {
+    queue: Mutex<VecDeque<Queued>>,
+    clock: RwLock<Arc<dyn Clock>>,
+    /// the thread delivering an event, the events it posts are caused by that delivery
+    delivering: Mutex<Option<ThreadId>>,
+}
+
+impl Mailbox {
+    fn now(&self) -> SystemTime {
+        self.clock.read().expect("failed to get lock").now()
+    }
+}
+
+type Queue = Arc<Mailbox>;
+
+/// An event dropped by the bus because it expired before it was dispatched
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeadLetter {
+    /// the name of the target machine
+    pub machine: String,
+    pub event: Event,
+    /// when the event expired
+    pub expired: SystemTime,
+}
+
+/// Creates the child machine of a parent, see [`EventBus::spawn_on_entry`]
+pub type ChildFactory<Err = Error> = Box<dyn Fn(&str) -> StateMachine<Err>>;
+
+/// Exchanges events between several state machines through a queue.
+///
+/// A machine holds its lock while its action runs, so an action that calls
+/// `event()` on another machine which in turn calls back into the first one
+/// deadlocks. The bus owns the machines and only hands out [`BusSender`]s to
+/// actions: posting an event just queues it, and [`EventBus::run`] dispatches
+/// the queued events one by one after the posting action has returned.
+pub struct EventBus<Err = Error> {
+    machines: HashMap<String, StateMachine<Err>>,
+    queue: Queue,
+    closed: bool,
+    /// the child factories by parent and state
+    children: HashMap<(String, State), ChildFactory<Err>>,
+    /// the name of the running child by parent and state
+    spawned: HashMap<(String, State), String>,
+    /// the parent of every running child
+    parents: HashMap<String, String>,
+    /// the tags of the machines
+    tags: HashMap<String, BTreeSet<String>>,
+    rules: Vec<Forward>,
+    dead_letters: Vec<DeadLetter>,
+}
+
+/// Cloneable handle used by actions to post events to machines on the bus
+#[derive(Clone)]
+pub struct BusSender {
+    queue: Queue,
+}
+
+impl BusSender {
+    /// Queue an event for a machine on the bus
+    /// # Arguments
+    /// * `machine` - the name of the target machine
+    /// * `event` - the event to deliver
+    /// # Panics
+    /// If the lock is poisoned
+    pub fn post(&self, machine: impl Into<String>, event: Event) {
+        self.push(machine.into(), event, None, None);
+    }
+
+    /// Queue an event and get its result once the bus delivered it
+    ///
+    /// The receiver gets a single [`Reply`], wait for it with `recv` on another thread or poll it with `try_recv`.
+    /// It is disconnected without a reply if the event is dropped, e.g. at the deadline of [`EventBus::shutdown`].
+    /// # Panics
+    /// If the lock is poisoned
+    pub fn request(&self, machine: impl Into<String>, event: Event) -> Receiver<Reply> {
+        let (reply, receiver) = mpsc::channel();
+        self.push(machine.into(), event, None, Some(reply));
+        receiver
+    }
+
+    /// Queue an event that is dropped if it is not dispatched within `ttl`, see [`EventBus::dead_letters`]
+    /// # Panics
+    /// If the lock is poisoned
+    pub fn post_with_ttl(&self, machine: impl Into<String>, event: Event, ttl: Duration) {
+        let expires = self.queue.now() + ttl;
+        self.push(machine.into(), event, Some(expires), None);
+    }
+
+    fn push(
+        &self,
+        machine: String,
+        event: Event,
+        expires: Option<SystemTime>,
+        reply: Option<Sender<Reply>>,
+    ) {
+        let from_delivery = *self.queue.delivering.lock().expect("failed to get lock")
+            == Some(thread::current().id());
+        self.queue
+            .queue
+            .lock()
+            .expect("failed to get lock")
+            .push_back(Queued {
+                machine,
+                event,
+                expires,
+                cause: None,
+                from_delivery,
+                reply,
+            });
+    }
+
+    /// Queue an event generated by a transition
+    fn post_caused(&self, machine: String, event: Event, cause: Cause) {
+        self.queue
+            .queue
+            .lock()
+            .expect("failed to get lock")
+            .push_back(Queued {
+                machine,
+                event,
+                expires: None,
+                cause: Some(cause),
+                from_delivery: false,
+                reply: None,
+            });
+    }
+}
+
+impl<Err: From<Error> + fmt::Display + 'static> Default for EventBus<Err> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Err> EventBus<Err>
+where
+    Err: From<Error> + fmt::Display + 'static,
+{
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            machines: HashMap::new(),
+            queue: Arc::new(Mailbox {
+                queue: Mutex::new(VecDeque::new()),
+                clock: RwLock::new(Arc::new(SystemClock)),
+                delivering: Mutex::new(None),
+            }),
+            closed: false,
+            children: HashMap::new(),
+            spawned: HashMap::new(),
+            parents: HashMap::new(),
+            tags: HashMap::new(),
+            rules: Vec::new(),
+            dead_letters: Vec::new(),
+        }
+    }
+
+    #[must_use]
+    /// Use another clock than the system clock for the expiry of events
+    /// The senders taken before share the new clock
+    /// # Panics
+    /// If the lock is poisoned
+    pub fn with_clock(self, clock: Arc<dyn Clock>) -> Self {
+        *self.queue.clock.write().expect("failed to get lock") = clock;
+        self
+    }
+
+    /// Get a sender that can be moved into actions
+    #[must_use]
+    pub fn sender(&self) -> BusSender {
+        BusSender {
+            queue: self.queue.clone(),
+        }
+    }
+
+    /// Register a machine on the bus, under its own name
+    /// A machine previously registered with the same name is replaced
+    pub fn register(&mut self, machine: StateMachine<Err>) {
+        self.machines.insert(machine.name().to_string(), machine);
+    }
+
+    /// Run a child machine while a parent machine is in a state
+    ///
+    /// When the parent enters `state`, the factory creates the child, which is registered under its own name.
+    /// When the child enters a terminal state (see [`StateMachine::is_terminal`]),
+    /// an event named after that state is posted to the parent.
+    /// When the parent leaves `state`, the child and its own children are removed from the bus.
+    /// # Arguments
+    /// * `parent` - the name of the parent machine
+    /// * `state` - the state of the parent during which the child runs
+    /// * `factory` - creates the child, gets the name of the parent
+    pub fn spawn_on_entry(
+        &mut self,
+        parent: impl Into<String>,
+        state: State,
+        factory: impl Fn(&str) -> StateMachine<Err> + 'static,
+    ) {
+        self.children
+            .insert((parent.into(), state), Box::new(factory));
+    }
+
+    /// Tag a machine, for the forwarding rules, a machine can have several tags
+    pub fn tag(&mut self, machine: impl Into<String>, tag: impl Into<String>) {
+        self.tags
+            .entry(machine.into())
+            .or_default()
+            .insert(tag.into());
+    }
+
+    /// Add a forwarding rule, see [`Forward`]
+    ///
+    /// The events are queued when the watched machine enters the state, after its action ran,
+    /// so they are dispatched like the events posted by actions.
+    pub fn forward(&mut self, rule: Forward) {
+        self.rules.push(rule);
+    }
+
+    /// Get a registered machine
+    pub fn machine(&self, name: &str) -> Option<&StateMachine<Err>> {
+        self.machines.get(name)
+    }
+
+    /// Queue an event for a machine on the bus
+    /// Events posted after [`EventBus::shutdown`] are dropped
+    pub fn post(&self, machine: impl Into<String>, event: Event) {
+        if self.closed {
+            error!("bus: shut down, dropping {event}");
+            return;
+        }
+        self.sender().post(machine, event);
+    }
+
+    /// Queue an event that is dropped if it is not dispatched within `ttl`
+    /// Events posted after [`EventBus::shutdown`] are dropped
+    pub fn post_with_ttl(&self, machine: impl Into<String>, event: Event, ttl: Duration) {
+        if self.closed {
+            error!("bus: shut down, dropping {event}");
+            return;
+        }
+        self.sender().post_with_ttl(machine, event, ttl);
+    }
+
+    /// Queue an event and get its result once it is delivered, see [`BusSender::request`]
+    /// The receiver of an event posted after [`EventBus::shutdown`] is disconnected
+    pub fn request(&self, machine: impl Into<String>, event: Event) -> Receiver<Reply> {
+        if self.closed {
+            error!("bus: shut down, dropping {event}");
+            return mpsc::channel().1;
+        }
+        self.sender().request(machine, event)
+    }
+
+    /// Get the events dropped because they expired before they were dispatched, oldest first
+    pub fn dead_letters(&self) -> &[DeadLetter] {
+        &self.dead_letters
+    }
+
+    /// Get and forget the events dropped because they expired
+    pub fn take_dead_letters(&mut self) -> Vec<DeadLetter> {
+        std::mem::take(&mut self.dead_letters)
+    }
+
+    /// Take the oldest queued event that did not expire, expired events become dead letters
+    fn pop(&mut self) -> Option<Queued> {
+        let now = self.queue.now();
+        loop {
+            // release the queue lock before dispatching, actions post to the same queue
+            let next = self
+                .queue
+                .queue
+                .lock()
+                .expect("failed to get lock")
+                .pop_front()?;
+            match next.expires {
+                Some(expired) if expired < now => {
+                    error!("bus: {} for {} expired", next.event, next.machine);
+                    self.dead_letters.push(DeadLetter {
+                        machine: next.machine,
+                        event: next.event,
+                        expired,
+                    });
+                }
+                _ => return Some(next),
+            }
+        }
+    }
+
+    /// Number of queued events
+    /// # Panics
+    /// If the lock is poisoned
+    pub fn pending(&self) -> usize {
+        self.queue.queue.lock().expect("failed to get lock").len()
+    }
+
+    /// Get the status of a registered machine, with the number of events queued for it
+    /// # Returns
+    /// `None` if no machine with that name is registered
+    /// # Panics
+    /// If the lock is poisoned
+    pub fn health(&self, name: &str) -> Option<Health> {
+        let mut health = self.machines.get(name)?.health();
+        health.queue_depth = self
+            .queue
+            .queue
+            .lock()
+            .expect("failed to get lock")
+            .iter()
+            .filter(|queued| queued.machine == name)
+            .count();
+        Some(health)
+    }
+
+    /// Dispatch the oldest queued event, dropping the expired events before it
+    ///
+    /// The events posted by the actions of the target, by forwarding rules and to parents
+    /// record the transition of the target as their cause, see [`TransitionRecord::cause`](crate::TransitionRecord::cause).
+    /// # Returns
+    /// `false` if the queue was empty
+    /// # Errors
+    /// If the target machine is not registered or fails to handle the event
+    /// # Panics
+    /// If the lock is poisoned
+    pub fn step(&mut self) -> Result<bool, Err> {
+        let Some(queued) = self.pop() else {
+            return Ok(false);
+        };
+        self.deliver(&queued.machine, &queued.event, queued.cause, queued.reply)?;
+        Ok(true)
+    }
+
+    fn deliver(
+        &mut self,
+        name: &str,
+        event: &Event,
+        cause: Option<Cause>,
+        reply: Option<Sender<Reply>>,
+    ) -> Result<(), Err> {
+        match cause {
+            Some(ref cause) => debug!("bus: delivering {event} to {name}, caused by {cause}"),
+            None => debug!("bus: delivering {event} to {name}"),
+        }
+        let Some(machine) = self.machines.get(name) else {
+            error!("bus: no machine named {name}");
+            let error = StateMachineError::NoMachine {
+                name: name.to_string(),
+            };
+            if let Some(reply) = reply {
+                let _ = reply.send(Err(error.clone()));
+            }
+            return Err(Error::from(error).into());
+        };
+        let before = machine.current_state();
+        let last = machine.last_transition().map(|record| record.seq);
+        *self.queue.delivering.lock().expect("failed to get lock") = Some(thread::current().id());
+        let result = match cause {
+            Some(cause) => machine.caused_event(event, cause),
+            None => machine.event(event),
+        };
+        *self.queue.delivering.lock().expect("failed to get lock") = None;
+        let after = machine.current_state();
+        let record = machine
+            .last_transition()
+            .filter(|record| Some(record.seq) != last);
+        if let Some(reply) = reply {
+            // the requester may have stopped waiting
+            let _ = reply.send(match result {
+                Ok(()) => Ok(record.clone()),
+                Err(ref e) => Err(StateMachineError::of(e)),
+            });
+        }
+        let taken = record.map(|record| Cause {
+            machine: name.to_string(),
+            seq: record.seq,
+        });
+        for queued in self
+            .queue
+            .queue
+            .lock()
+            .expect("failed to get lock")
+            .iter_mut()
+            .filter(|queued| queued.from_delivery)
+        {
+            queued.from_delivery = false;
+            queued.cause.clone_from(&taken);
+        }
+        let Some(taken) = taken else {
+            return result;
+        };
+        // a failed action still leaves the state
+        if before != after {
+            let terminal = machine.is_terminal(&after);
+            if let Some(child) = self.spawned.remove(&(name.to_string(), before)) {
+                self.teardown(&child);
+            }
+            self.spawn(name, &after);
+            self.apply_rules(name, &after, &taken);
+            if let Some(parent) = self.parents.get(name).filter(|_| terminal) {
+                debug!("bus: {name} finished in {after}, notifying {parent}");
+                self.sender()
+                    .post_caused(parent.clone(), Event::new(after.to_string()), taken);
+            }
+        }
+        result
+    }
+
+    fn spawn(&mut self, parent: &str, state: &State) {
+        let key = (parent.to_string(), state.clone());
+        let Some(factory) = self.children.get(&key) else {
+            return;
+        };
+        let child = factory(parent);
+        let name = child.name().to_string();
+        debug!("bus: {parent} entered {state}, spawning {name}");
+        self.parents.insert(name.clone(), parent.to_string());
+        self.spawned.insert(key, name);
+        self.register(child);
+    }
+
+    /// Queue the events of the forwarding rules of a machine entering a state
+    fn apply_rules(&self, name: &str, state: &State, cause: &Cause) {
+        let sender = self.sender();
+        let rules = self
+            .rules
+            .iter()
+            .filter(|rule| rule.machine == name && rule.state == *state);
+        for rule in rules {
+            for target in &rule.targets {
+                let machines: Vec<&String> = match target {
+                    Target::Machine(machine) => vec![machine],
+                    Target::Tagged(tag) => self
+                        .tags
+                        .iter()
+                        .filter(|(machine, tags)| *machine != name && tags.contains(tag))
+                        .map(|(machine, _)| machine)
+                        .collect(),
+                };
+                for machine in machines {
+                    debug!(
+                        "bus: {name} entered {state}, forwarding {} to {machine}",
+                        rule.event
+                    );
+                    sender.post_caused(machine.clone(), rule.event.clone(), cause.clone());
+                }
+            }
+        }
+    }
+
+    /// Remove a child and its own children from the bus
+    fn teardown(&mut self, child: &str) {
+        debug!("bus: removing {child}");
+        self.machines.remove(child);
+        self.parents.remove(child);
+        let grandchildren: Vec<(String, State)> = self
+            .spawned
+            .keys()
+            .filter(|(parent, _)| parent == child)
+            .cloned()
+            .collect();
+        for key in grandchildren {
+            if let Some(grandchild) = self.spawned.remove(&key) {
+                self.teardown(&grandchild);
+            }
+        }
+    }
+
+    /// Dispatch queued events until the queue is empty
+    /// # Returns
+    /// The number of dispatched events
+    /// # Errors
+    /// On the first event that fails, the remaining events stay queued
+    pub fn run(&mut self) -> Result<usize, Err> {
+        let mut count = 0;
+        while self.step()? {
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Stop accepting events from [`EventBus::post`] and dispatch the queued events until `deadline`
+    ///
+    /// Unlike [`EventBus::run`], a failing event does not stop the dispatch.
+    /// Events posted by actions while draining are dispatched as well.
+    /// # Returns
+    /// The number of dispatched events, and the events that failed or were still queued at the deadline
+    /// # Panics
+    /// If the lock is poisoned
+    pub fn shutdown(&mut self, deadline: Instant) -> ShutdownReport<(String, Event)> {
+        self.closed = true;
+        let mut report = ShutdownReport::default();
+        while Instant::now() < deadline {
+            let Some(queued) = self.pop() else {
+                return report;
+            };
+            match self.deliver(&queued.machine, &queued.event, queued.cause, queued.reply) {
+                Ok(()) => report.completed += 1,
+                Err(e) => report
+                    .unfinished
+                    .push(((queued.machine, queued.event), e.to_string())),
+            }
+        }
+        let mut queue = self.queue.queue.lock().expect("failed to get lock");
+        report.unfinished.extend(queue.drain(..).map(|queued| {
+            (
+                (queued.machine, queued.event),
+                "deadline exceeded".to_string(),
+            )
+        }));
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{State, StateMachineBuilder};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::sync::mpsc;
+    use std::time::Duration;
+    #[cfg(feature = "tracing")]
+    use tracing_test::traced_test;
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_direct_calls_deadlock() {
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            let idle = State::new("idle");
+            let waiting = State::new("waiting");
+            let busy = State::new("busy");
+            let go = Event::new("go");
+            let request = Event::new("request");
+            let reply = Event::new("reply");
+            let a_slot: Rc<RefCell<Option<Rc<StateMachine>>>> = Rc::new(RefCell::new(None));
+            let b_slot: Rc<RefCell<Option<Rc<StateMachine>>>> = Rc::new(RefCell::new(None));
+            let (b, r) = (b_slot.clone(), request.clone());
+            let a = StateMachineBuilder::new("a", &idle)
+                .add_event(
+                    idle.clone(),
+                    go.clone(),
+                    waiting.clone(),
+                    Some(Box::new(move || {
+                        let b = b.borrow().clone().expect("b registered");
+                        b.event(&r)
+                    })),
+                )
+                .add_event(waiting, reply.clone(), idle.clone(), None)
+                .build();
+            let (a_ref, r) = (a_slot.clone(), reply);
+            let b = StateMachineBuilder::new("b", &idle)
+                .add_event(
+                    idle,
+                    request,
+                    busy,
+                    Some(Box::new(move || {
+                        let a = a_ref.borrow().clone().expect("a registered");
+                        // `a` is still locked by the action that called us
+                        a.event(&r)
+                    })),
+                )
+                .build();
+            let a = Rc::new(a);
+            *a_slot.borrow_mut() = Some(a.clone());
+            *b_slot.borrow_mut() = Some(Rc::new(b));
+            let _ = a.event(&go);
+            let _ = tx.send(());
+        });
+        // the dispatch never completes (or panics on platforms detecting the re-entrant lock)
+        assert!(rx.recv_timeout(Duration::from_millis(200)).is_err());
+    }
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_bus_exchange() -> Result<()> {
+        let idle = State::new("idle");
+        let waiting = State::new("waiting");
+        let done = State::new("done");
+        let busy = State::new("busy");
+        let go = Event::new("go");
+        let request = Event::new("request");
+        let reply = Event::new("reply");
+        let mut bus = EventBus::new();
+        let sender = bus.sender();
+        let r = request.clone();
+        bus.register(
+            StateMachineBuilder::new("a", &idle)
+                .add_event(
+                    idle.clone(),
+                    go.clone(),
+                    waiting.clone(),
+                    Some(Box::new(move || {
+                        sender.post("b", r.clone());
+                        Ok(())
+                    })),
+                )
+                .add_event(waiting, reply.clone(), done.clone(), None)
+                .build(),
+        );
+        let sender = bus.sender();
+        bus.register(
+            StateMachineBuilder::new("b", &idle)
+                .add_event(
+                    idle,
+                    request,
+                    busy.clone(),
+                    Some(Box::new(move || {
+                        sender.post("a", reply.clone());
+                        Ok(())
+                    })),
+                )
+                .build(),
+        );
+
+        bus.post("a", go);
+        assert_eq!(bus.pending(), 1);
+        assert_eq!(bus.run()?, 3);
+        assert_eq!(bus.pending(), 0);
+        assert_eq!(
+            bus.machine("a").map(StateMachine::current_state),
+            Some(done)
+        );
+        assert_eq!(
+            bus.machine("b").map(StateMachine::current_state),
+            Some(busy)
+        );
+        Ok(())
+    }
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_unknown_machine() {
+        let mut bus: EventBus = EventBus::new();
+        bus.post("nope", Event::new("e1"));
+        assert!(bus.run().is_err());
+    }
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_bus_shutdown() {
+        let idle = State::new("idle");
+        let ping = Event::new("ping");
+        let mut bus: EventBus = EventBus::new();
+        bus.register(
+            StateMachineBuilder::new("a", &idle)
+                .add_event(idle.clone(), ping.clone(), idle.clone(), None)
+                .build(),
+        );
+        bus.post("a", ping.clone());
+        bus.post("nope", ping.clone());
+        bus.post("a", ping.clone());
+        assert_eq!(bus.health("a").map(|h| h.queue_depth), Some(2));
+        let report = bus.shutdown(Instant::now() + Duration::from_secs(10));
+        assert_eq!(report.completed, 2);
+        assert_eq!(
+            report.unfinished,
+            vec![(
+                ("nope".to_string(), ping.clone()),
+                "no machine named nope".to_string()
+            )]
+        );
+
+        bus.post("a", ping.clone());
+        assert_eq!(bus.pending(), 0);
+        bus.sender().post("a", ping);
+        assert_eq!(bus.shutdown(Instant::now()).unfinished.len(), 1);
+    }
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_child_lifecycle() -> Result<()> {
+        let (idle, running, done) = (
+            State::new("idle"),
+            State::new("running"),
+            State::new("done"),
+        );
+        let (queued, succeeded) = (State::new("queued"), State::new("succeeded"));
+        let mut bus: EventBus = EventBus::new();
+        bus.register(
+            StateMachineBuilder::new("job", &idle)
+                .add_event(idle, Event::new("start"), running.clone(), None)
+                .add_event(running.clone(), Event::new("succeeded"), done.clone(), None)
+                .build(),
+        );
+        bus.spawn_on_entry("job", running, move |parent| {
+            StateMachineBuilder::new(format!("{parent}/worker"), &queued)
+                .add_event(queued.clone(), Event::new("run"), succeeded.clone(), None)
+                .build()
+        });
+
+        bus.post("job", Event::new("start"));
+        bus.run()?;
+        assert!(bus.machine("job/worker").is_some());
+        bus.post("job/worker", Event::new("run"));
+        // the worker finishes, the job is notified and removes it
+        assert_eq!(bus.run()?, 2);
+        assert_eq!(
+            bus.machine("job").map(StateMachine::current_state),
+            Some(done)
+        );
+        assert!(bus.machine("job/worker").is_none());
+        Ok(())
+    }
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_forwarding_rules() -> Result<()> {
+        let (ok, failed, blocked) = (
+            State::new("ok"),
+            State::new("failed"),
+            State::new("blocked"),
+        );
+        let dependency_failed = Event::new("dependency_failed");
+        let mut bus: EventBus = EventBus::new();
+        for name in ["db", "api", "web", "cron"] {
+            bus.register(
+                StateMachineBuilder::new(name, &ok)
+                    .add_event(ok.clone(), Event::new("crash"), failed.clone(), None)
+                    .add_event(ok.clone(), dependency_failed.clone(), blocked.clone(), None)
+                    .build(),
+            );
+        }
+        bus.tag("api", "downstream");
+        bus.tag("web", "downstream");
+        bus.forward(Forward::new("db", failed.clone(), dependency_failed).to_tagged("downstream"));
+
+        bus.post("db", Event::new("crash"));
+        assert_eq!(bus.run()?, 3);
+        let state = |name| bus.machine(name).map(StateMachine::current_state);
+        assert_eq!(state("db"), Some(failed));
+        assert_eq!(state("api"), Some(blocked.clone()));
+        assert_eq!(state("web"), Some(blocked));
+        assert_eq!(state("cron"), Some(ok));
+        Ok(())
+    }
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_expired_events() -> Result<()> {
+        let idle = State::new("idle");
+        let reading = Event::new("reading");
+        let clock = Arc::new(crate::ManualClock::default());
+        let bus: EventBus = EventBus::new();
+        let sender = bus.sender();
+        let mut bus = bus.with_clock(clock.clone());
+        bus.register(
+            StateMachineBuilder::new("sensor", &idle)
+                .add_event(idle.clone(), reading.clone(), idle, None)
+                .build(),
+        );
+        sender.post_with_ttl("sensor", reading.clone(), Duration::from_secs(1));
+        bus.post_with_ttl("sensor", reading.clone(), Duration::from_secs(10));
+        bus.post("sensor", reading.clone());
+        clock.advance(Duration::from_secs(5));
+
+        assert_eq!(bus.run()?, 2);
+        assert_eq!(
+            bus.take_dead_letters(),
+            vec![DeadLetter {
+                machine: "sensor".to_string(),
+                event: reading,
+                expired: SystemTime::UNIX_EPOCH + Duration::from_secs(1),
+            }]
+        );
+        assert!(bus.dead_letters().is_empty());
+        Ok(())
+    }
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_event_provenance() -> Result<()> {
+        let (idle, busy, done) = (State::new("idle"), State::new("busy"), State::new("done"));
+        let (go, ping, pong) = (Event::new("go"), Event::new("ping"), Event::new("pong"));
+        let mut bus = EventBus::new();
+        let sender = bus.sender();
+        let p = ping.clone();
+        bus.register(
+            StateMachineBuilder::new("a", &idle)
+                .add_event(
+                    idle.clone(),
+                    go.clone(),
+                    busy.clone(),
+                    Some(Box::new(move || {
+                        sender.post("b", p.clone());
+                        Ok(())
+                    })),
+                )
+                .add_event(busy.clone(), pong.clone(), done, None)
+                .with_history(10)
+                .build(),
+        );
+        bus.register(
+            StateMachineBuilder::new("b", &idle)
+                .add_event(idle.clone(), ping, busy.clone(), None)
+                .with_history(10)
+                .build(),
+        );
+        bus.forward(Forward::new("b", busy, pong).to("a"));
+
+        bus.post("a", go);
+        assert_eq!(bus.run()?, 3);
+        let causes = |name| {
+            bus.machine(name).map_or_else(Vec::new, |machine| {
+                machine
+                    .history()
+                    .into_iter()
+                    .map(|record| record.cause.map(|cause| cause.to_string()))
+                    .collect()
+            })
+        };
+        assert_eq!(causes("a"), vec![None, Some("b#1".to_string())]);
+        assert_eq!(causes("b"), vec![Some("a#1".to_string())]);
+        Ok(())
+    }
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_request_reply() -> Result<()> {
+        let (idle, running) = (State::new("idle"), State::new("running"));
+        let start = Event::new("start");
+        let mut bus: EventBus = EventBus::new();
+        bus.register(
+            StateMachineBuilder::new("worker", &idle)
+                .add_event(idle, start.clone(), running.clone(), None)
+                .build(),
+        );
+
+        let started = bus.request("worker", start.clone());
+        assert!(started.try_recv().is_err());
+        assert!(bus.step()?);
+        let record = started.try_recv().unwrap().unwrap().unwrap();
+        assert_eq!((record.event, record.to), (start.clone(), running));
+
+        let refused = bus.sender().request("worker", start.clone());
+        assert!(bus.step().is_err());
+        assert!(matches!(
+            refused.try_recv().unwrap(),
+            Err(StateMachineError::NoTransition { state, .. }) if state.name == "running"
+        ));
+        let unknown = bus.request("nobody", start.clone());
+        assert!(bus.step().is_err());
+        assert!(matches!(
+            unknown.try_recv().unwrap(),
+            Err(StateMachineError::NoMachine { name }) if name == "nobody"
+        ));
+
+        bus.shutdown(Instant::now());
+        let dropped = bus.request("worker", start);
+        assert!(dropped.recv().is_err());
+        Ok(())
+    }
+}
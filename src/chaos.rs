@@ -0,0 +1,164 @@
+//! Failure injection, to check that retries and supervision hold up in tests
+
+use crate::backoff::splitmix64;
+use crate::trace::debug;
+use crate::{Error, ManualClock, StateMachineBuilder, StateMachineError};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// What to inject into a machine, see [`StateMachineBuilder::with_chaos`]
+///
+/// The draws are derived from the seed, so a failing run can be reproduced with the same seed.
+#[derive(Debug, Clone, Default)]
+pub struct ChaosConfig {
+    seed: u64,
+    fail_rate: f64,
+    delay_rate: f64,
+    delay: Duration,
+    drop_rate: f64,
+    clock: Option<Arc<ManualClock>>,
+}
+
+impl ChaosConfig {
+    /// Create a configuration that injects nothing yet
+    #[must_use]
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            ..Self::default()
+        }
+    }
+
+    #[must_use]
+    /// Fail this fraction of the actions, between 0 and 1, without running them
+    pub fn fail_actions(mut self, rate: f64) -> Self {
+        self.fail_rate = rate;
+        self
+    }
+
+    #[must_use]
+    /// Delay this fraction of the actions, between 0 and 1, by `delay` before running them
+    pub fn delay_actions(mut self, rate: f64, delay: Duration) -> Self {
+        self.delay_rate = rate;
+        self.delay = delay;
+        self
+    }
+
+    #[must_use]
+    /// Drop this fraction of the due deadlines, between 0 and 1, see [`StateMachine::fire_due`](crate::StateMachine::fire_due)
+    pub fn drop_deadlines(mut self, rate: f64) -> Self {
+        self.drop_rate = rate;
+        self
+    }
+
+    #[must_use]
+    /// Delay by advancing `clock` instead of sleeping, use the same clock for the machine
+    pub fn with_clock(mut self, clock: Arc<ManualClock>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+}
+
+/// The injection state of a machine
+pub(crate) struct Chaos {
+    config: ChaosConfig,
+    draws: AtomicU64,
+}
+
+impl Chaos {
+    /// Draw a number in [0, 1) and check if it falls below `rate`
+    fn roll(&self, rate: f64) -> bool {
+        if rate <= 0.0 {
+            return false;
+        }
+        let draw = self.draws.fetch_add(1, Ordering::Relaxed);
+        let random = splitmix64(self.config.seed ^ draw) as f64 / u64::MAX as f64;
+        random < rate
+    }
+
+    /// Delay an action and decide if it fails
+    /// # Returns
+    /// The injected error, the action must then not run
+    pub(crate) fn before_action(&self) -> Option<Error> {
+        if self.roll(self.config.delay_rate) {
+            debug!("chaos: delaying action by {:?}", self.config.delay);
+            match self.config.clock {
+                Some(ref clock) => clock.advance(self.config.delay),
+                None => thread::sleep(self.config.delay),
+            }
+        }
+        self.roll(self.config.fail_rate)
+            .then(|| Error::from(StateMachineError::InjectedFailure))
+    }
+
+    /// Decide if a due deadline is dropped
+    pub(crate) fn drop_deadline(&self) -> bool {
+        self.roll(self.config.drop_rate)
+    }
+}
+
+impl<Err> StateMachineBuilder<Err> {
+    #[must_use]
+    /// Inject failures and delays into the actions and drop deadlines, for tests
+    pub fn with_chaos(mut self, config: ChaosConfig) -> Self {
+        self.chaos = Some(Chaos {
+            config,
+            draws: AtomicU64::new(0),
+        });
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Clock, Event, State};
+    use std::time::SystemTime;
+    #[cfg(feature = "tracing")]
+    use tracing_test::traced_test;
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_chaos_is_reproducible() {
+        let idle = State::new("idle");
+        let work = Event::new("work");
+        let run = |seed| {
+            let clock = Arc::new(ManualClock::default());
+            let machine = StateMachineBuilder::new("worker", &idle)
+                .add_event(
+                    idle.clone(),
+                    work.clone(),
+                    idle.clone(),
+                    Some(Box::new(|| Ok(()))),
+                )
+                .add_event(idle.clone(), Event::new("timeout"), idle.clone(), None)
+                .with_chaos(
+                    ChaosConfig::new(seed)
+                        .fail_actions(0.3)
+                        .delay_actions(0.5, Duration::from_secs(1))
+                        .drop_deadlines(0.5)
+                        .with_clock(clock.clone()),
+                )
+                .with_clock(clock.clone())
+                .build();
+            let failures = (0..100).filter(|_| machine.event(&work).is_err()).count();
+            for _ in 0..20 {
+                machine.schedule_at(SystemTime::UNIX_EPOCH, Event::new("timeout"));
+            }
+            let fired = machine.fire_due().unwrap();
+            (failures, clock.now(), fired)
+        };
+
+        let (failures, now, fired) = run(7);
+        assert_eq!(run(7), (failures, now, fired));
+        assert!((15..45).contains(&failures), "{failures} failures");
+        let delayed = now
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        assert!((30..70).contains(&delayed), "{delayed} delays");
+        assert!((3..17).contains(&fired), "{fired} fired");
+    }
+}
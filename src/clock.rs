@@ -0,0 +1,67 @@
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+/// Source of the time used for timestamps and durations
+pub trait Clock: Send + Sync {
+    /// Get the current time
+    fn now(&self) -> SystemTime;
+}
+
+/// The system wall clock, used by default
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A clock that only moves when told to, for deterministic tests
+#[derive(Debug)]
+pub struct ManualClock {
+    now: Mutex<SystemTime>,
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new(SystemTime::UNIX_EPOCH)
+    }
+}
+
+impl ManualClock {
+    /// Create a new clock
+    /// # Arguments
+    /// * `start` - the initial time of the clock
+    #[must_use]
+    pub fn new(start: SystemTime) -> Self {
+        Self {
+            now: Mutex::new(start),
+        }
+    }
+
+    /// Move the clock forward
+    /// # Panics
+    /// If the lock is poisoned
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().expect("failed to get lock") += duration;
+    }
+
+    /// Set the clock to a given time
+    /// # Panics
+    /// If the lock is poisoned
+    pub fn set(&self, now: SystemTime) {
+        *self.now.lock().expect("failed to get lock") = now;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> SystemTime {
+        *self.now.lock().expect("failed to get lock")
+    }
+}
+
+/// Time elapsed between two instants of a clock, zero if the clock went backwards
+pub(crate) fn elapsed(since: SystemTime, now: SystemTime) -> Duration {
+    now.duration_since(since).unwrap_or_default()
+}
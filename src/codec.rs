@@ -0,0 +1,233 @@
+//! Encoding of snapshots for stores that keep bytes, e.g. to encrypt or compress them
+
+use crate::{Deadline, Event, Result, Snapshot, State, StateMachineError, Store};
+use std::marker::PhantomData;
+use std::time::{Duration, SystemTime};
+
+/// Turns snapshots into bytes and back
+///
+/// Codecs can be layered: an encrypting codec can wrap [`PlainCodec`] and encrypt its output.
+pub trait SnapshotCodec {
+    /// Encode a snapshot
+    /// # Errors
+    /// If the snapshot cannot be encoded
+    fn encode(&self, snapshot: &Snapshot) -> Result<Vec<u8>>;
+
+    /// Decode a snapshot encoded by [`SnapshotCodec::encode`]
+    /// # Errors
+    /// If the bytes are not a valid encoding
+    fn decode(&self, bytes: &[u8]) -> Result<Snapshot>;
+}
+
+/// Encodes a snapshot as UTF-8 text: the version `v2:`, `<seq>:` then the id, the machine name and the state as `<length>:<text>`,
+/// then for every deadline `<nanoseconds since the epoch>:` and the event as `<length>:<text>`
+///
+/// Decodes the encodings of the first version too, without version nor id, their snapshots get an empty id.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PlainCodec;
+
+/// The version of the encodings of [`PlainCodec`] with an id
+const VERSION: &str = "v2:";
+
+impl SnapshotCodec for PlainCodec {
+    fn encode(&self, snapshot: &Snapshot) -> Result<Vec<u8>> {
+        let field = |text: &str| format!("{}:{text}", text.len());
+        let mut out = format!(
+            "{VERSION}{}:{}{}{}",
+            snapshot.seq,
+            field(&snapshot.id),
+            field(&snapshot.machine),
+            field(&snapshot.state.to_string())
+        );
+        for deadline in &snapshot.deadlines {
+            let since_epoch = deadline
+                .at
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default();
+            out.push_str(&format!(
+                "{}:{}",
+                since_epoch.as_nanos(),
+                field(&deadline.event.to_string())
+            ));
+        }
+        Ok(out.into_bytes())
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<Snapshot> {
+        let text = std::str::from_utf8(bytes).map_err(|_| invalid())?;
+        // the first version has no id
+        let (text, versioned) = match text.strip_prefix(VERSION) {
+            Some(rest) => (rest, true),
+            None => (text, false),
+        };
+        let mut reader = Reader(text);
+        let seq = u64::try_from(reader.number()?).map_err(|_| invalid())?;
+        let id = if versioned {
+            reader.field()?.to_string()
+        } else {
+            String::new()
+        };
+        let machine = reader.field()?.to_string();
+        let state = State::new(reader.field()?);
+        let mut deadlines = Vec::new();
+        while !reader.0.is_empty() {
+            let nanos = u64::try_from(reader.number()?).map_err(|_| invalid())?;
+            deadlines.push(Deadline {
+                at: SystemTime::UNIX_EPOCH + Duration::from_nanos(nanos),
+                event: Event::new(reader.field()?),
+            });
+        }
+        Ok(Snapshot {
+            id,
+            machine,
+            state,
+            seq,
+            deadlines,
+        })
+    }
+}
+
+fn invalid() -> crate::Error {
+    crate::Error::from(StateMachineError::InvalidEncoding)
+}
+
+/// Reads the fields of a [`PlainCodec`] encoding
+struct Reader<'a>(&'a str);
+
+impl<'a> Reader<'a> {
+    /// Read `<number>:`
+    fn number(&mut self) -> Result<u128> {
+        let (n, rest) = self.0.split_once(':').ok_or_else(invalid)?;
+        self.0 = rest;
+        n.parse().map_err(|_| invalid())
+    }
+
+    /// Read `<length>:<text>`
+    fn field(&mut self) -> Result<&'a str> {
+        let len = usize::try_from(self.number()?).map_err(|_| invalid())?;
+        let value = self.0.get(..len).ok_or_else(invalid)?;
+        self.0 = &self.0[len..];
+        Ok(value)
+    }
+}
+
+/// Keeps encoded snapshots by key, e.g. in files or a key-value database
+pub trait BlobStore<K> {
+    /// Get the bytes saved for a key, if any
+    /// # Errors
+    /// If the backend fails
+    fn load(&self, key: &K) -> Result<Option<Vec<u8>>>;
+
+    /// Save the bytes of a key, replacing the previous ones
+    /// # Errors
+    /// If the backend fails
+    fn save(&self, key: &K, bytes: &[u8]) -> Result<()>;
+}
+
+/// A [`Store`] encoding the snapshots with a codec before saving them to a [`BlobStore`]
+pub struct EncodedStore<K, B, C = PlainCodec> {
+    blobs: B,
+    codec: C,
+    key: PhantomData<fn(&K)>,
+}
+
+impl<K, B: BlobStore<K>, C: SnapshotCodec> EncodedStore<K, B, C> {
+    /// Create a store
+    /// # Arguments
+    /// * `blobs` - where the encoded snapshots are saved
+    /// * `codec` - how the snapshots are encoded
+    pub fn new(blobs: B, codec: C) -> Self {
+        Self {
+            blobs,
+            codec,
+            key: PhantomData,
+        }
+    }
+}
+
+impl<K, B: BlobStore<K>, C: SnapshotCodec> Store<K> for EncodedStore<K, B, C> {
+    fn load(&self, key: &K) -> Result<Option<Snapshot>> {
+        self.blobs
+            .load(key)?
+            .map(|bytes| self.codec.decode(&bytes))
+            .transpose()
+    }
+
+    fn save(&self, key: &K, snapshot: &Snapshot) -> Result<()> {
+        self.blobs.save(key, &self.codec.encode(snapshot)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    #[cfg(feature = "tracing")]
+    use tracing_test::traced_test;
+
+    #[derive(Default)]
+    struct Blobs(RefCell<HashMap<u32, Vec<u8>>>);
+
+    impl BlobStore<u32> for &Blobs {
+        fn load(&self, key: &u32) -> Result<Option<Vec<u8>>> {
+            Ok(self.0.borrow().get(key).cloned())
+        }
+
+        fn save(&self, key: &u32, bytes: &[u8]) -> Result<()> {
+            self.0.borrow_mut().insert(*key, bytes.to_vec());
+            Ok(())
+        }
+    }
+
+    /// Stands in for a real cipher
+    struct Xor<C>(u8, C);
+
+    impl<C: SnapshotCodec> SnapshotCodec for Xor<C> {
+        fn encode(&self, snapshot: &Snapshot) -> Result<Vec<u8>> {
+            let bytes = self.1.encode(snapshot)?;
+            Ok(bytes.into_iter().map(|b| b ^ self.0).collect())
+        }
+
+        fn decode(&self, bytes: &[u8]) -> Result<Snapshot> {
+            let bytes: Vec<u8> = bytes.iter().map(|b| b ^ self.0).collect();
+            self.1.decode(&bytes)
+        }
+    }
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_encoded_store() {
+        let snapshot = Snapshot {
+            id: "s1".to_string(),
+            machine: "order:1".to_string(),
+            state: State::new("paid"),
+            seq: 42,
+            deadlines: vec![Deadline {
+                at: SystemTime::UNIX_EPOCH + Duration::from_secs(2),
+                event: Event::new("ship"),
+            }],
+        };
+        let encoded = b"v2:42:2:s17:order:14:paid2000000000:4:ship";
+        assert_eq!(PlainCodec.encode(&snapshot).unwrap(), encoded);
+        assert_eq!(PlainCodec.decode(encoded).unwrap(), snapshot);
+        assert!(PlainCodec.decode(b"v2:1:2:s19:order").is_err());
+        // the encodings saved before snapshots had an id
+        assert_eq!(
+            PlainCodec
+                .decode(b"42:7:order:14:paid2000000000:4:ship")
+                .unwrap(),
+            Snapshot {
+                id: String::new(),
+                ..snapshot.clone()
+            }
+        );
+
+        let blobs = Blobs::default();
+        let store = EncodedStore::new(&blobs, Xor(0x5a, PlainCodec));
+        store.save(&1, &snapshot).unwrap();
+        assert_ne!(blobs.0.borrow()[&1], encoded);
+        assert_eq!(store.load(&1).unwrap(), Some(snapshot));
+        assert_eq!(store.load(&2).unwrap(), None);
+    }
+}
@@ -0,0 +1,193 @@
+//! Machines whose guards and actions share a context, e.g. counters, buffers or handles
+
+use crate::{Error, Event, State, StateMachine, StateMachineBuilder};
+use std::cell::{Ref, RefCell, RefMut};
+use std::fmt;
+use std::rc::Rc;
+
+/// An action updating the context of a [`ContextMachine`]
+pub type ContextAction<C, Err = Error> = Box<dyn Fn(&mut C) -> Result<(), Err>>;
+
+/// Builds a [`ContextMachine`], see [`StateMachineBuilder`] for the options
+pub struct ContextBuilder<C, Err = Error> {
+    builder: StateMachineBuilder<Err>,
+    context: Rc<RefCell<C>>,
+}
+
+impl<C: 'static> ContextBuilder<C> {
+    #[must_use]
+    pub fn new(name: impl Into<String>, initial_state: &State, context: C) -> Self {
+        Self::with_error_type(name, initial_state, context)
+    }
+}
+
+impl<C: 'static, Err: 'static> ContextBuilder<C, Err> {
+    /// Create a builder for a machine whose actions return `Err`, see [`StateMachineBuilder::with_error_type`]
+    #[must_use]
+    pub fn with_error_type(name: impl Into<String>, initial_state: &State, context: C) -> Self {
+        Self {
+            builder: StateMachineBuilder::with_error_type(name, initial_state),
+            context: Rc::new(RefCell::new(context)),
+        }
+    }
+
+    /// Wrap an action into an action borrowing the context while it runs
+    fn bind(&self, action: Option<ContextAction<C, Err>>) -> Option<crate::Action<Err>> {
+        let context = self.context.clone();
+        action.map(|action| -> crate::Action<Err> {
+            Box::new(move || action(&mut context.borrow_mut()))
+        })
+    }
+
+    #[must_use]
+    /// See [`StateMachineBuilder::add_event`]
+    pub fn add_event(
+        mut self,
+        old_state: State,
+        event: Event,
+        new_state: State,
+        action: Option<ContextAction<C, Err>>,
+    ) -> Self {
+        let action = self.bind(action);
+        self.builder = self.builder.add_event(old_state, event, new_state, action);
+        self
+    }
+
+    #[must_use]
+    /// See [`StateMachineBuilder::add_any_state_event`]
+    pub fn add_any_state_event(
+        mut self,
+        event: Event,
+        new_state: State,
+        action: Option<ContextAction<C, Err>>,
+    ) -> Self {
+        let action = self.bind(action);
+        self.builder = self.builder.add_any_state_event(event, new_state, action);
+        self
+    }
+
+    #[must_use]
+    /// Guard the last added transition with a check of the context, see [`StateMachineBuilder::with_guard`]
+    /// # Panics
+    /// If no transition was added yet
+    pub fn with_guard(
+        mut self,
+        name: impl Into<String>,
+        guard: impl Fn(&C) -> bool + 'static,
+    ) -> Self {
+        let context = self.context.clone();
+        self.builder = self
+            .builder
+            .with_guard(name, move || guard(&context.borrow()));
+        self
+    }
+
+    #[must_use]
+    /// Set the other options of the machine on the underlying builder, e.g. the history
+    pub fn configure(
+        mut self,
+        configure: impl FnOnce(StateMachineBuilder<Err>) -> StateMachineBuilder<Err>,
+    ) -> Self {
+        self.builder = configure(self.builder);
+        self
+    }
+
+    #[must_use]
+    pub fn build(self) -> ContextMachine<C, Err> {
+        ContextMachine {
+            machine: self.builder.build(),
+            context: self.context,
+        }
+    }
+}
+
+/// A state machine owning a context, passed to its guards and actions instead of captured handles
+///
+/// Like [`StateMachine`], it is neither `Send` nor `Sync`.
+pub struct ContextMachine<C, Err = Error> {
+    machine: StateMachine<Err>,
+    context: Rc<RefCell<C>>,
+}
+
+impl<C, Err> ContextMachine<C, Err>
+where
+    Err: From<Error> + fmt::Display + 'static,
+{
+    /// See [`StateMachine::event`]
+    /// # Errors
+    /// As [`StateMachine::event`]
+    /// # Panics
+    /// If the context is borrowed, e.g. with [`ContextMachine::context`], while a guard or an action uses it
+    pub fn event(&self, event: &Event) -> Result<(), Err> {
+        self.machine.event(event)
+    }
+
+    /// Read the context
+    /// # Panics
+    /// If an action is updating the context
+    pub fn context(&self) -> Ref<'_, C> {
+        self.context.borrow()
+    }
+
+    /// Update the context outside of an action, e.g. to seed it
+    /// # Panics
+    /// If the context is already borrowed
+    pub fn context_mut(&self) -> RefMut<'_, C> {
+        self.context.borrow_mut()
+    }
+
+    /// Get the underlying machine, for the other operations
+    pub fn machine(&self) -> &StateMachine<Err> {
+        &self.machine
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "tracing")]
+    use tracing_test::traced_test;
+
+    #[derive(Default)]
+    struct Retries {
+        attempts: u32,
+        log: Vec<String>,
+    }
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_context() {
+        let (idle, trying, failed) = (
+            State::new("idle"),
+            State::new("trying"),
+            State::new("failed"),
+        );
+        let (attempt, give_up) = (Event::new("attempt"), Event::new("give_up"));
+        let machine = ContextBuilder::new("retrier", &idle, Retries::default())
+            .add_any_state_event(
+                attempt.clone(),
+                trying.clone(),
+                Some(Box::new(|retries: &mut Retries| {
+                    retries.attempts += 1;
+                    retries.log.push(format!("attempt {}", retries.attempts));
+                    Ok(())
+                })),
+            )
+            .add_event(trying.clone(), give_up.clone(), failed.clone(), None)
+            .with_guard("out of attempts", |retries| retries.attempts >= 3)
+            .build();
+
+        machine.event(&attempt).unwrap();
+        machine.event(&attempt).unwrap();
+        assert!(machine.event(&give_up).is_err());
+        assert_eq!(machine.machine().current_state(), trying);
+        machine.event(&attempt).unwrap();
+        machine.event(&give_up).unwrap();
+        assert_eq!(machine.machine().current_state(), failed);
+        assert_eq!(machine.context().attempts, 3);
+        assert_eq!(machine.context().log.last().unwrap(), "attempt 3");
+
+        machine.context_mut().attempts = 0;
+        assert_eq!(machine.context().attempts, 0);
+    }
+}
@@ -0,0 +1,8 @@
+//! The machines and what runs them: states, events, handles, the event bus and the sharded pool
+
+pub use crate::{
+    Action, AdminHandle, BusSender, ChildFactory, Clock, ContextAction, ContextMachine, DeadLetter,
+    Deadline, Delivery, DispatchHandle, Envelope, Event, EventBus, Forward, Frozen, ManualClock,
+    PayloadAction, Reply, ServiceAction, Services, ShardStats, ShardedPool, ShutdownReport, State,
+    StateMachine, SystemClock, TypedMachine, ViewHandle,
+};
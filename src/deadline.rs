@@ -0,0 +1,132 @@
+use crate::chaos::Chaos;
+use crate::trace::debug;
+use crate::{Error, Event, StateMachine};
+use std::fmt;
+use std::time::SystemTime;
+
+/// An event to fire at an absolute time, see [`StateMachine::schedule_at`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Deadline {
+    /// when the event is due
+    pub at: SystemTime,
+    pub event: Event,
+}
+
+impl<Err> StateMachine<Err> {
+    /// Fire `event` once the clock of the machine reaches `at`, e.g. a contract expiring at a date
+    ///
+    /// Deadlines are kept in snapshots, so they survive a restart when the machine is restored.
+    /// The machine has no timer: due deadlines are fired by [`StateMachine::fire_due`].
+    /// # Panics
+    /// If the lock is poisoned
+    pub fn schedule_at(&self, at: SystemTime, event: Event) {
+        let mut deadlines = self.deadlines.lock().expect("failed to get lock");
+        let position = deadlines.partition_point(|d| d.at <= at);
+        deadlines.insert(position, Deadline { at, event });
+    }
+
+    /// Cancel the deadlines of an event
+    /// # Returns
+    /// The number of cancelled deadlines
+    /// # Panics
+    /// If the lock is poisoned
+    pub fn cancel_deadlines(&self, event: &Event) -> usize {
+        let mut deadlines = self.deadlines.lock().expect("failed to get lock");
+        let before = deadlines.len();
+        deadlines.retain(|d| d.event != *event);
+        before - deadlines.len()
+    }
+
+    /// Get the pending deadlines, earliest first
+    /// # Panics
+    /// If the lock is poisoned
+    pub fn deadlines(&self) -> Vec<Deadline> {
+        self.deadlines.lock().expect("failed to get lock").clone()
+    }
+}
+
+impl<Err> StateMachine<Err>
+where
+    Err: From<Error> + fmt::Display + 'static,
+{
+    /// Fire the events whose deadline has passed, earliest first
+    ///
+    /// A deadline is removed before its event is fired, whether the event succeeds or not.
+    /// Deadlines dropped by [`ChaosConfig::drop_deadlines`](crate::ChaosConfig::drop_deadlines) are not counted.
+    /// Then, if the machine stayed in a time-boxed state past its limit, the state escalates once,
+    /// see [`StateMachineBuilder::time_box`](crate::StateMachineBuilder::time_box).
+    /// # Returns
+    /// The number of fired events, counting a handled escalation as one
+    /// # Errors
+    /// The error of the first event that fails, the later deadlines stay pending,
+    /// or the error of the last escalation event if none was handled
+    /// # Panics
+    /// If the lock is poisoned
+    pub fn fire_due(&self) -> Result<usize, Err> {
+        let mut fired = 0;
+        loop {
+            let now = self.clock.now();
+            let due = {
+                let mut deadlines = self.deadlines.lock().expect("failed to get lock");
+                match deadlines.first() {
+                    Some(deadline) if deadline.at <= now => deadlines.remove(0),
+                    _ => break,
+                }
+            };
+            if self.chaos.as_ref().is_some_and(Chaos::drop_deadline) {
+                debug!(
+                    "{}: chaos: dropping deadline of {}",
+                    self.log_name(),
+                    due.event
+                );
+                continue;
+            }
+            debug!("{}: deadline of {} passed", self.log_name(), due.event);
+            self.event(&due.event)?;
+            fired += 1;
+        }
+        if self.escalate()? {
+            fired += 1;
+        }
+        Ok(fired)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ManualClock, State, StateMachineBuilder};
+    use std::sync::Arc;
+    use std::time::Duration;
+    #[cfg(feature = "tracing")]
+    use tracing_test::traced_test;
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_deadlines_survive_restore() {
+        let (active, expired) = (State::new("active"), State::new("expired"));
+        let expire = Event::new("contract_expired");
+        let clock = Arc::new(ManualClock::default());
+        let build = || {
+            StateMachineBuilder::new("contract", &active)
+                .add_event(active.clone(), expire.clone(), expired.clone(), None)
+                .with_clock(clock.clone())
+                .build()
+        };
+        let machine = build();
+        let at = SystemTime::UNIX_EPOCH + Duration::from_secs(3600);
+        machine.schedule_at(at, expire.clone());
+        machine.schedule_at(at + Duration::from_secs(1), Event::new("reminder"));
+        assert_eq!(machine.fire_due().unwrap(), 0);
+
+        // restart before the deadline
+        let restored = build();
+        restored.restore(&machine.snapshot()).unwrap();
+        assert_eq!(restored.deadlines(), machine.deadlines());
+        assert_eq!(restored.cancel_deadlines(&Event::new("reminder")), 1);
+        clock.advance(Duration::from_secs(7200));
+        assert_eq!(restored.fire_due().unwrap(), 1);
+        assert_eq!(restored.current_state(), expired);
+        assert!(restored.deadlines().is_empty());
+    }
+}
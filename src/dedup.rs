@@ -0,0 +1,336 @@
+use crate::trace::debug;
+use crate::{Cause, Error, Event, Outcome, StateMachine, StateMachineBuilder, StateMachineError};
+use std::borrow::Cow;
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+use std::time::{Duration, SystemTime};
+
+/// An event with the id of its delivery, e.g. the message id of a queue
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Envelope {
+    pub id: String,
+    pub event: Event,
+    /// who sent the event, for the [`Authorizer`](crate::Authorizer)
+    pub principal: Option<String>,
+    /// the transition that generated the event, recorded in the history
+    pub cause: Option<Cause>,
+}
+
+impl Envelope {
+    pub fn new(id: impl Into<String>, event: Event) -> Self {
+        Self {
+            id: id.into(),
+            event,
+            principal: None,
+            cause: None,
+        }
+    }
+
+    #[must_use]
+    /// Set who sent the event
+    pub fn with_principal(mut self, principal: impl Into<String>) -> Self {
+        self.principal = Some(principal.into());
+        self
+    }
+
+    #[must_use]
+    /// Set the transition that generated the event
+    pub fn with_cause(mut self, cause: Cause) -> Self {
+        self.cause = Some(cause);
+        self
+    }
+
+    /// The id recorded in the history, the events sent without an envelope have an empty id
+    pub(crate) fn recorded_id(&self) -> Option<String> {
+        (!self.id.is_empty()).then(|| self.id.clone())
+    }
+}
+
+/// An event being handled, with the envelope it was delivered in, if any
+///
+/// Borrows the event, so that handling an event sent without an envelope does not copy it.
+#[derive(Clone, Copy)]
+pub(crate) struct Dispatch<'a> {
+    pub(crate) event: &'a Event,
+    pub(crate) envelope: Option<&'a Envelope>,
+    pub(crate) cause: Option<&'a Cause>,
+}
+
+impl<'a> Dispatch<'a> {
+    /// An event sent without an envelope
+    pub(crate) fn event(event: &'a Event) -> Self {
+        Self {
+            event,
+            envelope: None,
+            cause: None,
+        }
+    }
+
+    /// An event generated by another transition
+    pub(crate) fn caused(event: &'a Event, cause: &'a Cause) -> Self {
+        Self {
+            cause: Some(cause),
+            ..Self::event(event)
+        }
+    }
+
+    /// The event of a delivered envelope
+    pub(crate) fn envelope(envelope: &'a Envelope) -> Self {
+        Self {
+            event: &envelope.event,
+            envelope: Some(envelope),
+            cause: envelope.cause.as_ref(),
+        }
+    }
+
+    pub(crate) fn principal(self) -> Option<&'a String> {
+        self.envelope?.principal.as_ref()
+    }
+
+    /// The id recorded in the history, see [`Envelope::recorded_id`]
+    pub(crate) fn recorded_id(self) -> Option<String> {
+        self.envelope.and_then(Envelope::recorded_id)
+    }
+
+    /// The envelope, for the authorizer, an event sent without one gets an envelope without id nor principal
+    pub(crate) fn to_envelope(self) -> Cow<'a, Envelope> {
+        match self.envelope {
+            Some(envelope) => Cow::Borrowed(envelope),
+            None => Cow::Owned(Envelope {
+                cause: self.cause.cloned(),
+                ..Envelope::new("", self.event.clone())
+            }),
+        }
+    }
+}
+
+/// What the machine did with a delivered envelope
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delivery {
+    /// the event was handled
+    Handled,
+    /// the envelope was delivered before, the event was ignored
+    Duplicate,
+}
+
+/// The ids of the recently handled envelopes
+pub(crate) struct Dedup {
+    capacity: usize,
+    max_age: Option<Duration>,
+    ids: VecDeque<(String, SystemTime)>,
+    seen: HashSet<String>,
+}
+
+impl Dedup {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            max_age: None,
+            ids: VecDeque::new(),
+            seen: HashSet::new(),
+        }
+    }
+
+    pub(crate) fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+    }
+
+    pub(crate) fn set_max_age(&mut self, max_age: Duration) {
+        self.max_age = Some(max_age);
+    }
+
+    fn contains(&mut self, id: &str, now: SystemTime) -> bool {
+        if let Some(max_age) = self.max_age {
+            while let Some((oldest, at)) = self.ids.front() {
+                match now.duration_since(*at) {
+                    Ok(age) if age > max_age => {
+                        self.seen.remove(oldest);
+                        self.ids.pop_front();
+                    }
+                    _ => break,
+                }
+            }
+        }
+        self.seen.contains(id)
+    }
+
+    fn insert(&mut self, id: &str, now: SystemTime) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.ids.len() == self.capacity {
+            if let Some((oldest, _)) = self.ids.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+        self.ids.push_back((id.to_string(), now));
+        self.seen.insert(id.to_string());
+    }
+}
+
+impl<Err> StateMachine<Err>
+where
+    Err: From<Error> + fmt::Display + 'static,
+{
+    /// Handle the event of an envelope, unless an envelope with the same id was handled before
+    ///
+    /// The id is remembered once the event fired a transition, even if its action failed.
+    /// Deliveries that fail without a transition are not remembered, so they can be retried.
+    /// Without a deduplication window (see [`StateMachineBuilder::dedup_window`]), every envelope is handled.
+    /// Ignored duplicates are recorded in the history with [`Outcome::Duplicate`].
+    /// Duplicates are ignored before asking the authorizer, refused envelopes are not remembered,
+    /// nor are envelopes whose transition is rolled back, see [`FailurePolicy::RollbackOnError`](crate::FailurePolicy::RollbackOnError).
+    /// # Errors
+    /// As [`StateMachine::event`]
+    /// # Panics
+    /// If the lock is poisoned
+    pub fn deliver(&self, envelope: &Envelope) -> Result<Delivery, Err> {
+        let Some(ref dedup) = self.dedup else {
+            let mut state = self
+                .state
+                .write()
+                .map_err(|_| Error::from(StateMachineError::LockPoisoned))?;
+            return self
+                .handle(&mut state, Dispatch::envelope(envelope), None, || {})
+                .map(|()| Delivery::Handled);
+        };
+        let mut dedup = dedup.lock().expect("failed to get lock");
+        let now = self.clock.now();
+        let mut state = self
+            .state
+            .write()
+            .map_err(|_| Error::from(StateMachineError::LockPoisoned))?;
+        if dedup.contains(&envelope.id, now) {
+            debug!(
+                "{}: ignoring duplicate delivery {} of {}",
+                self.log_name(),
+                envelope.id,
+                envelope.event
+            );
+            self.record_not_taken(
+                &state,
+                Dispatch::envelope(envelope),
+                Outcome::Duplicate(envelope.id.clone()),
+            );
+            return Ok(Delivery::Duplicate);
+        }
+        self.handle(&mut state, Dispatch::envelope(envelope), None, || {
+            dedup.insert(&envelope.id, now)
+        })
+        .map(|()| Delivery::Handled)
+    }
+}
+
+impl<Err> StateMachineBuilder<Err> {
+    #[must_use]
+    /// Ignore envelopes whose id was among the last `capacity` handled ones, see [`StateMachine::deliver`]
+    pub fn dedup_window(mut self, capacity: usize) -> Self {
+        self.dedup
+            .get_or_insert_with(|| Dedup::new(capacity))
+            .set_capacity(capacity);
+        self
+    }
+
+    #[must_use]
+    /// Forget the ids of envelopes handled more than `max_age` ago
+    /// Enables deduplication without a limit on the number of ids, unless `dedup_window` is used
+    pub fn dedup_max_age(mut self, max_age: Duration) -> Self {
+        self.dedup
+            .get_or_insert_with(|| Dedup::new(usize::MAX))
+            .set_max_age(max_age);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ManualClock, State};
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use std::sync::Arc;
+    #[cfg(feature = "tracing")]
+    use tracing_test::traced_test;
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_duplicate_deliveries() {
+        let idle = State::new("idle");
+        let count = Event::new("count");
+        let fired = Rc::new(Cell::new(0));
+        let fired_clone = fired.clone();
+        let clock = Arc::new(ManualClock::default());
+        let machine = StateMachineBuilder::new("counter", &idle)
+            .add_event(
+                idle.clone(),
+                count.clone(),
+                idle.clone(),
+                Some(Box::new(move || {
+                    fired_clone.set(fired_clone.get() + 1);
+                    Ok(())
+                })),
+            )
+            .dedup_window(2)
+            .dedup_max_age(Duration::from_secs(60))
+            .with_history(10)
+            .with_clock(clock.clone())
+            .build();
+        let deliver = |id: &str| machine.deliver(&Envelope::new(id, count.clone())).unwrap();
+
+        assert_eq!(deliver("m1"), Delivery::Handled);
+        assert_eq!(deliver("m1"), Delivery::Duplicate);
+        assert_eq!(fired.get(), 1);
+        assert_eq!(
+            machine.history()[1].outcome,
+            Outcome::Duplicate("m1".to_string())
+        );
+
+        // the window keeps the last 2 ids
+        assert_eq!(deliver("m2"), Delivery::Handled);
+        assert_eq!(deliver("m3"), Delivery::Handled);
+        assert_eq!(deliver("m1"), Delivery::Handled);
+        // and forgets them after a minute
+        clock.advance(Duration::from_secs(61));
+        assert_eq!(deliver("m3"), Delivery::Handled);
+        assert_eq!(fired.get(), 5);
+
+        // failed deliveries can be retried
+        let unknown = Envelope::new("m9", Event::new("unknown"));
+        assert!(machine.deliver(&unknown).is_err());
+        assert!(machine.deliver(&unknown).is_err());
+    }
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_rolled_back_delivery_retried() {
+        let (idle, sent) = (State::new("idle"), State::new("sent"));
+        let send = Event::new("send");
+        let attempts = Rc::new(Cell::new(0));
+        let attempts_clone = attempts.clone();
+        let machine = StateMachineBuilder::new("sender", &idle)
+            .add_event(
+                idle.clone(),
+                send.clone(),
+                sent.clone(),
+                Some(Box::new(move || {
+                    attempts_clone.set(attempts_clone.get() + 1);
+                    if attempts_clone.get() == 1 {
+                        return Err(crate::error::message("timeout".to_string()));
+                    }
+                    Ok(())
+                })),
+            )
+            .on_action_error(crate::FailurePolicy::RollbackOnError)
+            .dedup_window(10)
+            .build();
+        let envelope = Envelope::new("m1", send);
+
+        assert!(machine.deliver(&envelope).is_err());
+        assert_eq!(machine.current_state(), idle);
+        // the retry of the queue is handled, not ignored as a duplicate
+        assert_eq!(machine.deliver(&envelope).unwrap(), Delivery::Handled);
+        assert_eq!(machine.current_state(), sent);
+        assert_eq!(machine.deliver(&envelope).unwrap(), Delivery::Duplicate);
+        assert_eq!(attempts.get(), 2);
+    }
+}
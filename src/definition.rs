@@ -0,0 +1,488 @@
+use crate::table::{self, Scope};
+use crate::{Event, State, StateMachine, StateMachineBuilder, StateMachineError};
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// A transition of a [`Definition`]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TransitionDef {
+    /// the state before the transition, `None` if declared for any state
+    pub from: Option<State>,
+    /// the event triggering the transition
+    pub event: Event,
+    /// the state after the transition
+    pub to: State,
+    /// name of the guard, if any
+    pub guard: Option<String>,
+    /// explicit priority, if any
+    pub priority: Option<i32>,
+}
+
+impl TransitionDef {
+    fn scope(&self) -> Scope {
+        if self.from.is_some() {
+            Scope::State
+        } else {
+            Scope::AnyState
+        }
+    }
+}
+
+/// The structure of a state machine: its states and transitions, without actions and guard implementations
+///
+/// Definitions can be analysed and combined without running a machine.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Definition {
+    name: String,
+    initial_state: State,
+    transitions: Vec<TransitionDef>,
+}
+
+impl Definition {
+    /// Create a definition without transitions
+    pub(crate) fn new(name: impl Into<String>, initial_state: State) -> Self {
+        Self {
+            name: name.into(),
+            initial_state,
+            transitions: Vec::new(),
+        }
+    }
+
+    pub(crate) fn add(&mut self, transition: TransitionDef) {
+        self.transitions.push(transition);
+    }
+
+    /// Get the name of the machine
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Get the initial state
+    pub fn initial_state(&self) -> &State {
+        &self.initial_state
+    }
+
+    /// Get the transitions, in registration order
+    pub fn transitions(&self) -> &[TransitionDef] {
+        &self.transitions
+    }
+
+    /// A hash of the name, the initial state and the transitions in registration order,
+    /// to tell whether two machines run the same definition
+    pub fn fingerprint(&self) -> u64 {
+        let mut text = format!("{}\n{}\n", self.name, self.initial_state);
+        for t in &self.transitions {
+            let from = t.from.as_ref().map(ToString::to_string);
+            text.push_str(&format!(
+                "{:?} {} {} {:?} {:?}\n",
+                from, t.event, t.to, t.guard, t.priority
+            ));
+        }
+        crate::fnv1a(text.as_bytes())
+    }
+
+    /// Get all states: the initial state and the states mentioned by the transitions
+    pub fn states(&self) -> Vec<State> {
+        let mut seen = HashSet::new();
+        std::iter::once(&self.initial_state)
+            .chain(
+                self.transitions
+                    .iter()
+                    .flat_map(|t| t.from.iter().chain(std::iter::once(&t.to))),
+            )
+            .filter(|s| seen.insert(*s))
+            .cloned()
+            .collect()
+    }
+
+    /// Get all events handled by some transition, in registration order
+    pub fn events(&self) -> Vec<Event> {
+        let mut seen = HashSet::new();
+        self.transitions
+            .iter()
+            .filter(|t| seen.insert(&t.event))
+            .map(|t| t.event.clone())
+            .collect()
+    }
+
+    /// Get the transitions that could handle `event` in `state`, in the order the machine tries them
+    pub fn candidates(&self, state: &State, event: &Event) -> Vec<&TransitionDef> {
+        let mut candidates: Vec<(usize, &TransitionDef)> = self
+            .transitions
+            .iter()
+            .enumerate()
+            .filter(|(_, t)| &t.event == event && t.from.as_ref().is_none_or(|f| f == state))
+            .collect();
+        candidates.sort_by_key(|(i, t)| {
+            table::precedence(t.priority, t.scope(), 0, t.guard.is_some(), *i)
+        });
+        candidates.into_iter().map(|(_, t)| t).collect()
+    }
+
+    /// Build the synchronous product of two definitions
+    ///
+    /// The states of the product are the reachable pairs `(a, b)` of states of both machines.
+    /// An event handled by both machines moves both, an event handled by only one of them moves that one.
+    /// When both transitions are guarded, the product transition is guarded by `guard_a && guard_b`.
+    pub fn product(&self, other: &Definition) -> Definition {
+        let pair = |a: &State, b: &State| State::new(format!("({a}, {b})"));
+        let mut events = self.events();
+        for event in other.events() {
+            if !events.contains(&event) {
+                events.push(event);
+            }
+        }
+        let initial = (self.initial_state.clone(), other.initial_state.clone());
+        let mut product = Definition::new(
+            format!("{} x {}", self.name, other.name),
+            pair(&initial.0, &initial.1),
+        );
+        let mut seen = HashSet::from([initial.clone()]);
+        let mut queue = VecDeque::from([initial]);
+        while let Some((a, b)) = queue.pop_front() {
+            for event in &events {
+                let ours = self.candidates(&a, event);
+                let theirs = other.candidates(&b, event);
+                let moves: Vec<(State, State, Option<String>)> =
+                    match (ours.is_empty(), theirs.is_empty()) {
+                        (true, true) => continue,
+                        (false, true) => ours
+                            .iter()
+                            .map(|t| (t.to.clone(), b.clone(), t.guard.clone()))
+                            .collect(),
+                        (true, false) => theirs
+                            .iter()
+                            .map(|t| (a.clone(), t.to.clone(), t.guard.clone()))
+                            .collect(),
+                        (false, false) => ours
+                            .iter()
+                            .flat_map(|ta| theirs.iter().map(move |tb| (ta, tb)))
+                            .map(|(ta, tb)| {
+                                let guard = match (&ta.guard, &tb.guard) {
+                                    (Some(ga), Some(gb)) => Some(format!("{ga} && {gb}")),
+                                    (ga, gb) => ga.clone().or_else(|| gb.clone()),
+                                };
+                                (ta.to.clone(), tb.to.clone(), guard)
+                            })
+                            .collect(),
+                    };
+                for (to_a, to_b, guard) in moves {
+                    product.add(TransitionDef {
+                        from: Some(pair(&a, &b)),
+                        event: event.clone(),
+                        to: pair(&to_a, &to_b),
+                        guard,
+                        priority: None,
+                    });
+                    if seen.insert((to_a.clone(), to_b.clone())) {
+                        queue.push_back((to_a, to_b));
+                    }
+                }
+            }
+        }
+        product
+    }
+}
+
+/// Result of [`Definition::minimize`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Minimization {
+    /// the minimized definition
+    pub definition: Definition,
+    /// for every reachable state of the original definition, the state it was merged into
+    pub mapping: HashMap<State, State>,
+    /// the states that cannot be reached from the initial state, they are dropped
+    pub unreachable: Vec<State>,
+}
+
+impl Minimization {
+    /// The groups of original states that were merged into one state, each with the state they became
+    pub fn merged(&self) -> Vec<(State, Vec<State>)> {
+        self.definition
+            .states()
+            .into_iter()
+            .map(|merged| {
+                let mut from: Vec<State> = self
+                    .mapping
+                    .iter()
+                    .filter(|(_, to)| **to == merged)
+                    .map(|(from, _)| from.clone())
+                    .collect();
+                from.sort_by(|a, b| a.name.cmp(&b.name));
+                (merged, from)
+            })
+            .filter(|(_, from)| from.len() > 1)
+            .collect()
+    }
+}
+
+impl Definition {
+    /// The states reachable from the initial state, ignoring guards, in discovery order
+    fn reachable(&self) -> Vec<State> {
+        let events = self.events();
+        let mut seen = HashSet::from([self.initial_state.clone()]);
+        let mut order = vec![self.initial_state.clone()];
+        let mut queue = VecDeque::from([self.initial_state.clone()]);
+        while let Some(state) = queue.pop_front() {
+            for event in &events {
+                for t in self.candidates(&state, event) {
+                    if seen.insert(t.to.clone()) {
+                        order.push(t.to.clone());
+                        queue.push_back(t.to.clone());
+                    }
+                }
+            }
+        }
+        order
+    }
+
+    /// Merge behaviorally equivalent states and drop unreachable states
+    ///
+    /// Two states are equivalent when, for every event, they have the same candidate transitions
+    /// (same scope, guard and priority, in the same order) leading to equivalent states.
+    /// Definitions carry no actions, so actions are ignored.
+    /// A merged state keeps the name of its first state in discovery order.
+    pub fn minimize(&self) -> Minimization {
+        let states = self.reachable();
+        let events = self.events();
+        // partition refinement: split classes until the signatures are stable
+        let mut class: HashMap<&State, usize> = states.iter().map(|s| (s, 0)).collect();
+        let mut count = 1;
+        loop {
+            let mut signatures = HashMap::new();
+            let mut next = HashMap::new();
+            for state in &states {
+                let signature: Vec<Vec<_>> = events
+                    .iter()
+                    .map(|event| {
+                        self.candidates(state, event)
+                            .into_iter()
+                            .map(|t| (t.scope(), t.guard.clone(), t.priority, class[&t.to]))
+                            .collect()
+                    })
+                    .collect();
+                let len = signatures.len();
+                let id = *signatures.entry((class[state], signature)).or_insert(len);
+                next.insert(state, id);
+            }
+            class = next;
+            if signatures.len() == count {
+                break;
+            }
+            count = signatures.len();
+        }
+
+        let mut representative: HashMap<usize, &State> = HashMap::new();
+        for state in &states {
+            representative.entry(class[state]).or_insert(state);
+        }
+        let mapping: HashMap<State, State> = states
+            .iter()
+            .map(|s| (s.clone(), representative[&class[s]].clone()))
+            .collect();
+        let mut definition = Definition::new(self.name.clone(), self.initial_state.clone());
+        for t in &self.transitions {
+            let keep = t
+                .from
+                .as_ref()
+                .is_none_or(|from| mapping.get(from).is_some_and(|merged| merged == from));
+            if keep {
+                definition.add(TransitionDef {
+                    to: mapping.get(&t.to).cloned().unwrap_or_else(|| t.to.clone()),
+                    ..t.clone()
+                });
+            }
+        }
+        let unreachable = self
+            .states()
+            .into_iter()
+            .filter(|s| !mapping.contains_key(s))
+            .collect();
+        Minimization {
+            definition,
+            mapping,
+            unreachable,
+        }
+    }
+}
+
+impl<Err> StateMachine<Err> {
+    /// Get the definition of the state machine
+    /// Definitions have no hierarchy, substates get a copy of the transitions they inherit.
+    pub fn definition(&self) -> Definition {
+        definition(&self.name, &self.initial_state, &self.table)
+    }
+}
+
+impl<Err> StateMachineBuilder<Err> {
+    /// Get the definition of the state machine being built
+    pub fn definition(&self) -> Definition {
+        definition(&self.name, &self.initial_state, &self.table)
+    }
+
+    /// Create a builder with the transitions of a definition, without actions
+    /// # Errors
+    /// If a transition has a guard, definitions only carry the name of the guard
+    pub fn from_definition(definition: &Definition) -> crate::Result<Self> {
+        let mut builder = Self::with_error_type(definition.name(), definition.initial_state());
+        for t in definition.transitions() {
+            if let Some(ref guard) = t.guard {
+                return Err(crate::Error::from(StateMachineError::GuardedDefinition {
+                    guard: guard.clone(),
+                    event: t.event.clone(),
+                }));
+            }
+            builder = match t.from {
+                Some(ref from) => {
+                    builder.add_event(from.clone(), t.event.clone(), t.to.clone(), None)
+                }
+                None => builder.add_any_state_event(t.event.clone(), t.to.clone(), None),
+            };
+            if let Some(priority) = t.priority {
+                builder = builder.with_priority(priority);
+            }
+        }
+        Ok(builder)
+    }
+}
+
+fn definition<Err>(name: &str, initial: &State, table: &table::TransitionTable<Err>) -> Definition {
+    let mut definition = Definition::new(name, initial.clone());
+    for t in table.iter() {
+        definition.add(TransitionDef {
+            from: t.from.clone(),
+            event: t.trigger.clone(),
+            to: t.new_state.clone(),
+            guard: t.guard_name().map(str::to_string),
+            priority: t.priority,
+        });
+    }
+    // definitions have no hierarchy, substates get a copy of the transitions they inherit,
+    // unless an unguarded transition is tried before
+    for state in table.states(initial) {
+        for t in table.leaving(&state) {
+            let reachable = || {
+                table
+                    .unresolved_candidates(&state, &t.trigger)
+                    .take_while(|c| !std::ptr::eq(*c, t))
+                    .all(|c| c.guard.is_some())
+            };
+            if t.from.as_ref().is_some_and(|from| *from != state) && reachable() {
+                definition.add(TransitionDef {
+                    from: Some(state.clone()),
+                    event: t.trigger.clone(),
+                    to: t.new_state.clone(),
+                    guard: t.guard_name().map(str::to_string),
+                    priority: t.priority,
+                });
+            }
+        }
+    }
+    definition
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "tracing")]
+    use tracing_test::traced_test;
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_definition() {
+        let idle = State::new("idle");
+        let running = State::new("running");
+        let go = Event::new("go");
+        let stop = Event::new("stop");
+        let definition = StateMachineBuilder::new("test", &idle)
+            .add_event(idle.clone(), go.clone(), running.clone(), None)
+            .add_any_state_event(stop.clone(), idle.clone(), None)
+            .add_event(running.clone(), stop.clone(), running.clone(), None)
+            .with_guard("busy", || true)
+            .definition();
+
+        assert_eq!(definition.name(), "test");
+        assert_eq!(definition.states(), vec![idle.clone(), running.clone()]);
+        assert_eq!(definition.events(), vec![go, stop.clone()]);
+        let candidates = definition.candidates(&running, &stop);
+        assert_eq!(candidates.len(), 2);
+        assert_eq!(candidates[0].guard.as_deref(), Some("busy"));
+        assert_eq!(candidates[1].from, None);
+    }
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_product() {
+        let off = State::new("off");
+        let on = State::new("on");
+        let closed = State::new("closed");
+        let open = State::new("open");
+        let toggle = Event::new("toggle");
+        let door = Event::new("door");
+        let light = StateMachineBuilder::new("light", &off)
+            .add_event(off.clone(), toggle.clone(), on.clone(), None)
+            .add_event(on.clone(), toggle.clone(), off.clone(), None)
+            .add_event(off.clone(), door.clone(), on.clone(), None)
+            .definition();
+        let fridge = StateMachineBuilder::new("fridge", &closed)
+            .add_event(closed.clone(), door.clone(), open.clone(), None)
+            .add_event(open.clone(), door.clone(), closed.clone(), None)
+            .definition();
+
+        let product = light.product(&fridge);
+        assert_eq!(product.name(), "light x fridge");
+        assert_eq!(product.initial_state(), &State::new("(off, closed)"));
+        let names: Vec<String> = product.states().iter().map(ToString::to_string).collect();
+        assert_eq!(
+            names,
+            vec!["(off, closed)", "(on, closed)", "(on, open)", "(off, open)"]
+        );
+        // both machines move on `door` in (off, closed)
+        let door_moves = product.candidates(&State::new("(off, closed)"), &door);
+        assert_eq!(door_moves.len(), 1);
+        assert_eq!(door_moves[0].to, State::new("(on, open)"));
+        // only the fridge moves on `door` in (on, open)
+        let door_moves = product.candidates(&State::new("(on, open)"), &door);
+        assert_eq!(door_moves[0].to, State::new("(on, closed)"));
+    }
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_minimize() {
+        let start = State::new("start");
+        let a1 = State::new("a1");
+        let a2 = State::new("a2");
+        let done = State::new("done");
+        let orphan = State::new("orphan");
+        let left = Event::new("left");
+        let right = Event::new("right");
+        let next = Event::new("next");
+        let definition = StateMachineBuilder::new("test", &start)
+            .add_event(start.clone(), left.clone(), a1.clone(), None)
+            .add_event(start.clone(), right.clone(), a2.clone(), None)
+            .add_event(a1.clone(), next.clone(), done.clone(), None)
+            .add_event(a2.clone(), next.clone(), done.clone(), None)
+            .add_event(orphan.clone(), next.clone(), done.clone(), None)
+            .definition();
+
+        let minimization = definition.minimize();
+        assert_eq!(minimization.unreachable, vec![orphan]);
+        assert_eq!(minimization.mapping[&a2], a1);
+        assert_eq!(
+            minimization.merged(),
+            vec![(a1.clone(), vec![a1.clone(), a2.clone()])]
+        );
+        let minimized = &minimization.definition;
+        assert_eq!(minimized.states(), vec![start.clone(), a1.clone(), done]);
+        assert_eq!(minimized.candidates(&start, &right)[0].to, a1);
+
+        // a guard makes the states distinguishable
+        let definition = StateMachineBuilder::new("test", &start)
+            .add_event(start.clone(), left, a1.clone(), None)
+            .add_event(start, right, a2.clone(), None)
+            .add_event(a1, next.clone(), State::new("done"), None)
+            .add_event(a2, next, State::new("done"), None)
+            .with_guard("ready", || true)
+            .definition();
+        assert!(definition.minimize().merged().is_empty());
+    }
+}
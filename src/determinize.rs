@@ -0,0 +1,209 @@
+use crate::{Definition, Event, State, TransitionDef};
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::fmt;
+
+/// How [`Definition::determinize`] handles nondeterministic choices
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Determinize {
+    /// fail with the nondeterministic choices
+    Reject,
+    /// replace the choices by transitions to sets of states (powerset construction)
+    Powerset,
+}
+
+/// An event that can lead to several states, because several unguarded transitions
+/// of the same priority and scope handle it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Choice {
+    pub state: State,
+    pub event: Event,
+    /// the possible states after the transition, in registration order
+    pub targets: Vec<State>,
+}
+
+/// The nondeterministic choices of a definition
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Nondeterminism {
+    pub choices: Vec<Choice>,
+}
+
+impl fmt::Display for Nondeterminism {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for choice in &self.choices {
+            let targets: Vec<String> = choice.targets.iter().map(ToString::to_string).collect();
+            writeln!(
+                f,
+                "state {}, event {}: -> {}",
+                choice.state,
+                choice.event,
+                targets.join(" | ")
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Nondeterminism {}
+
+impl Definition {
+    /// The candidates that can fire: the guarded candidates before the first unguarded one,
+    /// and the unguarded candidates of the same priority and scope as the first unguarded one
+    fn effective(&self, state: &State, event: &Event) -> Vec<&TransitionDef> {
+        let candidates = self.candidates(state, event);
+        let Some(first) = candidates.iter().position(|t| t.guard.is_none()) else {
+            return candidates;
+        };
+        let key = |t: &TransitionDef| (t.priority.unwrap_or(0), t.from.is_some());
+        let tied = key(candidates[first]);
+        candidates
+            .into_iter()
+            .enumerate()
+            .filter(|(i, t)| *i < first || (t.guard.is_none() && key(t) == tied))
+            .map(|(_, t)| t)
+            .collect()
+    }
+
+    /// Find the (state, event) pairs that can lead to different states without a guard deciding
+    pub fn nondeterminism(&self) -> Vec<Choice> {
+        let events = self.events();
+        let mut choices = Vec::new();
+        for state in self.states() {
+            for event in &events {
+                let mut targets: Vec<State> = Vec::new();
+                // the tied unguarded candidates come last, most recently added first
+                for t in self.effective(&state, event).into_iter().rev() {
+                    if t.guard.is_none() && !targets.contains(&t.to) {
+                        targets.push(t.to.clone());
+                    }
+                }
+                if targets.len() > 1 {
+                    choices.push(Choice {
+                        state: state.clone(),
+                        event: event.clone(),
+                        targets,
+                    });
+                }
+            }
+        }
+        choices
+    }
+
+    /// Make the definition deterministic
+    /// # Arguments
+    /// * `strategy` - reject nondeterministic definitions or apply the powerset construction
+    ///
+    /// The powerset construction merges the targets of a choice into one state named `{a, b}`.
+    /// Transitions with the same guard from the merged states are merged the same way,
+    /// the resulting transitions keep their guard and drop their priority.
+    /// # Errors
+    /// With [`Determinize::Reject`], the nondeterministic choices if there are any
+    pub fn determinize(&self, strategy: Determinize) -> Result<Definition, Nondeterminism> {
+        let choices = self.nondeterminism();
+        if choices.is_empty() {
+            return Ok(self.clone());
+        }
+        match strategy {
+            Determinize::Reject => Err(Nondeterminism { choices }),
+            Determinize::Powerset => Ok(self.powerset()),
+        }
+    }
+
+    fn powerset(&self) -> Definition {
+        fn name(set: &BTreeSet<State>) -> State {
+            if set.len() == 1 {
+                return set.iter().next().cloned().expect("set is not empty");
+            }
+            let names: Vec<&str> = set.iter().map(|s| &*s.name).collect();
+            State::new(format!("{{{}}}", names.join(", ")))
+        }
+
+        let events = self.events();
+        let initial = BTreeSet::from([self.initial_state().clone()]);
+        let mut result = Definition::new(self.name(), self.initial_state().clone());
+        let mut seen = HashSet::from([initial.clone()]);
+        let mut queue = VecDeque::from([initial]);
+        while let Some(set) = queue.pop_front() {
+            let from = name(&set);
+            for event in &events {
+                // targets per guard, guarded transitions first, in the order they are found
+                let mut order: Vec<Option<String>> = Vec::new();
+                let mut targets: HashMap<Option<String>, BTreeSet<State>> = HashMap::new();
+                for state in &set {
+                    for t in self.effective(state, event) {
+                        if !targets.contains_key(&t.guard) {
+                            order.push(t.guard.clone());
+                        }
+                        targets
+                            .entry(t.guard.clone())
+                            .or_default()
+                            .insert(t.to.clone());
+                    }
+                }
+                order.sort_by_key(Option::is_none);
+                for guard in order {
+                    let to = targets.remove(&guard).expect("targets of every guard");
+                    result.add(TransitionDef {
+                        from: Some(from.clone()),
+                        event: event.clone(),
+                        to: name(&to),
+                        guard,
+                        priority: None,
+                    });
+                    if seen.insert(to.clone()) {
+                        queue.push_back(to);
+                    }
+                }
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::StateMachineBuilder;
+    #[cfg(feature = "tracing")]
+    use tracing_test::traced_test;
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_determinize() {
+        let s = State::new("s");
+        let a = State::new("a");
+        let b = State::new("b");
+        let c = State::new("c");
+        let go = Event::new("go");
+        let next = Event::new("next");
+        let definition = StateMachineBuilder::new("nfa", &s)
+            .add_event(s.clone(), go.clone(), a.clone(), None)
+            .add_event(s.clone(), go.clone(), b.clone(), None)
+            .add_event(a.clone(), next.clone(), c.clone(), None)
+            .add_event(b.clone(), next.clone(), s.clone(), None)
+            .with_guard("again", || true)
+            .definition();
+
+        let error = definition.determinize(Determinize::Reject).unwrap_err();
+        assert_eq!(error.to_string(), "state s, event go: -> a | b\n");
+
+        let dfa = definition.determinize(Determinize::Powerset).unwrap();
+        assert!(dfa.nondeterminism().is_empty());
+        let ab = State::new("{a, b}");
+        assert_eq!(dfa.candidates(&s, &go)[0].to, ab);
+        let next_moves = dfa.candidates(&ab, &next);
+        assert_eq!(next_moves.len(), 2);
+        assert_eq!(next_moves[0].guard.as_deref(), Some("again"));
+        assert_eq!(next_moves[0].to, s);
+        assert_eq!(next_moves[1].to, c);
+
+        // a deterministic definition is left as is
+        let deterministic = StateMachineBuilder::new("dfa", &s)
+            .add_event(s.clone(), go.clone(), a.clone(), None)
+            .add_any_state_event(go, b, None)
+            .definition();
+        assert_eq!(
+            deterministic.determinize(Determinize::Reject),
+            Ok(deterministic.clone())
+        );
+    }
+}
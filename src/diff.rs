@@ -0,0 +1,236 @@
+//! Side by side comparison of two recorded runs
+
+use crate::{Outcome, TransitionRecord};
+use std::fmt;
+
+/// How two aligned records differ, they handled the same event in the same state
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Difference {
+    /// both took a transition, to different states
+    Transition,
+    /// a guard rejected the event in one run only, or different guards rejected it
+    Guard,
+    /// same transition, but the action ended differently or the event was not handled the same way
+    Outcome,
+}
+
+/// A step of the alignment of two runs, with the positions of the records in their traces
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aligned {
+    /// the records are the same, timestamps and sequence numbers aside
+    Same { left: usize, right: usize },
+    /// the runs diverge on this event
+    Changed {
+        left: usize,
+        right: usize,
+        difference: Difference,
+    },
+    /// only the left run has this record
+    Left(usize),
+    /// only the right run has this record
+    Right(usize),
+}
+
+/// The alignment of two runs, see [`diff_traces`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceDiff<'a> {
+    left: &'a [TransitionRecord],
+    right: &'a [TransitionRecord],
+    /// the steps of the alignment, in the order of both runs
+    pub alignment: Vec<Aligned>,
+}
+
+impl TraceDiff<'_> {
+    /// Check if both runs took the same steps
+    pub fn is_identical(&self) -> bool {
+        self.alignment
+            .iter()
+            .all(|step| matches!(step, Aligned::Same { .. }))
+    }
+
+    /// The first step where the runs diverge
+    pub fn first_divergence(&self) -> Option<&Aligned> {
+        self.alignment
+            .iter()
+            .find(|step| !matches!(step, Aligned::Same { .. }))
+    }
+}
+
+fn same(a: &TransitionRecord, b: &TransitionRecord) -> bool {
+    a.from == b.from && a.event == b.event && a.to == b.to && a.outcome == b.outcome
+}
+
+fn difference(a: &TransitionRecord, b: &TransitionRecord) -> Difference {
+    let guard = |r: &TransitionRecord| matches!(r.outcome, Outcome::GuardRejected(_));
+    if guard(a) || guard(b) {
+        Difference::Guard
+    } else if a.to != b.to && a.outcome.is_taken() && b.outcome.is_taken() {
+        Difference::Transition
+    } else {
+        Difference::Outcome
+    }
+}
+
+/// Align two runs of the same definition and find where they diverge,
+/// e.g. a failing production run and a passing replay
+///
+/// The records are matched on their states, event and outcome, with a longest common subsequence.
+/// Between two matches, records handling the same event in the same state are paired as changed.
+pub fn diff_traces<'a>(
+    left: &'a [TransitionRecord],
+    right: &'a [TransitionRecord],
+) -> TraceDiff<'a> {
+    // common[i][j]: length of the longest common subsequence of left[i..] and right[j..]
+    let mut common = vec![vec![0usize; right.len() + 1]; left.len() + 1];
+    for i in (0..left.len()).rev() {
+        for j in (0..right.len()).rev() {
+            common[i][j] = if same(&left[i], &right[j]) {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+    let mut alignment = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    let (mut only_left, mut only_right) = (Vec::new(), Vec::new());
+    while i < left.len() || j < right.len() {
+        if i < left.len() && j < right.len() && same(&left[i], &right[j]) {
+            pair(left, right, &mut only_left, &mut only_right, &mut alignment);
+            alignment.push(Aligned::Same { left: i, right: j });
+            i += 1;
+            j += 1;
+        } else if j == right.len() || (i < left.len() && common[i + 1][j] >= common[i][j + 1]) {
+            only_left.push(i);
+            i += 1;
+        } else {
+            only_right.push(j);
+            j += 1;
+        }
+    }
+    pair(left, right, &mut only_left, &mut only_right, &mut alignment);
+    TraceDiff {
+        left,
+        right,
+        alignment,
+    }
+}
+
+/// Flush the unmatched records between two matches, pairing those handling the same event in the same state
+///
+/// A record is paired with one that was also taken or also not taken if possible,
+/// so that a guard rejection only in one run does not hide the transition taken instead.
+fn pair(
+    left: &[TransitionRecord],
+    right: &[TransitionRecord],
+    only_left: &mut Vec<usize>,
+    only_right: &mut Vec<usize>,
+    alignment: &mut Vec<Aligned>,
+) {
+    let mut rights = std::mem::take(only_right);
+    for l in std::mem::take(only_left) {
+        let handles =
+            |r: &usize| right[*r].from == left[l].from && right[*r].event == left[l].event;
+        let position = rights
+            .iter()
+            .position(|r| handles(r) && right[*r].outcome.is_taken() == left[l].outcome.is_taken())
+            .or_else(|| rights.iter().position(handles));
+        let Some(position) = position else {
+            alignment.push(Aligned::Left(l));
+            continue;
+        };
+        alignment.extend(rights.drain(..position).map(Aligned::Right));
+        let r = rights.remove(0);
+        alignment.push(Aligned::Changed {
+            left: l,
+            right: r,
+            difference: difference(&left[l], &right[r]),
+        });
+    }
+    alignment.extend(rights.into_iter().map(Aligned::Right));
+}
+
+impl fmt::Display for TraceDiff<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let step =
+            |r: &TransitionRecord| format!("{} -{}-> {} ({:?})", r.from, r.event, r.to, r.outcome);
+        for aligned in &self.alignment {
+            match *aligned {
+                Aligned::Same { left, .. } => writeln!(f, "  {}", step(&self.left[left]))?,
+                Aligned::Changed {
+                    left,
+                    right,
+                    difference,
+                } => {
+                    writeln!(f, "- {}", step(&self.left[left]))?;
+                    writeln!(f, "+ {} [{difference:?}]", step(&self.right[right]))?;
+                }
+                Aligned::Left(left) => writeln!(f, "- {}", step(&self.left[left]))?,
+                Aligned::Right(right) => writeln!(f, "+ {}", step(&self.right[right]))?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Event, State, StateMachineBuilder};
+    use std::cell::Cell;
+    use std::rc::Rc;
+    #[cfg(feature = "tracing")]
+    use tracing_test::traced_test;
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_diff_traces() {
+        let (open, paid, review) = (State::new("open"), State::new("paid"), State::new("review"));
+        let run = |risky: bool| {
+            let flag = Rc::new(Cell::new(risky));
+            let machine = StateMachineBuilder::new("order", &open)
+                .add_event(open.clone(), Event::new("pay"), review.clone(), None)
+                .with_guard("risky", move || flag.get())
+                .add_event(open.clone(), Event::new("pay"), paid.clone(), None)
+                .add_event(review.clone(), Event::new("approve"), paid.clone(), None)
+                .add_event(
+                    paid.clone(),
+                    Event::new("ship"),
+                    State::new("shipped"),
+                    None,
+                )
+                .record_guard_rejections()
+                .build();
+            for event in ["pay", "approve", "ship"] {
+                let _ = machine.event(&Event::new(event));
+            }
+            machine.history()
+        };
+        let (production, replay) = (run(true), run(false));
+
+        let diff = diff_traces(&production, &replay);
+        assert!(!diff.is_identical());
+        assert_eq!(
+            diff.alignment,
+            vec![
+                Aligned::Right(0),
+                Aligned::Changed {
+                    left: 0,
+                    right: 1,
+                    difference: Difference::Transition,
+                },
+                Aligned::Left(1),
+                Aligned::Same { left: 2, right: 2 },
+            ]
+        );
+        assert_eq!(
+            diff.to_string(),
+            "+ open -pay-> review (GuardRejected(\"risky\"))\n\
+             - open -pay-> review (Ok)\n\
+             + open -pay-> paid (Ok) [Transition]\n\
+             - review -approve-> paid (Ok)\n  \
+             paid -ship-> shipped (Ok)\n"
+        );
+        assert!(diff_traces(&replay, &replay).is_identical());
+    }
+}
@@ -0,0 +1,336 @@
+//! Import of a constrained Graphviz DOT syntax, see [`Definition::from_dot`]
+
+use crate::{Definition, Event, Result, State, TransitionDef};
+
+/// The node the DOT export draws transitions declared for any state from
+const ANY_STATE: &str = "any state";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Id(String),
+    Arrow,
+    Open,
+    Close,
+    OpenAttrs,
+    CloseAttrs,
+    Equals,
+    Separator,
+}
+
+fn is_id(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '.'
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>> {
+    let unterminated = |what: &str| crate::error::syntax(format!("unterminated {what}"));
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '{' => tokens.push(Token::Open),
+            '}' => tokens.push(Token::Close),
+            '[' => tokens.push(Token::OpenAttrs),
+            ']' => tokens.push(Token::CloseAttrs),
+            '=' => tokens.push(Token::Equals),
+            ';' | ',' => tokens.push(Token::Separator),
+            '#' => while chars.next_if(|c| *c != '\n').is_some() {},
+            '/' if chars.next_if_eq(&'/').is_some() => {
+                while chars.next_if(|c| *c != '\n').is_some() {}
+            }
+            '/' if chars.next_if_eq(&'*').is_some() => {
+                let mut last = ' ';
+                loop {
+                    match chars.next() {
+                        Some('/') if last == '*' => break,
+                        Some(c) => last = c,
+                        None => return Err(unterminated("comment")),
+                    }
+                }
+            }
+            '-' if chars.next_if_eq(&'>').is_some() => tokens.push(Token::Arrow),
+            '"' => {
+                let mut id = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some('"') => id.push('"'),
+                            Some('\\') => id.push('\\'),
+                            Some(c) => {
+                                id.push('\\');
+                                id.push(c);
+                            }
+                            None => {}
+                        },
+                        Some(c) => id.push(c),
+                        None => return Err(unterminated("string")),
+                    }
+                }
+                tokens.push(Token::Id(id));
+            }
+            c if is_id(c) || c == '-' => {
+                let mut id = c.to_string();
+                while let Some(c) = chars.next_if(|c| is_id(*c)) {
+                    id.push(c);
+                }
+                tokens.push(Token::Id(id));
+            }
+            c => return Err(crate::error::syntax(format!("unexpected character `{c}`"))),
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        if self.peek() == Some(token) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn id(&mut self) -> Result<String> {
+        match self.next() {
+            Some(Token::Id(id)) => Ok(id),
+            other => Err(crate::error::syntax(format!(
+                "expected an identifier, found {other:?}"
+            ))),
+        }
+    }
+
+    /// Parse an optional attribute list `[a=b, c=d]`
+    fn attributes(&mut self) -> Result<Vec<(String, String)>> {
+        let mut attributes = Vec::new();
+        while self.eat(&Token::OpenAttrs) {
+            loop {
+                if self.eat(&Token::CloseAttrs) {
+                    break;
+                }
+                if self.eat(&Token::Separator) {
+                    continue;
+                }
+                let key = self.id()?;
+                if !self.eat(&Token::Equals) {
+                    return Err(crate::error::syntax(format!(
+                        "expected `=` after attribute {key}"
+                    )));
+                }
+                attributes.push((key, self.id()?));
+            }
+        }
+        Ok(attributes)
+    }
+}
+
+fn attribute<'a>(attributes: &'a [(String, String)], key: &str) -> Option<&'a str> {
+    attributes
+        .iter()
+        .find(|(k, _)| k == key)
+        .map(|(_, v)| v.as_str())
+}
+
+impl Definition {
+    /// Parse a definition from a constrained Graphviz DOT syntax
+    ///
+    /// * nodes are states, edges are transitions labeled with the event, optionally followed by a guard name: `go [ready]`
+    /// * the initial state is the target of the edge from a `shape=point` node, or else the first state
+    /// * edges from the node `"any state"` are transitions declared for any state
+    /// * subgraphs are flattened, graph, node and edge attributes are ignored
+    ///
+    /// The output of [`Definition::to_dot`] is accepted.
+    /// # Errors
+    /// If the input is not valid in this syntax, an edge has no label or there are no states
+    pub fn from_dot(input: &str) -> Result<Definition> {
+        let mut parser = Parser {
+            tokens: tokenize(input)?,
+            pos: 0,
+        };
+        parser.eat(&Token::Id("strict".into()));
+        if parser.id()? != "digraph" {
+            return Err(crate::error::syntax("expected `digraph`".into()));
+        }
+        let name = match parser.peek() {
+            Some(Token::Id(_)) => parser.id()?,
+            _ => String::new(),
+        };
+        if !parser.eat(&Token::Open) {
+            return Err(crate::error::syntax("expected `{`".into()));
+        }
+
+        let mut states: Vec<String> = Vec::new();
+        let mut points: Vec<String> = Vec::new();
+        let mut initial = None;
+        let mut transitions = Vec::new();
+        let mut depth = 1;
+        while depth > 0 {
+            let Some(token) = parser.next() else {
+                return Err(crate::error::syntax("expected `}`".into()));
+            };
+            let id = match token {
+                Token::Close => {
+                    depth -= 1;
+                    continue;
+                }
+                Token::Open => {
+                    depth += 1;
+                    continue;
+                }
+                Token::Separator => continue,
+                Token::Id(id) => id,
+                other => return Err(crate::error::syntax(format!("unexpected {other:?}"))),
+            };
+            if id == "subgraph" {
+                if let Some(Token::Id(_)) = parser.peek() {
+                    parser.id()?;
+                }
+                continue;
+            }
+            if parser.eat(&Token::Equals) {
+                // graph attribute, e.g. `rankdir=LR` or `rank=same`
+                parser.id()?;
+                continue;
+            }
+            if matches!(id.as_str(), "graph" | "node" | "edge") {
+                parser.attributes()?;
+                continue;
+            }
+            if parser.eat(&Token::Arrow) {
+                let to = parser.id()?;
+                if parser.peek() == Some(&Token::Arrow) {
+                    return Err(crate::error::syntax(format!(
+                        "edge chains are not supported: {id} -> {to} -> ..."
+                    )));
+                }
+                let attributes = parser.attributes()?;
+                if points.contains(&id) {
+                    initial.get_or_insert_with(|| to.clone());
+                } else {
+                    let label = attribute(&attributes, "label").ok_or_else(|| {
+                        crate::error::syntax(format!("edge {id} -> {to} has no label"))
+                    })?;
+                    let (event, guard) =
+                        match label.strip_suffix(']').and_then(|l| l.split_once(" [")) {
+                            Some((event, guard)) => (event, Some(guard.to_string())),
+                            None => (label, None),
+                        };
+                    transitions.push(TransitionDef {
+                        from: (id != ANY_STATE).then(|| State::new(id.clone())),
+                        event: Event::new(event),
+                        to: State::new(to.clone()),
+                        guard,
+                        priority: None,
+                    });
+                    if id != ANY_STATE && !states.contains(&id) {
+                        states.push(id);
+                    }
+                }
+                if !states.contains(&to) {
+                    states.push(to);
+                }
+                continue;
+            }
+            let attributes = parser.attributes()?;
+            if attribute(&attributes, "shape") == Some("point") {
+                points.push(id);
+            } else if id != ANY_STATE && !states.contains(&id) {
+                states.push(id);
+            }
+        }
+        if parser.peek().is_some() {
+            return Err(crate::error::syntax(
+                "unexpected input after the graph".into(),
+            ));
+        }
+
+        let initial = initial
+            .or_else(|| states.first().cloned())
+            .ok_or_else(|| crate::error::syntax("the graph has no states".into()))?;
+        let mut definition = Definition::new(name, State::new(initial));
+        for transition in transitions {
+            definition.add(transition);
+        }
+        Ok(definition)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Diagram, StateMachineBuilder};
+    #[cfg(feature = "tracing")]
+    use tracing_test::traced_test;
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_from_dot() {
+        let definition = Definition::from_dot(
+            r#"
+            // drawn in an editor
+            digraph door {
+                rankdir=LR;
+                node [shape=ellipse];
+                closed; open;
+                closed -> open [label="open"];
+                open -> closed [label="close [no_draft]"];
+                "any state" -> closed [label=reset]
+            }"#,
+        )
+        .unwrap();
+        let closed = State::new("closed");
+        let open = State::new("open");
+        assert_eq!(definition.name(), "door");
+        assert_eq!(definition.initial_state(), &closed);
+        assert_eq!(definition.states(), vec![closed.clone(), open.clone()]);
+        let close = definition.candidates(&open, &Event::new("close"));
+        assert_eq!(close[0].guard.as_deref(), Some("no_draft"));
+        assert_eq!(definition.transitions()[2].from, None);
+
+        let machine = StateMachineBuilder::<crate::Error>::from_definition(
+            &Definition::from_dot("digraph { a -> b [label=go] }").unwrap(),
+        )
+        .unwrap()
+        .build();
+        machine.event(&Event::new("go")).unwrap();
+        assert_eq!(machine.current_state(), State::new("b"));
+        assert!(StateMachineBuilder::<crate::Error>::from_definition(&definition).is_err());
+
+        let error = Definition::from_dot("digraph { a -> b }").unwrap_err();
+        assert_eq!(error.to_string(), "edge a -> b has no label");
+    }
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_dot_round_trip() {
+        let idle = State::new("idle");
+        let running = State::new("running");
+        let definition = StateMachineBuilder::new("test", &running)
+            .add_event(idle.clone(), Event::new("go"), running.clone(), None)
+            .with_guard("fuel", || true)
+            .add_event(running, Event::new("stop"), idle.clone(), None)
+            .add_any_state_event(Event::new("reset"), idle.clone(), None)
+            .definition();
+        let diagram = Diagram::new().cluster("all", &idle);
+        let parsed = Definition::from_dot(&definition.to_dot(&diagram)).unwrap();
+        assert_eq!(parsed.name(), definition.name());
+        assert_eq!(parsed.initial_state(), definition.initial_state());
+        assert_eq!(parsed.transitions(), definition.transitions());
+    }
+}
@@ -0,0 +1,214 @@
+//! A compact syntax for quick machines, e.g. `idle -start-> running -stop-> idle`
+
+use crate::{Definition, Event, Result, State, StateMachine, StateMachineBuilder, TransitionDef};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    State(String),
+    Event(String),
+    /// `;` or a new line
+    End,
+}
+
+/// A token with the line and column it starts at, counted from 1
+struct Spanned {
+    token: Token,
+    line: usize,
+    column: usize,
+}
+
+fn is_name(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '.'
+}
+
+fn error(line: usize, column: usize, message: &str) -> crate::Error {
+    crate::Error::from(crate::StateMachineError::Parse {
+        line: Some(line),
+        column: Some(column),
+        message: message.to_string(),
+    })
+}
+
+fn tokenize(input: &str) -> Result<Vec<Spanned>> {
+    let mut tokens = Vec::new();
+    for (number, text) in input.lines().enumerate() {
+        let line = number + 1;
+        let mut chars = text.char_indices().peekable();
+        while let Some((i, c)) = chars.next() {
+            let column = text[..i].chars().count() + 1;
+            let mut name = |first: Option<char>| {
+                let mut name: String = first.into_iter().collect();
+                while let Some((_, c)) = chars.next_if(|(_, c)| is_name(*c)) {
+                    name.push(c);
+                }
+                name
+            };
+            let token = match c {
+                c if c.is_whitespace() => continue,
+                ';' => Token::End,
+                '-' => {
+                    let event = name(None);
+                    if event.is_empty() {
+                        return Err(error(line, column, "expected an event name after `-`"));
+                    }
+                    if chars.next_if(|(_, c)| *c == '-').is_none()
+                        || chars.next_if(|(_, c)| *c == '>').is_none()
+                    {
+                        return Err(error(
+                            line,
+                            column,
+                            &format!("expected `->` to close event `{event}`"),
+                        ));
+                    }
+                    Token::Event(event)
+                }
+                c if is_name(c) => Token::State(name(Some(c))),
+                c => return Err(error(line, column, &format!("unexpected character `{c}`"))),
+            };
+            tokens.push(Spanned {
+                token,
+                line,
+                column,
+            });
+        }
+        tokens.push(Spanned {
+            token: Token::End,
+            line,
+            column: text.chars().count() + 1,
+        });
+    }
+    Ok(tokens)
+}
+
+impl Definition {
+    /// Parse chains of transitions like `idle -start-> running -stop-> idle`, e.g. for tests and prototypes
+    ///
+    /// Chains are separated by `;` or new lines, the first state of the first chain is the initial state.
+    /// The machine is named `dsl`.
+    /// # Errors
+    /// If the input is not valid, the message starts with the line and column of the offending token
+    pub fn parse(input: &str) -> Result<Definition> {
+        let mut initial: Option<State> = None;
+        let mut transitions = Vec::new();
+        let mut state: Option<State> = None;
+        let mut event: Option<Event> = None;
+        for Spanned {
+            token,
+            line,
+            column,
+        } in tokenize(input)?
+        {
+            match (token, &state, &event) {
+                (Token::State(name), None, _) => {
+                    let name = State::new(name);
+                    initial.get_or_insert_with(|| name.clone());
+                    state = Some(name);
+                }
+                (Token::State(name), Some(from), Some(trigger)) => {
+                    let to = State::new(name);
+                    transitions.push(TransitionDef {
+                        from: Some(from.clone()),
+                        event: trigger.clone(),
+                        to: to.clone(),
+                        guard: None,
+                        priority: None,
+                    });
+                    state = Some(to);
+                    event = None;
+                }
+                (Token::State(name), Some(from), None) => {
+                    return Err(error(
+                        line,
+                        column,
+                        &format!("expected an event after state `{from}`, found state `{name}`"),
+                    ))
+                }
+                (Token::Event(name), None, _) => {
+                    return Err(error(
+                        line,
+                        column,
+                        &format!("expected a state, found event `{name}`"),
+                    ))
+                }
+                (Token::Event(name), Some(_), Some(previous)) => {
+                    return Err(error(
+                        line,
+                        column,
+                        &format!("expected a state after event `{previous}`, found event `{name}`"),
+                    ))
+                }
+                (Token::Event(name), Some(_), None) => event = Some(Event::new(name)),
+                (Token::End, _, Some(previous)) => {
+                    return Err(error(
+                        line,
+                        column,
+                        &format!("expected a state after event `{previous}`"),
+                    ))
+                }
+                (Token::End, _, None) => state = None,
+            }
+        }
+        let initial = initial.ok_or_else(|| error(1, 1, "expected a state"))?;
+        let mut definition = Definition::new("dsl", initial);
+        for transition in transitions {
+            definition.add(transition);
+        }
+        Ok(definition)
+    }
+}
+
+impl StateMachine {
+    /// Build a machine without actions from the syntax of [`Definition::parse`]
+    /// # Errors
+    /// If the input is not valid
+    pub fn parse(input: &str) -> Result<StateMachine> {
+        Ok(StateMachineBuilder::from_definition(&Definition::parse(input)?)?.build())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "tracing")]
+    use tracing_test::traced_test;
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_parse() {
+        let machine = StateMachine::parse(
+            "idle -start-> running -stop-> idle\n\
+             running -pause-> paused; paused -resume-> running",
+        )
+        .unwrap();
+        assert_eq!(machine.current_state(), State::new("idle"));
+        for event in ["start", "pause", "resume", "stop"] {
+            machine.event(&Event::new(event)).unwrap();
+        }
+        assert_eq!(machine.current_state(), State::new("idle"));
+        assert_eq!(machine.definition().transitions().len(), 4);
+
+        let error = |input| Definition::parse(input).unwrap_err().to_string();
+        assert_eq!(
+            error("idle -start-> running stop idle"),
+            "1:23: expected an event after state `running`, found state `stop`"
+        );
+        assert_eq!(
+            error("idle -start-> running\n-stop-> idle"),
+            "2:1: expected a state, found event `stop`"
+        );
+        assert_eq!(
+            error("idle -start-> -stop-> idle"),
+            "1:15: expected a state after event `start`, found event `stop`"
+        );
+        assert_eq!(
+            error("idle -start->"),
+            "1:14: expected a state after event `start`"
+        );
+        assert_eq!(
+            error("idle -start running"),
+            "1:6: expected `->` to close event `start`"
+        );
+        assert_eq!(error("idle ! running"), "1:6: unexpected character `!`");
+        assert_eq!(error(""), "1:1: expected a state");
+    }
+}
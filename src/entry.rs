@@ -0,0 +1,176 @@
+//! Named entry points, so one definition serves several lifecycles
+
+use crate::{Error, State, StateMachine, StateMachineBuilder, StateMachineError};
+use std::fmt;
+
+/// An entry point whose state is not a state of the machine, see [`StateMachine::validate`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidEntryPoint {
+    /// the name of the entry point
+    pub name: String,
+    /// the state it starts in
+    pub state: State,
+}
+
+impl<Err> StateMachineBuilder<Err> {
+    #[must_use]
+    /// Declare a named entry point, e.g. "resume" next to the initial state of fresh sessions,
+    /// see [`StateMachine::start_as`]
+    /// Declaring the same name again replaces its state
+    pub fn entry_point(mut self, name: impl Into<String>, state: State) -> Self {
+        let name = name.into();
+        match self.entry_points.iter_mut().find(|(n, _)| *n == name) {
+            Some(entry) => entry.1 = state,
+            None => self.entry_points.push((name, state)),
+        }
+        self
+    }
+
+    #[must_use]
+    /// Choose the starting state when the machine is started, e.g. from data loaded from a database,
+    /// see [`StateMachine::start`]
+    /// The closure captures the context it needs, it must return a state of the machine
+    pub fn initial_state_fn(mut self, initial: impl Fn() -> State + 'static) -> Self {
+        self.initial_state_fn = Some(Box::new(initial));
+        self
+    }
+}
+
+impl<Err> StateMachine<Err>
+where
+    Err: From<Error> + fmt::Display + 'static,
+{
+    /// Put the machine in the state of a named entry point, without running any action
+    ///
+    /// Like [`StateMachine::reset`], but for another lifecycle than the one of the initial state.
+    /// # Errors
+    /// If no entry point has that name, or its state is not a state of the machine
+    /// # Panics
+    /// If the lock is poisoned
+    pub fn start_as(&self, name: &str) -> Result<(), Err> {
+        let Some((_, entry)) = self.entry_points.iter().find(|(n, _)| n == name) else {
+            return Err(Error::from(StateMachineError::NoEntryPoint {
+                machine: self.name.clone(),
+                name: name.to_string(),
+            })
+            .into());
+        };
+        if !self.table.states(&self.initial_state).contains(entry) {
+            return Err(Error::from(StateMachineError::UnknownEntryState {
+                machine: self.name.clone(),
+                name: name.to_string(),
+                state: entry.clone(),
+            })
+            .into());
+        }
+        let mut state = self.state.write().expect("failed to get lock");
+        self.force_state(&mut state, entry);
+        Ok(())
+    }
+
+    /// Put the machine in the state chosen by the initial state function, without running any action
+    ///
+    /// Does nothing without an initial state function, see [`StateMachineBuilder::initial_state_fn`].
+    /// The [`MachineManager`](crate::MachineManager) starts the machines it creates without a snapshot.
+    /// # Errors
+    /// If the function returns a state that is not a state of the machine
+    /// # Panics
+    /// If the lock is poisoned
+    pub fn start(&self) -> Result<(), Err> {
+        let Some(ref initial) = self.initial_state_fn else {
+            return Ok(());
+        };
+        let initial = initial();
+        if !self.table.states(&self.initial_state).contains(&initial) {
+            return Err(Error::from(StateMachineError::UnknownInitialState {
+                machine: self.name.clone(),
+                state: initial,
+            })
+            .into());
+        }
+        let mut state = self.state.write().expect("failed to get lock");
+        self.force_state(&mut state, &initial);
+        Ok(())
+    }
+
+    /// Get the names of the entry points, in declaration order
+    pub fn entry_points(&self) -> impl Iterator<Item = &str> {
+        self.entry_points.iter().map(|(name, _)| name.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Event;
+    #[cfg(feature = "tracing")]
+    use tracing_test::traced_test;
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_entry_points() {
+        let (new, active, resuming) = (
+            State::new("new"),
+            State::new("active"),
+            State::new("resuming"),
+        );
+        let machine = StateMachineBuilder::new("session", &new)
+            .add_event(new.clone(), Event::new("login"), active.clone(), None)
+            .add_event(resuming.clone(), Event::new("restore"), active, None)
+            .entry_point("resume", resuming.clone())
+            .entry_point("broken", State::new("gone"))
+            .build();
+
+        assert_eq!(
+            machine.entry_points().collect::<Vec<_>>(),
+            vec!["resume", "broken"]
+        );
+        machine.start_as("resume").unwrap();
+        assert_eq!(machine.current_state(), resuming);
+        assert_eq!(
+            machine.start_as("fresh").unwrap_err().to_string(),
+            "session has no entry point fresh"
+        );
+        assert_eq!(
+            machine.start_as("broken").unwrap_err().to_string(),
+            "entry point broken of session starts in unknown state gone"
+        );
+        assert_eq!(
+            machine.validate().invalid_entry_points,
+            vec![InvalidEntryPoint {
+                name: "broken".to_string(),
+                state: State::new("gone"),
+            }]
+        );
+        assert_eq!(
+            machine.validate().to_string(),
+            "state active has no transitions\n\
+             entry point broken starts in unknown state gone\n"
+        );
+    }
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_initial_state_fn() {
+        let (draft, published) = (State::new("draft"), State::new("published"));
+        let build = |loaded: &'static str| {
+            StateMachineBuilder::new("post", &draft)
+                .add_event(
+                    draft.clone(),
+                    Event::new("publish"),
+                    published.clone(),
+                    None,
+                )
+                .initial_state_fn(move || State::new(loaded))
+                .build()
+        };
+        let machine = build("published");
+        assert_eq!(machine.current_state(), draft);
+        machine.start().unwrap();
+        assert_eq!(machine.current_state(), published);
+        assert_eq!(
+            build("deleted").start().unwrap_err().to_string(),
+            "cannot start post: the initial state function returned unknown state deleted"
+        );
+    }
+}
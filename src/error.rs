@@ -0,0 +1,354 @@
+use crate::{Event, State, Unauthorized};
+use std::any::Any;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The error type returned by the state machine and its actions
+#[cfg(feature = "anyhow")]
+pub type Error = anyhow::Error;
+
+/// The error type returned by the state machine and its actions
+#[cfg(not(feature = "anyhow"))]
+pub type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Create an error from a message
+#[cfg(all(test, feature = "anyhow"))]
+pub(crate) fn message(msg: String) -> Error {
+    anyhow::Error::msg(msg)
+}
+
+/// Create an error from a message
+#[cfg(all(test, not(feature = "anyhow")))]
+pub(crate) fn message(msg: String) -> Error {
+    msg.into()
+}
+
+/// Create a parse error, for inputs without positions
+pub(crate) fn syntax(message: String) -> Error {
+    Error::from(StateMachineError::Parse {
+        line: None,
+        column: None,
+        message,
+    })
+}
+
+/// Wrap the error of a failed action in [`StateMachineError::ActionFailed`]
+///
+/// Only for machines using [`Error`], the errors of other error types are returned as they are.
+pub(crate) fn action_failed<Err: 'static>(error: Err) -> Err {
+    let mut error = Some(error);
+    if let Some(slot) = (&mut error as &mut dyn Any).downcast_mut::<Option<Error>>() {
+        *slot = slot
+            .take()
+            .map(|e| Error::from(StateMachineError::action_failed(e)));
+    }
+    error.expect("the error is put back")
+}
+
+/// The errors of the machine itself, as opposed to the errors returned by actions
+///
+/// Returned by the machine, its manager and its bus converted to the error type of the machine,
+/// it can be recovered by downcasting. An event refused by the authorizer is a [`StateMachineError::Unauthorized`].
+/// Machines with their own error type get the errors of their actions back as they are,
+/// the others get them wrapped in [`StateMachineError::ActionFailed`].
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum StateMachineError {
+    /// no transition handles the event in the state
+    NoTransition {
+        state: State,
+        event: Event,
+        /// the state has no transitions at all
+        dead_end: bool,
+    },
+    /// the machine is over the limit of a [`Throttle`](crate::Throttle) rejecting events
+    Throttled {
+        state: State,
+        event: Event,
+        /// when a token is available again
+        retry_in: Duration,
+    },
+    /// the action of the transition returned this error
+    ActionFailed(Arc<dyn std::error::Error + Send + Sync>),
+    /// the action panicked, with the panic message, see [`StateMachineBuilder::error_state`](crate::StateMachineBuilder::error_state)
+    ActionPanicked(String),
+    /// the action was replaced by a failure, see [`ChaosConfig`](crate::ChaosConfig)
+    InjectedFailure,
+    /// a thread panicked while holding the state lock
+    LockPoisoned,
+    /// the authorizer refused the event
+    Unauthorized(Unauthorized),
+    /// the action of the transition needs a payload of the `expected` type, see [`StateMachine::event_with`](crate::StateMachine::event_with)
+    MissingPayload {
+        event: Event,
+        expected: &'static str,
+    },
+    /// the transition needs approvals, the envelope has no principal to approve it
+    MissingPrincipal {
+        machine: String,
+        event: Event,
+        approvals: usize,
+    },
+    /// the machine has no entry point with that name
+    NoEntryPoint { machine: String, name: String },
+    /// the entry point starts in a state the machine does not have
+    UnknownEntryState {
+        machine: String,
+        name: String,
+        state: State,
+    },
+    /// the initial state function returned a state the machine does not have
+    UnknownInitialState { machine: String, state: State },
+    /// the snapshot is in a state the machine does not have, and the recovery policy does not recover it
+    UnknownSnapshotState { machine: String, state: State },
+    /// the snapshot was taken of another machine
+    SnapshotOfOtherMachine { machine: String, snapshot: String },
+    /// the record is not in the history, because it is not enabled or the record was dropped
+    NotInHistory { machine: String, seq: u64 },
+    /// the branch of a machine is already in memory
+    BranchExists { key: String, branch: String },
+    /// the manager is shut down
+    ShutDown { key: String },
+    /// the machine could not be evicted, it is kept in memory
+    EvictionFailed { key: String, reason: String },
+    /// no machine with that name is registered on the bus
+    NoMachine { name: String },
+    /// the shard of a pool stopped before handling the event
+    ShardStopped,
+    /// the remap has no new state for the state of a snapshot
+    UnmappedState { state: State },
+    /// the template does not declare the parameter
+    UnknownParameter { template: String, name: String },
+    /// the parameter has another type than its default value
+    ParameterType {
+        template: String,
+        name: String,
+        expected: &'static str,
+        got: &'static str,
+    },
+    /// a guard cannot be built from a definition, which only carries its name
+    GuardedDefinition { guard: String, event: Event },
+    /// a snapshot encoding could not be decoded
+    InvalidEncoding,
+    /// an input could not be parsed, e.g. a DOT graph, the compact syntax or a remap
+    Parse {
+        line: Option<usize>,
+        column: Option<usize>,
+        message: String,
+    },
+    /// another error, e.g. of the store of a manager or of the backend of a lease
+    Other(Arc<dyn std::error::Error + Send + Sync>),
+}
+
+impl StateMachineError {
+    /// Wrap the error of an action
+    // without anyhow, the error is already boxed
+    #[allow(clippy::useless_conversion)]
+    pub(crate) fn action_failed(error: Error) -> Self {
+        let error: Box<dyn std::error::Error + Send + Sync> = error.into();
+        StateMachineError::ActionFailed(Arc::from(error))
+    }
+
+    /// Recover the kind of an error returned by a machine, e.g. to send it to another thread
+    ///
+    /// The other errors of machines using [`Error`], e.g. the errors of a store, are [`StateMachineError::Other`]
+    /// with their message. The errors of machines with their own error type cannot be told apart,
+    /// they are [`StateMachineError::ActionFailed`] with their message.
+    pub fn of<E: fmt::Display + 'static>(error: &E) -> Self {
+        let any: &dyn Any = error;
+        let message: Box<dyn std::error::Error + Send + Sync> = error.to_string().into();
+        if let Some(error) = any.downcast_ref::<Error>() {
+            if let Some(error) = error.downcast_ref::<StateMachineError>() {
+                return error.clone();
+            }
+            if let Some(refused) = error.downcast_ref::<Unauthorized>() {
+                return StateMachineError::Unauthorized(refused.clone());
+            }
+            // the errors of the actions are wrapped in ActionFailed
+            return StateMachineError::Other(Arc::from(message));
+        }
+        StateMachineError::ActionFailed(Arc::from(message))
+    }
+}
+
+impl fmt::Display for StateMachineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateMachineError::NoTransition {
+                state,
+                event,
+                dead_end,
+            } => {
+                write!(f, "no transition found for event {event} in state {state}")?;
+                if *dead_end {
+                    write!(f, ", which has no transitions at all")?;
+                }
+                Ok(())
+            }
+            StateMachineError::Throttled {
+                state,
+                event,
+                retry_in,
+            } => write!(
+                f,
+                "throttled, rejecting event {event} in state {state}, retry in {retry_in:?}"
+            ),
+            StateMachineError::ActionFailed(source) => write!(f, "{source}"),
+            StateMachineError::ActionPanicked(message) => write!(f, "action panicked: {message}"),
+            StateMachineError::InjectedFailure => write!(f, "chaos: injected failure"),
+            StateMachineError::LockPoisoned => write!(f, "lock error"),
+            StateMachineError::Unauthorized(refused) => write!(f, "{refused}"),
+            StateMachineError::MissingPayload { event, expected } => {
+                write!(f, "event {event} needs a payload of type {expected}")
+            }
+            StateMachineError::MissingPrincipal {
+                machine,
+                event,
+                approvals,
+            } => write!(
+                f,
+                "{machine}: event {event} needs {approvals} approvals, the envelope has no principal"
+            ),
+            StateMachineError::NoEntryPoint { machine, name } => {
+                write!(f, "{machine} has no entry point {name}")
+            }
+            StateMachineError::UnknownEntryState {
+                machine,
+                name,
+                state,
+            } => write!(
+                f,
+                "entry point {name} of {machine} starts in unknown state {state}"
+            ),
+            StateMachineError::UnknownInitialState { machine, state } => write!(
+                f,
+                "cannot start {machine}: the initial state function returned unknown state {state}"
+            ),
+            StateMachineError::UnknownSnapshotState { machine, state } => {
+                write!(f, "cannot restore {machine}: unknown state {state}")
+            }
+            StateMachineError::SnapshotOfOtherMachine { machine, snapshot } => {
+                write!(f, "cannot restore {machine}: the snapshot is of {snapshot}")
+            }
+            StateMachineError::NotInHistory { machine, seq } => write!(
+                f,
+                "cannot rewind {machine}: record {seq} is not in the history"
+            ),
+            StateMachineError::BranchExists { key, branch } => {
+                write!(f, "cannot branch {key}: {branch} already exists")
+            }
+            StateMachineError::ShutDown { key } => {
+                write!(f, "manager is shut down, rejecting {key}")
+            }
+            StateMachineError::EvictionFailed { key, reason } => {
+                write!(f, "cannot evict {key}: {reason}")
+            }
+            StateMachineError::NoMachine { name } => write!(f, "no machine named {name}"),
+            StateMachineError::ShardStopped => write!(f, "shard stopped"),
+            StateMachineError::UnmappedState { state } => write!(f, "no new state for {state}"),
+            StateMachineError::UnknownParameter { template, name } => {
+                write!(f, "template {template} has no parameter {name}")
+            }
+            StateMachineError::ParameterType {
+                template,
+                name,
+                expected,
+                got,
+            } => write!(
+                f,
+                "parameter {name} of template {template} is a {expected}, got a {got}"
+            ),
+            StateMachineError::GuardedDefinition { guard, event } => write!(
+                f,
+                "guard {guard} of event {event} cannot be built from a definition"
+            ),
+            StateMachineError::InvalidEncoding => write!(f, "invalid snapshot encoding"),
+            StateMachineError::Parse {
+                line,
+                column,
+                message,
+            } => match (line, column) {
+                (Some(line), Some(column)) => write!(f, "{line}:{column}: {message}"),
+                (Some(line), None) => write!(f, "line {line}: {message}"),
+                _ => write!(f, "{message}"),
+            },
+            StateMachineError::Other(source) => write!(f, "{source}"),
+        }
+    }
+}
+
+impl std::error::Error for StateMachineError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StateMachineError::ActionFailed(source) | StateMachineError::Other(source) => {
+                Some(source.as_ref())
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::StateMachineBuilder;
+    #[cfg(feature = "tracing")]
+    use tracing_test::traced_test;
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_error_kinds() {
+        let (idle, broken) = (State::new("idle"), State::new("broken"));
+        let (crash, fail, start) = (Event::new("crash"), Event::new("fail"), Event::new("start"));
+        let machine = StateMachineBuilder::new("kinds", &idle)
+            .add_event(
+                idle.clone(),
+                fail.clone(),
+                idle.clone(),
+                Some(Box::new(|| Err(message("disk full".to_string())))),
+            )
+            .add_event(
+                idle.clone(),
+                crash.clone(),
+                idle.clone(),
+                Some(Box::new(|| panic!("bug"))),
+            )
+            .error_state(&broken, 2)
+            .build();
+
+        let error = machine.event(&start).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<StateMachineError>(),
+            Some(StateMachineError::NoTransition { state, event, dead_end: false })
+                if *state == idle && *event == start
+        ));
+        let error = machine.event(&fail).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<StateMachineError>(),
+            Some(StateMachineError::ActionFailed(source)) if source.to_string() == "disk full"
+        ));
+        assert_eq!(error.to_string(), "disk full");
+        assert!(matches!(
+            StateMachineError::of(&error),
+            StateMachineError::ActionFailed(_)
+        ));
+        let error = machine.event(&crash).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<StateMachineError>(),
+            Some(StateMachineError::ActionPanicked(message)) if message == "bug"
+        ));
+        let error = machine.event(&start).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "no transition found for event start in state broken, which has no transitions at all"
+        );
+
+        assert!(matches!(
+            StateMachineError::of(&message("store down".to_string())),
+            StateMachineError::Other(source) if source.to_string() == "store down"
+        ));
+    }
+}
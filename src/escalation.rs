@@ -0,0 +1,146 @@
+//! Time-boxed states that escalate when the machine stays in them for too long
+
+use crate::dedup::Dispatch;
+use crate::trace::error;
+use crate::{Error, Event, Outcome, State, StateMachine, StateMachineBuilder, StateMachineError};
+use std::fmt;
+use std::time::Duration;
+
+/// The time limit of a state and the events tried in order when it passes
+pub(crate) struct TimeBox {
+    pub(crate) limit: Duration,
+    pub(crate) chain: Vec<Event>,
+}
+
+impl<Err> StateMachine<Err>
+where
+    Err: From<Error> + fmt::Display + 'static,
+{
+    /// Escalate once if the machine stayed in a time-boxed state for longer than its limit
+    ///
+    /// The events of the chain are tried in order until one is handled and its action succeeds.
+    /// An event whose action fails leaves the machine in its new state, the next event is handled there.
+    /// Unhandled events are recorded in the history with [`Outcome::EscalationSkipped`].
+    /// # Returns
+    /// `true` if an event of the chain was handled
+    /// # Errors
+    /// The error of the last event of the chain if none was handled
+    /// # Panics
+    /// If the lock is poisoned
+    pub(crate) fn escalate(&self) -> Result<bool, Err> {
+        let mut state = self
+            .state
+            .write()
+            .map_err(|_| Error::from(StateMachineError::LockPoisoned))?;
+        let Some(time_box) = self.time_boxes.get(&*state) else {
+            return Ok(false);
+        };
+        {
+            let mut dwell = self.dwell.lock().expect("failed to get lock");
+            if dwell.current(self.clock.now()) <= time_box.limit || !dwell.escalate() {
+                return Ok(false);
+            }
+        }
+        error!(
+            "{}: time box of {} expired after {:?}, escalating",
+            self.log_name(),
+            state,
+            time_box.limit
+        );
+        let mut last = None;
+        for event in &time_box.chain {
+            let dispatch = Dispatch::event(event);
+            match self.fire(&mut state, dispatch, None) {
+                Some((Ok(()), _)) => return Ok(true),
+                Some((Err(e), _)) => last = Some(e),
+                None => {
+                    let e = self.no_transition(&state, event);
+                    self.record_not_taken(
+                        &state,
+                        dispatch,
+                        Outcome::EscalationSkipped(e.to_string()),
+                    );
+                    last = Some(e);
+                }
+            }
+        }
+        last.map_or(Ok(false), Err)
+    }
+}
+
+impl<Err> StateMachineBuilder<Err> {
+    #[must_use]
+    /// Escalate when the machine stays in `state` for longer than `limit`, see [`StateMachine::fire_due`]
+    /// # Arguments
+    /// * `state` - the time-boxed state
+    /// * `limit` - the time the machine may stay in the state
+    /// * `chain` - the escalation events, the next one is tried when an event is unhandled or its action fails
+    pub fn time_box(mut self, state: &State, limit: Duration, chain: Vec<Event>) -> Self {
+        self.time_boxes
+            .insert(state.clone(), TimeBox { limit, chain });
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ManualClock;
+    use std::sync::Arc;
+    #[cfg(feature = "tracing")]
+    use tracing_test::traced_test;
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_escalation_chain() {
+        let (waiting, paged, manager) = (
+            State::new("waiting"),
+            State::new("paged"),
+            State::new("manager"),
+        );
+        let (page, call, notify) = (Event::new("page"), Event::new("call"), Event::new("notify"));
+        let clock = Arc::new(ManualClock::default());
+        let machine = StateMachineBuilder::new("approval", &waiting)
+            .add_event(
+                waiting.clone(),
+                page.clone(),
+                paged.clone(),
+                Some(Box::new(|| {
+                    Err(crate::error::message("pager down".to_string()))
+                })),
+            )
+            .add_event(paged.clone(), notify.clone(), manager.clone(), None)
+            .time_box(
+                &waiting,
+                Duration::from_secs(60),
+                vec![page.clone(), call.clone(), notify.clone()],
+            )
+            .with_history(10)
+            .with_clock(clock.clone())
+            .build();
+
+        assert_eq!(machine.fire_due().unwrap(), 0);
+        clock.advance(Duration::from_secs(61));
+        assert_eq!(machine.fire_due().unwrap(), 1);
+        assert_eq!(machine.current_state(), manager);
+        let path: Vec<(Event, Outcome)> = machine
+            .history()
+            .into_iter()
+            .map(|r| (r.event, r.outcome))
+            .collect();
+        assert_eq!(
+            path,
+            vec![
+                (page, Outcome::ActionFailed("pager down".to_string())),
+                (
+                    call,
+                    Outcome::EscalationSkipped(
+                        "no transition found for event call in state paged".to_string()
+                    )
+                ),
+                (notify, Outcome::Ok),
+            ]
+        );
+        assert_eq!(machine.fire_due().unwrap(), 0);
+    }
+}
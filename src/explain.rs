@@ -0,0 +1,218 @@
+use crate::{Candidate, Event, State, StateMachine};
+use std::fmt;
+
+/// What happened to a candidate transition
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    /// the transition would fire
+    Selected,
+    /// the guard of the transition returned false
+    GuardRejected,
+    /// the transition is not tried, because one before it was selected
+    Skipped,
+}
+
+/// A candidate transition and what happened to it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Step {
+    pub candidate: Candidate,
+    pub verdict: Verdict,
+}
+
+/// Why the machine would (not) take a transition for an event
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Explanation {
+    /// the current state
+    pub state: State,
+    /// the explained event
+    pub event: Event,
+    /// the candidate transitions, in the order they are tried
+    pub steps: Vec<Step>,
+    /// whether the state handles no event at all, which is probably a modeling error
+    pub dead_end: bool,
+}
+
+impl Explanation {
+    /// The transition that would fire, if any
+    pub fn selected(&self) -> Option<&Candidate> {
+        self.steps
+            .iter()
+            .find(|s| s.verdict == Verdict::Selected)
+            .map(|s| &s.candidate)
+    }
+
+    /// The names of the guards that rejected the event
+    pub fn rejected_guards(&self) -> Vec<&str> {
+        self.steps
+            .iter()
+            .filter(|s| s.verdict == Verdict::GuardRejected)
+            .filter_map(|s| s.candidate.guard.as_deref())
+            .collect()
+    }
+}
+
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.dead_end {
+            return write!(
+                f,
+                "no transition for event {} in state {}, which has no transitions at all",
+                self.event, self.state
+            );
+        }
+        if self.steps.is_empty() {
+            return write!(
+                f,
+                "no transition for event {} in state {}",
+                self.event, self.state
+            );
+        }
+        writeln!(f, "event {} in state {}:", self.event, self.state)?;
+        for step in &self.steps {
+            let c = &step.candidate;
+            write!(f, "  -> {}", c.to)?;
+            match step.verdict {
+                Verdict::Selected => write!(f, ": selected")?,
+                Verdict::GuardRejected => write!(
+                    f,
+                    ": rejected by guard {}",
+                    c.guard.as_deref().unwrap_or_default()
+                )?,
+                Verdict::Skipped => write!(f, ": not tried")?,
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+impl<Err> StateMachine<Err> {
+    /// Explain how the current state would handle an event, without taking any transition
+    ///
+    /// The guards of the candidate transitions are evaluated, the actions are not executed.
+    /// # Panics
+    /// If the lock is poisoned
+    pub fn explain(&self, event: &Event) -> Explanation {
+        let state = self.current_state();
+        let mut selected = false;
+        let steps = self
+            .table
+            .candidates(&state, event)
+            .map(|t| {
+                let verdict = if selected {
+                    Verdict::Skipped
+                } else if t.allowed() {
+                    selected = true;
+                    Verdict::Selected
+                } else {
+                    Verdict::GuardRejected
+                };
+                Step {
+                    candidate: Candidate::from(t),
+                    verdict,
+                }
+            })
+            .collect();
+        Explanation {
+            dead_end: !self.table.has_transitions(&state),
+            state,
+            event: event.clone(),
+            steps,
+        }
+    }
+
+    /// Get the events handled in the current state, whether their guards pass or not
+    /// # Returns
+    /// An empty list if the current state has no transitions at all
+    /// # Panics
+    /// If the lock is poisoned
+    pub fn available_events(&self) -> Vec<Event> {
+        self.events_in(&self.current_state()).cloned().collect()
+    }
+
+    /// Get the events handled in a state, borrowed from the machine, in registration order
+    pub fn events_in<'a>(&'a self, state: &'a State) -> impl Iterator<Item = &'a Event> {
+        self.table.events_in(state)
+    }
+
+    /// Get the states reachable from a state in one transition, borrowed from the machine
+    ///
+    /// Guards are not evaluated, the targets of guarded transitions are included.
+    pub fn successors<'a>(&'a self, state: &'a State) -> impl Iterator<Item = &'a State> {
+        self.table.successors(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::StateMachineBuilder;
+    #[cfg(feature = "tracing")]
+    use tracing_test::traced_test;
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_explain() {
+        let idle = State::new("idle");
+        let running = State::new("running");
+        let fast = State::new("fast");
+        let go = Event::new("go");
+        let stop = Event::new("stop");
+        let machine = StateMachineBuilder::new("test", &idle)
+            .add_event(idle.clone(), go.clone(), fast.clone(), None)
+            .with_guard("turbo", || false)
+            .add_event(idle.clone(), go.clone(), running.clone(), None)
+            .with_guard("has_fuel", || true)
+            .add_event(idle.clone(), go.clone(), idle.clone(), None)
+            .build();
+
+        let explanation = machine.explain(&go);
+        assert_eq!(explanation.selected().map(|c| &c.to), Some(&running));
+        assert_eq!(explanation.rejected_guards(), vec!["turbo"]);
+        assert_eq!(
+            explanation.to_string(),
+            "event go in state idle:\n  \
+             -> fast: rejected by guard turbo\n  \
+             -> running: selected\n  \
+             -> idle: not tried\n"
+        );
+        // nothing was executed
+        assert_eq!(machine.current_state(), idle);
+
+        let explanation = machine.explain(&stop);
+        assert!(explanation.selected().is_none());
+        assert_eq!(
+            explanation.to_string(),
+            "no transition for event stop in state idle"
+        );
+    }
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_dead_end_state() {
+        let idle = State::new("idle");
+        let done = State::new("done");
+        let (go, stop) = (Event::new("go"), Event::new("stop"));
+        let machine = StateMachineBuilder::new("test", &idle)
+            .add_event(idle.clone(), go.clone(), done.clone(), None)
+            .add_event(idle.clone(), stop.clone(), idle.clone(), None)
+            .build();
+        assert_eq!(machine.available_events(), vec![go.clone(), stop.clone()]);
+        let successors: Vec<&State> = machine.successors(&idle).collect();
+        assert_eq!(successors, vec![&done, &idle]);
+        assert_eq!(machine.events_in(&done).count(), 0);
+        assert!(!machine.explain(&Event::new("other")).dead_end);
+
+        machine.event(&go).unwrap();
+        assert!(machine.available_events().is_empty());
+        assert_eq!(
+            machine.explain(&stop).to_string(),
+            "no transition for event stop in state done, which has no transitions at all"
+        );
+        assert_eq!(
+            machine.event(&stop).unwrap_err().to_string(),
+            "no transition found for event stop in state done, which has no transitions at all"
+        );
+        assert_eq!(machine.validate().dead_ends, vec![done]);
+    }
+}
@@ -0,0 +1,691 @@
+//! Diagram exports of a [`Definition`]: Graphviz DOT, Mermaid and PlantUML, and of a recorded run
+
+use crate::{
+    json, Definition, Event, Outcome, State, StateMachine, StateMachineBuilder, TransitionDef,
+    TransitionRecord,
+};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt::Write;
+
+/// Layout hints and filters for the diagram exports
+///
+/// Hints are honored where the format supports them:
+/// ranks only in DOT, transition colors in DOT and PlantUML.
+/// Filters select the states to draw, a transition is drawn when both its states are.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Diagram {
+    state_tags: HashMap<State, Vec<String>>,
+    event_tags: HashMap<Event, Vec<String>>,
+    colors: Vec<(String, String)>,
+    clusters: Vec<(String, Vec<State>)>,
+    ranks: Vec<(usize, Vec<State>)>,
+    only_tags: Vec<String>,
+    around: Option<(State, usize)>,
+}
+
+impl Diagram {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    /// Tag a state
+    pub fn tag_state(mut self, state: &State, tag: impl Into<String>) -> Self {
+        self.state_tags
+            .entry(state.clone())
+            .or_default()
+            .push(tag.into());
+        self
+    }
+
+    #[must_use]
+    /// Tag the transitions triggered by an event
+    pub fn tag_event(mut self, event: &Event, tag: impl Into<String>) -> Self {
+        self.event_tags
+            .entry(event.clone())
+            .or_default()
+            .push(tag.into());
+        self
+    }
+
+    #[must_use]
+    /// Color the states and transitions with a tag, the first colored tag of an element wins
+    /// # Arguments
+    /// * `tag` - the tag
+    /// * `color` - a color understood by the target format, e.g. `red` or `#ff0000`
+    pub fn color(mut self, tag: impl Into<String>, color: impl Into<String>) -> Self {
+        self.colors.push((tag.into(), color.into()));
+        self
+    }
+
+    #[must_use]
+    /// Draw a state inside a named cluster, a state belongs to at most one cluster
+    pub fn cluster(mut self, name: impl Into<String>, state: &State) -> Self {
+        let name = name.into();
+        for (_, states) in &mut self.clusters {
+            states.retain(|s| s != state);
+        }
+        match self.clusters.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, states)) => states.push(state.clone()),
+            None => self.clusters.push((name, vec![state.clone()])),
+        }
+        self
+    }
+
+    #[must_use]
+    /// Draw the states with the same rank on the same level
+    pub fn rank(mut self, state: &State, rank: usize) -> Self {
+        for (_, states) in &mut self.ranks {
+            states.retain(|s| s != state);
+        }
+        match self.ranks.iter_mut().find(|(r, _)| *r == rank) {
+            Some((_, states)) => states.push(state.clone()),
+            None => self.ranks.push((rank, vec![state.clone()])),
+        }
+        self
+    }
+
+    #[must_use]
+    /// Only draw the states with the tag, call again to also draw the states with other tags
+    pub fn only_tagged(mut self, tag: impl Into<String>) -> Self {
+        self.only_tags.push(tag.into());
+        self
+    }
+
+    #[must_use]
+    /// Only draw the states within `distance` transitions of `state`, in either direction
+    ///
+    /// Transitions declared for any state do not count, they would put every state next to their target.
+    pub fn around(mut self, state: &State, distance: usize) -> Self {
+        self.around = Some((state.clone(), distance));
+        self
+    }
+
+    /// Get the tags of a state
+    pub fn state_tags(&self, state: &State) -> &[String] {
+        self.state_tags.get(state).map_or(&[], Vec::as_slice)
+    }
+
+    fn color_of(&self, tags: &[String]) -> Option<&str> {
+        self.colors
+            .iter()
+            .find(|(tag, _)| tags.contains(tag))
+            .map(|(_, color)| color.as_str())
+    }
+
+    fn state_color(&self, state: &State) -> Option<&str> {
+        self.color_of(self.state_tags(state))
+    }
+
+    fn transition_color(&self, t: &TransitionDef) -> Option<&str> {
+        self.color_of(self.event_tags.get(&t.event).map_or(&[], Vec::as_slice))
+    }
+
+    fn cluster_of(&self, state: &State) -> Option<usize> {
+        self.clusters
+            .iter()
+            .position(|(_, states)| states.contains(state))
+    }
+}
+
+/// Quote a string for DOT
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Escape the quotes of a Mermaid label, with its entity code
+fn mermaid(s: &str) -> String {
+    s.replace('"', "#quot;")
+}
+
+/// Escape the quotes of a PlantUML label, with its Unicode syntax
+fn plantuml(s: &str) -> String {
+    s.replace('"', "<U+0022>")
+}
+
+fn label(t: &TransitionDef) -> String {
+    match t.guard {
+        Some(ref guard) => format!("{} [{guard}]", t.event),
+        None => t.event.to_string(),
+    }
+}
+
+const ANY_STATE: &str = "any state";
+
+/// What the filters of a [`Diagram`] leave of a definition
+struct View<'a> {
+    /// the drawn states, in definition order
+    states: Vec<State>,
+    /// the ids of the states in Mermaid and PlantUML, stable across filters
+    ids: HashMap<State, String>,
+    /// the drawn transitions, in registration order
+    transitions: Vec<&'a TransitionDef>,
+    /// whether the initial state is drawn
+    initial: bool,
+    /// whether transitions declared for any state are drawn
+    any_state: bool,
+}
+
+impl View<'_> {
+    fn members(&self, states: &[State]) -> Vec<&State> {
+        self.states.iter().filter(|s| states.contains(s)).collect()
+    }
+}
+
+impl Definition {
+    /// The states within `distance` transitions of `center`, in either direction
+    fn neighborhood(&self, center: &State, distance: usize) -> HashSet<State> {
+        let mut seen = HashSet::from([center.clone()]);
+        let mut queue = VecDeque::from([(center.clone(), 0)]);
+        while let Some((state, d)) = queue.pop_front() {
+            if d == distance {
+                continue;
+            }
+            for t in self.transitions() {
+                let Some(ref from) = t.from else {
+                    continue;
+                };
+                let next = if *from == state {
+                    &t.to
+                } else if t.to == state {
+                    from
+                } else {
+                    continue;
+                };
+                if seen.insert(next.clone()) {
+                    queue.push_back((next.clone(), d + 1));
+                }
+            }
+        }
+        seen
+    }
+
+    fn view(&self, diagram: &Diagram) -> View<'_> {
+        let all = self.states();
+        let ids = all
+            .iter()
+            .enumerate()
+            .map(|(i, s)| (s.clone(), format!("s{i}")))
+            .collect();
+        let near = diagram
+            .around
+            .as_ref()
+            .map(|(center, distance)| self.neighborhood(center, *distance));
+        let states: Vec<State> = all
+            .into_iter()
+            .filter(|s| {
+                diagram.only_tags.is_empty()
+                    || diagram
+                        .state_tags(s)
+                        .iter()
+                        .any(|tag| diagram.only_tags.contains(tag))
+            })
+            .filter(|s| near.as_ref().is_none_or(|near| near.contains(s)))
+            .collect();
+        let transitions: Vec<&TransitionDef> = self
+            .transitions()
+            .iter()
+            .filter(|t| t.from.as_ref().is_none_or(|from| states.contains(from)))
+            .filter(|t| states.contains(&t.to))
+            .collect();
+        View {
+            initial: states.contains(self.initial_state()),
+            any_state: transitions.iter().any(|t| t.from.is_none()),
+            states,
+            ids,
+            transitions,
+        }
+    }
+
+    /// Render the definition as a Graphviz DOT graph
+    ///
+    /// Transitions declared for any state start from a dashed `any state` node.
+    pub fn to_dot(&self, diagram: &Diagram) -> String {
+        let view = self.view(diagram);
+        let mut out = String::new();
+        // the start node is named like no state
+        let mut start = String::from("__start");
+        while start == ANY_STATE || view.states.iter().any(|s| *s.name == start) {
+            start.push('_');
+        }
+        let start = quote(&start);
+        let _ = writeln!(out, "digraph {} {{", quote(self.name()));
+        if view.initial {
+            let _ = writeln!(out, "    {start} [shape=point];");
+        }
+        if view.any_state {
+            let _ = writeln!(out, "    {} [style=dashed];", quote(ANY_STATE));
+        }
+        let node = |out: &mut String, indent: &str, state: &State| {
+            let _ = write!(out, "{indent}{}", quote(&state.name));
+            if let Some(color) = diagram.state_color(state) {
+                let _ = write!(out, " [style=filled, fillcolor={}]", quote(color));
+            }
+            let _ = writeln!(out, ";");
+        };
+        for (i, (name, members)) in diagram.clusters.iter().enumerate() {
+            let members = view.members(members);
+            if members.is_empty() {
+                continue;
+            }
+            let _ = writeln!(out, "    subgraph \"cluster_{i}\" {{");
+            let _ = writeln!(out, "        label={};", quote(name));
+            for state in members {
+                node(&mut out, "        ", state);
+            }
+            let _ = writeln!(out, "    }}");
+        }
+        for state in view
+            .states
+            .iter()
+            .filter(|s| diagram.cluster_of(s).is_none())
+        {
+            node(&mut out, "    ", state);
+        }
+        for (_, members) in &diagram.ranks {
+            let names: Vec<String> = view
+                .members(members)
+                .iter()
+                .map(|s| quote(&s.name))
+                .collect();
+            if !names.is_empty() {
+                let _ = writeln!(out, "    {{ rank=same; {}; }}", names.join("; "));
+            }
+        }
+        if view.initial {
+            let _ = writeln!(out, "    {start} -> {};", quote(&self.initial_state().name));
+        }
+        for t in view.transitions {
+            let from = t.from.as_ref().map_or(ANY_STATE, |s| &*s.name);
+            let _ = write!(
+                out,
+                "    {} -> {} [label={}",
+                quote(from),
+                quote(&t.to.name),
+                quote(&label(t))
+            );
+            if let Some(color) = diagram.transition_color(t) {
+                let _ = write!(out, ", color={}", quote(color));
+            }
+            let _ = writeln!(out, "];");
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    /// Render the definition as a Mermaid state diagram
+    ///
+    /// Transitions declared for any state start from an `any state` node.
+    pub fn to_mermaid(&self, diagram: &Diagram) -> String {
+        let view = self.view(diagram);
+        let ids = &view.ids;
+        let mut out = String::from("stateDiagram-v2\n");
+        for state in &view.states {
+            let _ = writeln!(
+                out,
+                "    state \"{}\" as {}",
+                mermaid(&state.name),
+                ids[state]
+            );
+        }
+        if view.any_state {
+            let _ = writeln!(out, "    state \"{ANY_STATE}\" as any");
+        }
+        for (i, (name, members)) in diagram.clusters.iter().enumerate() {
+            let members = view.members(members);
+            if members.is_empty() {
+                continue;
+            }
+            let _ = writeln!(out, "    state \"{}\" as c{i}", mermaid(name));
+            let _ = writeln!(out, "    state c{i} {{");
+            for state in members {
+                let _ = writeln!(out, "        {}", ids[state]);
+            }
+            let _ = writeln!(out, "    }}");
+        }
+        if view.initial {
+            let _ = writeln!(out, "    [*] --> {}", ids[self.initial_state()]);
+        }
+        for t in &view.transitions {
+            let from = t.from.as_ref().map_or("any", |s| ids[s].as_str());
+            let _ = writeln!(out, "    {from} --> {}: {}", ids[&t.to], label(t));
+        }
+        for (i, (tag, color)) in diagram.colors.iter().enumerate() {
+            let colored: Vec<&str> = view
+                .states
+                .iter()
+                .filter(|s| {
+                    diagram.state_color(s) == Some(color) && diagram.state_tags(s).contains(tag)
+                })
+                .map(|s| ids[s].as_str())
+                .collect();
+            if !colored.is_empty() {
+                let _ = writeln!(out, "    classDef tag{i} fill:{color}");
+                let _ = writeln!(out, "    class {} tag{i}", colored.join(","));
+            }
+        }
+        out
+    }
+
+    /// Render the definition as a PlantUML state diagram
+    ///
+    /// Transitions declared for any state start from an `any state` node.
+    pub fn to_plantuml(&self, diagram: &Diagram) -> String {
+        let view = self.view(diagram);
+        let ids = &view.ids;
+        let mut out = String::from("@startuml\n");
+        let node = |out: &mut String, indent: &str, state: &State| {
+            let _ = write!(
+                out,
+                "{indent}state \"{}\" as {}",
+                plantuml(&state.name),
+                ids[state]
+            );
+            if let Some(color) = diagram.state_color(state) {
+                let _ = write!(out, " #{}", color.trim_start_matches('#'));
+            }
+            out.push('\n');
+        };
+        for (i, (name, members)) in diagram.clusters.iter().enumerate() {
+            let members = view.members(members);
+            if members.is_empty() {
+                continue;
+            }
+            let _ = writeln!(out, "state \"{}\" as c{i} {{", plantuml(name));
+            for state in members {
+                node(&mut out, "    ", state);
+            }
+            out.push_str("}\n");
+        }
+        for state in view
+            .states
+            .iter()
+            .filter(|s| diagram.cluster_of(s).is_none())
+        {
+            node(&mut out, "", state);
+        }
+        if view.any_state {
+            let _ = writeln!(out, "state \"{ANY_STATE}\" as any ##[dashed]");
+        }
+        if view.initial {
+            let _ = writeln!(out, "[*] --> {}", ids[self.initial_state()]);
+        }
+        for t in &view.transitions {
+            let from = t.from.as_ref().map_or("any", |s| ids[s].as_str());
+            let arrow = match diagram.transition_color(t) {
+                Some(color) => format!("-[#{}]->", color.trim_start_matches('#')),
+                None => "-->".to_string(),
+            };
+            let _ = writeln!(out, "{from} {arrow} {} : {}", ids[&t.to], label(t));
+        }
+        out.push_str("@enduml\n");
+        out
+    }
+}
+
+impl<Err> StateMachine<Err> {
+    /// Render the states and transitions of the machine as a Graphviz DOT graph, see [`Definition::to_dot`]
+    ///
+    /// Edges are labeled with their event and guard, the initial state is pointed at by a start node.
+    pub fn to_dot(&self) -> String {
+        self.definition().to_dot(&Diagram::default())
+    }
+}
+
+impl<Err> StateMachineBuilder<Err> {
+    /// Render the machine being built as a Graphviz DOT graph, see [`StateMachine::to_dot`]
+    pub fn to_dot(&self) -> String {
+        self.definition().to_dot(&Diagram::default())
+    }
+}
+
+/// Render one recorded run as a Mermaid sequence diagram, e.g. for a postmortem
+///
+/// Every state is a participant, in order of appearance, every record an arrow labeled with
+/// its sequence number, event, timestamp and duration.
+/// Failed actions end with a cross, transitions that were not taken are dotted crosses back to the state.
+/// # Arguments
+/// * `history` - the records of the run, oldest first, see [`StateMachine::history`](crate::StateMachine::history)
+pub fn trace_to_mermaid(history: &[TransitionRecord]) -> String {
+    let mut ids: HashMap<&State, String> = HashMap::new();
+    let mut out = String::from("sequenceDiagram\n");
+    for state in history.iter().flat_map(|r| [&r.from, &r.to]) {
+        if !ids.contains_key(state) {
+            let id = format!("s{}", ids.len());
+            let _ = writeln!(out, "    participant {id} as {}", state.name);
+            ids.insert(state, id);
+        }
+    }
+    for record in history {
+        let (arrow, to, note) = match record.outcome {
+            Outcome::Ok => ("->>", &record.to, String::new()),
+            Outcome::ActionFailed(ref e) => ("-x", &record.to, format!(", failed: {e}")),
+            Outcome::ActionPanicked(ref e) => ("-x", &record.to, format!(", panicked: {e}")),
+            Outcome::GuardRejected(ref guard) => {
+                ("--x", &record.from, format!(", rejected by {guard}"))
+            }
+            Outcome::Duplicate(ref id) => ("--x", &record.from, format!(", duplicate {id}")),
+            Outcome::Unauthorized(ref reason) => {
+                ("--x", &record.from, format!(", unauthorized: {reason}"))
+            }
+            Outcome::AwaitingApproval(ref principal) => {
+                ("--x", &record.from, format!(", approved by {principal}"))
+            }
+            Outcome::EscalationSkipped(ref e) => {
+                ("--x", &record.from, format!(", escalation skipped: {e}"))
+            }
+            Outcome::RolledBack(ref e) => ("--x", &record.from, format!(", rolled back: {e}")),
+        };
+        let _ = writeln!(
+            out,
+            "    {}{arrow}{}: {}. {} at {} in {}us{note}",
+            ids[&record.from],
+            ids[to],
+            record.seq,
+            record.event,
+            json::timestamp(record.timestamp),
+            record.duration.as_micros()
+        );
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::StateMachineBuilder;
+    #[cfg(feature = "tracing")]
+    use tracing_test::traced_test;
+
+    fn order() -> (Definition, Diagram) {
+        let open = State::new("open");
+        let paid = State::new("paid");
+        let refunded = State::new("refunded");
+        let pay = Event::new("pay");
+        let refund = Event::new("refund");
+        let definition = StateMachineBuilder::new("order", &open)
+            .add_event(open.clone(), pay.clone(), paid.clone(), None)
+            .add_event(paid.clone(), refund.clone(), refunded.clone(), None)
+            .with_guard("refundable", || true)
+            .add_any_state_event(Event::new("reset"), open.clone(), None)
+            .definition();
+        let diagram = Diagram::new()
+            .tag_state(&refunded, "money")
+            .tag_event(&refund, "money")
+            .color("money", "red")
+            .cluster("payment", &paid)
+            .cluster("payment", &refunded)
+            .rank(&open, 0)
+            .rank(&paid, 0);
+        (definition, diagram)
+    }
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_dot() {
+        let (definition, diagram) = order();
+        assert_eq!(
+            definition.to_dot(&diagram),
+            r#"digraph "order" {
+    "__start" [shape=point];
+    "any state" [style=dashed];
+    subgraph "cluster_0" {
+        label="payment";
+        "paid";
+        "refunded" [style=filled, fillcolor="red"];
+    }
+    "open";
+    { rank=same; "open"; "paid"; }
+    "__start" -> "open";
+    "open" -> "paid" [label="pay"];
+    "paid" -> "refunded" [label="refund [refundable]", color="red"];
+    "any state" -> "open" [label="reset"];
+}
+"#
+        );
+
+        let (idle, busy) = (State::new("idle"), State::new("busy"));
+        let builder = StateMachineBuilder::new("worker", &idle).add_event(
+            idle.clone(),
+            Event::new("work"),
+            busy,
+            None,
+        );
+        let expected = r#"digraph "worker" {
+    "__start" [shape=point];
+    "idle";
+    "busy";
+    "__start" -> "idle";
+    "idle" -> "busy" [label="work"];
+}
+"#;
+        assert_eq!(builder.to_dot(), expected);
+        assert_eq!(builder.build().to_dot(), expected);
+    }
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_mermaid_and_plantuml() {
+        let (definition, diagram) = order();
+        assert_eq!(
+            definition.to_mermaid(&diagram),
+            "stateDiagram-v2\n    \
+             state \"open\" as s0\n    \
+             state \"paid\" as s1\n    \
+             state \"refunded\" as s2\n    \
+             state \"any state\" as any\n    \
+             state \"payment\" as c0\n    \
+             state c0 {\n        s1\n        s2\n    }\n    \
+             [*] --> s0\n    \
+             s0 --> s1: pay\n    \
+             s1 --> s2: refund [refundable]\n    \
+             any --> s0: reset\n    \
+             classDef tag0 fill:red\n    \
+             class s2 tag0\n"
+        );
+        assert_eq!(
+            definition.to_plantuml(&diagram),
+            "@startuml\n\
+             state \"payment\" as c0 {\n    \
+             state \"paid\" as s1\n    \
+             state \"refunded\" as s2 #red\n\
+             }\n\
+             state \"open\" as s0\n\
+             state \"any state\" as any ##[dashed]\n\
+             [*] --> s0\n\
+             s0 --> s1 : pay\n\
+             s1 -[#red]-> s2 : refund [refundable]\n\
+             any --> s0 : reset\n\
+             @enduml\n"
+        );
+    }
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_escaped_names() {
+        let (start, quoted) = (State::new("__start"), State::new("say \"hi\""));
+        let definition = StateMachineBuilder::new("quotes", &start)
+            .add_event(start.clone(), Event::new("greet"), quoted.clone(), None)
+            .definition();
+
+        let dot = definition.to_dot(&Diagram::default());
+        assert!(dot.contains("    \"__start_\" -> \"__start\";\n"), "{dot}");
+        let parsed = Definition::from_dot(&dot).unwrap();
+        assert_eq!(parsed.initial_state(), &start);
+        assert_eq!(parsed.states(), definition.states());
+        assert!(definition
+            .to_mermaid(&Diagram::default())
+            .contains("state \"say #quot;hi#quot;\" as s1\n"));
+        assert!(definition
+            .to_plantuml(&Diagram::default())
+            .contains("state \"say <U+0022>hi<U+0022>\" as s1\n"));
+    }
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_filtered_export() {
+        let states: Vec<State> = (0..6).map(|i| State::new(format!("s{i}"))).collect();
+        let next = Event::new("next");
+        let mut builder = StateMachineBuilder::new("chain", &states[0]);
+        for pair in states.windows(2) {
+            builder = builder.add_event(pair[0].clone(), next.clone(), pair[1].clone(), None);
+        }
+        let definition = builder.definition();
+
+        let around = Diagram::new().around(&states[3], 1);
+        assert_eq!(
+            definition.to_dot(&around),
+            "digraph \"chain\" {\n    \
+             \"s2\";\n    \
+             \"s3\";\n    \
+             \"s4\";\n    \
+             \"s2\" -> \"s3\" [label=\"next\"];\n    \
+             \"s3\" -> \"s4\" [label=\"next\"];\n\
+             }\n"
+        );
+
+        let tagged = Diagram::new()
+            .tag_state(&states[0], "edge")
+            .tag_state(&states[1], "edge")
+            .tag_state(&states[5], "end")
+            .only_tagged("edge")
+            .only_tagged("end");
+        assert_eq!(
+            definition.to_mermaid(&tagged),
+            "stateDiagram-v2\n    \
+             state \"s0\" as s0\n    \
+             state \"s1\" as s1\n    \
+             state \"s5\" as s5\n    \
+             [*] --> s0\n    \
+             s0 --> s1: next\n"
+        );
+    }
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_trace_to_mermaid() {
+        let (open, paid) = (State::new("open"), State::new("paid"));
+        let machine = StateMachineBuilder::new("order", &open)
+            .add_event(open.clone(), Event::new("pay"), paid.clone(), None)
+            .add_event(paid.clone(), Event::new("refund"), open, None)
+            .with_guard("refundable", || false)
+            .add_event(paid, Event::new("ship"), State::new("shipped"), None)
+            .record_guard_rejections()
+            .with_clock(std::sync::Arc::new(crate::ManualClock::default()))
+            .build();
+        for event in ["pay", "refund", "ship"] {
+            let _ = machine.event(&Event::new(event));
+        }
+        assert_eq!(
+            trace_to_mermaid(&machine.history()),
+            "sequenceDiagram\n    \
+             participant s0 as open\n    \
+             participant s1 as paid\n    \
+             participant s2 as shipped\n    \
+             s0->>s1: 1. pay at 1970-01-01T00:00:00.000Z in 0us\n    \
+             s1--xs1: 2. refund at 1970-01-01T00:00:00.000Z in 0us, rejected by refundable\n    \
+             s1->>s2: 3. ship at 1970-01-01T00:00:00.000Z in 0us\n"
+        );
+    }
+}
@@ -0,0 +1,311 @@
+//! Routing to a machine-wide error state when actions keep failing
+
+use crate::approval::Approvals;
+use crate::stats::Dwell;
+use crate::trace::error;
+use crate::{Cause, Event, Outcome, State, StateMachine, StateMachineBuilder, TransitionInfo};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// The event of the transitions to the error state, see [`StateMachineBuilder::error_state`]
+pub const FAILED: Event = Event::from_static("failed");
+
+/// What happens to the state when an action returns an error, see [`StateMachineBuilder::on_action_error`]
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum FailurePolicy {
+    /// stay in the new state, the default
+    #[default]
+    CommitOnError,
+    /// go back to the state before the transition
+    RollbackOnError,
+    /// move to this state with the [`FAILED`] event, like [`StateMachineBuilder::error_state`] after one failure
+    GoToErrorState(State),
+}
+
+/// The error state of a machine and the failures counted towards it
+pub(crate) struct ErrorState {
+    state: State,
+    after: u32,
+    failures: AtomicU32,
+}
+
+impl<Err> StateMachine<Err> {
+    /// Number of actions that failed in a row since the last successful action or the last move to the error state
+    pub fn consecutive_failures(&self) -> u32 {
+        self.error_state
+            .as_ref()
+            .map_or(0, |e| e.failures.load(Ordering::Relaxed))
+    }
+
+    pub(crate) fn action_succeeded(&self) {
+        if let Some(ref error_state) = self.error_state {
+            error_state.failures.store(0, Ordering::Relaxed);
+        }
+    }
+
+    /// Set the number of actions that failed in a row, e.g. to take back the success counted for an async action before it ran
+    #[cfg(feature = "unstable")]
+    pub(crate) fn set_consecutive_failures(&self, failures: u32) {
+        if let Some(ref error_state) = self.error_state {
+            error_state.failures.store(failures, Ordering::Relaxed);
+        }
+    }
+
+    /// Count a failed action, moving to the error state when the limit is reached
+    pub(crate) fn action_failed(&self, state: &mut State, error: &str) {
+        let Some(ref error_state) = self.error_state else {
+            return;
+        };
+        if error_state.failures.fetch_add(1, Ordering::Relaxed) + 1 >= error_state.after {
+            self.enter_error_state(state, error);
+        }
+    }
+
+    /// Move to the error state after a panicking action
+    /// # Returns
+    /// `false` if there is no error state, the panic must then be resumed
+    pub(crate) fn action_panicked(&self, state: &mut State, message: &str) -> bool {
+        if self.error_state.is_none() {
+            return false;
+        }
+        self.enter_error_state(state, &format!("action panicked: {message}"));
+        true
+    }
+
+    /// Undo a transition whose action failed
+    ///
+    /// The dwell times and the approvals are restored as they were before the transition,
+    /// the visit of the old state goes on.
+    pub(crate) fn roll_back(
+        &self,
+        state: &mut State,
+        old_state: &State,
+        dwell: Option<Dwell>,
+        approvals: Approvals,
+    ) {
+        error!(
+            "{}: action failed, rolling back {state} -> {old_state}",
+            self.log_name()
+        );
+        if let Some(dwell) = dwell {
+            *self.dwell.lock().expect("failed to get lock") = dwell;
+        }
+        *self.approvals.lock().expect("failed to get lock") = approvals;
+        *state = old_state.clone();
+    }
+
+    /// Move to the error state, recording the original error with the failed transition as cause
+    fn enter_error_state(&self, state: &mut State, error: &str) {
+        let Some(ref error_state) = self.error_state else {
+            return;
+        };
+        error_state.failures.store(0, Ordering::Relaxed);
+        if *state == error_state.state {
+            return;
+        }
+        error!(
+            "{}: {state} -> {} after failure: {error}",
+            self.log_name(),
+            error_state.state
+        );
+        let timestamp = self.clock.now();
+        let cause = Cause {
+            machine: self.name.clone(),
+            seq: self.seq.load(Ordering::Relaxed),
+        };
+        self.dwell
+            .lock()
+            .expect("failed to get lock")
+            .enter(&error_state.state, timestamp);
+        let info = TransitionInfo {
+            machine: &self.name,
+            from: state,
+            event: &FAILED,
+            to: &error_state.state,
+            cause: Some(&cause),
+        };
+        self.record(
+            &info,
+            None,
+            timestamp,
+            Outcome::ActionFailed(error.to_string()),
+        );
+        self.clear_approvals();
+        *state = error_state.state.clone();
+    }
+}
+
+impl<Err> StateMachineBuilder<Err> {
+    #[must_use]
+    /// Move to `state` with the [`FAILED`] event when `after_failures` actions failed in a row or an action panicked
+    ///
+    /// The move is recorded with the original error, caused by the transition whose action failed.
+    /// A panicking action then makes the event fail instead of resuming the panic.
+    pub fn error_state(mut self, state: &State, after_failures: u32) -> Self {
+        self.error_state = Some(ErrorState {
+            state: state.clone(),
+            after: after_failures.max(1),
+            failures: AtomicU32::new(0),
+        });
+        self
+    }
+
+    #[must_use]
+    /// Choose what happens to the state when an action returns an error
+    ///
+    /// A rolled back transition is recorded with [`Outcome::RolledBack`], and is not taken:
+    /// observers get [`Observer::on_rolled_back`](crate::Observer::on_rolled_back),
+    /// the dwell times and the approvals of the old state are kept.
+    /// Failures still count towards the error state, see [`StateMachineBuilder::error_state`].
+    /// A panicking action is not rolled back.
+    pub fn on_action_error(mut self, policy: FailurePolicy) -> Self {
+        self.rollback_on_error = policy == FailurePolicy::RollbackOnError;
+        if let FailurePolicy::GoToErrorState(ref state) = policy {
+            self = self.error_state(state, 1);
+        }
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Envelope, ManualClock, Monitor};
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use std::sync::Arc;
+    use std::time::Duration;
+    #[cfg(feature = "tracing")]
+    use tracing_test::traced_test;
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_error_state() {
+        let (idle, sending, failed) = (
+            State::new("idle"),
+            State::new("sending"),
+            State::new("failed"),
+        );
+        let send = Event::new("send");
+        let ok = Rc::new(Cell::new(false));
+        let ok_clone = ok.clone();
+        let machine = StateMachineBuilder::new("mailer", &idle)
+            .add_event(
+                idle.clone(),
+                send.clone(),
+                sending.clone(),
+                Some(Box::new(move || {
+                    if ok_clone.get() {
+                        Ok(())
+                    } else {
+                        Err(crate::error::message("smtp down".to_string()))
+                    }
+                })),
+            )
+            .add_any_state_event(Event::new("retry"), idle.clone(), None)
+            .add_event(
+                idle.clone(),
+                Event::new("crash"),
+                sending.clone(),
+                Some(Box::new(|| panic!("bug"))),
+            )
+            .error_state(&failed, 2)
+            .with_history(10)
+            .build();
+        let retry = || machine.event(&Event::new("retry")).unwrap();
+
+        assert!(machine.event(&send).is_err());
+        assert_eq!(machine.consecutive_failures(), 1);
+        retry();
+        ok.set(true);
+        machine.event(&send).unwrap();
+        assert_eq!(machine.consecutive_failures(), 0);
+        ok.set(false);
+        retry();
+        assert!(machine.event(&send).is_err());
+        retry();
+        assert!(machine.event(&send).is_err());
+        assert_eq!(machine.current_state(), failed);
+        let routed = machine.last_transition().unwrap();
+        assert_eq!(routed.event, FAILED);
+        assert_eq!(routed.from, sending);
+        assert_eq!(
+            routed.outcome,
+            Outcome::ActionFailed("smtp down".to_string())
+        );
+        assert_eq!(routed.cause.map(|c| c.seq), Some(routed.seq - 1));
+
+        retry();
+        let panicked = machine.event(&Event::new("crash")).unwrap_err();
+        assert_eq!(panicked.to_string(), "action panicked: bug");
+        assert_eq!(machine.current_state(), failed);
+    }
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_failure_policy() {
+        let (idle, charging, declined) = (
+            State::new("idle"),
+            State::new("charging"),
+            State::new("declined"),
+        );
+        let charge = Event::new("charge");
+        let builder = || {
+            StateMachineBuilder::new("payment", &idle).add_event(
+                idle.clone(),
+                charge.clone(),
+                charging.clone(),
+                Some(Box::new(|| {
+                    Err(crate::error::message("card declined".to_string()))
+                })),
+            )
+        };
+
+        let committing = builder().build();
+        assert!(committing.event(&charge).is_err());
+        assert_eq!(committing.current_state(), charging);
+
+        // charging is never reached, a monitor flags it
+        let (waiting, violation) = (State::new("waiting"), State::new("violation"));
+        let monitor = Monitor::new(
+            StateMachineBuilder::new("never charged", &waiting)
+                .add_event(waiting.clone(), charge.clone(), violation.clone(), None)
+                .build(),
+            violation,
+        );
+        let refund = Event::new("refund");
+        let clock = Arc::new(ManualClock::default());
+        let rolling_back = builder()
+            .add_event(idle.clone(), refund.clone(), idle.clone(), None)
+            .with_approvals(2)
+            .on_action_error(FailurePolicy::RollbackOnError)
+            .add_observer(Box::new(monitor.clone()))
+            .with_clock(clock.clone())
+            .with_history(10)
+            .build();
+        rolling_back
+            .deliver(&Envelope::new("", refund.clone()).with_principal("alice"))
+            .unwrap();
+        clock.advance(Duration::from_secs(5));
+        assert!(rolling_back.event(&charge).is_err());
+        assert_eq!(rolling_back.current_state(), idle);
+        let record = rolling_back.last_transition().unwrap();
+        assert_eq!(
+            record.outcome,
+            Outcome::RolledBack("card declined".to_string())
+        );
+        assert!(!record.outcome.is_taken());
+        assert!(!monitor.is_violated());
+        let dwell = rolling_back.dwell_stats();
+        assert_eq!(dwell[&idle].visits, 1);
+        assert_eq!(dwell[&idle].current, Some(Duration::from_secs(5)));
+        assert!(!dwell.contains_key(&charging));
+        assert_eq!(rolling_back.approvals(&refund), vec!["alice".to_string()]);
+
+        let routing = builder()
+            .on_action_error(FailurePolicy::GoToErrorState(declined.clone()))
+            .build();
+        assert!(routing.event(&charge).is_err());
+        assert_eq!(routing.current_state(), declined);
+        assert_eq!(routing.last_transition().unwrap().event, FAILED);
+    }
+}
@@ -0,0 +1,292 @@
+//! Export of a builder to a definition file, for data-driven and visualization tools
+
+use crate::json;
+use crate::StateMachineBuilder;
+use std::fmt::Write;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+/// The syntax of a definition file, see [`StateMachineBuilder::export_definition`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Yaml,
+    Json,
+}
+
+/// A node of a definition file
+enum Value {
+    Null,
+    Number(String),
+    Text(String),
+    List(Vec<Value>),
+    Map(Vec<(&'static str, Value)>),
+}
+
+impl Value {
+    fn text(text: &impl ToString) -> Self {
+        Value::Text(text.to_string())
+    }
+
+    fn seconds(duration: Duration) -> Self {
+        Value::Number(duration.as_secs_f64().to_string())
+    }
+
+    fn is_scalar(&self) -> bool {
+        !matches!(self, Value::List(_) | Value::Map(_))
+    }
+
+    fn to_json(&self, out: &mut String) {
+        match self {
+            Value::Null => out.push_str("null"),
+            Value::Number(n) => out.push_str(n),
+            Value::Text(s) => out.push_str(&json::string(s)),
+            Value::List(items) => {
+                out.push('[');
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        out.push_str(", ");
+                    }
+                    item.to_json(out);
+                }
+                out.push(']');
+            }
+            Value::Map(entries) => {
+                out.push('{');
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        out.push_str(", ");
+                    }
+                    let _ = write!(out, "{}: ", json::string(key));
+                    value.to_json(out);
+                }
+                out.push('}');
+            }
+        }
+    }
+
+    /// Write the entries of a map, one per line, lists of scalars are written inline
+    fn to_yaml(entries: &[(&'static str, Value)], indent: usize, out: &mut String) {
+        let pad = " ".repeat(indent);
+        for (key, value) in entries {
+            match value {
+                Value::List(items) if !items.is_empty() && !items.iter().all(Value::is_scalar) => {
+                    let _ = writeln!(out, "{pad}{key}:");
+                    for item in items {
+                        if let Value::Map(entries) = item {
+                            let mut lines = String::new();
+                            Value::to_yaml(entries, indent + 4, &mut lines);
+                            let _ = write!(out, "{pad}  - {}", &lines[indent + 4..]);
+                        } else {
+                            let mut inline = String::new();
+                            item.to_json(&mut inline);
+                            let _ = writeln!(out, "{pad}  - {inline}");
+                        }
+                    }
+                }
+                Value::Map(entries) if !entries.is_empty() => {
+                    let _ = writeln!(out, "{pad}{key}:");
+                    Value::to_yaml(entries, indent + 2, out);
+                }
+                scalar => {
+                    let mut inline = String::new();
+                    scalar.to_json(&mut inline);
+                    let _ = writeln!(out, "{pad}{key}: {inline}");
+                }
+            }
+        }
+    }
+}
+
+impl<Err> StateMachineBuilder<Err> {
+    /// Describe the declared machine: its states, transitions, entry points, timeouts and constraints, without closures
+    fn describe(&self) -> Vec<(&'static str, Value)> {
+        let definition = self.definition();
+        let transitions = definition
+            .transitions()
+            .iter()
+            .map(|t| {
+                let mut entries = vec![
+                    ("from", t.from.as_ref().map_or(Value::Null, Value::text)),
+                    ("event", Value::text(&t.event)),
+                    ("to", Value::text(&t.to)),
+                ];
+                if let Some(ref guard) = t.guard {
+                    entries.push(("guard", Value::text(guard)));
+                }
+                if let Some(priority) = t.priority {
+                    entries.push(("priority", Value::Number(priority.to_string())));
+                }
+                Value::Map(entries)
+            })
+            .collect();
+        let entry_points = self
+            .entry_points
+            .iter()
+            .map(|(name, state)| {
+                Value::Map(vec![
+                    ("name", Value::text(name)),
+                    ("state", Value::text(state)),
+                ])
+            })
+            .collect();
+        let mut max_dwell: Vec<_> = self.max_dwell.iter().collect();
+        max_dwell.sort_by_key(|(state, _)| state.to_string());
+        let mut time_boxes: Vec<_> = self.time_boxes.iter().collect();
+        time_boxes.sort_by_key(|(state, _)| state.to_string());
+        vec![
+            ("name", Value::text(&self.name)),
+            ("initial_state", Value::text(&self.initial_state)),
+            (
+                "states",
+                Value::List(definition.states().iter().map(Value::text).collect()),
+            ),
+            ("transitions", Value::List(transitions)),
+            ("entry_points", Value::List(entry_points)),
+            (
+                "timeouts",
+                Value::Map(vec![
+                    (
+                        "stuck_after",
+                        self.stuck_after.map_or(Value::Null, Value::seconds),
+                    ),
+                    (
+                        "max_dwell",
+                        Value::List(
+                            max_dwell
+                                .into_iter()
+                                .map(|(state, limit)| {
+                                    Value::Map(vec![
+                                        ("state", Value::text(state)),
+                                        ("limit", Value::seconds(*limit)),
+                                    ])
+                                })
+                                .collect(),
+                        ),
+                    ),
+                    (
+                        "time_boxes",
+                        Value::List(
+                            time_boxes
+                                .into_iter()
+                                .map(|(state, time_box)| {
+                                    Value::Map(vec![
+                                        ("state", Value::text(state)),
+                                        ("limit", Value::seconds(time_box.limit)),
+                                        (
+                                            "escalations",
+                                            Value::List(
+                                                time_box.chain.iter().map(Value::text).collect(),
+                                            ),
+                                        ),
+                                    ])
+                                })
+                                .collect(),
+                        ),
+                    ),
+                ]),
+            ),
+            (
+                "must_follow",
+                Value::List(
+                    self.ordering
+                        .iter()
+                        .map(|constraint| {
+                            Value::Map(vec![
+                                ("event", Value::text(&constraint.event)),
+                                ("after", Value::text(&constraint.after)),
+                            ])
+                        })
+                        .collect(),
+                ),
+            ),
+        ]
+    }
+
+    /// Render the declared machine as a definition file, see [`StateMachineBuilder::export_definition`]
+    pub fn definition_file(&self, format: Format) -> String {
+        let entries = self.describe();
+        let mut out = String::new();
+        match format {
+            Format::Yaml => Value::to_yaml(&entries, 0, &mut out),
+            Format::Json => {
+                Value::Map(entries).to_json(&mut out);
+                out.push('\n');
+            }
+        }
+        out
+    }
+
+    /// Write the declared machine to a definition file, e.g. to seed data-driven or visualization tools
+    ///
+    /// The file has the name, the initial state, the states and transitions of the machine,
+    /// its entry points, timeouts (in seconds) and ordering constraints.
+    /// Actions and guards cannot be exported, guards are named.
+    /// # Errors
+    /// If writing the file fails
+    pub fn export_definition(&self, path: impl AsRef<Path>, format: Format) -> io::Result<()> {
+        std::fs::write(path, self.definition_file(format))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Event, State};
+    #[cfg(feature = "tracing")]
+    use tracing_test::traced_test;
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_export_definition() {
+        let (open, closed) = (State::new("open"), State::new("closed"));
+        let close = Event::new("close");
+        let builder = StateMachineBuilder::new("door", &open)
+            .add_event(open.clone(), close.clone(), closed.clone(), None)
+            .with_guard("unlocked", || true)
+            .add_any_state_event(Event::new("reset"), open.clone(), None)
+            .entry_point("shut", closed.clone())
+            .time_box(
+                &open,
+                Duration::from_secs(30),
+                vec![close.clone(), Event::new("alarm")],
+            );
+
+        assert_eq!(
+            builder.definition_file(Format::Yaml),
+            "name: \"door\"\n\
+             initial_state: \"open\"\n\
+             states: [\"open\", \"closed\"]\n\
+             transitions:\n  \
+               - from: \"open\"\n    \
+                 event: \"close\"\n    \
+                 to: \"closed\"\n    \
+                 guard: \"unlocked\"\n  \
+               - from: null\n    \
+                 event: \"reset\"\n    \
+                 to: \"open\"\n\
+             entry_points:\n  \
+               - name: \"shut\"\n    \
+                 state: \"closed\"\n\
+             timeouts:\n  \
+               stuck_after: null\n  \
+               max_dwell: []\n  \
+               time_boxes:\n    \
+                 - state: \"open\"\n      \
+                   limit: 30\n      \
+                   escalations: [\"close\", \"alarm\"]\n\
+             must_follow: []\n"
+        );
+        let json = builder.definition_file(Format::Json);
+        assert!(json.starts_with("{\"name\": \"door\", \"initial_state\": \"open\""));
+        assert!(json.contains("\"escalations\": [\"close\", \"alarm\"]"));
+
+        let path = std::env::temp_dir().join(format!("door-{}.yaml", std::process::id()));
+        builder.export_definition(&path, Format::Yaml).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            builder.definition_file(Format::Yaml)
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,50 @@
+use crate::{Event, State};
+
+/// Where a forwarding rule sends its event
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Target {
+    /// the machine with this name
+    Machine(String),
+    /// every machine with this tag, see [`EventBus::tag`](crate::EventBus::tag)
+    Tagged(String),
+}
+
+/// A declarative reaction of the [`EventBus`](crate::EventBus):
+/// when a machine enters a state, send an event to other machines
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Forward {
+    pub(crate) machine: String,
+    pub(crate) state: State,
+    pub(crate) event: Event,
+    pub(crate) targets: Vec<Target>,
+}
+
+impl Forward {
+    /// Create a rule without targets
+    /// # Arguments
+    /// * `machine` - the name of the watched machine
+    /// * `state` - the state that triggers the rule when the machine enters it
+    /// * `event` - the event to send
+    pub fn new(machine: impl Into<String>, state: State, event: Event) -> Self {
+        Self {
+            machine: machine.into(),
+            state,
+            event,
+            targets: Vec::new(),
+        }
+    }
+
+    #[must_use]
+    /// Send the event to the machine with this name
+    pub fn to(mut self, machine: impl Into<String>) -> Self {
+        self.targets.push(Target::Machine(machine.into()));
+        self
+    }
+
+    #[must_use]
+    /// Send the event to every machine with this tag, except the watched machine itself
+    pub fn to_tagged(mut self, tag: impl Into<String>) -> Self {
+        self.targets.push(Target::Tagged(tag.into()));
+        self
+    }
+}
@@ -0,0 +1,110 @@
+use crate::{
+    Delivery, DispatchHandle, Envelope, Error, Event, Snapshot, State, StateMachine, ViewHandle,
+};
+use std::fmt;
+use std::rc::Rc;
+
+/// A machine whose state only changes through its events, see [`StateMachine::freeze`]
+///
+/// A frozen machine cannot be reset, restored or started in another state,
+/// there is no way back to the machine from it.
+/// Clones share the same machine.
+pub struct Frozen<Err = Error> {
+    machine: Rc<StateMachine<Err>>,
+}
+
+impl<Err> Clone for Frozen<Err> {
+    fn clone(&self) -> Self {
+        Self {
+            machine: self.machine.clone(),
+        }
+    }
+}
+
+impl<Err> Frozen<Err>
+where
+    Err: From<Error> + fmt::Display + 'static,
+{
+    /// See [`StateMachine::event`]
+    /// # Errors
+    /// As [`StateMachine::event`]
+    pub fn event(&self, event: &Event) -> Result<(), Err> {
+        self.machine.event(event)
+    }
+
+    /// See [`StateMachine::deliver`]
+    /// # Errors
+    /// As [`StateMachine::deliver`]
+    pub fn deliver(&self, envelope: &Envelope) -> Result<Delivery, Err> {
+        self.machine.deliver(envelope)
+    }
+
+    /// See [`StateMachine::fire_due`]
+    /// # Errors
+    /// As [`StateMachine::fire_due`]
+    pub fn fire_due(&self) -> Result<usize, Err> {
+        self.machine.fire_due()
+    }
+}
+
+impl<Err> Frozen<Err> {
+    /// See [`StateMachine::name`]
+    pub fn name(&self) -> &str {
+        self.machine.name()
+    }
+
+    /// See [`StateMachine::current_state`]
+    pub fn current_state(&self) -> State {
+        self.machine.current_state()
+    }
+
+    /// See [`StateMachine::snapshot`]
+    pub fn snapshot(&self) -> Snapshot {
+        self.machine.snapshot()
+    }
+
+    /// Get a read-only view of the machine
+    pub fn view(&self) -> ViewHandle<Err> {
+        self.machine.view()
+    }
+
+    /// Get a handle that can only send events to the machine, there is no admin handle of a frozen machine
+    pub fn dispatcher(&self) -> DispatchHandle<Err> {
+        self.machine.dispatcher()
+    }
+}
+
+impl<Err> StateMachine<Err> {
+    /// Freeze the machine for production: it then only changes state through its events,
+    /// plugins given the frozen machine cannot reset, restore or move it to another state
+    pub fn freeze(self) -> Frozen<Err> {
+        Frozen {
+            machine: Rc::new(self),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::StateMachineBuilder;
+    #[cfg(feature = "tracing")]
+    use tracing_test::traced_test;
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_frozen_machine() {
+        let (off, on) = (State::new("off"), State::new("on"));
+        let toggle = Event::new("toggle");
+        let frozen = StateMachineBuilder::new("switch", &off)
+            .add_event(off.clone(), toggle.clone(), on.clone(), None)
+            .build()
+            .freeze();
+        let plugin = frozen.clone();
+        plugin.event(&toggle).unwrap();
+        assert_eq!(frozen.current_state(), on);
+        assert_eq!(frozen.view().current_state(), on);
+        assert!(frozen.event(&toggle).is_err());
+        assert_eq!(frozen.snapshot().state, on);
+    }
+}
@@ -0,0 +1,91 @@
+//! Feature flags deciding at runtime whether a transition is enabled
+
+use crate::table::Guard;
+use crate::StateMachineBuilder;
+use std::rc::Rc;
+
+/// Tells whether a feature flag is on, e.g. backed by a flags service
+pub trait FeatureGate {
+    fn is_enabled(&self, flag: &str) -> bool;
+}
+
+impl<F> FeatureGate for F
+where
+    F: Fn(&str) -> bool,
+{
+    fn is_enabled(&self, flag: &str) -> bool {
+        self(flag)
+    }
+}
+
+impl<Err> StateMachineBuilder<Err> {
+    #[must_use]
+    /// Only fire the last added transition while the feature flag `flag` is on
+    ///
+    /// The flag is checked like a guard named `feature <flag>`, so it shows up in
+    /// [`StateMachine::explain`](crate::StateMachine::explain) and in the recorded guard rejections.
+    /// A guard already set on the transition must pass as well.
+    /// # Arguments
+    /// * `gate` - the flags provider, usually shared by all gated transitions
+    /// * `flag` - the name of the flag
+    /// # Panics
+    /// If no transition was added yet
+    pub fn with_feature_gate(
+        mut self,
+        gate: &Rc<dyn FeatureGate>,
+        flag: impl Into<String>,
+    ) -> Self {
+        let flag = flag.into();
+        let transition = self.last_transition("with_feature_gate");
+        let gate = gate.clone();
+        transition.guard = Some(match transition.guard.take() {
+            Some(guard) => Guard {
+                name: format!("feature {flag}, {}", guard.name),
+                check: Box::new(move || gate.is_enabled(&flag) && (guard.check)()),
+            },
+            None => Guard {
+                name: format!("feature {flag}"),
+                check: Box::new(move || gate.is_enabled(&flag)),
+            },
+        });
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Event, Outcome, State};
+    use std::cell::Cell;
+    #[cfg(feature = "tracing")]
+    use tracing_test::traced_test;
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_feature_gate() {
+        let (cart, review, paid) = (State::new("cart"), State::new("review"), State::new("paid"));
+        let pay = Event::new("pay");
+        let rollout = Rc::new(Cell::new(false));
+        let flags = rollout.clone();
+        let gate: Rc<dyn FeatureGate> =
+            Rc::new(move |flag: &str| flag == "fraud_review" && flags.get());
+        let machine = StateMachineBuilder::new("checkout", &cart)
+            .add_event(cart.clone(), pay.clone(), review.clone(), None)
+            .with_feature_gate(&gate, "fraud_review")
+            .add_event(cart.clone(), pay.clone(), paid.clone(), None)
+            .record_guard_rejections()
+            .build();
+
+        machine.event(&pay).unwrap();
+        assert_eq!(machine.current_state(), paid);
+        assert_eq!(
+            machine.history()[0].outcome,
+            Outcome::GuardRejected("feature fraud_review".to_string())
+        );
+        machine.reset();
+        rollout.set(true);
+        assert!(machine.explain(&pay).rejected_guards().is_empty());
+        machine.event(&pay).unwrap();
+        assert_eq!(machine.current_state(), review);
+    }
+}
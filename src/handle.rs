@@ -0,0 +1,154 @@
+//! Capability handles: subsystems get a handle with exactly the power they need
+//!
+//! Next to the read-only [`ViewHandle`](crate::ViewHandle), a [`DispatchHandle`] can only send events
+//! and an [`AdminHandle`] can move the machine to any state.
+
+use crate::{Delivery, Envelope, Error, Event, Result, Snapshot, StateMachine};
+use std::fmt;
+use std::rc::Rc;
+use std::time::SystemTime;
+
+/// Sends events to a shared machine, without access to its state or history
+/// Clones share the same machine.
+pub struct DispatchHandle<Err = Error> {
+    machine: Rc<StateMachine<Err>>,
+}
+
+impl<Err> Clone for DispatchHandle<Err> {
+    fn clone(&self) -> Self {
+        Self {
+            machine: self.machine.clone(),
+        }
+    }
+}
+
+impl<Err> DispatchHandle<Err>
+where
+    Err: From<Error> + fmt::Display + 'static,
+{
+    /// See [`StateMachine::event`]
+    /// # Errors
+    /// As [`StateMachine::event`]
+    pub fn event(&self, event: &Event) -> Result<(), Err> {
+        self.machine.event(event)
+    }
+
+    /// See [`StateMachine::deliver`]
+    /// # Errors
+    /// As [`StateMachine::deliver`]
+    pub fn deliver(&self, envelope: &Envelope) -> Result<Delivery, Err> {
+        self.machine.deliver(envelope)
+    }
+
+    /// See [`StateMachine::fire_due`]
+    /// # Errors
+    /// As [`StateMachine::fire_due`]
+    pub fn fire_due(&self) -> Result<usize, Err> {
+        self.machine.fire_due()
+    }
+}
+
+impl<Err> DispatchHandle<Err> {
+    /// See [`StateMachine::schedule_at`]
+    pub fn schedule_at(&self, at: SystemTime, event: Event) {
+        self.machine.schedule_at(at, event);
+    }
+}
+
+/// Moves a shared machine to any state, e.g. for operators repairing a stranded instance
+/// Clones share the same machine.
+pub struct AdminHandle<Err = Error> {
+    machine: Rc<StateMachine<Err>>,
+}
+
+impl<Err> Clone for AdminHandle<Err> {
+    fn clone(&self) -> Self {
+        Self {
+            machine: self.machine.clone(),
+        }
+    }
+}
+
+impl<Err> AdminHandle<Err>
+where
+    Err: From<Error> + fmt::Display + 'static,
+{
+    /// See [`StateMachine::start_as`]
+    /// # Errors
+    /// As [`StateMachine::start_as`]
+    pub fn start_as(&self, name: &str) -> Result<(), Err> {
+        self.machine.start_as(name)
+    }
+}
+
+impl<Err> AdminHandle<Err> {
+    /// See [`StateMachine::reset`]
+    pub fn reset(&self) {
+        self.machine.reset();
+    }
+
+    /// See [`StateMachine::restore`]
+    /// # Errors
+    /// As [`StateMachine::restore`]
+    pub fn restore(&self, snapshot: &Snapshot) -> Result<()> {
+        self.machine.restore(snapshot)
+    }
+
+    /// See [`StateMachine::cancel_deadlines`]
+    pub fn cancel_deadlines(&self, event: &Event) -> usize {
+        self.machine.cancel_deadlines(event)
+    }
+
+    /// See [`StateMachine::reset_stats`]
+    pub fn reset_stats(&self) {
+        self.machine.reset_stats();
+    }
+}
+
+impl<Err> StateMachine<Err> {
+    /// Get a handle that can only send events to a shared machine
+    pub fn dispatcher(self: &Rc<Self>) -> DispatchHandle<Err> {
+        DispatchHandle {
+            machine: self.clone(),
+        }
+    }
+
+    /// Get a handle that can reset, restore and start a shared machine in another state
+    pub fn admin(self: &Rc<Self>) -> AdminHandle<Err> {
+        AdminHandle {
+            machine: self.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{State, StateMachineBuilder};
+    #[cfg(feature = "tracing")]
+    use tracing_test::traced_test;
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_capability_handles() {
+        let (off, on) = (State::new("off"), State::new("on"));
+        let toggle = Event::new("toggle");
+        let machine = Rc::new(
+            StateMachineBuilder::new("switch", &off)
+                .add_event(off.clone(), toggle.clone(), on.clone(), None)
+                .build(),
+        );
+        let (dispatcher, view, admin) = (machine.dispatcher(), machine.view(), machine.admin());
+        dispatcher.event(&toggle).unwrap();
+        assert_eq!(view.current_state(), on);
+        admin.reset();
+        assert_eq!(view.current_state(), off);
+
+        let frozen = StateMachineBuilder::new("switch", &off)
+            .add_event(off, toggle.clone(), on.clone(), None)
+            .build()
+            .freeze();
+        frozen.dispatcher().event(&toggle).unwrap();
+        assert_eq!(frozen.current_state(), on);
+    }
+}
@@ -0,0 +1,228 @@
+use crate::trace::error;
+use crate::{State, StateMachine, StateMachineBuilder};
+use std::time::{Duration, SystemTime};
+
+/// The status of a machine, e.g. for the health endpoint of a service
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Health {
+    /// the name of the machine
+    pub machine: String,
+    /// the current state
+    pub state: State,
+    /// the time spent in the current state
+    pub time_in_state: Duration,
+    /// the number of events waiting for the machine, see [`EventBus::health`](crate::EventBus::health)
+    pub queue_depth: usize,
+    /// the last error of an event: no transition found or a failed action
+    pub last_error: Option<String>,
+    /// when the last transition was taken, `None` if none was taken yet
+    pub last_transition: Option<SystemTime>,
+    /// whether the machine stayed in its state for longer than allowed,
+    /// see [`StateMachineBuilder::stuck_after`] and [`StateMachineBuilder::max_dwell`]
+    pub stuck: bool,
+}
+
+/// A machine that stayed in a state for longer than its maximum dwell time
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stuck {
+    /// the name of the machine
+    pub machine: String,
+    /// the state the machine is stuck in
+    pub state: State,
+    /// the time spent in the state
+    pub time_in_state: Duration,
+    /// the maximum dwell time of the state
+    pub limit: Duration,
+}
+
+impl Health {
+    /// Check if the machine is not stuck
+    pub fn is_healthy(&self) -> bool {
+        !self.stuck
+    }
+}
+
+impl<Err> StateMachine<Err> {
+    /// Get the status of the machine
+    /// # Panics
+    /// If the lock is poisoned
+    pub fn health(&self) -> Health {
+        let state = self.current_state();
+        let time_in_state = self.time_in_state();
+        let stuck = self
+            .dwell_limit(&state)
+            .is_some_and(|limit| time_in_state > limit);
+        Health {
+            machine: self.name.clone(),
+            state,
+            time_in_state,
+            queue_depth: 0,
+            last_error: self.last_error.lock().expect("failed to get lock").clone(),
+            last_transition: self.last_transition().map(|record| record.timestamp),
+            stuck,
+        }
+    }
+
+    /// Check if the machine stayed in its current state for longer than the maximum dwell time
+    ///
+    /// The observers are notified with [`Observer::on_stuck`](crate::Observer::on_stuck)
+    /// the first time a visit of a state is found stuck.
+    /// # Returns
+    /// `None` if the machine is not stuck
+    /// # Panics
+    /// If the lock is poisoned
+    pub fn check_stuck(&self) -> Option<Stuck> {
+        let state = self.state.read().expect("failed to get lock");
+        let limit = self.dwell_limit(&state)?;
+        let mut dwell = self.dwell.lock().expect("failed to get lock");
+        let time_in_state = dwell.current(self.clock.now());
+        if time_in_state <= limit {
+            return None;
+        }
+        let stuck = Stuck {
+            machine: self.name.clone(),
+            state: state.clone(),
+            time_in_state,
+            limit,
+        };
+        if dwell.alert() {
+            error!(
+                "{}: stuck in {} for {:?}, expected at most {:?}",
+                self.log_name(),
+                stuck.state,
+                time_in_state,
+                limit
+            );
+            for observer in &self.observers {
+                observer.on_stuck(&stuck);
+            }
+        }
+        Some(stuck)
+    }
+
+    /// The maximum dwell time of a state
+    fn dwell_limit(&self, state: &State) -> Option<Duration> {
+        self.max_dwell.get(state).copied().or(self.stuck_after)
+    }
+
+    /// Remember the last error for [`StateMachine::health`]
+    pub(crate) fn set_last_error(&self, error: String) {
+        *self.last_error.lock().expect("failed to get lock") = Some(error);
+    }
+}
+
+impl<Err> StateMachineBuilder<Err> {
+    #[must_use]
+    /// Report the machine as stuck in [`StateMachine::health`] when it stays in a state for longer than `limit`
+    /// This is the maximum dwell time of the states without one set by `max_dwell`
+    pub fn stuck_after(mut self, limit: Duration) -> Self {
+        self.stuck_after = Some(limit);
+        self
+    }
+
+    #[must_use]
+    /// Set the maximum expected time the machine stays in `state`, see [`StateMachine::check_stuck`]
+    pub fn max_dwell(mut self, state: State, limit: Duration) -> Self {
+        self.max_dwell.insert(state, limit);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Event, MachineManager, ManualClock, Observer};
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use std::sync::Arc;
+    #[cfg(feature = "tracing")]
+    use tracing_test::traced_test;
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_health() {
+        let idle = State::new("idle");
+        let busy = State::new("busy");
+        let start = Event::new("start");
+        let clock = Arc::new(ManualClock::default());
+        let machine = StateMachineBuilder::new("worker", &idle)
+            .add_event(idle.clone(), start.clone(), busy.clone(), None)
+            .stuck_after(Duration::from_secs(60))
+            .with_clock(clock.clone())
+            .build();
+        let health = machine.health();
+        assert!(health.is_healthy());
+        assert_eq!(health.last_transition, None);
+
+        clock.advance(Duration::from_secs(10));
+        machine.event(&start).unwrap();
+        assert!(machine.event(&start).is_err());
+        clock.advance(Duration::from_secs(61));
+        assert_eq!(
+            machine.health(),
+            Health {
+                machine: "worker".to_string(),
+                state: busy,
+                time_in_state: Duration::from_secs(61),
+                queue_depth: 0,
+                last_error: Some(
+                    "no transition found for event start in state busy, which has no transitions at all"
+                        .to_string()
+                ),
+                last_transition: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(10)),
+                stuck: true,
+            }
+        );
+    }
+
+    struct Alerts(Rc<Cell<usize>>);
+
+    impl Observer for Alerts {
+        fn on_stuck(&self, _stuck: &Stuck) {
+            self.0.set(self.0.get() + 1);
+        }
+    }
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_max_dwell() {
+        let clock = Arc::new(ManualClock::default());
+        let alerts = Rc::new(Cell::new(0));
+        let (new, shipped) = (State::new("new"), State::new("shipped"));
+        let factory = {
+            let clock = clock.clone();
+            let alerts = alerts.clone();
+            move |key: &u32| {
+                StateMachineBuilder::new(format!("order {key}"), &new)
+                    .add_event(new.clone(), Event::new("ship"), shipped.clone(), None)
+                    .max_dwell(new.clone(), Duration::from_secs(60))
+                    .add_observer(Box::new(Alerts(alerts.clone())))
+                    .with_clock(clock.clone())
+                    .build()
+            }
+        };
+        let mut manager = MachineManager::new(factory);
+        manager.machine(&1).unwrap();
+        manager.machine(&2).unwrap();
+        manager.event(&2, &Event::new("ship")).unwrap();
+        clock.advance(Duration::from_secs(61));
+
+        let stuck = manager.check_stuck();
+        assert_eq!(
+            stuck,
+            vec![(
+                1,
+                Stuck {
+                    machine: "order 1".to_string(),
+                    state: State::new("new"),
+                    time_in_state: Duration::from_secs(61),
+                    limit: Duration::from_secs(60),
+                }
+            )]
+        );
+        // observers are notified once per visit
+        assert_eq!(manager.check_stuck().len(), 1);
+        assert_eq!(alerts.get(), 1);
+        assert!(!manager.get(&1).unwrap().health().is_healthy());
+    }
+}
@@ -0,0 +1,121 @@
+//! Composite states: substates inherit the transitions of their parent
+
+use crate::{State, StateMachine, StateMachineBuilder};
+
+impl<Err> StateMachine<Err> {
+    /// Check if the machine is in `state` or in one of its substates
+    /// # Panics
+    /// If the lock is poisoned
+    pub fn is_in(&self, state: &State) -> bool {
+        self.table
+            .lineage(&self.current_state())
+            .any(|s| s == state)
+    }
+}
+
+impl<Err> StateMachineBuilder<Err> {
+    #[must_use]
+    /// Make `state` a substate of `parent`, e.g. `idle` and `busy` in `connected`
+    ///
+    /// The transitions of `parent` also handle the events of `state`, after its own:
+    /// when no transition of `state` passes its guard, those of `parent` are tried,
+    /// then those of its own parent, before the transitions declared for any state.
+    /// # Panics
+    /// If `parent` is `state` or one of its substates
+    pub fn with_parent(mut self, state: &State, parent: &State) -> Self {
+        self.table.set_parent(state, parent);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Event;
+    use std::cell::Cell;
+    use std::rc::Rc;
+    #[cfg(feature = "tracing")]
+    use tracing_test::traced_test;
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_substates() {
+        let (connected, idle, busy) = (
+            State::new("connected"),
+            State::new("idle"),
+            State::new("busy"),
+        );
+        let (disconnected, draining) = (State::new("disconnected"), State::new("draining"));
+        let (work, done, drop) = (Event::new("work"), Event::new("done"), Event::new("drop"));
+        let machine = StateMachineBuilder::new("link", &idle)
+            .add_event(connected.clone(), drop.clone(), disconnected.clone(), None)
+            .add_event(idle.clone(), work.clone(), busy.clone(), None)
+            .add_event(busy.clone(), done.clone(), idle.clone(), None)
+            .add_event(busy.clone(), drop.clone(), draining.clone(), None)
+            .with_parent(&idle, &connected)
+            .with_parent(&busy, &connected)
+            .build();
+
+        assert!(machine.is_in(&connected));
+        assert!(!machine.is_terminal(&idle));
+        machine.event(&drop).unwrap();
+        assert_eq!(machine.current_state(), disconnected);
+        assert!(!machine.is_in(&connected));
+
+        // busy overrides the transition of its parent
+        machine.reset();
+        machine.event(&work).unwrap();
+        machine.event(&drop).unwrap();
+        assert_eq!(machine.current_state(), draining);
+
+        let inherited = machine
+            .definition()
+            .candidates(&idle, &drop)
+            .iter()
+            .map(|t| t.to.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(inherited, vec![disconnected]);
+    }
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_guarded_substate_falls_through() {
+        let (connected, idle, busy) = (
+            State::new("connected"),
+            State::new("idle"),
+            State::new("busy"),
+        );
+        let (disconnected, broken) = (State::new("disconnected"), State::new("broken"));
+        let (work, drop) = (Event::new("work"), Event::new("drop"));
+        let draining = Rc::new(Cell::new(false));
+        let guard = draining.clone();
+        let machine = StateMachineBuilder::new("link", &idle)
+            .add_event(idle.clone(), work.clone(), busy.clone(), None)
+            .add_event(busy.clone(), drop.clone(), idle.clone(), None)
+            .with_guard("draining", move || guard.get())
+            .add_event(connected.clone(), drop.clone(), disconnected.clone(), None)
+            .add_any_state_event(drop.clone(), broken, None)
+            .with_parent(&idle, &connected)
+            .with_parent(&busy, &connected)
+            .build();
+
+        let order = machine
+            .definition()
+            .candidates(&busy, &drop)
+            .iter()
+            .map(|t| t.to.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(order[..2], [idle.clone(), disconnected.clone()]);
+
+        draining.set(true);
+        machine.event(&work).unwrap();
+        machine.event(&drop).unwrap();
+        assert_eq!(machine.current_state(), idle);
+
+        // the guard of busy fails, its parent handles the event before the any-state transition
+        draining.set(false);
+        machine.event(&work).unwrap();
+        machine.event(&drop).unwrap();
+        assert_eq!(machine.current_state(), disconnected);
+    }
+}
@@ -0,0 +1,210 @@
+use crate::{Event, State};
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::{Duration, SystemTime};
+
+/// How a transition ended
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// the action (if any) succeeded
+    Ok,
+    /// the action returned an error, with its message
+    ActionFailed(String),
+    /// the action panicked, with the panic message
+    ActionPanicked(String),
+    /// the transition was not taken, the named guard rejected the event
+    GuardRejected(String),
+    /// the event was not handled, the envelope with this id was delivered before
+    Duplicate(String),
+    /// the event was not handled, the authorizer refused it for this reason
+    Unauthorized(String),
+    /// the event was not handled yet, this principal approved it and more approvals are needed
+    AwaitingApproval(String),
+    /// the escalation event was not handled, with the error, the next event of the chain was tried
+    EscalationSkipped(String),
+    /// the action returned an error, with its message, and the machine went back to the state before the transition
+    RolledBack(String),
+}
+
+impl Outcome {
+    /// Check if the transition succeeded
+    pub fn is_ok(&self) -> bool {
+        matches!(self, Outcome::Ok)
+    }
+
+    /// Check if the transition was taken, whether its action succeeded or not
+    pub fn is_taken(&self) -> bool {
+        !matches!(
+            self,
+            Outcome::GuardRejected(_)
+                | Outcome::Duplicate(_)
+                | Outcome::Unauthorized(_)
+                | Outcome::AwaitingApproval(_)
+                | Outcome::EscalationSkipped(_)
+                | Outcome::RolledBack(_)
+        )
+    }
+}
+
+/// The transition that generated an event, e.g. through an [`EventBus`](crate::EventBus)
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Cause {
+    /// the name of the machine that took the transition
+    pub machine: String,
+    /// the sequence number of its record
+    pub seq: u64,
+}
+
+impl fmt::Display for Cause {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}#{}", self.machine, self.seq)
+    }
+}
+
+/// A transition taken by the state machine, rejected by a guard, a duplicate delivery or a refused event
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransitionRecord {
+    /// sequence number, starting at 1 and incremented for every record
+    pub seq: u64,
+    /// the state before the transition
+    pub from: State,
+    /// the event that triggered the transition
+    pub event: Event,
+    /// the state after the transition
+    pub to: State,
+    /// when the transition was taken
+    pub timestamp: SystemTime,
+    /// how long the action took
+    pub duration: Duration,
+    /// how the transition ended
+    pub outcome: Outcome,
+    /// the transition that generated the event, `None` for events from outside
+    pub cause: Option<Cause>,
+    /// the id of the envelope that delivered the event, see [`StateMachine::deliver`](crate::StateMachine::deliver)
+    pub envelope: Option<String>,
+    /// who sent the event, see [`Envelope::with_principal`](crate::Envelope::with_principal)
+    pub principal: Option<String>,
+}
+
+/// Decides which transitions are kept in the history
+pub type HistoryFilter = Box<dyn Fn(&TransitionRecord) -> bool + Send + Sync>;
+
+/// Log of the most recent transitions
+///
+/// Records are bounded by count, and optionally by age and a filter.
+/// Expired records are pruned from the front on every push, records are in
+/// timestamp order so this only touches the records that actually expire.
+pub(crate) struct History {
+    records: VecDeque<TransitionRecord>,
+    capacity: usize,
+    max_age: Option<Duration>,
+    filter: Option<HistoryFilter>,
+}
+
+impl History {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            records: VecDeque::new(),
+            capacity,
+            max_age: None,
+            filter: None,
+        }
+    }
+
+    pub(crate) fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+    }
+
+    pub(crate) fn set_max_age(&mut self, max_age: Duration) {
+        self.max_age = Some(max_age);
+    }
+
+    pub(crate) fn set_filter(&mut self, filter: HistoryFilter) {
+        self.filter = Some(filter);
+    }
+
+    /// Append a record if it passes the filter, dropping the oldest ones when full
+    pub(crate) fn push(&mut self, record: TransitionRecord) {
+        let now = record.timestamp;
+        self.prune(now);
+        if self.capacity == 0 || self.filter.as_ref().is_some_and(|f| !f(&record)) {
+            return;
+        }
+        if self.records.len() == self.capacity {
+            self.records.pop_front();
+        }
+        self.records.push_back(record);
+    }
+
+    /// Drop the records older than the maximum age
+    pub(crate) fn prune(&mut self, now: SystemTime) {
+        let Some(max_age) = self.max_age else {
+            return;
+        };
+        while let Some(oldest) = self.records.front() {
+            match now.duration_since(oldest.timestamp) {
+                Ok(age) if age > max_age => self.records.pop_front(),
+                _ => break,
+            };
+        }
+    }
+
+    pub(crate) fn records(&self) -> impl Iterator<Item = &TransitionRecord> {
+        self.records.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(seq: u64, secs: u64, outcome: Outcome) -> TransitionRecord {
+        TransitionRecord {
+            seq,
+            from: State::new("a"),
+            event: Event::new("e"),
+            to: State::new("b"),
+            timestamp: SystemTime::UNIX_EPOCH + Duration::from_secs(secs),
+            duration: Duration::ZERO,
+            outcome,
+            cause: None,
+            envelope: None,
+            principal: None,
+        }
+    }
+
+    fn seqs(history: &History) -> Vec<u64> {
+        history.records().map(|r| r.seq).collect()
+    }
+
+    #[test]
+    fn test_capacity() {
+        let mut history = History::new(2);
+        for seq in 1..=3 {
+            history.push(record(seq, seq, Outcome::Ok));
+        }
+        assert_eq!(seqs(&history), vec![2, 3]);
+    }
+
+    #[test]
+    fn test_max_age() {
+        let mut history = History::new(usize::MAX);
+        history.set_max_age(Duration::from_secs(10));
+        history.push(record(1, 0, Outcome::Ok));
+        history.push(record(2, 5, Outcome::Ok));
+        history.push(record(3, 12, Outcome::Ok));
+        assert_eq!(seqs(&history), vec![2, 3]);
+        history.prune(SystemTime::UNIX_EPOCH + Duration::from_secs(20));
+        assert_eq!(seqs(&history), vec![3]);
+    }
+
+    #[test]
+    fn test_filter() {
+        let mut history = History::new(usize::MAX);
+        history.set_filter(Box::new(|r| !r.outcome.is_ok()));
+        history.push(record(1, 0, Outcome::Ok));
+        history.push(record(2, 1, Outcome::ActionFailed("oops".to_string())));
+        history.push(record(3, 2, Outcome::Ok));
+        assert_eq!(seqs(&history), vec![2]);
+    }
+}
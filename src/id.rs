@@ -0,0 +1,158 @@
+//! Generation of the ids of machine instances, envelopes and snapshots
+
+#[cfg(feature = "uuid-v7")]
+use crate::{Clock, SystemClock};
+use crate::{Envelope, Event, StateMachine, StateMachineBuilder};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+#[cfg(feature = "uuid-v7")]
+use std::time::SystemTime;
+
+/// Generates unique ids, inject a [`CounterIds`] for deterministic tests
+pub trait IdGenerator: Send + Sync {
+    /// Get a new id
+    fn next_id(&self) -> String;
+}
+
+/// Numbers ids from 1, with an optional prefix: `order-1`, `order-2`...
+#[derive(Debug, Default)]
+pub struct CounterIds {
+    prefix: String,
+    next: AtomicU64,
+}
+
+impl CounterIds {
+    #[must_use]
+    pub fn new(prefix: impl Into<String>) -> Self {
+        Self {
+            prefix: prefix.into(),
+            next: AtomicU64::new(0),
+        }
+    }
+}
+
+impl IdGenerator for CounterIds {
+    fn next_id(&self) -> String {
+        let n = self.next.fetch_add(1, Ordering::Relaxed) + 1;
+        if self.prefix.is_empty() {
+            n.to_string()
+        } else {
+            format!("{}-{n}", self.prefix)
+        }
+    }
+}
+
+/// Time ordered UUIDs (version 7), the random bits are derived from the time the generator was created
+#[cfg(feature = "uuid-v7")]
+pub struct UuidV7 {
+    clock: Arc<dyn Clock>,
+    seed: u64,
+    count: AtomicU64,
+}
+
+#[cfg(feature = "uuid-v7")]
+impl UuidV7 {
+    #[must_use]
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        let seed = crate::clock::elapsed(SystemTime::UNIX_EPOCH, SystemTime::now()).as_nanos();
+        Self {
+            clock,
+            seed: seed as u64 ^ u64::from(std::process::id()),
+            count: AtomicU64::new(0),
+        }
+    }
+}
+
+#[cfg(feature = "uuid-v7")]
+impl IdGenerator for UuidV7 {
+    fn next_id(&self) -> String {
+        let millis = crate::clock::elapsed(SystemTime::UNIX_EPOCH, self.clock.now()).as_millis();
+        let count = self.count.fetch_add(1, Ordering::Relaxed);
+        let random = crate::backoff::splitmix64(self.seed.wrapping_add(count));
+        let rand_a = (random >> 52) & 0x0fff;
+        let rand_b = random & 0x3fff_ffff_ffff_ffff;
+        format!(
+            "{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+            (millis >> 16) & 0xffff_ffff,
+            millis & 0xffff,
+            0x7000 | rand_a,
+            0x8000 | (rand_b >> 48),
+            rand_b & 0xffff_ffff_ffff
+        )
+    }
+}
+
+/// The generator used unless another one is injected, shared by all machines:
+/// [`UuidV7`] with the `uuid-v7` feature, a [`CounterIds`] without prefix otherwise
+pub fn default_id_generator() -> Arc<dyn IdGenerator> {
+    static DEFAULT: OnceLock<Arc<dyn IdGenerator>> = OnceLock::new();
+    DEFAULT
+        .get_or_init(|| {
+            #[cfg(feature = "uuid-v7")]
+            let ids: Arc<dyn IdGenerator> = Arc::new(UuidV7::new(Arc::new(SystemClock)));
+            #[cfg(not(feature = "uuid-v7"))]
+            let ids: Arc<dyn IdGenerator> = Arc::new(CounterIds::default());
+            ids
+        })
+        .clone()
+}
+
+impl<Err> StateMachine<Err> {
+    /// Get the id of this instance, unlike the name it differs between machines built from the same definition
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Wrap an event in an envelope with a new id, see [`StateMachine::deliver`]
+    pub fn envelope(&self, event: Event) -> Envelope {
+        Envelope::new(self.ids.next_id(), event)
+    }
+}
+
+impl<Err> StateMachineBuilder<Err> {
+    #[must_use]
+    /// Generate the id of the machine, of its envelopes and of its snapshots with `ids`, see [`default_id_generator`]
+    pub fn with_id_generator(mut self, ids: Arc<dyn IdGenerator>) -> Self {
+        self.ids = ids;
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::State;
+    #[cfg(feature = "tracing")]
+    use tracing_test::traced_test;
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_injected_ids() {
+        let idle = State::new("idle");
+        let ids = Arc::new(CounterIds::new("order"));
+        let build = || {
+            StateMachineBuilder::new("order", &idle)
+                .add_event(idle.clone(), Event::new("tick"), idle.clone(), None)
+                .with_id_generator(ids.clone())
+                .build()
+        };
+        let (first, second) = (build(), build());
+        assert_eq!((first.id(), second.id()), ("order-1", "order-2"));
+        assert_eq!(first.envelope(Event::new("tick")).id, "order-3");
+        assert_eq!(second.snapshot().id, "order-4");
+    }
+
+    #[cfg(feature = "uuid-v7")]
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_uuid_v7() {
+        let clock = Arc::new(crate::ManualClock::default());
+        clock.advance(std::time::Duration::from_millis(0x0123_4567_89ab));
+        let ids = UuidV7::new(clock);
+        let (a, b) = (ids.next_id(), ids.next_id());
+        assert_ne!(a, b);
+        assert!(a.starts_with("01234567-89ab-7"));
+        assert_eq!(a.len(), 36);
+        assert!(matches!(a.as_bytes()[19], b'8' | b'9' | b'a' | b'b'));
+    }
+}
@@ -1,14 +1,154 @@
-use anyhow::Result;
+#![cfg_attr(not(feature = "unsafe-opt"), forbid(unsafe_code))]
+
+use approval::Approvals;
+use chaos::Chaos;
+use dedup::{Dedup, Dispatch};
 use derive_more::Display;
+use escalation::TimeBox;
+use fault::ErrorState;
+use history::History;
+use services::SharedServices;
+use stats::{Counters, Dwell};
+use std::borrow::Cow;
 use std::collections::HashMap;
-use std::hash::Hash;
-use std::sync::RwLock;
-use tracing::{debug, error};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+use table::{Guard, Payload, Run, Transition, TransitionTable};
+use throttle::Throttles;
+use trace::{debug, error};
+
+pub mod analysis;
+mod approval;
+#[cfg(feature = "unstable")]
+mod async_machine;
+#[cfg(feature = "unstable")]
+pub mod async_rt;
+mod audit;
+mod authz;
+mod backoff;
+pub mod builder;
+mod bus;
+mod chaos;
+mod clock;
+mod codec;
+mod context;
+pub mod core;
+mod deadline;
+mod dedup;
+mod definition;
+mod determinize;
+mod diff;
+mod dot;
+mod dsl;
+mod entry;
+pub mod error;
+mod escalation;
+mod explain;
+mod export;
+mod fault;
+mod file;
+mod forward;
+mod freeze;
+mod gate;
+mod handle;
+mod health;
+#[cfg(feature = "unstable")]
+mod hierarchy;
+mod history;
+mod id;
+mod json;
+#[cfg(feature = "unstable")]
+mod limit;
+mod manager;
+mod migrate;
+mod monitor;
+pub mod observe;
+mod observer;
+mod order;
+mod payload;
+pub mod persist;
+mod pool;
+pub mod prelude;
+mod replay;
+mod rewind;
+mod services;
+mod shutdown;
+mod snapshot;
+mod stats;
+mod status;
+mod step;
+mod table;
+mod template;
+mod temporal;
+mod throttle;
+mod trace;
+mod typed;
+#[cfg(feature = "unstable")]
+pub mod unstable;
+mod validation;
+mod view;
+
+pub use audit::AUDIT_SCHEMA;
+pub use authz::{Authorizer, Unauthorized};
+pub use backoff::{Backoff, Jitter};
+pub use bus::{BusSender, ChildFactory, DeadLetter, EventBus, Reply};
+pub use chaos::ChaosConfig;
+pub use clock::{Clock, ManualClock, SystemClock};
+pub use codec::{BlobStore, EncodedStore, PlainCodec, SnapshotCodec};
+pub use context::{ContextAction, ContextBuilder, ContextMachine};
+pub use deadline::Deadline;
+pub use dedup::{Delivery, Envelope};
+pub use definition::{Definition, Minimization, TransitionDef};
+pub use determinize::{Choice, Determinize, Nondeterminism};
+pub use diff::{diff_traces, Aligned, Difference, TraceDiff};
+pub use entry::InvalidEntryPoint;
+pub use error::{Error, Result, StateMachineError};
+pub use explain::{Explanation, Step, Verdict};
+pub use export::{trace_to_mermaid, Diagram};
+pub use fault::{FailurePolicy, FAILED};
+pub use file::Format;
+pub use forward::Forward;
+pub use freeze::Frozen;
+pub use gate::FeatureGate;
+pub use handle::{AdminHandle, DispatchHandle};
+pub use health::{Health, Stuck};
+pub use history::{Cause, HistoryFilter, Outcome, TransitionRecord};
+#[cfg(feature = "uuid-v7")]
+pub use id::UuidV7;
+pub use id::{default_id_generator, CounterIds, IdGenerator};
+pub use manager::{Factory, MachineManager, EVICT, REHYDRATE};
+pub use migrate::{Match, Remap, RemapEntry};
+pub use monitor::{EventMapper, Monitor, MonitorViolation};
+pub use observer::{Observer, TransitionInfo};
+pub use order::MustFollow;
+pub use payload::PayloadAction;
+pub use pool::{ShardStats, ShardedPool};
+pub use replay::{replay_many, Divergence, Replay};
+pub use rewind::Rewind;
+pub use services::{ServiceAction, Services};
+pub use shutdown::ShutdownReport;
+pub use snapshot::{MemoryStore, Recovery, Snapshot, Store};
+pub use stats::{DwellStats, TransitionStats};
+pub use status::STATUS_SCHEMA;
+pub use step::{step, step_with_guards, Scan, ScanPolicy, States};
+pub use table::Scope;
+pub use template::{Param, Params, Template};
+pub use temporal::{
+    assert_trace, After, Assertions, Checker, Pattern, Property, TemporalViolation,
+};
+pub use throttle::{Overflow, Throttle};
+pub use typed::{TypedBuilder, TypedMachine};
+pub use validation::{Candidate, Conflict, Validation};
+pub use view::ViewHandle;
 
 #[allow(dead_code)]
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Display)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Display)]
 pub struct State {
-    name: String,
+    name: Cow<'static, str>,
 }
 
 impl State {
@@ -18,13 +158,28 @@ impl State {
     /// # Returns
     /// The new state
     pub fn new(name: impl Into<String>) -> Self {
-        Self { name: name.into() }
+        Self {
+            name: Cow::Owned(name.into()),
+        }
+    }
+
+    /// Create a state named by a literal, without allocating
+    pub const fn from_static(name: &'static str) -> Self {
+        Self {
+            name: Cow::Borrowed(name),
+        }
     }
 }
 
-#[derive(Debug, Eq, PartialEq, Hash, Clone, Display)]
+/// An event, identified by its name
+///
+/// The hash of the name is computed once when the event is created,
+/// so looking up the transitions of an event does not hash its name again on every dispatch.
+#[derive(Clone, Display)]
+#[display(fmt = "{}", name)]
 pub struct Event {
-    name: String,
+    name: Cow<'static, str>,
+    hash: u64,
 }
 
 impl Event {
@@ -34,70 +189,479 @@ impl Event {
     /// # Returns
     /// The new event
     pub fn new(name: impl Into<String>) -> Self {
-        Self { name: name.into() }
+        let name = name.into();
+        Self {
+            hash: fnv1a(name.as_bytes()),
+            name: Cow::Owned(name),
+        }
+    }
+
+    /// Create an event named by a literal, without allocating, e.g. for events dispatched in a hot loop
+    pub const fn from_static(name: &'static str) -> Self {
+        Self {
+            name: Cow::Borrowed(name),
+            hash: fnv1a(name.as_bytes()),
+        }
     }
 }
 
-#[allow(dead_code)]
-struct Transition {
-    trigger: Event,
-    new_state: State,
-    action: Option<Box<dyn Fn() -> Result<()>>>,
+impl PartialEq for Event {
+    fn eq(&self, other: &Self) -> bool {
+        self.hash == other.hash && self.name == other.name
+    }
+}
+
+impl Eq for Event {}
+
+impl Hash for Event {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u64(self.hash);
+    }
 }
 
+impl fmt::Debug for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Event").field("name", &self.name).finish()
+    }
+}
+
+/// 64-bit FNV-1a hash, usable in const functions
+const fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    let mut i = 0;
+    while i < bytes.len() {
+        hash ^= bytes[i] as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+        i += 1;
+    }
+    hash
+}
+
+/// An action executed when a transition fires
+/// `Err` is the error type of the action, `anyhow::Error` by default
+///
+/// Actions do not need to be `Send` or `Sync`, they can capture `Rc`s and `RefCell`s,
+/// which makes the machine itself neither `Send` nor `Sync`, see [`StateMachine`].
+pub type Action<Err = Error> = Box<dyn Fn() -> Result<(), Err>>;
+
+/// Contributes fields to the log lines of a machine, e.g. its tenant or order id
+type LogFields = Box<dyn Fn() -> Vec<(String, String)>>;
+
+/// A state machine, built by a [`StateMachineBuilder`]
+///
+/// # Thread safety
+/// The actions and guards are plain closures without `Send` or `Sync` bounds,
+/// so a machine stays on the thread that built it: moving it to another thread or sharing it
+/// through an `Arc` fails to compile with an error about `dyn Fn` not being `Send`/`Sync`.
+/// Build the machine on the thread that uses it (e.g. with a factory, see [`MachineManager`]),
+/// share it within a thread with an `Rc`, and exchange events between machines with an [`EventBus`].
 #[allow(dead_code)]
-pub struct StateMachine {
+pub struct StateMachine<Err = Error> {
     name: String,
+    id: String,
+    ids: Arc<dyn IdGenerator>,
     state: RwLock<State>,
     initial_state: State,
-    events: HashMap<State, HashMap<Event, Transition>>,
+    table: TransitionTable<Err>,
+    observers: Vec<Box<dyn Observer<Err>>>,
+    history: Option<Mutex<History>>,
+    last_transition: Mutex<Option<TransitionRecord>>,
+    seq: AtomicU64,
+    clock: Arc<dyn Clock>,
+    dwell: Mutex<Dwell>,
+    record_guard_rejections: bool,
+    dedup: Option<Mutex<Dedup>>,
+    authorizer: Option<Box<dyn Authorizer>>,
+    stuck_after: Option<Duration>,
+    max_dwell: HashMap<State, Duration>,
+    last_error: Mutex<Option<String>>,
+    deadlines: Mutex<Vec<Deadline>>,
+    entry_points: Vec<(String, State)>,
+    initial_state_fn: Option<Box<dyn Fn() -> State>>,
+    counters: Mutex<Counters>,
+    log_fields: Option<LogFields>,
+    chaos: Option<Chaos>,
+    recovery: Recovery,
+    throttles: Mutex<Throttles>,
+    error_state: Option<ErrorState>,
+    ordering: Vec<MustFollow>,
+    ordering_monitors: Vec<Monitor>,
+    approvals: Mutex<Approvals>,
+    time_boxes: HashMap<State, TimeBox>,
+    rollback_on_error: bool,
+    services: SharedServices,
 }
 
-impl StateMachine {
+impl<Err> StateMachine<Err>
+where
+    Err: From<Error> + fmt::Display + 'static,
+{
     /// Handle an event
+    ///
+    /// The candidate transitions are tried in order of precedence (see [`StateMachine::validate`]),
+    /// the first one whose guard passes fires.
+    /// Events over the limit of a deferring [`Throttle`] return `Ok` and fire later, see [`StateMachine::fire_due`].
     /// # Errors
     /// If no transition is found for the event in the current state
-    /// or if the action fails
+    /// or if the action fails, as a [`StateMachineError::ActionFailed`] wrapping the error of the action
+    /// (machines with their own error type get the error of the action as is)
+    /// or if the event is over the limit of a rejecting [`Throttle`]
     /// or if the lock is poisoned
-    pub fn event(&self, event: &Event) -> Result<()> {
-        debug!("handling event: {event}");
+    pub fn event(&self, event: &Event) -> Result<(), Err> {
+        let mut state = self
+            .state
+            .write()
+            .map_err(|_| Error::from(StateMachineError::LockPoisoned))?;
+        self.handle(&mut state, Dispatch::event(event), None, || {})
+    }
+
+    /// Handle an event generated by another transition, recording that transition as its cause
+    /// # Errors
+    /// As [`StateMachine::event`]
+    pub fn caused_event(&self, event: &Event, cause: Cause) -> Result<(), Err> {
         let mut state = self
             .state
             .write()
-            .map_err(|_| anyhow::anyhow!("lock error"))?;
-        let state_events = self.events.get(&state);
-        if let Some(state_events) = state_events {
-            let transition = state_events.get(event);
-            if let Some(transition) = transition {
-                let new_state = transition.new_state.clone();
-                debug!("{}: {} -> {}", self.name, state, new_state.clone());
-                *state = new_state;
-                if let Some(ref action) = transition.action {
-                    action()
-                } else {
-                    // no action, just return Ok
-                    Ok(())
+            .map_err(|_| Error::from(StateMachineError::LockPoisoned))?;
+        self.handle(&mut state, Dispatch::caused(event, &cause), None, || {})
+    }
+
+    /// Authorize an event and fire it
+    ///
+    /// `accepted` runs once the event is taken in: its approval is collected, a throttle defers it
+    /// or a transition fires, even if its action fails, unless the transition is rolled back.
+    fn handle(
+        &self,
+        state: &mut State,
+        dispatch: Dispatch,
+        payload: Payload,
+        accepted: impl FnOnce(),
+    ) -> Result<(), Err> {
+        self.authorize(dispatch, state)
+            .map_err(|refused| Error::from(StateMachineError::Unauthorized(refused)))?;
+        if !self.approve(state, dispatch)?
+            || !self.admit(state, dispatch.event, payload.is_some())?
+        {
+            accepted();
+            return Ok(());
+        }
+        let (result, rolled_back) = self
+            .fire(state, dispatch, payload)
+            .ok_or_else(|| self.no_transition(state, dispatch.event))?;
+        if !rolled_back {
+            accepted();
+        }
+        result
+    }
+
+    /// Take the first allowed candidate transition and run its action
+    /// # Returns
+    /// The result of the action and whether the transition was rolled back, or `None` if no transition fires
+    pub(crate) fn fire(
+        &self,
+        state: &mut State,
+        dispatch: Dispatch,
+        payload: Payload,
+    ) -> Option<(Result<(), Err>, bool)> {
+        let (event, cause) = (dispatch.event, dispatch.cause);
+        debug!("{}: handling event: {event}", self.log_name());
+        let mut transition = None;
+        for candidate in self.table.candidates(state, event) {
+            if candidate.allowed() {
+                transition = Some(candidate);
+                break;
+            }
+            self.record_rejection(state, candidate, dispatch);
+        }
+        if let Some(transition) = transition {
+            let old_state = state.clone();
+            let new_state = transition.new_state.clone();
+            debug!("{}: {} -> {}", self.log_name(), state, new_state.clone());
+            let timestamp = self.clock.now();
+            let mut dwell = self.dwell.lock().expect("failed to get lock");
+            let dwell_before = self.rollback_on_error.then(|| dwell.clone());
+            dwell.enter(&new_state, timestamp);
+            drop(dwell);
+            let approvals_before = self.clear_approvals();
+            *state = new_state;
+            let info = TransitionInfo {
+                machine: &self.name,
+                from: &old_state,
+                event,
+                to: &transition.new_state,
+                cause,
+            };
+            let injected = transition
+                .action
+                .as_ref()
+                .and_then(|_| self.chaos.as_ref()?.before_action());
+            // injected failures are returned as they are, the errors of the action are wrapped
+            let wrap_action_error = injected.is_none();
+            let result = if let Some(failure) = injected {
+                Err(failure.into())
+            } else if let Some(ref action) = transition.action {
+                match panic::catch_unwind(AssertUnwindSafe(|| action.call(payload))) {
+                    Ok(result) => result,
+                    Err(panic) => {
+                        let message = observer::panic_message(panic.as_ref());
+                        self.set_last_error(format!(
+                            "action panicked: {}",
+                            message.unwrap_or_default()
+                        ));
+                        for observer in &self.observers {
+                            observer.on_action_panicked(&info, message);
+                        }
+                        let outcome =
+                            Outcome::ActionPanicked(message.unwrap_or_default().to_string());
+                        self.record(&info, Some(dispatch), timestamp, outcome);
+                        let message = message.unwrap_or_default().to_string();
+                        if !self.action_panicked(state, &message) {
+                            panic::resume_unwind(panic)
+                        }
+                        return Some((
+                            Err(Error::from(StateMachineError::ActionPanicked(message)).into()),
+                            false,
+                        ));
+                    }
                 }
             } else {
-                error!("no transition found for event {event} in state {state}");
-                Err(anyhow::anyhow!(
-                    "no transition found for event {event} in state {state}"
-                ))
+                // no action, just return Ok
+                Ok(())
+            };
+            let rolled_back = result.is_err() && self.rollback_on_error;
+            let outcome = match result {
+                Ok(()) => Outcome::Ok,
+                Err(ref e) => {
+                    self.set_last_error(e.to_string());
+                    if rolled_back {
+                        self.roll_back(state, &old_state, dwell_before, approvals_before);
+                        Outcome::RolledBack(e.to_string())
+                    } else {
+                        Outcome::ActionFailed(e.to_string())
+                    }
+                }
+            };
+            self.record(&info, Some(dispatch), timestamp, outcome);
+            match result {
+                Ok(()) if transition.action.is_some() => self.action_succeeded(),
+                Ok(()) => {}
+                Err(ref e) => self.action_failed(state, &e.to_string()),
+            }
+            for observer in &self.observers {
+                match result {
+                    Ok(()) => observer.on_transition(&info),
+                    Err(ref e) if rolled_back => observer.on_rolled_back(&info, e),
+                    Err(ref e) => observer.on_action_failed(&info, e),
+                }
             }
+            if wrap_action_error {
+                return Some((result.map_err(error::action_failed), rolled_back));
+            }
+            Some((result, rolled_back))
         } else {
-            error!("no transition found for event {event} in state {state}");
-            Err(anyhow::anyhow!(
-                "no transition found for event {event} in state {state}"
-            ))
+            None
+        }
+    }
+
+    fn no_transition(&self, state: &State, event: &Event) -> Err {
+        let error = StateMachineError::NoTransition {
+            state: state.clone(),
+            event: event.clone(),
+            dead_end: !self.table.has_transitions(state),
+        };
+        error!("{}: {error}", self.log_name());
+        self.set_last_error(error.to_string());
+        Error::from(error).into()
+    }
+}
+
+impl<Err> StateMachine<Err> {
+    /// The name of the machine for log lines, followed by its log fields, e.g. `order [tenant=acme]`
+    pub(crate) fn log_name(&self) -> Cow<'_, str> {
+        let Some(ref fields) = self.log_fields else {
+            return Cow::Borrowed(&self.name);
+        };
+        let fields: Vec<String> = fields()
+            .into_iter()
+            .map(|(key, value)| format!("{key}={value}"))
+            .collect();
+        Cow::Owned(format!("{} [{}]", self.name, fields.join(" ")))
+    }
+
+    fn next_seq(&self) -> u64 {
+        self.seq.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// Record a guard that rejected an event in the history, if enabled
+    fn record_rejection(&self, state: &State, transition: &Transition<Err>, dispatch: Dispatch) {
+        let event = dispatch.event;
+        let Some(ref history) = self.history else {
+            return;
+        };
+        if !self.record_guard_rejections {
+            return;
         }
+        let guard = transition.guard_name().unwrap_or_default();
+        debug!(
+            "{}: guard {guard} rejected {event} in {state}",
+            self.log_name()
+        );
+        let timestamp = self.clock.now();
+        history
+            .lock()
+            .expect("failed to get lock")
+            .push(TransitionRecord {
+                seq: self.next_seq(),
+                from: state.clone(),
+                event: event.clone(),
+                to: transition.new_state.clone(),
+                timestamp,
+                duration: Duration::ZERO,
+                outcome: Outcome::GuardRejected(guard.to_string()),
+                cause: dispatch.cause.cloned(),
+                envelope: dispatch.recorded_id(),
+                principal: dispatch.principal().cloned(),
+            });
+    }
+
+    /// Record an event that was not handled in the history, if enabled, the machine stays in `state`
+    pub(crate) fn record_not_taken(&self, state: &State, dispatch: Dispatch, outcome: Outcome) {
+        let Some(ref history) = self.history else {
+            return;
+        };
+        history
+            .lock()
+            .expect("failed to get lock")
+            .push(TransitionRecord {
+                seq: self.next_seq(),
+                from: state.clone(),
+                event: dispatch.event.clone(),
+                to: state.clone(),
+                timestamp: self.clock.now(),
+                duration: Duration::ZERO,
+                outcome,
+                cause: dispatch.cause.cloned(),
+                envelope: dispatch.recorded_id(),
+                principal: dispatch.principal().cloned(),
+            });
+    }
+
+    /// Number a transition, remember it as the last one and append it to the history, if enabled
+    fn record(
+        &self,
+        info: &TransitionInfo,
+        dispatch: Option<Dispatch>,
+        timestamp: SystemTime,
+        outcome: Outcome,
+    ) {
+        let now = self.clock.now();
+        let duration = clock::elapsed(timestamp, now);
+        self.counters
+            .lock()
+            .expect("failed to get lock")
+            .add(now, duration, &outcome);
+        let record = TransitionRecord {
+            seq: self.next_seq(),
+            from: info.from.clone(),
+            event: info.event.clone(),
+            to: info.to.clone(),
+            timestamp,
+            duration,
+            outcome,
+            cause: info.cause.cloned(),
+            envelope: dispatch.and_then(|d| d.recorded_id()),
+            principal: dispatch.and_then(|d| d.principal().cloned()),
+        };
+        if let Some(ref history) = self.history {
+            history
+                .lock()
+                .expect("failed to get lock")
+                .push(record.clone());
+        }
+        *self.last_transition.lock().expect("failed to get lock") = Some(record);
+    }
+
+    /// Get the most recent transition, also when the history is not enabled
+    /// # Returns
+    /// `None` if no transition was taken yet
+    /// # Panics
+    /// If the lock is poisoned
+    pub fn last_transition(&self) -> Option<TransitionRecord> {
+        self.last_transition
+            .lock()
+            .expect("failed to get lock")
+            .clone()
+    }
+
+    /// Get the recorded transitions, oldest first
+    /// # Returns
+    /// An empty list if the history is not enabled
+    /// # Panics
+    /// If the lock is poisoned
+    pub fn history(&self) -> Vec<TransitionRecord> {
+        self.history.as_ref().map_or_else(Vec::new, |history| {
+            let mut history = history.lock().expect("failed to get lock");
+            history.prune(self.clock.now());
+            history.records().cloned().collect()
+        })
+    }
+
+    /// Get the time spent in every visited state
+    /// # Panics
+    /// If the lock is poisoned
+    pub fn dwell_stats(&self) -> HashMap<State, DwellStats> {
+        self.dwell
+            .lock()
+            .expect("failed to get lock")
+            .stats(self.clock.now())
     }
 
-    /// Reset the state machine to its initial state
+    /// Get the time spent in the current state
+    /// # Panics
+    /// If the lock is poisoned
+    pub fn time_in_state(&self) -> Duration {
+        self.dwell
+            .lock()
+            .expect("failed to get lock")
+            .current(self.clock.now())
+    }
+
+    /// Reset the state machine to its initial state, its ordering monitors and its throttles
     /// #Panics
     /// If the lock is poisoned
     pub fn reset(&self) {
         let mut state = self.state.write().expect("failed to get lock");
-        *state = self.initial_state.clone();
+        self.force_state(&mut state, &self.initial_state);
+        self.reset_monitors();
+    }
+
+    /// Forget the events seen by the ordering monitors and the throttles, e.g. on a reset or a restore
+    pub(crate) fn reset_monitors(&self) {
+        for monitor in &self.ordering_monitors {
+            monitor.reset();
+        }
+        self.throttles.lock().expect("failed to get lock").reset();
+    }
+
+    /// Put the machine in `to` without a transition, e.g. on a reset or a restore:
+    /// a new visit starts and the approvals collected in the previous state are dropped
+    pub(crate) fn force_state(&self, state: &mut State, to: &State) {
+        self.dwell
+            .lock()
+            .expect("failed to get lock")
+            .enter(to, self.clock.now());
+        self.clear_approvals();
+        *state = to.clone();
+    }
+
+    /// Check if no transition is declared on `state` itself, transitions declared for any state are ignored
+    pub fn is_terminal(&self, state: &State) -> bool {
+        !self.table.has_own_transitions(state)
+    }
+
+    /// Get the name of the state machine
+    pub fn name(&self) -> &str {
+        &self.name
     }
 
     /// Get the current state
@@ -108,21 +672,72 @@ impl StateMachine {
     }
 }
 
-pub struct StateMachineBuilder {
+pub struct StateMachineBuilder<Err = Error> {
     name: String,
     state: RwLock<State>,
     initial_state: State,
-    events: HashMap<State, HashMap<Event, Transition>>,
+    table: TransitionTable<Err>,
+    observers: Vec<Box<dyn Observer<Err>>>,
+    history: Option<History>,
+    clock: Arc<dyn Clock>,
+    record_guard_rejections: bool,
+    dedup: Option<Dedup>,
+    authorizer: Option<Box<dyn Authorizer>>,
+    stuck_after: Option<Duration>,
+    max_dwell: HashMap<State, Duration>,
+    entry_points: Vec<(String, State)>,
+    initial_state_fn: Option<Box<dyn Fn() -> State>>,
+    stats_window: Option<Duration>,
+    ids: Arc<dyn IdGenerator>,
+    log_fields: Option<LogFields>,
+    chaos: Option<Chaos>,
+    recovery: Recovery,
+    throttles: Throttles,
+    error_state: Option<ErrorState>,
+    ordering: Vec<MustFollow>,
+    time_boxes: HashMap<State, TimeBox>,
+    rollback_on_error: bool,
+    services: SharedServices,
 }
 
 impl StateMachineBuilder {
     #[must_use]
     pub fn new(name: impl Into<String>, initial_state: &State) -> Self {
+        Self::with_error_type(name, initial_state)
+    }
+}
+
+impl<Err> StateMachineBuilder<Err> {
+    /// Create a builder for a machine whose actions return `Err`
+    /// `event()` returns the errors of the actions unchanged, its own errors are converted with `From<Error>`
+    #[must_use]
+    pub fn with_error_type(name: impl Into<String>, initial_state: &State) -> Self {
         Self {
             name: name.into(),
             state: RwLock::new(initial_state.clone()),
             initial_state: initial_state.clone(),
-            events: HashMap::new(),
+            table: TransitionTable::default(),
+            observers: Vec::new(),
+            history: None,
+            clock: Arc::new(SystemClock),
+            record_guard_rejections: false,
+            dedup: None,
+            authorizer: None,
+            stuck_after: None,
+            max_dwell: HashMap::new(),
+            entry_points: Vec::new(),
+            initial_state_fn: None,
+            stats_window: None,
+            ids: default_id_generator(),
+            log_fields: None,
+            chaos: None,
+            recovery: Recovery::Fail,
+            throttles: Throttles::default(),
+            error_state: None,
+            ordering: Vec::new(),
+            time_boxes: HashMap::new(),
+            rollback_on_error: false,
+            services: SharedServices::default(),
         }
     }
 
@@ -130,35 +745,206 @@ impl StateMachineBuilder {
     /// Add an event to the state machine
     /// # Arguments
     /// * `old_state` - the state in which the event is handled
-    ///            (the state before the transition)
+    ///   (the state before the transition)
     /// * `event` - the event
     /// * `new_state` - the state after the transition
     /// * `action` - an optional action to execute when the event is handled
+    ///
     /// Make sure this never panics - as this would poison the lock and cause the state machine to fail
+    ///
+    /// Adding the same event again for a state replaces the unguarded transition added before,
+    /// guarded transitions for the same event are tried first, in order of precedence, see [`StateMachine::validate`]
     pub fn add_event(
         mut self,
         old_state: State,
         event: Event,
         new_state: State,
-        action: Option<Box<dyn Fn() -> Result<()>>>,
+        action: Option<Action<Err>>,
     ) -> Self {
-        let state_events = self.events.entry(old_state).or_insert_with(HashMap::new);
-        let t = Transition {
-            trigger: event.clone(),
+        self.table.add(Transition {
+            from: Some(old_state),
+            trigger: event,
             new_state,
-            action,
-        };
-        state_events.insert(event, t);
+            action: action.map(Run::Plain),
+            guard: None,
+            priority: None,
+            approvals: None,
+        });
+        self
+    }
+
+    #[must_use]
+    /// Add an event that is handled in any state
+    /// Transitions declared on the current state take precedence
+    /// # Arguments
+    /// * `event` - the event
+    /// * `new_state` - the state after the transition
+    /// * `action` - an optional action to execute when the event is handled
+    pub fn add_any_state_event(
+        mut self,
+        event: Event,
+        new_state: State,
+        action: Option<Action<Err>>,
+    ) -> Self {
+        self.table.add(Transition {
+            from: None,
+            trigger: event,
+            new_state,
+            action: action.map(Run::Plain),
+            guard: None,
+            priority: None,
+            approvals: None,
+        });
+        self
+    }
+
+    #[must_use]
+    /// Guard the last added transition, it only fires if `guard` returns true
+    /// Guarded transitions are tried before unguarded ones of the same scope
+    /// # Arguments
+    /// * `name` - the name of the guard, used in reports
+    /// * `guard` - the condition
+    /// # Panics
+    /// If no transition was added yet
+    pub fn with_guard(
+        mut self,
+        name: impl Into<String>,
+        guard: impl Fn() -> bool + 'static,
+    ) -> Self {
+        self.last_transition("with_guard").guard = Some(Guard {
+            name: name.into(),
+            check: Box::new(guard),
+        });
+        self
+    }
+
+    #[must_use]
+    /// Override the precedence of the last added transition
+    /// Transitions with a higher priority are tried first, regardless of their scope, the default is 0
+    /// # Panics
+    /// If no transition was added yet
+    pub fn with_priority(mut self, priority: i32) -> Self {
+        self.last_transition("with_priority").priority = Some(priority);
+        self
+    }
+
+    fn last_transition(&mut self, method: &str) -> &mut Transition<Err> {
+        self.table
+            .last_mut()
+            .unwrap_or_else(|| panic!("{method} called before adding a transition"))
+    }
+
+    #[must_use]
+    /// Add an observer that gets notified of every transition
+    pub fn add_observer(mut self, observer: Box<dyn Observer<Err>>) -> Self {
+        self.observers.push(observer);
+        self
+    }
+
+    #[must_use]
+    /// Keep a history of the last transitions
+    /// # Arguments
+    /// * `capacity` - the number of transitions to keep, older ones are dropped
+    pub fn with_history(mut self, capacity: usize) -> Self {
+        self.history
+            .get_or_insert_with(|| History::new(capacity))
+            .set_capacity(capacity);
+        self
+    }
+
+    #[must_use]
+    /// Drop transitions older than `max_age` from the history
+    /// Enables the history without a limit on the number of transitions, unless `with_history` is used
+    pub fn history_max_age(mut self, max_age: Duration) -> Self {
+        self.history
+            .get_or_insert_with(|| History::new(usize::MAX))
+            .set_max_age(max_age);
+        self
+    }
+
+    #[must_use]
+    /// Only keep the transitions matching `filter` in the history, e.g. the failed ones
+    /// Enables the history without a limit on the number of transitions, unless `with_history` is used
+    pub fn history_filter(
+        mut self,
+        filter: impl Fn(&TransitionRecord) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.history
+            .get_or_insert_with(|| History::new(usize::MAX))
+            .set_filter(Box::new(filter));
+        self
+    }
+
+    #[must_use]
+    /// Also record the guards that rejected an event in the history,
+    /// with the state, the event, the target of the rejected transition and the guard name
+    /// Enables the history without a limit on the number of transitions, unless `with_history` is used
+    pub fn record_guard_rejections(mut self) -> Self {
+        self.history.get_or_insert_with(|| History::new(usize::MAX));
+        self.record_guard_rejections = true;
+        self
+    }
+
+    #[must_use]
+    /// Use another clock than the system clock for timestamps and durations
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
         self
     }
 
     #[must_use]
-    pub fn build(self) -> StateMachine {
+    /// Add fields to every log line of the machine, e.g. the tenant and order it belongs to,
+    /// so logs can be correlated to business entities
+    /// # Arguments
+    /// * `fields` - returns the (key, value) pairs, called for every log line
+    pub fn log_fields(mut self, fields: impl Fn() -> Vec<(String, String)> + 'static) -> Self {
+        self.log_fields = Some(Box::new(fields));
+        self
+    }
+
+    #[must_use]
+    pub fn build(mut self) -> StateMachine<Err> {
+        self.table.resolve();
+        let dwell = Dwell::new(&self.initial_state, self.clock.now());
+        let ordering_monitors: Vec<Monitor> =
+            self.ordering.iter().map(MustFollow::monitor).collect();
+        for monitor in &ordering_monitors {
+            self.observers.push(Box::new(monitor.clone()));
+        }
         StateMachine {
             name: self.name,
+            id: self.ids.next_id(),
+            ids: self.ids,
             state: self.state,
             initial_state: self.initial_state,
-            events: self.events,
+            table: self.table,
+            observers: self.observers,
+            history: self.history.map(Mutex::new),
+            last_transition: Mutex::new(None),
+            seq: AtomicU64::new(0),
+            clock: self.clock,
+            dwell: Mutex::new(dwell),
+            record_guard_rejections: self.record_guard_rejections,
+            dedup: self.dedup.map(Mutex::new),
+            authorizer: self.authorizer,
+            stuck_after: self.stuck_after,
+            max_dwell: self.max_dwell,
+            last_error: Mutex::new(None),
+            deadlines: Mutex::new(Vec::new()),
+            entry_points: self.entry_points,
+            initial_state_fn: self.initial_state_fn,
+            counters: Mutex::new(Counters::new(self.stats_window)),
+            log_fields: self.log_fields,
+            chaos: self.chaos,
+            recovery: self.recovery,
+            throttles: Mutex::new(self.throttles),
+            error_state: self.error_state,
+            ordering: self.ordering,
+            ordering_monitors,
+            approvals: Mutex::new(Approvals::default()),
+            time_boxes: self.time_boxes,
+            rollback_on_error: self.rollback_on_error,
+            services: self.services,
         }
     }
 }
@@ -170,9 +956,10 @@ mod tests {
         atomic::{AtomicBool, Ordering},
         Arc,
     };
+    #[cfg(feature = "tracing")]
     use tracing_test::traced_test;
 
-    #[traced_test]
+    #[cfg_attr(feature = "tracing", traced_test)]
     #[test]
     fn test_one_state() -> Result<()> {
         let initial = State::new("initial");
@@ -186,7 +973,7 @@ mod tests {
         Ok(())
     }
 
-    #[traced_test]
+    #[cfg_attr(feature = "tracing", traced_test)]
     #[test]
     fn test_two_states() -> Result<()> {
         let initial = State::new("initial");
@@ -220,7 +1007,7 @@ mod tests {
         Ok(())
     }
 
-    #[traced_test]
+    #[cfg_attr(feature = "tracing", traced_test)]
     #[test]
     fn test_two_states_circular() -> Result<()> {
         let initial = State::new("initial");
@@ -256,7 +1043,7 @@ mod tests {
         Ok(())
     }
 
-    #[traced_test]
+    #[cfg_attr(feature = "tracing", traced_test)]
     #[test]
     fn test_action_fails() -> Result<()> {
         let initial = State::new("initial");
@@ -267,7 +1054,7 @@ mod tests {
         let action = Box::new(move || {
             debug!("action directe!");
             action_called_clone.store(true, Ordering::SeqCst);
-            Err(anyhow::anyhow!("action failed"))
+            Err(crate::error::message("action failed".to_string()))
         });
         let machine = StateMachineBuilder::new("test", &initial)
             .add_event(initial.clone(), e1.clone(), second.clone(), Some(action))
@@ -279,12 +1066,186 @@ mod tests {
         Ok(())
     }
 
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_last_transition() {
+        let initial = State::new("initial");
+        let second = State::new("second");
+        let e1 = Event::new("e1");
+        let e2 = Event::new("e2");
+        let machine = StateMachineBuilder::new("test", &initial)
+            .add_event(initial.clone(), e1.clone(), second.clone(), None)
+            .add_event(
+                second.clone(),
+                e2.clone(),
+                initial.clone(),
+                Some(Box::new(|| {
+                    Err(crate::error::message("action failed".to_string()))
+                })),
+            )
+            .build();
+        assert!(machine.last_transition().is_none());
+
+        machine.event(&e1).unwrap();
+        let last = machine.last_transition().unwrap();
+        assert_eq!((last.seq, &last.from, &last.to), (1, &initial, &second));
+        assert!(last.outcome.is_ok());
+
+        assert!(machine.event(&e2).is_err());
+        // no transition, the last one is kept
+        assert!(machine.event(&e2).is_err());
+        let last = machine.last_transition().unwrap();
+        assert_eq!((last.seq, &last.event), (2, &e2));
+        assert_eq!(
+            last.outcome,
+            Outcome::ActionFailed("action failed".to_string())
+        );
+        assert!(machine.history().is_empty());
+    }
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_precedence() -> Result<()> {
+        let idle = State::new("idle");
+        let running = State::new("running");
+        let fast = State::new("fast");
+        let stopped = State::new("stopped");
+        let go = Event::new("go");
+        let stop = Event::new("stop");
+        let turbo = Arc::new(AtomicBool::new(false));
+        let turbo_clone = turbo.clone();
+        let machine = StateMachineBuilder::new("test", &idle)
+            .add_event(idle.clone(), go.clone(), running.clone(), None)
+            .add_event(idle.clone(), go.clone(), fast.clone(), None)
+            .with_guard("turbo", move || turbo_clone.load(Ordering::SeqCst))
+            .add_event(running.clone(), stop.clone(), idle.clone(), None)
+            .add_event(fast.clone(), stop.clone(), idle.clone(), None)
+            .add_any_state_event(stop.clone(), stopped.clone(), None)
+            .build();
+
+        // the guarded transition is tried first, but fails
+        machine.event(&go)?;
+        assert_eq!(machine.current_state(), running);
+        // the state's own transition wins over the any state one
+        machine.event(&stop)?;
+        assert_eq!(machine.current_state(), idle);
+        turbo.store(true, Ordering::SeqCst);
+        machine.event(&go)?;
+        assert_eq!(machine.current_state(), fast);
+        machine.reset();
+        machine.event(&stop)?;
+        assert_eq!(machine.current_state(), stopped);
+        Ok(())
+    }
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_duplicate_transition_replaced() -> Result<()> {
+        let a = State::new("a");
+        let b = State::new("b");
+        let c = State::new("c");
+        let go = Event::new("go");
+        let machine = StateMachineBuilder::new("test", &a)
+            .add_event(a.clone(), go.clone(), b.clone(), None)
+            .add_event(a.clone(), go.clone(), c.clone(), None)
+            .build();
+
+        machine.event(&go)?;
+        assert_eq!(machine.current_state(), c);
+        Ok(())
+    }
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_priority_override() -> Result<()> {
+        let idle = State::new("idle");
+        let running = State::new("running");
+        let stopped = State::new("stopped");
+        let stop = Event::new("stop");
+        let machine = StateMachineBuilder::new("test", &idle)
+            .add_event(idle.clone(), stop.clone(), running.clone(), None)
+            .add_any_state_event(stop.clone(), stopped.clone(), None)
+            .with_priority(1)
+            .build();
+
+        machine.event(&stop)?;
+        assert_eq!(machine.current_state(), stopped);
+        Ok(())
+    }
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_record_guard_rejections() -> Result<()> {
+        let idle = State::new("idle");
+        let running = State::new("running");
+        let fast = State::new("fast");
+        let go = Event::new("go");
+        let machine = StateMachineBuilder::new("test", &idle)
+            .add_event(idle.clone(), go.clone(), fast.clone(), None)
+            .with_guard("turbo", || false)
+            .add_event(idle.clone(), go.clone(), running.clone(), None)
+            .with_history(10)
+            .record_guard_rejections()
+            .build();
+
+        machine.event(&go)?;
+        let history = machine.history();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].seq, 1);
+        assert_eq!((&history[0].from, &history[0].to), (&idle, &fast));
+        assert_eq!(
+            history[0].outcome,
+            Outcome::GuardRejected("turbo".to_string())
+        );
+        assert_eq!(history[1].seq, 2);
+        assert_eq!(history[1].to, running);
+        assert_eq!(machine.last_transition().map(|t| t.seq), Some(2));
+        Ok(())
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum DomainError {
+        OutOfStock(u32),
+        Machine(String),
+    }
+
+    impl fmt::Display for DomainError {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                DomainError::OutOfStock(id) => write!(f, "item {id} out of stock"),
+                DomainError::Machine(e) => write!(f, "{e}"),
+            }
+        }
+    }
+
+    impl From<Error> for DomainError {
+        fn from(e: Error) -> Self {
+            DomainError::Machine(e.to_string())
+        }
+    }
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_custom_error_type() {
+        let initial = State::new("initial");
+        let second = State::new("second");
+        let e1 = Event::new("e1");
+        let action: Action<DomainError> = Box::new(|| Err(DomainError::OutOfStock(42)));
+        let machine = StateMachineBuilder::with_error_type("test", &initial)
+            .add_event(initial.clone(), e1.clone(), second.clone(), Some(action))
+            .build();
+
+        assert_eq!(machine.event(&e1), Err(DomainError::OutOfStock(42)));
+        // in `second` state, there are no transitions
+        assert!(matches!(machine.event(&e1), Err(DomainError::Machine(_))));
+    }
+
     fn regular_function() -> Result<()> {
         debug!("action indirecte!");
         Ok(())
     }
 
-    #[traced_test]
+    #[cfg_attr(feature = "tracing", traced_test)]
     #[test]
     fn test_regular_function() -> Result<()> {
         let initial = State::new("initial");
@@ -308,10 +1269,10 @@ mod tests {
         Ok(())
     }
 
-    #[traced_test]
+    #[cfg_attr(feature = "tracing", traced_test)]
     #[test]
     #[should_panic]
-    fn test_panics() -> () {
+    fn test_panics() {
         let initial = State::new("initial");
         let e1 = Event::new("e1");
         let action = Box::new(|| {
@@ -323,4 +1284,61 @@ mod tests {
 
         machine.event(&e1).unwrap();
     }
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_static_names() -> Result<()> {
+        const IDLE: State = State::from_static("idle");
+        const PING: Event = Event::from_static("ping");
+        assert_eq!(IDLE, State::new("idle"));
+        assert_eq!(PING.to_string(), "ping");
+        let machine = StateMachineBuilder::new("test", &State::new("idle"))
+            .add_event(IDLE, Event::new("ping"), State::new("idle"), None)
+            .build();
+        machine.event(&PING)?;
+        Ok(())
+    }
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_prehashed_events() {
+        use std::collections::HashSet;
+        let events: HashSet<Event> = [Event::new("a"), Event::from_static("b")].into();
+        assert!(events.contains(&Event::from_static("a")));
+        assert!(events.contains(&Event::new(String::from("b"))));
+        assert!(!events.contains(&Event::new("c")));
+        assert_eq!(format!("{:?}", Event::new("a")), "Event { name: \"a\" }");
+    }
+
+    #[cfg(feature = "tracing")]
+    #[traced_test]
+    #[test]
+    fn test_log_fields() {
+        let open = State::new("open");
+        let tenant = Arc::new(Mutex::new("acme".to_string()));
+        let t = tenant.clone();
+        let machine = StateMachineBuilder::new("order", &open)
+            .add_event(open.clone(), Event::new("pay"), State::new("paid"), None)
+            .log_fields(move || {
+                vec![
+                    ("tenant".to_string(), t.lock().unwrap().clone()),
+                    ("order".to_string(), "42".to_string()),
+                ]
+            })
+            .on_unknown_state(Recovery::Reset)
+            .build();
+        machine.event(&Event::new("pay")).unwrap();
+        assert!(logs_contain("order [tenant=acme order=42]: open -> paid"));
+        *tenant.lock().unwrap() = "globex".to_string();
+        assert!(machine.event(&Event::new("pay")).is_err());
+        assert!(logs_contain(
+            "order [tenant=globex order=42]: no transition found"
+        ));
+        let mut snapshot = machine.snapshot();
+        snapshot.state = State::new("archived");
+        machine.restore(&snapshot).unwrap();
+        assert!(logs_contain(
+            "order [tenant=globex order=42]: restoring unknown state archived"
+        ));
+    }
 }
@@ -0,0 +1,95 @@
+//! Limits on the number of async actions running at once
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll, Waker};
+
+struct Slots {
+    available: usize,
+    waiting: VecDeque<Waker>,
+}
+
+/// A semaphore for the actions of [`AsyncStateMachine`](crate::unstable::AsyncStateMachine), clones share the same slots
+///
+/// Like the async machines, it is neither `Send` nor `Sync` and is meant for a local executor.
+#[derive(Clone)]
+pub struct ConcurrencyLimit {
+    slots: Rc<RefCell<Slots>>,
+}
+
+impl ConcurrencyLimit {
+    /// Allow at most `max` actions to run at once
+    /// # Panics
+    /// If `max` is 0, no action could ever run
+    #[must_use]
+    pub fn new(max: usize) -> Self {
+        assert!(max > 0, "a concurrency limit needs at least one slot");
+        Self {
+            slots: Rc::new(RefCell::new(Slots {
+                available: max,
+                waiting: VecDeque::new(),
+            })),
+        }
+    }
+
+    /// Get the number of free slots
+    pub fn available(&self) -> usize {
+        self.slots.borrow().available
+    }
+
+    /// Wait for a free slot, it is released when the permit is dropped
+    pub(crate) fn acquire(&self) -> Acquire<'_> {
+        Acquire { limit: self }
+    }
+}
+
+impl fmt::Debug for ConcurrencyLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let slots = self.slots.borrow();
+        f.debug_struct("ConcurrencyLimit")
+            .field("available", &slots.available)
+            .field("waiting", &slots.waiting.len())
+            .finish()
+    }
+}
+
+pub(crate) struct Acquire<'a> {
+    limit: &'a ConcurrencyLimit,
+}
+
+impl Future for Acquire<'_> {
+    type Output = Permit;
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<Permit> {
+        let mut slots = self.limit.slots.borrow_mut();
+        if slots.available > 0 {
+            slots.available -= 1;
+            return Poll::Ready(Permit {
+                slots: self.limit.slots.clone(),
+            });
+        }
+        if !slots.waiting.iter().any(|w| w.will_wake(context.waker())) {
+            slots.waiting.push_back(context.waker().clone());
+        }
+        Poll::Pending
+    }
+}
+
+/// A slot taken from a [`ConcurrencyLimit`], freed on drop
+pub(crate) struct Permit {
+    slots: Rc<RefCell<Slots>>,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let mut slots = self.slots.borrow_mut();
+        slots.available += 1;
+        if let Some(waker) = slots.waiting.pop_front() {
+            waker.wake();
+        }
+    }
+}
@@ -0,0 +1,616 @@
+use crate::approval::Approvals;
+use crate::dedup::Dispatch;
+use crate::stats::Dwell;
+use crate::trace::{debug, error};
+use crate::{
+    Clock, Error, Event, Result, ShutdownReport, State, StateMachine, StateMachineError, Store,
+    Stuck, SystemClock,
+};
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Fired by the manager before it evicts a machine handling it in its current state,
+/// e.g. to flush caches or move to a `passivated` state, the snapshot is taken after the transition
+///
+/// Like [`REHYDRATE`], it is not authorized, approved or throttled, and a guard that rejects it skips the transition.
+pub const EVICT: Event = Event::from_static("evict");
+
+/// Fired by the manager after it restores a machine handling it in its restored state,
+/// e.g. to subscribe to external resources again
+pub const REHYDRATE: Event = Event::from_static("rehydrate");
+
+/// Creates the machine of a key
+pub type Factory<K, Err = Error> = Box<dyn Fn(&K) -> StateMachine<Err>>;
+
+struct Instance<Err> {
+    machine: StateMachine<Err>,
+    last_used: SystemTime,
+    /// order of use, for the least recently used eviction
+    tick: u64,
+}
+
+/// Manages one machine per key, e.g. per order or per device
+///
+/// Machines are created on first use by a factory, usually building the same definition for every key.
+/// Idle machines are evicted when there are too many of them or when they were not used for a while,
+/// their snapshot is saved to the store and restored when the key is used again.
+pub struct MachineManager<K, Err = Error> {
+    factory: Factory<K, Err>,
+    machines: HashMap<K, Instance<Err>>,
+    capacity: Option<usize>,
+    idle_ttl: Option<Duration>,
+    store: Option<Box<dyn Store<K>>>,
+    clock: Arc<dyn Clock>,
+    tick: u64,
+    closed: bool,
+}
+
+impl<K, Err> MachineManager<K, Err>
+where
+    K: Eq + Hash + Clone + fmt::Display,
+    Err: From<Error> + fmt::Display + 'static,
+{
+    /// Create a manager
+    /// # Arguments
+    /// * `factory` - creates the machine of a key
+    #[must_use]
+    pub fn new(factory: impl Fn(&K) -> StateMachine<Err> + 'static) -> Self {
+        Self {
+            factory: Box::new(factory),
+            machines: HashMap::new(),
+            capacity: None,
+            idle_ttl: None,
+            store: None,
+            clock: Arc::new(SystemClock),
+            tick: 0,
+            closed: false,
+        }
+    }
+
+    #[must_use]
+    /// Keep at most `capacity` machines in memory, evicting the least recently used one
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = Some(capacity);
+        self
+    }
+
+    #[must_use]
+    /// Evict the machines that were not used for `ttl`
+    pub fn idle_ttl(mut self, ttl: Duration) -> Self {
+        self.idle_ttl = Some(ttl);
+        self
+    }
+
+    #[must_use]
+    /// Save the snapshots of evicted machines to `store` and restore them from it
+    pub fn with_store(mut self, store: Box<dyn Store<K>>) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    #[must_use]
+    /// Use another clock than the system clock to measure idle time
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Handle an event in the machine of a key, creating or restoring the machine if needed
+    /// # Errors
+    /// If the machine fails to handle the event, or restoring or evicting a machine fails
+    pub fn event(&mut self, key: &K, event: &Event) -> Result<(), Err> {
+        self.machine(key)?.event(event)
+    }
+
+    /// Get the machine of a key, creating or restoring it if needed
+    ///
+    /// Using a machine makes it the most recently used one, other idle machines may be evicted.
+    /// Machines that cannot be evicted are kept in memory, the capacity is then exceeded until a later eviction succeeds.
+    /// # Errors
+    /// If restoring the machine fails, or the manager is shut down
+    pub fn machine(&mut self, key: &K) -> Result<&StateMachine<Err>, Err> {
+        if self.closed {
+            return Err(Error::from(StateMachineError::ShutDown {
+                key: key.to_string(),
+            })
+            .into());
+        }
+        if let Err(e) = self.evict_idle() {
+            error!("manager: {e}");
+        }
+        let now = self.clock.now();
+        self.tick += 1;
+        if !self.machines.contains_key(key) {
+            let machine = (self.factory)(key);
+            match self
+                .store
+                .as_ref()
+                .map(|store| store.load(key))
+                .transpose()?
+            {
+                Some(Some(snapshot)) => {
+                    debug!("manager: restoring {key} in {}", snapshot.state);
+                    machine.restore(&snapshot)?;
+                    lifecycle(&machine, &REHYDRATE)?;
+                }
+                _ => machine.start()?,
+            }
+            self.machines.insert(
+                key.clone(),
+                Instance {
+                    machine,
+                    last_used: now,
+                    tick: self.tick,
+                },
+            );
+            self.evict_over_capacity(key);
+        }
+        let instance = self
+            .machines
+            .get_mut(key)
+            .expect("the machine was just inserted");
+        instance.last_used = now;
+        instance.tick = self.tick;
+        Ok(&instance.machine)
+    }
+
+    /// Get the machine of a key if it is in memory, without creating it
+    pub fn get(&self, key: &K) -> Option<&StateMachine<Err>> {
+        self.machines.get(key).map(|instance| &instance.machine)
+    }
+
+    /// Number of machines in memory
+    pub fn len(&self) -> usize {
+        self.machines.len()
+    }
+
+    /// Check if there are no machines in memory
+    pub fn is_empty(&self) -> bool {
+        self.machines.is_empty()
+    }
+
+    /// Find the machines in memory that stayed in their state for longer than its maximum dwell time,
+    /// see [`StateMachine::check_stuck`]
+    pub fn check_stuck(&self) -> Vec<(K, Stuck)> {
+        self.machines
+            .iter()
+            .filter_map(|(key, instance)| Some((key.clone(), instance.machine.check_stuck()?)))
+            .collect()
+    }
+
+    /// Evict the machine of a key, firing [`EVICT`] and saving its snapshot to the store
+    /// # Returns
+    /// `false` if the machine was not in memory
+    /// # Errors
+    /// If the [`EVICT`] event or saving the snapshot fails, the machine is then kept
+    /// in the state, with the dwell times and approvals, it had before [`EVICT`]
+    pub fn evict(&mut self, key: &K) -> Result<bool> {
+        let Some(instance) = self.machines.get(key) else {
+            return Ok(false);
+        };
+        let machine = &instance.machine;
+        let before = SavePoint::of(machine);
+        let evicted = lifecycle(machine, &EVICT)
+            .map_err(|e| {
+                Error::from(StateMachineError::EvictionFailed {
+                    key: key.to_string(),
+                    reason: e.to_string(),
+                })
+            })
+            .and_then(|()| match self.store {
+                Some(ref store) => store.save(key, &machine.snapshot()),
+                None => Ok(()),
+            });
+        if let Err(e) = evicted {
+            before.restore(machine);
+            return Err(e);
+        }
+        debug!("manager: evicting {key}");
+        self.machines.remove(key);
+        Ok(true)
+    }
+
+    /// Evict the machines that were idle for longer than the idle TTL
+    /// # Returns
+    /// The number of evicted machines
+    /// # Errors
+    /// The first failing eviction, the other idle machines are still evicted
+    pub fn evict_idle(&mut self) -> Result<usize> {
+        let Some(ttl) = self.idle_ttl else {
+            return Ok(0);
+        };
+        let now = self.clock.now();
+        let idle: Vec<K> = self
+            .machines
+            .iter()
+            .filter(|(_, instance)| {
+                now.duration_since(instance.last_used)
+                    .is_ok_and(|idle| idle > ttl)
+            })
+            .map(|(key, _)| key.clone())
+            .collect();
+        let mut evicted = 0;
+        let mut failure = None;
+        for key in &idle {
+            match self.evict(key) {
+                Ok(_) => evicted += 1,
+                Err(e) => {
+                    failure.get_or_insert(e);
+                }
+            }
+        }
+        failure.map_or(Ok(evicted), Err)
+    }
+
+    /// Stop accepting events and save the snapshots of all machines in memory until `deadline`
+    ///
+    /// Without a store, the machines are just dropped.
+    /// # Returns
+    /// The number of saved machines, and the keys of the machines that could not be saved
+    pub fn shutdown(&mut self, deadline: Instant) -> ShutdownReport<K> {
+        self.closed = true;
+        let mut report = ShutdownReport::default();
+        let keys: Vec<K> = self.machines.keys().cloned().collect();
+        for key in keys {
+            if Instant::now() >= deadline {
+                report
+                    .unfinished
+                    .push((key, "deadline exceeded".to_string()));
+                continue;
+            }
+            match self.evict(&key) {
+                Ok(_) => report.completed += 1,
+                Err(e) => report.unfinished.push((key, e.to_string())),
+            }
+        }
+        report
+    }
+
+    /// Save the snapshots of all machines in memory to `store`, e.g. to checkpoint them during a deploy
+    ///
+    /// The machines stay in memory, the snapshots are taken and saved one at a time.
+    /// # Arguments
+    /// * `store` - where to save the snapshots, usually not the store of the manager
+    /// * `progress` - called after every saved snapshot with the number of saved snapshots and the total
+    /// # Returns
+    /// The number of saved snapshots
+    /// # Errors
+    /// On the first snapshot that cannot be saved, the snapshots saved before stay in the store
+    pub fn snapshot_all(
+        &self,
+        store: &dyn Store<K>,
+        mut progress: impl FnMut(usize, usize),
+    ) -> Result<usize> {
+        let total = self.machines.len();
+        for (done, (key, instance)) in self.machines.iter().enumerate() {
+            store.save(key, &instance.machine.snapshot())?;
+            progress(done + 1, total);
+        }
+        debug!("manager: saved {total} snapshots");
+        Ok(total)
+    }
+
+    /// Restore the machines of `keys` from the snapshots in `store`, e.g. after a deploy
+    ///
+    /// The keys are consumed one at a time, so they can be streamed from a database.
+    /// Restored machines replace the machines in memory with the same key,
+    /// the capacity of the manager still applies: older machines are evicted to its own store,
+    /// those that cannot be evicted are kept.
+    /// Keys without a snapshot are skipped.
+    /// # Arguments
+    /// * `store` - where to load the snapshots from
+    /// * `keys` - the keys to restore
+    /// * `progress` - called after every key with the number of keys done
+    /// # Returns
+    /// The number of restored machines
+    /// # Errors
+    /// On the first snapshot that cannot be loaded or restored
+    pub fn restore_all(
+        &mut self,
+        store: &dyn Store<K>,
+        keys: impl IntoIterator<Item = K>,
+        mut progress: impl FnMut(usize),
+    ) -> Result<usize, Err> {
+        let mut restored = 0;
+        for (done, key) in keys.into_iter().enumerate() {
+            if let Some(snapshot) = store.load(&key)? {
+                let machine = (self.factory)(&key);
+                machine.restore(&snapshot)?;
+                lifecycle(&machine, &REHYDRATE)?;
+                self.tick += 1;
+                self.machines.insert(
+                    key.clone(),
+                    Instance {
+                        machine,
+                        last_used: self.clock.now(),
+                        tick: self.tick,
+                    },
+                );
+                self.evict_over_capacity(&key);
+                restored += 1;
+            }
+            progress(done + 1);
+        }
+        debug!("manager: restored {restored} machines");
+        Ok(restored)
+    }
+
+    /// Evict the least recently used machines, other than `keep`, until the capacity is respected
+    ///
+    /// Machines that cannot be evicted are kept and skipped.
+    fn evict_over_capacity(&mut self, keep: &K) {
+        let Some(capacity) = self.capacity else {
+            return;
+        };
+        let mut kept = Vec::new();
+        while self.machines.len() > capacity.max(1) {
+            let oldest = self
+                .machines
+                .iter()
+                .filter(|(key, _)| *key != keep && !kept.contains(*key))
+                .min_by_key(|(_, instance)| instance.tick)
+                .map(|(key, _)| key.clone());
+            let Some(key) = oldest else {
+                break;
+            };
+            if let Err(e) = self.evict(&key) {
+                error!("manager: {e}");
+                kept.push(key);
+            }
+        }
+    }
+}
+
+/// The state of a machine before a lifecycle event, to go back to it if the eviction fails
+struct SavePoint {
+    state: State,
+    dwell: Dwell,
+    approvals: Approvals,
+}
+
+impl SavePoint {
+    fn of<Err>(machine: &StateMachine<Err>) -> Self {
+        Self {
+            state: machine.current_state(),
+            dwell: machine.dwell.lock().expect("failed to get lock").clone(),
+            approvals: machine
+                .approvals
+                .lock()
+                .expect("failed to get lock")
+                .clone(),
+        }
+    }
+
+    fn restore<Err>(self, machine: &StateMachine<Err>) {
+        let mut state = machine.state.write().expect("failed to get lock");
+        *machine.dwell.lock().expect("failed to get lock") = self.dwell;
+        *machine.approvals.lock().expect("failed to get lock") = self.approvals;
+        *state = self.state;
+    }
+}
+
+/// Fire a lifecycle event if an allowed transition handles it in the current state
+///
+/// Lifecycle events come from the manager, they are not authorized, approved or throttled.
+fn lifecycle<Err>(machine: &StateMachine<Err>, event: &Event) -> Result<(), Err>
+where
+    Err: From<Error> + fmt::Display + 'static,
+{
+    let mut state = machine
+        .state
+        .write()
+        .map_err(|_| Error::from(StateMachineError::LockPoisoned))?;
+    machine
+        .fire(&mut state, Dispatch::event(event), None)
+        .map_or(Ok(()), |(result, _)| result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Envelope, ManualClock, MemoryStore, Snapshot, StateMachineBuilder};
+    use std::rc::Rc;
+    #[cfg(feature = "tracing")]
+    use tracing_test::traced_test;
+
+    /// Shares a memory store between the manager and the test
+    struct Shared(Rc<MemoryStore<u32>>);
+
+    impl Store<u32> for Shared {
+        fn load(&self, key: &u32) -> Result<Option<Snapshot>> {
+            self.0.load(key)
+        }
+
+        fn save(&self, key: &u32, snapshot: &Snapshot) -> Result<()> {
+            self.0.save(key, snapshot)
+        }
+    }
+
+    fn order(key: &u32) -> StateMachine {
+        let open = State::new("open");
+        StateMachineBuilder::new(format!("order {key}"), &open)
+            .add_event(open, Event::new("pay"), State::new("paid"), None)
+            .build()
+    }
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_manager_lru() {
+        let store = Rc::new(MemoryStore::new());
+        let mut manager = MachineManager::new(order)
+            .with_capacity(2)
+            .with_store(Box::new(Shared(store.clone())));
+        let pay = Event::new("pay");
+
+        manager.event(&1, &pay).unwrap();
+        manager.machine(&2).unwrap();
+        manager.machine(&1).unwrap();
+        // 2 is the least recently used
+        manager.machine(&3).unwrap();
+        assert_eq!(manager.len(), 2);
+        assert!(manager.get(&2).is_none());
+        assert_eq!(store.len(), 1);
+
+        // 1 is evicted and restored in its state
+        manager.machine(&2).unwrap();
+        assert!(manager.get(&1).is_none());
+        let machine = manager.machine(&1).unwrap();
+        assert_eq!(machine.name(), "order 1");
+        assert_eq!(machine.current_state(), State::new("paid"));
+    }
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_manager_idle_ttl() {
+        let clock = Arc::new(ManualClock::default());
+        let mut manager = MachineManager::new(order)
+            .idle_ttl(Duration::from_secs(60))
+            .with_clock(clock.clone());
+        manager.machine(&1).unwrap();
+        clock.advance(Duration::from_secs(30));
+        manager.machine(&2).unwrap();
+        clock.advance(Duration::from_secs(31));
+        assert_eq!(manager.evict_idle().unwrap(), 1);
+        assert!(manager.get(&1).is_none());
+        assert!(manager.get(&2).is_some());
+        // without a store, an evicted machine starts over
+        manager.event(&1, &Event::new("pay")).unwrap();
+        assert!(manager.event(&1, &Event::new("pay")).is_err());
+    }
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_manager_shutdown() {
+        let store = Rc::new(MemoryStore::new());
+        let mut manager = MachineManager::new(order).with_store(Box::new(Shared(store.clone())));
+        manager.event(&1, &Event::new("pay")).unwrap();
+        manager.machine(&2).unwrap();
+
+        let report = manager.shutdown(Instant::now() + Duration::from_secs(10));
+        assert!(report.is_clean());
+        assert_eq!(report.completed, 2);
+        assert_eq!(store.len(), 2);
+        assert!(manager.is_empty());
+        assert_eq!(
+            manager.machine(&1).err().map(|e| e.to_string()),
+            Some("manager is shut down, rejecting 1".to_string())
+        );
+    }
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_bulk_snapshot_restore() {
+        let checkpoint = MemoryStore::new();
+        let mut manager = MachineManager::new(order);
+        for key in 1..=3 {
+            manager.event(&key, &Event::new("pay")).unwrap();
+        }
+        let mut saved = Vec::new();
+        let count = manager
+            .snapshot_all(&checkpoint, |done, total| saved.push((done, total)))
+            .unwrap();
+        assert_eq!(count, 3);
+        assert_eq!(saved, vec![(1, 3), (2, 3), (3, 3)]);
+
+        let mut restarted = MachineManager::new(order).with_capacity(2);
+        let mut done = 0;
+        let restored = restarted
+            .restore_all(&checkpoint, [1, 2, 3, 4], |n| done = n)
+            .unwrap();
+        assert_eq!((restored, done), (3, 4));
+        assert_eq!(restarted.len(), 2);
+        assert_eq!(
+            restarted.get(&3).map(StateMachine::current_state),
+            Some(State::new("paid"))
+        );
+    }
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_passivation_events() {
+        let store = Rc::new(MemoryStore::new());
+        let flushed = Rc::new(std::cell::Cell::new(0));
+        let f = flushed.clone();
+        let mut manager = MachineManager::new(move |key: &u32| {
+            let (active, passive) = (State::new("active"), State::new("passive"));
+            let f = f.clone();
+            StateMachineBuilder::new(format!("session {key}"), &active)
+                .add_event(
+                    active.clone(),
+                    EVICT,
+                    passive.clone(),
+                    Some(Box::new(move || {
+                        f.set(f.get() + 1);
+                        Ok(())
+                    })),
+                )
+                .add_event(passive, REHYDRATE, active.clone(), None)
+                .add_event(active.clone(), Event::new("ping"), active, None)
+                .build()
+        })
+        .with_capacity(1)
+        .with_store(Box::new(Shared(store.clone())));
+
+        manager.event(&1, &Event::new("ping")).unwrap();
+        manager.machine(&2).unwrap();
+        assert_eq!(flushed.get(), 1);
+        assert_eq!(
+            store.load(&1).unwrap().unwrap().state,
+            State::new("passive")
+        );
+        let machine = manager.machine(&1).unwrap();
+        assert_eq!(machine.current_state(), State::new("active"));
+    }
+
+    /// Refuses to save, e.g. while the database is down
+    struct Down;
+
+    impl Store<u32> for Down {
+        fn load(&self, _: &u32) -> Result<Option<Snapshot>> {
+            Ok(None)
+        }
+
+        fn save(&self, _: &u32, _: &Snapshot) -> Result<()> {
+            Err(crate::error::message("database down".to_string()))
+        }
+    }
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_failed_eviction() {
+        let (active, passive) = (State::new("active"), State::new("passive"));
+        let (a, p) = (active.clone(), passive.clone());
+        let mut manager = MachineManager::new(move |key: &u32| {
+            StateMachineBuilder::new(format!("session {key}"), &a)
+                .add_event(a.clone(), EVICT, p.clone(), None)
+                .with_authorizer(Box::new(|envelope: &Envelope, state: &State| {
+                    Err(crate::Unauthorized::new(envelope, state, "no principal"))
+                }))
+                .build()
+        })
+        .with_capacity(1)
+        .with_store(Box::new(Down));
+
+        manager.machine(&1).unwrap();
+        // the eviction of 1 fails, 2 is still created
+        assert!(manager.machine(&2).is_ok());
+        assert_eq!(manager.len(), 2);
+        // EVICT fired without the authorizer, then was undone when the save failed
+        assert_eq!(manager.get(&1).unwrap().current_state(), active);
+        assert!(manager.evict(&1).is_err());
+        assert_eq!(manager.get(&1).unwrap().current_state(), active);
+
+        let mut guarded = MachineManager::new(move |key: &u32| {
+            StateMachineBuilder::new(format!("session {key}"), &active)
+                .add_event(active.clone(), EVICT, passive.clone(), None)
+                .with_guard("never", || false)
+                .build()
+        });
+        guarded.machine(&1).unwrap();
+        // a guarded EVICT that does not fire does not prevent the eviction
+        assert!(guarded.evict(&1).unwrap());
+    }
+}
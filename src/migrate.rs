@@ -0,0 +1,266 @@
+//! Proposed state remaps between two versions of a definition
+
+use crate::{Definition, Diagram, Error, Result, Snapshot, State, StateMachineError};
+use std::collections::HashSet;
+use std::fmt;
+
+/// Why a state of the old definition is mapped to a state of the new one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Match {
+    /// same name
+    Exact,
+    /// the only unmatched new state sharing this tag
+    Tagged(String),
+    /// a similar name, with the edit distance between the names
+    Fuzzy(usize),
+    /// no proposal, the entry must be completed by hand
+    Unmatched,
+}
+
+/// The proposed new state of an old state
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemapEntry {
+    pub from: State,
+    /// `None` if no state was found
+    pub to: Option<State>,
+    pub reason: Match,
+}
+
+/// A state remap between two definitions, see [`Remap::propose`]
+///
+/// The remap is written as text, one entry per line, so it can be reviewed, edited and stored:
+/// ```text
+/// open -> open # exact
+/// awaiting_payment -> payment_pending # tag payment
+/// shiped -> shipped # fuzzy 1
+/// archived -> ? # unmatched
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Remap {
+    pub entries: Vec<RemapEntry>,
+}
+
+impl Remap {
+    /// Propose a remap of the states of `old` to the states of `new`
+    ///
+    /// States are matched by exact name first, then by a tag shared with exactly one remaining new state,
+    /// then by the closest name within an edit distance of a third of the name.
+    /// Every new state is proposed at most once.
+    /// # Arguments
+    /// * `tags` - tags of the states of both definitions, see [`Diagram::tag_state`]
+    pub fn propose(old: &Definition, new: &Definition, tags: &Diagram) -> Self {
+        let new_states = new.states();
+        let mut taken: HashSet<&State> = HashSet::new();
+        let mut entries: Vec<RemapEntry> = old
+            .states()
+            .into_iter()
+            .map(|from| match new_states.iter().find(|s| **s == from) {
+                Some(to) => {
+                    taken.insert(to);
+                    RemapEntry {
+                        from,
+                        to: Some(to.clone()),
+                        reason: Match::Exact,
+                    }
+                }
+                None => RemapEntry {
+                    from,
+                    to: None,
+                    reason: Match::Unmatched,
+                },
+            })
+            .collect();
+        for entry in entries.iter_mut().filter(|e| e.to.is_none()) {
+            for tag in tags.state_tags(&entry.from) {
+                let mut tagged = new_states
+                    .iter()
+                    .filter(|s| !taken.contains(s) && tags.state_tags(s).contains(tag));
+                if let (Some(to), None) = (tagged.next(), tagged.next()) {
+                    taken.insert(to);
+                    entry.to = Some(to.clone());
+                    entry.reason = Match::Tagged(tag.clone());
+                    break;
+                }
+            }
+        }
+        for entry in entries.iter_mut().filter(|e| e.to.is_none()) {
+            let closest = new_states
+                .iter()
+                .filter(|s| !taken.contains(s))
+                .map(|s| (distance(&entry.from.name, &s.name), s))
+                .filter(|(d, s)| *d <= entry.from.name.len().max(s.name.len()) / 3)
+                .min_by_key(|(d, _)| *d);
+            if let Some((d, to)) = closest {
+                taken.insert(to);
+                entry.to = Some(to.clone());
+                entry.reason = Match::Fuzzy(d);
+            }
+        }
+        Self { entries }
+    }
+
+    /// Parse a remap written by `to_string`
+    /// # Errors
+    /// If a line is not `<old> -> <new or ?>`, optionally followed by a `# comment`
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut entries = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let (mapping, comment) = line.split_once(" # ").unwrap_or((line, ""));
+            if mapping.trim().is_empty() {
+                continue;
+            }
+            let Some((from, to)) = mapping.split_once(" -> ") else {
+                return Err(Error::from(StateMachineError::Parse {
+                    line: Some(number + 1),
+                    column: None,
+                    message: "expected <old> -> <new>".to_string(),
+                }));
+            };
+            let to = to.trim();
+            let reason = match comment.trim().split_once(' ') {
+                _ if to == "?" => Match::Unmatched,
+                Some(("tag", tag)) => Match::Tagged(tag.to_string()),
+                Some(("fuzzy", d)) => Match::Fuzzy(d.parse().unwrap_or_default()),
+                _ => Match::Exact,
+            };
+            entries.push(RemapEntry {
+                from: State::new(from.trim()),
+                to: (to != "?").then(|| State::new(to)),
+                reason,
+            });
+        }
+        Ok(Self { entries })
+    }
+
+    /// The states without a proposal
+    pub fn unmatched(&self) -> Vec<&State> {
+        self.entries
+            .iter()
+            .filter(|e| e.to.is_none())
+            .map(|e| &e.from)
+            .collect()
+    }
+
+    /// Translate a snapshot taken with the old definition, to restore it into a machine of the new one
+    /// # Errors
+    /// If the state of the snapshot has no new state
+    pub fn apply(&self, snapshot: &Snapshot) -> Result<Snapshot> {
+        let to = self
+            .entries
+            .iter()
+            .find(|e| e.from == snapshot.state)
+            .and_then(|e| e.to.clone())
+            .ok_or_else(|| StateMachineError::UnmappedState {
+                state: snapshot.state.clone(),
+            })?;
+        Ok(Snapshot {
+            state: to,
+            ..snapshot.clone()
+        })
+    }
+}
+
+impl fmt::Display for Remap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for entry in &self.entries {
+            let to = entry
+                .to
+                .as_ref()
+                .map_or("?".to_string(), ToString::to_string);
+            let reason = match entry.reason {
+                Match::Exact => "exact".to_string(),
+                Match::Tagged(ref tag) => format!("tag {tag}"),
+                Match::Fuzzy(d) => format!("fuzzy {d}"),
+                Match::Unmatched => "unmatched".to_string(),
+            };
+            writeln!(f, "{} -> {to} # {reason}", entry.from)?;
+        }
+        Ok(())
+    }
+}
+
+/// Levenshtein distance between two names
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = (above + 1)
+                .min(row[j] + 1)
+                .min(diagonal + usize::from(ca != *cb));
+            diagonal = above;
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Event, StateMachineBuilder};
+    #[cfg(feature = "tracing")]
+    use tracing_test::traced_test;
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_propose_remap() {
+        let state = State::new;
+        let old = StateMachineBuilder::new("order", &state("open"))
+            .add_event(
+                state("open"),
+                Event::new("pay"),
+                state("awaiting_payment"),
+                None,
+            )
+            .add_event(
+                state("awaiting_payment"),
+                Event::new("ok"),
+                state("shiped"),
+                None,
+            )
+            .add_event(state("shiped"), Event::new("done"), state("archived"), None)
+            .definition();
+        let new = StateMachineBuilder::new("order", &state("open"))
+            .add_event(
+                state("open"),
+                Event::new("pay"),
+                state("payment_pending"),
+                None,
+            )
+            .add_event(
+                state("payment_pending"),
+                Event::new("ok"),
+                state("shipped"),
+                None,
+            )
+            .definition();
+        let tags = Diagram::new()
+            .tag_state(&state("awaiting_payment"), "payment")
+            .tag_state(&state("payment_pending"), "payment");
+
+        let remap = Remap::propose(&old, &new, &tags);
+        let text = remap.to_string();
+        assert_eq!(
+            text,
+            "open -> open # exact\n\
+             awaiting_payment -> payment_pending # tag payment\n\
+             shiped -> shipped # fuzzy 1\n\
+             archived -> ? # unmatched\n"
+        );
+        assert_eq!(Remap::parse(&text).unwrap(), remap);
+        assert_eq!(remap.unmatched(), vec![&state("archived")]);
+
+        let snapshot = Snapshot {
+            id: "s1".to_string(),
+            machine: "order".to_string(),
+            state: state("shiped"),
+            seq: 3,
+            deadlines: Vec::new(),
+        };
+        assert_eq!(remap.apply(&snapshot).unwrap().state, state("shipped"));
+        assert!(Remap::parse("open => closed").is_err());
+    }
+}
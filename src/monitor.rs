@@ -0,0 +1,247 @@
+use crate::{Event, Observer, State, StateMachine, TransitionInfo};
+use std::rc::Rc;
+use std::sync::Mutex;
+
+/// Maps a transition of the observed machine to an event of the monitor
+pub type EventMapper = Box<dyn Fn(&TransitionInfo) -> Option<Event>>;
+
+type ViolationCallback = Box<dyn Fn(&MonitorViolation)>;
+
+/// A transition of the observed machine that violated the property checked by a monitor
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MonitorViolation {
+    /// the state of the observed machine before the transition
+    pub from: State,
+    /// the event of the observed machine
+    pub event: Event,
+    /// the state of the observed machine after the transition
+    pub to: State,
+    /// the state of the monitor after consuming the transition
+    pub monitor_state: State,
+}
+
+struct Inner {
+    machine: StateMachine,
+    violation: State,
+    strict: bool,
+    mapper: EventMapper,
+    on_violation: Option<ViolationCallback>,
+    violations: Mutex<Vec<MonitorViolation>>,
+}
+
+/// Runtime verification of another machine
+///
+/// A monitor is a state machine whose events are the transitions of the observed machine,
+/// by default the event of every transition is forwarded with the same name.
+/// When the monitor enters its violation state, the transition is flagged as a violation.
+/// The monitor is attached as an observer, clones share the same monitor machine.
+#[derive(Clone)]
+pub struct Monitor {
+    inner: Rc<Inner>,
+}
+
+impl Monitor {
+    /// Create a monitor
+    /// # Arguments
+    /// * `machine` - the monitor machine
+    /// * `violation` - the state of the monitor machine that flags a violation
+    #[must_use]
+    pub fn new(machine: StateMachine, violation: State) -> Self {
+        Self {
+            inner: Rc::new(Inner {
+                machine,
+                violation,
+                strict: false,
+                mapper: Box::new(|t| Some(t.event.clone())),
+                on_violation: None,
+                violations: Mutex::new(Vec::new()),
+            }),
+        }
+    }
+
+    fn options(&mut self) -> &mut Inner {
+        Rc::get_mut(&mut self.inner).expect("monitor options must be set before attaching it")
+    }
+
+    #[must_use]
+    /// Also flag transitions the monitor machine has no transition for
+    /// # Panics
+    /// If the monitor is already attached
+    pub fn strict(mut self) -> Self {
+        self.options().strict = true;
+        self
+    }
+
+    #[must_use]
+    /// Choose which event the monitor receives for a transition, `None` skips the transition
+    /// # Panics
+    /// If the monitor is already attached
+    pub fn map_events(
+        mut self,
+        mapper: impl Fn(&TransitionInfo) -> Option<Event> + 'static,
+    ) -> Self {
+        self.options().mapper = Box::new(mapper);
+        self
+    }
+
+    #[must_use]
+    /// Call `callback` for every violation
+    /// # Panics
+    /// If the monitor is already attached
+    pub fn on_violation(mut self, callback: impl Fn(&MonitorViolation) + 'static) -> Self {
+        self.options().on_violation = Some(Box::new(callback));
+        self
+    }
+
+    /// Reset the monitor machine and forget the flagged violations
+    /// # Panics
+    /// If the lock is poisoned
+    pub fn reset(&self) {
+        self.inner.machine.reset();
+        self.inner
+            .violations
+            .lock()
+            .expect("failed to get lock")
+            .clear();
+    }
+
+    /// Get the violations flagged so far
+    /// # Panics
+    /// If the lock is poisoned
+    pub fn violations(&self) -> Vec<MonitorViolation> {
+        self.inner
+            .violations
+            .lock()
+            .expect("failed to get lock")
+            .clone()
+    }
+
+    /// Check if a violation was flagged
+    pub fn is_violated(&self) -> bool {
+        !self.violations().is_empty()
+    }
+
+    /// Get the current state of the monitor machine
+    pub fn current_state(&self) -> State {
+        self.inner.machine.current_state()
+    }
+
+    fn consume(&self, transition: &TransitionInfo) {
+        let inner = &self.inner;
+        let Some(event) = (inner.mapper)(transition) else {
+            return;
+        };
+        let handled = inner.machine.event(&event).is_ok();
+        let monitor_state = inner.machine.current_state();
+        if monitor_state == inner.violation || (!handled && inner.strict) {
+            let violation = MonitorViolation {
+                from: transition.from.clone(),
+                event: transition.event.clone(),
+                to: transition.to.clone(),
+                monitor_state,
+            };
+            if let Some(ref callback) = inner.on_violation {
+                callback(&violation);
+            }
+            inner
+                .violations
+                .lock()
+                .expect("failed to get lock")
+                .push(violation);
+        }
+    }
+}
+
+impl<Err> Observer<Err> for Monitor {
+    fn on_transition(&self, transition: &TransitionInfo) {
+        self.consume(transition);
+    }
+
+    fn on_action_failed(&self, transition: &TransitionInfo, _error: &Err) {
+        // the transition was taken, even if its action failed, unlike a rolled back one
+        self.consume(transition);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::StateMachineBuilder;
+    use std::cell::Cell;
+    #[cfg(feature = "tracing")]
+    use tracing_test::traced_test;
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_monitor_flags_violation() {
+        let created = State::new("created");
+        let paid = State::new("paid");
+        let shipped = State::new("shipped");
+        let pay = Event::new("pay");
+        let ship = Event::new("ship");
+        let reset = Event::new("reset");
+
+        // property: `ship` only after `pay`
+        let waiting = State::new("waiting");
+        let ok = State::new("ok");
+        let violation = State::new("violation");
+        let property = StateMachineBuilder::new("paid before shipped", &waiting)
+            .add_event(waiting.clone(), pay.clone(), ok.clone(), None)
+            .add_event(waiting.clone(), ship.clone(), violation.clone(), None)
+            .build();
+        let calls = Rc::new(Cell::new(0));
+        let calls_clone = calls.clone();
+        let monitor = Monitor::new(property, violation.clone())
+            .on_violation(move |_| calls_clone.set(calls_clone.get() + 1));
+
+        let machine = StateMachineBuilder::new("order", &created)
+            .add_event(created.clone(), pay.clone(), paid.clone(), None)
+            .add_event(created.clone(), ship.clone(), shipped.clone(), None)
+            .add_event(paid.clone(), ship.clone(), shipped.clone(), None)
+            .add_any_state_event(reset.clone(), created.clone(), None)
+            .add_observer(Box::new(monitor.clone()))
+            .build();
+
+        machine.event(&pay).unwrap();
+        machine.event(&ship).unwrap();
+        assert!(!monitor.is_violated());
+        assert_eq!(monitor.current_state(), ok);
+
+        machine.event(&reset).unwrap();
+        monitor.reset();
+        machine.event(&ship).unwrap();
+        assert_eq!(
+            monitor.violations(),
+            vec![MonitorViolation {
+                from: created,
+                event: ship,
+                to: shipped,
+                monitor_state: violation,
+            }]
+        );
+        assert_eq!(calls.get(), 1);
+    }
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_strict_monitor() {
+        let a = State::new("a");
+        let b = State::new("b");
+        let e1 = Event::new("e1");
+        let e2 = Event::new("e2");
+        let property = StateMachineBuilder::new("only e1", &a)
+            .add_event(a.clone(), e1.clone(), a.clone(), None)
+            .build();
+        let monitor = Monitor::new(property, b.clone()).strict();
+        let machine = StateMachineBuilder::new("test", &a)
+            .add_event(a.clone(), e1.clone(), b.clone(), None)
+            .add_event(b.clone(), e2.clone(), a.clone(), None)
+            .add_observer(Box::new(monitor.clone()))
+            .build();
+
+        machine.event(&e1).unwrap();
+        assert!(!monitor.is_violated());
+        machine.event(&e2).unwrap();
+        assert_eq!(monitor.violations()[0].event, e2);
+    }
+}
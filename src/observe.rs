@@ -0,0 +1,7 @@
+//! Watching machines run: observers, the history, monitors, statistics and health
+
+pub use crate::{
+    Cause, DwellStats, EventMapper, Health, HistoryFilter, Monitor, MonitorViolation, MustFollow,
+    Observer, Outcome, Stuck, TransitionInfo, TransitionRecord, TransitionStats, AUDIT_SCHEMA,
+    STATUS_SCHEMA,
+};
@@ -0,0 +1,130 @@
+use crate::{Cause, Error, Event, State, Stuck};
+use std::any::Any;
+
+/// A transition as seen by observers
+#[derive(Debug, Clone, Copy)]
+pub struct TransitionInfo<'a> {
+    /// name of the state machine
+    pub machine: &'a str,
+    /// the state before the transition
+    pub from: &'a State,
+    /// the event that triggered the transition
+    pub event: &'a Event,
+    /// the state after the transition
+    pub to: &'a State,
+    /// the transition that generated the event, if any
+    pub cause: Option<&'a Cause>,
+}
+
+/// Gets notified of the outcome of every transition
+///
+/// Observers are called while the machine is locked, they must not call back into the machine.
+/// All callbacks default to doing nothing, so implementors only override what they need.
+pub trait Observer<Err = Error> {
+    /// Called after a transition whose action (if any) succeeded
+    fn on_transition(&self, _transition: &TransitionInfo) {}
+
+    /// Called when the action of a transition returned an error
+    fn on_action_failed(&self, _transition: &TransitionInfo, _error: &Err) {}
+
+    /// Called instead of [`Observer::on_action_failed`] when the transition is rolled back,
+    /// see [`FailurePolicy::RollbackOnError`](crate::FailurePolicy::RollbackOnError)
+    ///
+    /// The transition is not taken, the machine is back in `transition.from`.
+    fn on_rolled_back(&self, _transition: &TransitionInfo, _error: &Err) {}
+
+    /// Called when the action of a transition panicked, before the panic is resumed
+    /// # Arguments
+    /// * `message` - the panic message, if it was a string
+    fn on_action_panicked(&self, _transition: &TransitionInfo, _message: Option<&str>) {}
+
+    /// Called once per visit of a state when [`StateMachine::check_stuck`](crate::StateMachine::check_stuck)
+    /// finds the machine in it for longer than its maximum dwell time
+    fn on_stuck(&self, _stuck: &Stuck) {}
+}
+
+/// Extract the message of a panic payload
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> Option<&str> {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::StateMachineBuilder;
+    use std::panic;
+    use std::sync::{Arc, Mutex};
+    #[cfg(feature = "tracing")]
+    use tracing_test::traced_test;
+
+    #[derive(Default)]
+    struct Recorder {
+        calls: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Observer for Recorder {
+        fn on_transition(&self, t: &TransitionInfo) {
+            let call = format!("ok {} {} -> {}", t.event, t.from, t.to);
+            self.calls.lock().unwrap().push(call);
+        }
+
+        fn on_action_failed(&self, t: &TransitionInfo, error: &Error) {
+            let call = format!("failed {}: {error}", t.event);
+            self.calls.lock().unwrap().push(call);
+        }
+
+        fn on_action_panicked(&self, t: &TransitionInfo, message: Option<&str>) {
+            let call = format!("panicked {}: {}", t.event, message.unwrap_or("?"));
+            self.calls.lock().unwrap().push(call);
+        }
+    }
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_observer_outcomes() {
+        let initial = State::new("initial");
+        let second = State::new("second");
+        let ok = Event::new("ok");
+        let fail = Event::new("fail");
+        let boom = Event::new("boom");
+        let recorder = Recorder::default();
+        let calls = recorder.calls.clone();
+        let machine = StateMachineBuilder::new("test", &initial)
+            .add_event(initial.clone(), ok.clone(), second.clone(), None)
+            .add_event(
+                second.clone(),
+                fail.clone(),
+                initial.clone(),
+                Some(Box::new(|| {
+                    Err(crate::error::message("action failed".to_string()))
+                })),
+            )
+            .add_event(
+                initial.clone(),
+                boom.clone(),
+                initial.clone(),
+                Some(Box::new(|| panic!("kaboom"))),
+            )
+            .add_observer(Box::new(recorder))
+            .build();
+
+        machine.event(&ok).unwrap();
+        assert!(machine.event(&fail).is_err());
+        // no transition: observers are not called
+        assert!(machine.event(&fail).is_err());
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| machine.event(&boom)));
+        assert!(result.is_err());
+
+        assert_eq!(
+            *calls.lock().unwrap(),
+            vec![
+                "ok ok initial -> second",
+                "failed fail: action failed",
+                "panicked boom: kaboom",
+            ]
+        );
+    }
+}
@@ -0,0 +1,147 @@
+//! Ordering constraints between events, e.g. `ship` must follow `pay`
+
+use crate::{Event, Monitor, MonitorViolation, State, StateMachine, StateMachineBuilder};
+use std::collections::HashSet;
+use std::fmt;
+
+/// An event that may only fire after another one fired somewhere before it in the trace
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MustFollow {
+    pub event: Event,
+    /// the event that must have fired before
+    pub after: Event,
+}
+
+impl fmt::Display for MustFollow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} must follow {}", self.event, self.after)
+    }
+}
+
+impl MustFollow {
+    /// Create a monitor flagging the transitions of `event` before any transition of `after`,
+    /// to enforce at runtime a constraint that [`StateMachine::validate`] cannot prove
+    /// Reset the monitor together with the observed machine, see [`Monitor::reset`].
+    pub fn monitor(&self) -> Monitor {
+        let (waiting, done, violation) = (
+            State::new("waiting"),
+            State::new("done"),
+            State::new("violation"),
+        );
+        let machine = StateMachineBuilder::new(self.to_string(), &waiting)
+            .add_event(waiting.clone(), self.after.clone(), done, None)
+            .add_event(waiting, self.event.clone(), violation.clone(), None)
+            .build();
+        Monitor::new(machine, violation)
+    }
+}
+
+impl<Err> StateMachine<Err> {
+    /// Get the transitions that broke an ordering constraint so far, see [`StateMachineBuilder::must_follow`]
+    ///
+    /// They are flagged, not prevented: the transition was taken.
+    /// The constraints are tracked since the machine was built or [`StateMachine::reset`],
+    /// a restored machine does not know which events fired before its snapshot.
+    pub fn ordering_violations(&self) -> Vec<MonitorViolation> {
+        self.ordering_monitors
+            .iter()
+            .flat_map(Monitor::violations)
+            .collect()
+    }
+
+    /// Check if a path from the initial state or an entry point fires the event of a constraint
+    /// without firing the event it must follow, assuming every guard can pass
+    pub(crate) fn may_violate(&self, constraint: &MustFollow) -> bool {
+        let mut pending: Vec<(State, bool)> = std::iter::once(&self.initial_state)
+            .chain(self.entry_points.iter().map(|(_, state)| state))
+            .map(|state| (state.clone(), false))
+            .collect();
+        let mut visited = HashSet::new();
+        let events = self.table.events();
+        while let Some((state, followed)) = pending.pop() {
+            if !visited.insert((state.clone(), followed)) {
+                continue;
+            }
+            for event in &events {
+                for transition in self.table.candidates(&state, event) {
+                    if !followed && *event == constraint.event {
+                        return true;
+                    }
+                    pending.push((
+                        transition.new_state.clone(),
+                        followed || *event == constraint.after,
+                    ));
+                }
+            }
+        }
+        false
+    }
+}
+
+impl<Err> StateMachineBuilder<Err> {
+    #[must_use]
+    /// Declare that `event` may only fire after `after` fired
+    ///
+    /// The constraint is checked statically by [`StateMachine::validate`],
+    /// and at runtime by a [`MustFollow::monitor`] that `build` attaches, see [`StateMachine::ordering_violations`].
+    pub fn must_follow(mut self, event: Event, after: Event) -> Self {
+        self.ordering.push(MustFollow { event, after });
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "tracing")]
+    use tracing_test::traced_test;
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_must_follow() {
+        let (cart, paid, shipped) = (
+            State::new("cart"),
+            State::new("paid"),
+            State::new("shipped"),
+        );
+        let (pay, ship, refund) = (Event::new("pay"), Event::new("ship"), Event::new("refund"));
+        let build = |shortcut: bool| {
+            let builder = StateMachineBuilder::new("order", &cart)
+                .add_event(cart.clone(), pay.clone(), paid.clone(), None)
+                .add_event(paid.clone(), ship.clone(), shipped.clone(), None)
+                .add_event(shipped.clone(), refund.clone(), cart.clone(), None)
+                .must_follow(ship.clone(), pay.clone())
+                .must_follow(refund.clone(), ship.clone());
+            if shortcut {
+                builder.add_event(cart.clone(), ship.clone(), shipped.clone(), None)
+            } else {
+                builder
+            }
+        };
+
+        assert!(build(false).build().validate().unordered.is_empty());
+        let validation = build(true).build().validate();
+        let constraint = MustFollow {
+            event: ship.clone(),
+            after: pay.clone(),
+        };
+        assert_eq!(validation.unordered, vec![constraint.clone()]);
+        assert!(validation
+            .to_string()
+            .contains("ship must follow pay, but may fire before it\n"));
+
+        let machine = build(true).build();
+        machine.event(&pay).unwrap();
+        machine.event(&ship).unwrap();
+        machine.event(&refund).unwrap();
+        assert!(machine.ordering_violations().is_empty());
+        machine.reset();
+        machine.event(&ship).unwrap();
+        let violations = machine.ordering_violations();
+        assert_eq!(violations.len(), 1);
+        assert_eq!(
+            (violations[0].from.clone(), violations[0].event.clone()),
+            (cart, ship)
+        );
+    }
+}
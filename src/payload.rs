@@ -0,0 +1,114 @@
+//! Events carrying data to the actions of their transitions
+
+use crate::dedup::Dispatch;
+use crate::table::{Payload, Run, Transition};
+use crate::{Error, Event, State, StateMachine, StateMachineBuilder, StateMachineError};
+use std::any::{self, Any};
+use std::fmt;
+
+/// An action getting the payload of its event, see [`StateMachineBuilder::add_event_with`]
+pub type PayloadAction<P, Err = Error> = Box<dyn Fn(&P) -> Result<(), Err>>;
+
+impl<Err> StateMachine<Err>
+where
+    Err: From<Error> + fmt::Display + 'static,
+{
+    /// Handle an event carrying data, e.g. the bytes received or the id of a user
+    ///
+    /// The action of the transition gets the payload if it was added with [`StateMachineBuilder::add_event_with`],
+    /// other actions ignore it.
+    /// An event over the limit of a deferring [`Throttle`](crate::Throttle) is rejected, the deferred event would lose its payload.
+    /// # Errors
+    /// As [`StateMachine::event`]
+    pub fn event_with<P: Any>(&self, event: &Event, payload: &P) -> Result<(), Err> {
+        let mut state = self
+            .state
+            .write()
+            .map_err(|_| Error::from(StateMachineError::LockPoisoned))?;
+        self.handle(&mut state, Dispatch::event(event), Some(payload), || {})
+    }
+}
+
+impl<Err> StateMachineBuilder<Err>
+where
+    Err: From<Error> + 'static,
+{
+    #[must_use]
+    /// Add an event whose action gets the payload passed to [`StateMachine::event_with`]
+    ///
+    /// The transition fires like the ones of `add_event`, its action fails when the event comes
+    /// without a payload of type `P`.
+    /// # Arguments
+    /// * `old_state` - the state before the transition
+    /// * `event` - the event
+    /// * `new_state` - the state after the transition
+    /// * `action` - the action, getting the payload
+    pub fn add_event_with<P: Any>(
+        mut self,
+        old_state: State,
+        event: Event,
+        new_state: State,
+        action: PayloadAction<P, Err>,
+    ) -> Self {
+        let name = event.clone();
+        let run = Run::WithPayload(Box::new(move |payload: Payload| {
+            match payload.and_then(<dyn Any>::downcast_ref::<P>) {
+                Some(payload) => action(payload),
+                None => Err(Error::from(StateMachineError::MissingPayload {
+                    event: name.clone(),
+                    expected: any::type_name::<P>(),
+                })
+                .into()),
+            }
+        }));
+        self.table.add(Transition {
+            from: Some(old_state),
+            trigger: event,
+            new_state,
+            action: Some(run),
+            guard: None,
+            priority: None,
+            approvals: None,
+        });
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    #[cfg(feature = "tracing")]
+    use tracing_test::traced_test;
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_event_payload() {
+        let (idle, receiving) = (State::new("idle"), State::new("receiving"));
+        let (data, close) = (Event::new("data"), Event::new("close"));
+        let received = Rc::new(RefCell::new(Vec::new()));
+        let received_clone = received.clone();
+        let machine = StateMachineBuilder::new("socket", &idle)
+            .add_event_with(
+                idle.clone(),
+                data.clone(),
+                receiving.clone(),
+                Box::new(move |bytes: &Vec<u8>| {
+                    received_clone.borrow_mut().extend_from_slice(bytes);
+                    Ok(())
+                }),
+            )
+            .add_event(receiving.clone(), close.clone(), idle.clone(), None)
+            .build();
+
+        machine.event_with(&data, &vec![1u8, 2]).unwrap();
+        assert_eq!(*received.borrow(), vec![1, 2]);
+        // actions of add_event ignore the payload
+        machine.event_with(&close, &"bye").unwrap();
+        assert_eq!(
+            machine.event(&data).unwrap_err().to_string(),
+            "event data needs a payload of type alloc::vec::Vec<u8>"
+        );
+    }
+}
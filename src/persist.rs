@@ -0,0 +1,6 @@
+//! Keeping machines across restarts: snapshots, stores, codecs, migrations and the manager
+
+pub use crate::{
+    BlobStore, EncodedStore, Factory, MachineManager, Match, MemoryStore, PlainCodec, Recovery,
+    Remap, RemapEntry, Rewind, Snapshot, SnapshotCodec, Store, EVICT, REHYDRATE,
+};
@@ -0,0 +1,315 @@
+//! Machines sharded over worker threads
+
+use crate::trace::{debug, error};
+use crate::{Event, MachineManager, Result, State, StateMachine, StateMachineError};
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread::{self, JoinHandle};
+
+enum Command<K> {
+    Event(K, Event, Option<mpsc::Sender<Result<()>>>),
+    State(K, mpsc::Sender<Option<State>>),
+}
+
+#[derive(Default)]
+struct Counters {
+    machines: AtomicUsize,
+    handled: AtomicU64,
+    failed: AtomicU64,
+    queued: AtomicUsize,
+    stopped: AtomicBool,
+}
+
+/// Marks its shard stopped when the worker exits, even when an action panicked
+struct Stopped {
+    index: usize,
+    counters: Arc<Counters>,
+}
+
+impl Drop for Stopped {
+    fn drop(&mut self) {
+        self.counters.stopped.store(true, Ordering::Relaxed);
+        // the commands left in the channel are dropped with it
+        self.counters.queued.store(0, Ordering::Relaxed);
+        if thread::panicking() {
+            error!("pool: shard {} panicked", self.index);
+        } else {
+            debug!("pool: shard {} stopped", self.index);
+        }
+    }
+}
+
+/// Activity of one shard of a [`ShardedPool`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ShardStats {
+    /// number of machines owned by the shard
+    pub machines: usize,
+    /// number of events handled successfully
+    pub handled: u64,
+    /// number of events that failed
+    pub failed: u64,
+    /// number of commands waiting for the shard
+    pub queued: usize,
+    /// the worker of the shard stopped, e.g. because an action panicked, its machines are lost
+    pub stopped: bool,
+}
+
+struct Shard<K> {
+    sender: Option<mpsc::Sender<Command<K>>>,
+    counters: Arc<Counters>,
+    worker: Option<JoinHandle<()>>,
+}
+
+/// Machines spread over worker threads by key
+///
+/// Every shard is a thread owning a [`MachineManager`] for the keys that hash to it,
+/// so machines never move between threads and shards share no locks.
+/// Machines are created on their shard by the factory, which must therefore be `Send` and `Sync`.
+/// Dropping the pool lets the shards handle the queued commands, then joins them.
+/// A shard stops when an action panics without an error state (see [`StateMachineBuilder::error_state`](crate::StateMachineBuilder::error_state)),
+/// its keys then get [`StateMachineError::ShardStopped`].
+pub struct ShardedPool<K> {
+    shards: Vec<Shard<K>>,
+}
+
+impl<K> ShardedPool<K>
+where
+    K: Eq + Hash + Clone + fmt::Display + Send + 'static,
+{
+    /// Start a pool
+    /// # Arguments
+    /// * `shards` - the number of worker threads, at least 1
+    /// * `factory` - creates the machine of a key, on the shard of the key
+    pub fn new(
+        shards: usize,
+        factory: impl Fn(&K) -> StateMachine + Send + Sync + 'static,
+    ) -> Self {
+        let factory = Arc::new(factory);
+        let shards = (0..shards.max(1))
+            .map(|index| {
+                let (sender, receiver) = mpsc::channel();
+                let counters = Arc::new(Counters::default());
+                let factory = factory.clone();
+                let shard_counters = counters.clone();
+                let worker = thread::spawn(move || {
+                    let _stopped = Stopped {
+                        index,
+                        counters: shard_counters.clone(),
+                    };
+                    let mut manager = MachineManager::new(move |key: &K| factory(key));
+                    for command in receiver {
+                        shard_counters.queued.fetch_sub(1, Ordering::Relaxed);
+                        run(&mut manager, command, &shard_counters);
+                        shard_counters
+                            .machines
+                            .store(manager.len(), Ordering::Relaxed);
+                    }
+                });
+                Shard {
+                    sender: Some(sender),
+                    counters,
+                    worker: Some(worker),
+                }
+            })
+            .collect();
+        Self { shards }
+    }
+
+    /// The index of the shard owning the machine of a key
+    pub fn shard_of(&self, key: &K) -> usize {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let shards = self.shards.len() as u64;
+        usize::try_from(hasher.finish() % shards).unwrap_or_default()
+    }
+
+    /// Queue a command on the shard of a key
+    /// # Errors
+    /// If the worker of the shard stopped
+    fn send(&self, key: &K, command: Command<K>) -> Result<(), StateMachineError> {
+        let shard = &self.shards[self.shard_of(key)];
+        let sender = shard
+            .sender
+            .as_ref()
+            .filter(|_| !shard.counters.stopped.load(Ordering::Relaxed))
+            .ok_or(StateMachineError::ShardStopped)?;
+        shard.counters.queued.fetch_add(1, Ordering::Relaxed);
+        sender.send(command).map_err(|_| {
+            // the worker exited since, it reset the count
+            let _ = shard.counters.queued.fetch_update(
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+                |queued| Some(queued.saturating_sub(1)),
+            );
+            StateMachineError::ShardStopped
+        })
+    }
+
+    /// Queue an event for the machine of a key, without waiting for it
+    ///
+    /// An event queued just before its shard stops is lost, use [`ShardedPool::dispatch_and_wait`] to know it was handled.
+    /// # Errors
+    /// If the shard of the key stopped
+    pub fn dispatch(&self, key: K, event: Event) -> Result<()> {
+        self.send(&key.clone(), Command::Event(key, event, None))?;
+        Ok(())
+    }
+
+    /// Queue an event for the machine of a key and wait until its shard handled it
+    /// # Errors
+    /// As [`MachineManager::event`], or if the shard stopped
+    pub fn dispatch_and_wait(&self, key: K, event: Event) -> Result<()> {
+        let (reply, result) = mpsc::channel();
+        self.send(&key.clone(), Command::Event(key, event, Some(reply)))?;
+        result.recv().map_err(|_| StateMachineError::ShardStopped)?
+    }
+
+    /// Get the current state of the machine of a key, once the commands queued before are handled
+    /// # Returns
+    /// `None` if the shard does not have a machine for the key
+    /// # Errors
+    /// If the shard of the key stopped
+    pub fn current_state(&self, key: K) -> Result<Option<State>> {
+        let (reply, state) = mpsc::channel();
+        self.send(&key.clone(), Command::State(key, reply))?;
+        Ok(state.recv().map_err(|_| StateMachineError::ShardStopped)?)
+    }
+
+    /// Get the activity of every shard
+    pub fn stats(&self) -> Vec<ShardStats> {
+        self.shards
+            .iter()
+            .map(|shard| ShardStats {
+                machines: shard.counters.machines.load(Ordering::Relaxed),
+                handled: shard.counters.handled.load(Ordering::Relaxed),
+                failed: shard.counters.failed.load(Ordering::Relaxed),
+                queued: shard.counters.queued.load(Ordering::Relaxed),
+                stopped: shard.counters.stopped.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+}
+
+fn run<K>(manager: &mut MachineManager<K>, command: Command<K>, counters: &Counters)
+where
+    K: Eq + Hash + Clone + fmt::Display,
+{
+    match command {
+        Command::Event(key, event, reply) => {
+            let result = manager.event(&key, &event);
+            let counter = if result.is_ok() {
+                &counters.handled
+            } else {
+                &counters.failed
+            };
+            counter.fetch_add(1, Ordering::Relaxed);
+            if let Some(reply) = reply {
+                let _ = reply.send(result);
+            }
+        }
+        Command::State(key, reply) => {
+            let _ = reply.send(manager.get(&key).map(StateMachine::current_state));
+        }
+    }
+}
+
+impl<K> Drop for ShardedPool<K> {
+    fn drop(&mut self) {
+        for shard in &mut self.shards {
+            shard.sender.take();
+        }
+        for shard in &mut self.shards {
+            if let Some(worker) = shard.worker.take() {
+                let _ = worker.join();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::StateMachineBuilder;
+    #[cfg(feature = "tracing")]
+    use tracing_test::traced_test;
+
+    fn counter(key: &u32) -> StateMachine {
+        let (even, odd) = (State::new("even"), State::new("odd"));
+        let tick = Event::new("tick");
+        StateMachineBuilder::new(format!("counter {key}"), &even)
+            .add_event(even.clone(), tick.clone(), odd.clone(), None)
+            .add_event(odd, tick, even, None)
+            .build()
+    }
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_sharded_pool() {
+        let pool = ShardedPool::new(4, counter);
+        for key in 0..20u32 {
+            for _ in 0..=key {
+                pool.dispatch(key, Event::new("tick")).unwrap();
+            }
+        }
+        assert!(pool.dispatch_and_wait(3, Event::new("tock")).is_err());
+        for key in 0..20u32 {
+            let expected = if key % 2 == 0 { "odd" } else { "even" };
+            assert_eq!(pool.current_state(key).unwrap(), Some(State::new(expected)));
+        }
+        assert_eq!(pool.current_state(99).unwrap(), None);
+
+        let stats = pool.stats();
+        assert_eq!(stats.len(), 4);
+        assert_eq!(stats.iter().map(|s| s.handled).sum::<u64>(), 210);
+        assert_eq!(stats.iter().map(|s| s.failed).sum::<u64>(), 1);
+        assert_eq!(stats.iter().map(|s| s.machines).sum::<usize>(), 20);
+        assert!(stats.iter().all(|s| s.queued == 0 && !s.stopped));
+        assert_eq!(pool.shard_of(&7), pool.shard_of(&7));
+    }
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_stopped_shard() {
+        let idle = State::new("idle");
+        let pool = ShardedPool::new(2, move |key: &u32| {
+            StateMachineBuilder::new(format!("worker {key}"), &idle)
+                .add_event(
+                    idle.clone(),
+                    Event::new("crash"),
+                    idle.clone(),
+                    Some(Box::new(|| panic!("bug"))),
+                )
+                .add_event(idle.clone(), Event::new("ping"), idle.clone(), None)
+                .build()
+        });
+        let other = (1..)
+            .find(|key| pool.shard_of(key) != pool.shard_of(&0))
+            .unwrap();
+
+        let stopped = |error: crate::Error| {
+            matches!(
+                error.downcast_ref::<StateMachineError>(),
+                Some(StateMachineError::ShardStopped)
+            )
+        };
+        assert!(stopped(
+            pool.dispatch_and_wait(0, Event::new("crash")).unwrap_err()
+        ));
+        // the reply is dropped while the worker unwinds, before it is marked stopped
+        while !pool.stats()[pool.shard_of(&0)].stopped {
+            thread::yield_now();
+        }
+        assert!(stopped(pool.dispatch(0, Event::new("ping")).unwrap_err()));
+        assert!(stopped(pool.current_state(0).unwrap_err()));
+        let stats = &pool.stats()[pool.shard_of(&0)];
+        assert!(stats.stopped);
+        assert_eq!(stats.queued, 0);
+
+        // the other shard keeps running
+        pool.dispatch_and_wait(other, Event::new("ping")).unwrap();
+        assert!(!pool.stats()[pool.shard_of(&other)].stopped);
+    }
+}
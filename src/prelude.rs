@@ -0,0 +1,10 @@
+//! The types most machines need, for a glob import: `use state_machine::prelude::*;`
+//!
+//! Everything is also exported at the root of the crate and in the module of its area, e.g. [`persist`](crate::persist),
+//! where new subsystems are added.
+//! The prelude only grows with types as common as these, so a glob import keeps compiling across releases.
+
+pub use crate::{
+    Action, Error, Event, Observer, Outcome, Result, Snapshot, State, StateMachine,
+    StateMachineBuilder, StateMachineError, Store, TransitionInfo, TransitionRecord,
+};
@@ -0,0 +1,164 @@
+//! Offline replay of recorded transitions against a [`Definition`]
+
+use crate::{Definition, Event, State, TransitionRecord};
+use std::thread;
+
+/// A recorded transition that the definition does not reproduce
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    /// position of the record in the log
+    pub position: usize,
+    pub event: Event,
+    /// the state before the transition
+    pub from: State,
+    /// the recorded state after the transition
+    pub recorded: State,
+    /// the state the definition leads to, `None` if it does not handle the event
+    pub replayed: Option<State>,
+}
+
+/// Result of replaying one log
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Replay {
+    /// the state after the last record
+    pub final_state: State,
+    /// the records the definition does not reproduce
+    pub divergences: Vec<Divergence>,
+}
+
+impl Replay {
+    /// Check if the definition reproduces the whole log
+    pub fn is_consistent(&self) -> bool {
+        self.divergences.is_empty()
+    }
+}
+
+impl Definition {
+    /// Replay a recorded log, without actions
+    ///
+    /// The replay starts in the state before the first record, or in the initial state for an empty log.
+    /// Guards cannot be evaluated offline: a guarded transition is assumed to have passed
+    /// when it leads to the recorded state.
+    /// After a divergence, the replay continues from the recorded state.
+    /// Records of transitions that were not taken (rejected by a guard, duplicate deliveries) are skipped.
+    pub fn replay(&self, log: &[TransitionRecord]) -> Replay {
+        let mut state = log
+            .first()
+            .map_or_else(|| self.initial_state().clone(), |r| r.from.clone());
+        let mut divergences = Vec::new();
+        for (position, record) in log.iter().enumerate() {
+            if !record.outcome.is_taken() {
+                continue;
+            }
+            let replayed = self
+                .candidates(&state, &record.event)
+                .into_iter()
+                .find(|t| t.guard.is_none() || t.to == record.to)
+                .map(|t| t.to.clone());
+            if state != record.from || replayed.as_ref() != Some(&record.to) {
+                divergences.push(Divergence {
+                    position,
+                    event: record.event.clone(),
+                    from: state,
+                    recorded: record.to.clone(),
+                    replayed,
+                });
+            }
+            state = record.to.clone();
+        }
+        Replay {
+            final_state: state,
+            divergences,
+        }
+    }
+}
+
+/// Replay many logs in parallel, one [`Definition::replay`] per log
+/// # Arguments
+/// * `definition` - the definition shared by all instances
+/// * `logs` - the recorded log of every instance
+/// # Returns
+/// The replay of every log, in the order of `logs`
+pub fn replay_many<L>(definition: &Definition, logs: &[L]) -> Vec<Replay>
+where
+    L: AsRef<[TransitionRecord]> + Sync,
+{
+    let threads = thread::available_parallelism().map_or(1, usize::from);
+    let chunk = logs.len().div_ceil(threads).max(1);
+    thread::scope(|scope| {
+        let handles: Vec<_> = logs
+            .chunks(chunk)
+            .map(|logs| {
+                scope.spawn(move || {
+                    logs.iter()
+                        .map(|log| definition.replay(log.as_ref()))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().expect("replay panicked"))
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Outcome, StateMachineBuilder};
+    use std::time::{Duration, SystemTime};
+    #[cfg(feature = "tracing")]
+    use tracing_test::traced_test;
+
+    fn record(from: &State, event: &Event, to: &State) -> TransitionRecord {
+        TransitionRecord {
+            seq: 0,
+            from: from.clone(),
+            event: event.clone(),
+            to: to.clone(),
+            timestamp: SystemTime::UNIX_EPOCH,
+            duration: Duration::ZERO,
+            outcome: Outcome::Ok,
+            cause: None,
+            envelope: None,
+            principal: None,
+        }
+    }
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_replay_many() {
+        let (cart, paid, shipped) = (
+            State::new("cart"),
+            State::new("paid"),
+            State::new("shipped"),
+        );
+        let (pay, ship) = (Event::new("pay"), Event::new("ship"));
+        let definition = StateMachineBuilder::new("order", &cart)
+            .add_event(cart.clone(), pay.clone(), paid.clone(), None)
+            .add_event(paid.clone(), ship.clone(), shipped.clone(), None)
+            .with_guard("in_stock", || true)
+            .definition();
+
+        let consistent = vec![record(&cart, &pay, &paid), record(&paid, &ship, &shipped)];
+        let diverging = vec![record(&cart, &ship, &shipped)];
+        let logs = vec![consistent, Vec::new(), diverging];
+        let replays = replay_many(&definition, &logs);
+
+        assert_eq!(replays.len(), 3);
+        assert!(replays[0].is_consistent());
+        assert_eq!(replays[0].final_state, shipped);
+        assert_eq!(replays[1].final_state, cart);
+        assert_eq!(
+            replays[2].divergences,
+            vec![Divergence {
+                position: 0,
+                event: ship,
+                from: cart,
+                recorded: shipped,
+                replayed: None,
+            }]
+        );
+    }
+}
@@ -0,0 +1,142 @@
+//! Time travel over the history of a machine, for debugging
+
+use crate::{Error, Result, Snapshot, State, StateMachine, StateMachineError, TransitionRecord};
+use std::fmt;
+use std::hash::Hash;
+
+/// A read-only view of a machine as of a past record of its history, see [`StateMachine::rewind`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rewind {
+    /// the id of the snapshot of the view, see [`Rewind::snapshot`]
+    pub id: String,
+    /// the name of the machine
+    pub machine: String,
+    /// the sequence number of the record
+    pub seq: u64,
+    /// the state of the machine after the record
+    pub state: State,
+    /// the history up to and including the record, oldest first
+    pub history: Vec<TransitionRecord>,
+}
+
+impl Rewind {
+    /// The snapshot of the machine at that point, to restore it or another instance,
+    /// see [`StateMachine::restore`]
+    ///
+    /// Deadlines are not part of the history, the snapshot has none.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            id: self.id.clone(),
+            machine: self.machine.clone(),
+            state: self.state.clone(),
+            seq: self.seq,
+            deadlines: Vec::new(),
+        }
+    }
+}
+
+impl<Err> StateMachine<Err> {
+    /// Look at the machine as it was right after the record with sequence number `seq`
+    /// # Errors
+    /// If the history does not contain the record, because it is not enabled or the record was dropped
+    /// # Panics
+    /// If the lock is poisoned
+    pub fn rewind(&self, seq: u64) -> Result<Rewind> {
+        let mut history = self.history();
+        let Some(position) = history.iter().position(|record| record.seq == seq) else {
+            return Err(Error::from(StateMachineError::NotInHistory {
+                machine: self.name.clone(),
+                seq,
+            }));
+        };
+        history.truncate(position + 1);
+        let record = &history[position];
+        let state = if record.outcome.is_taken() {
+            record.to.clone()
+        } else {
+            record.from.clone()
+        };
+        Ok(Rewind {
+            id: self.ids.next_id(),
+            machine: self.name.clone(),
+            seq,
+            state,
+            history,
+        })
+    }
+}
+
+impl<K, Err> crate::MachineManager<K, Err>
+where
+    K: Eq + Hash + Clone + fmt::Display,
+    Err: From<Error> + fmt::Display + 'static,
+{
+    /// Create the machine of `branch` in the state the machine of `key` was in right after record `seq`
+    ///
+    /// The branch is created by the factory, its history starts empty.
+    /// # Errors
+    /// If the record is not in the history of `key`, or `branch` is already in memory
+    pub fn branch_from(&mut self, key: &K, seq: u64, branch: K) -> Result<&StateMachine<Err>, Err> {
+        let snapshot = self.machine(key)?.rewind(seq)?.snapshot();
+        if self.get(&branch).is_some() {
+            return Err(Error::from(StateMachineError::BranchExists {
+                key: key.to_string(),
+                branch: branch.to_string(),
+            })
+            .into());
+        }
+        let machine = self.machine(&branch)?;
+        // the snapshot of `key`, taken over by the machine of the branch
+        machine.restore(&Snapshot {
+            machine: machine.name().to_string(),
+            ..snapshot
+        })?;
+        Ok(machine)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Event, MachineManager, StateMachineBuilder};
+    #[cfg(feature = "tracing")]
+    use tracing_test::traced_test;
+
+    fn pipeline(key: &u32) -> StateMachine {
+        let (build, test, deploy) = (
+            State::new("build"),
+            State::new("test"),
+            State::new("deploy"),
+        );
+        StateMachineBuilder::new(format!("pipeline {key}"), &build)
+            .add_event(build, Event::new("next"), test.clone(), None)
+            .add_event(test, Event::new("next"), deploy.clone(), None)
+            .add_event(deploy.clone(), Event::new("next"), deploy, None)
+            .with_history(10)
+            .build()
+    }
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_rewind_and_branch() {
+        let next = Event::new("next");
+        let mut manager = MachineManager::new(pipeline);
+        manager.event(&1, &next).unwrap();
+        manager.event(&1, &next).unwrap();
+
+        let machine = manager.get(&1).unwrap();
+        let rewind = machine.rewind(1).unwrap();
+        assert_eq!(rewind.state, State::new("test"));
+        assert_eq!(rewind.history.len(), 1);
+        assert_eq!(machine.current_state(), State::new("deploy"));
+        assert_eq!(
+            machine.rewind(7).unwrap_err().to_string(),
+            "cannot rewind pipeline 1: record 7 is not in the history"
+        );
+
+        let branch = manager.branch_from(&1, 1, 2).unwrap();
+        assert_eq!(branch.name(), "pipeline 2");
+        assert_eq!(branch.current_state(), State::new("test"));
+        assert!(manager.branch_from(&1, 1, 2).is_err());
+    }
+}
@@ -0,0 +1,167 @@
+//! Shared services, e.g. a database pool or an HTTP client, passed to guards and actions
+
+use crate::{Error, Event, State, StateMachine, StateMachineBuilder};
+use std::any::{Any, TypeId};
+use std::cell::{Ref, RefCell};
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+/// An action getting the services of its machine, see [`StateMachineBuilder::add_event_with_services`]
+pub type ServiceAction<Err = Error> = Box<dyn Fn(&Services) -> Result<(), Err>>;
+
+/// The services shared by a builder, the actions it binds and the machine it builds
+pub(crate) type SharedServices = Rc<RefCell<Services>>;
+
+/// The services of a machine, at most one per type
+#[derive(Default)]
+pub struct Services {
+    services: HashMap<TypeId, Box<dyn Any>>,
+}
+
+impl Services {
+    /// Register a service, replacing the previous service of the same type
+    /// # Returns
+    /// The replaced service, if any
+    pub fn insert<T: Any>(&mut self, service: T) -> Option<T> {
+        self.services
+            .insert(TypeId::of::<T>(), Box::new(service))
+            .and_then(|previous| previous.downcast().ok())
+            .map(|previous| *previous)
+    }
+
+    /// Get the service of type `T`
+    /// # Returns
+    /// `None` if no service of this type is registered
+    pub fn get<T: Any>(&self) -> Option<&T> {
+        self.services
+            .get(&TypeId::of::<T>())
+            .and_then(|service| service.downcast_ref())
+    }
+
+    /// Number of registered services
+    pub fn len(&self) -> usize {
+        self.services.len()
+    }
+
+    /// Check if no service is registered
+    pub fn is_empty(&self) -> bool {
+        self.services.is_empty()
+    }
+}
+
+impl fmt::Debug for Services {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Services")
+            .field("len", &self.services.len())
+            .finish()
+    }
+}
+
+impl<Err> StateMachine<Err> {
+    /// Get a service registered with [`StateMachineBuilder::with_service`]
+    /// # Returns
+    /// `None` if no service of this type is registered
+    pub fn service<T: Any>(&self) -> Option<Ref<'_, T>> {
+        Ref::filter_map(self.services.borrow(), Services::get::<T>).ok()
+    }
+}
+
+impl<Err: 'static> StateMachineBuilder<Err> {
+    #[must_use]
+    /// Register a service shared by the guards and actions of the machine, replacing the previous one of the same type
+    ///
+    /// Services can be registered before or after the transitions using them.
+    pub fn with_service<T: Any>(self, service: T) -> Self {
+        self.services.borrow_mut().insert(service);
+        self
+    }
+
+    #[must_use]
+    /// Add an event whose action gets the services of the machine, see [`StateMachineBuilder::add_event`]
+    ///
+    /// The action looks up what it needs, e.g. `services.get::<DbPool>()`,
+    /// instead of capturing its own handles.
+    pub fn add_event_with_services(
+        self,
+        old_state: State,
+        event: Event,
+        new_state: State,
+        action: ServiceAction<Err>,
+    ) -> Self {
+        let services = self.services.clone();
+        self.add_event(
+            old_state,
+            event,
+            new_state,
+            Some(Box::new(move || action(&services.borrow()))),
+        )
+    }
+
+    #[must_use]
+    /// Guard the last added transition with a check of the services, see [`StateMachineBuilder::with_guard`]
+    /// # Panics
+    /// If no transition was added yet
+    pub fn with_service_guard(
+        self,
+        name: impl Into<String>,
+        guard: impl Fn(&Services) -> bool + 'static,
+    ) -> Self {
+        let services = self.services.clone();
+        self.with_guard(name, move || guard(&services.borrow()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    #[cfg(feature = "tracing")]
+    use tracing_test::traced_test;
+
+    struct Mailer {
+        sent: Cell<u32>,
+        online: bool,
+    }
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_services() {
+        let (pending, notified) = (State::new("pending"), State::new("notified"));
+        let notify = Event::new("notify");
+        let machine = StateMachineBuilder::new("signup", &pending)
+            .add_event_with_services(
+                pending.clone(),
+                notify.clone(),
+                notified.clone(),
+                Box::new(|services| {
+                    let mailer = services.get::<Mailer>().expect("a mailer is registered");
+                    mailer.sent.set(mailer.sent.get() + 1);
+                    Ok(())
+                }),
+            )
+            .with_service_guard("mailer online", |services| {
+                services.get::<Mailer>().is_some_and(|mailer| mailer.online)
+            })
+            .with_service(Mailer {
+                sent: Cell::new(0),
+                online: true,
+            })
+            .with_service("noreply@example.com")
+            .build();
+
+        machine.event(&notify).unwrap();
+        assert_eq!(machine.current_state(), notified);
+        assert_eq!(machine.service::<Mailer>().unwrap().sent.get(), 1);
+        assert_eq!(
+            machine.service::<&str>().as_deref(),
+            Some(&"noreply@example.com")
+        );
+        assert!(machine.service::<u32>().is_none());
+
+        let mut services = Services::default();
+        assert_eq!(services.insert(1_u32), None);
+        assert_eq!(services.insert(2_u32), Some(1));
+        assert_eq!(services.len(), 1);
+    }
+}
@@ -0,0 +1,24 @@
+/// What a graceful shutdown finished before its deadline, and what it did not
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShutdownReport<T> {
+    /// the number of finished items: persisted machines, delivered events
+    pub completed: usize,
+    /// the items that could not be finished, with the reason
+    pub unfinished: Vec<(T, String)>,
+}
+
+impl<T> Default for ShutdownReport<T> {
+    fn default() -> Self {
+        Self {
+            completed: 0,
+            unfinished: Vec::new(),
+        }
+    }
+}
+
+impl<T> ShutdownReport<T> {
+    /// Check if everything was finished
+    pub fn is_clean(&self) -> bool {
+        self.unfinished.is_empty()
+    }
+}
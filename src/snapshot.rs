@@ -0,0 +1,296 @@
+use crate::trace::error;
+use crate::{Deadline, Error, Result, State, StateMachine, StateMachineBuilder, StateMachineError};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+
+/// The persistent part of a state machine: its current state
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    /// the id of the snapshot, see [`StateMachineBuilder::with_id_generator`]
+    pub id: String,
+    /// the name of the machine
+    pub machine: String,
+    /// the current state
+    pub state: State,
+    /// the sequence number of the last record, a restored machine continues numbering after it
+    pub seq: u64,
+    /// the pending deadlines, earliest first
+    pub deadlines: Vec<Deadline>,
+}
+
+/// What [`StateMachine::restore`] does with a snapshot whose state is not a state of the machine,
+/// e.g. a state removed from the definition since the snapshot was taken
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Recovery {
+    /// refuse the snapshot
+    #[default]
+    Fail,
+    /// restore the machine in its initial state
+    Reset,
+    /// restore the machine in this state, e.g. a `recovered` state to handle stranded instances
+    MapTo(State),
+}
+
+/// Keeps snapshots of machines by key
+pub trait Store<K> {
+    /// Get the snapshot saved for a key, if any
+    /// # Errors
+    /// If the backend fails
+    fn load(&self, key: &K) -> Result<Option<Snapshot>>;
+
+    /// Save the snapshot of a key, replacing the previous one
+    /// # Errors
+    /// If the backend fails
+    fn save(&self, key: &K, snapshot: &Snapshot) -> Result<()>;
+}
+
+/// A store keeping the snapshots in memory, e.g. for tests
+#[derive(Debug)]
+pub struct MemoryStore<K> {
+    snapshots: Mutex<HashMap<K, Snapshot>>,
+}
+
+impl<K> Default for MemoryStore<K> {
+    fn default() -> Self {
+        Self {
+            snapshots: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<K: Eq + Hash> MemoryStore<K> {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of saved snapshots
+    /// # Panics
+    /// If the lock is poisoned
+    pub fn len(&self) -> usize {
+        self.snapshots.lock().expect("failed to get lock").len()
+    }
+
+    /// Check if no snapshot was saved
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<K: Eq + Hash + Clone> Store<K> for MemoryStore<K> {
+    fn load(&self, key: &K) -> Result<Option<Snapshot>> {
+        Ok(self
+            .snapshots
+            .lock()
+            .expect("failed to get lock")
+            .get(key)
+            .cloned())
+    }
+
+    fn save(&self, key: &K, snapshot: &Snapshot) -> Result<()> {
+        self.snapshots
+            .lock()
+            .expect("failed to get lock")
+            .insert(key.clone(), snapshot.clone());
+        Ok(())
+    }
+}
+
+impl<Err> StateMachine<Err> {
+    /// Take a snapshot of the machine
+    /// # Panics
+    /// If the lock is poisoned
+    pub fn snapshot(&self) -> Snapshot {
+        let state = self.state.read().expect("failed to get lock");
+        Snapshot {
+            id: self.ids.next_id(),
+            machine: self.name.clone(),
+            state: state.clone(),
+            seq: self.seq.load(Ordering::Relaxed),
+            deadlines: self.deadlines(),
+        }
+    }
+
+    /// Put the machine in the state of a snapshot, without running any action
+    ///
+    /// The next record of the machine is numbered after the sequence number of the snapshot,
+    /// so that `restore(&snapshot())` on a fresh machine round-trips exactly.
+    /// A snapshot in an unknown state is handled by the recovery policy, see [`StateMachineBuilder::on_unknown_state`].
+    /// The ordering monitors and the throttles are reset, as by [`StateMachine::reset`].
+    /// # Errors
+    /// If the snapshot was taken of a machine with another name,
+    /// or its state is not a state of this machine and the policy does not recover it
+    /// # Panics
+    /// If the lock is poisoned
+    pub fn restore(&self, snapshot: &Snapshot) -> Result<()> {
+        if snapshot.machine != self.name {
+            return Err(Error::from(StateMachineError::SnapshotOfOtherMachine {
+                machine: self.name.clone(),
+                snapshot: snapshot.machine.clone(),
+            }));
+        }
+        let states = self.table.states(&self.initial_state);
+        let restored = if states.contains(&snapshot.state) {
+            &snapshot.state
+        } else {
+            let recovered = match self.recovery {
+                Recovery::Fail => None,
+                Recovery::Reset => Some(&self.initial_state),
+                Recovery::MapTo(ref state) => Some(state).filter(|state| states.contains(state)),
+            };
+            let Some(recovered) = recovered else {
+                return Err(Error::from(StateMachineError::UnknownSnapshotState {
+                    machine: self.name.clone(),
+                    state: snapshot.state.clone(),
+                }));
+            };
+            error!(
+                "{}: restoring unknown state {} as {recovered}",
+                self.log_name(),
+                snapshot.state
+            );
+            recovered
+        };
+        let mut state = self.state.write().expect("failed to get lock");
+        self.force_state(&mut state, restored);
+        self.seq.store(snapshot.seq, Ordering::Relaxed);
+        self.deadlines
+            .lock()
+            .expect("failed to get lock")
+            .clone_from(&snapshot.deadlines);
+        self.reset_monitors();
+        Ok(())
+    }
+}
+
+impl<Err> StateMachineBuilder<Err> {
+    #[must_use]
+    /// Choose what [`StateMachine::restore`] does with snapshots in an unknown state, the default is to fail
+    pub fn on_unknown_state(mut self, recovery: Recovery) -> Self {
+        self.recovery = recovery;
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Event, StateMachineBuilder};
+    #[cfg(feature = "tracing")]
+    use tracing_test::traced_test;
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_snapshot_restore() {
+        let idle = State::new("idle");
+        let running = State::new("running");
+        let go = Event::new("go");
+        let build = || {
+            StateMachineBuilder::new("test", &idle)
+                .add_event(idle.clone(), go.clone(), running.clone(), None)
+                .build()
+        };
+        let machine = build();
+        machine.event(&go).unwrap();
+        let store = MemoryStore::new();
+        store.save(&1, &machine.snapshot()).unwrap();
+
+        let restored = build();
+        restored.restore(&store.load(&1).unwrap().unwrap()).unwrap();
+        assert_eq!(restored.current_state(), running);
+        assert!(store.load(&2).unwrap().is_none());
+
+        let unknown = Snapshot {
+            id: "s1".to_string(),
+            machine: "test".to_string(),
+            state: State::new("gone"),
+            seq: 0,
+            deadlines: Vec::new(),
+        };
+        assert_eq!(
+            restored.restore(&unknown).unwrap_err().to_string(),
+            "cannot restore test: unknown state gone"
+        );
+        let other = Snapshot {
+            machine: "other".to_string(),
+            ..machine.snapshot()
+        };
+        assert_eq!(
+            restored.restore(&other).unwrap_err().to_string(),
+            "cannot restore test: the snapshot is of other"
+        );
+    }
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_snapshot_round_trip() {
+        let idle = State::new("idle");
+        let go = Event::new("go");
+        let build = || {
+            StateMachineBuilder::new("test", &idle)
+                .add_event(idle.clone(), go.clone(), idle.clone(), None)
+                .with_history(10)
+                .build()
+        };
+        let machine = build();
+        machine.event(&go).unwrap();
+        machine.event(&go).unwrap();
+        let snapshot = machine.snapshot();
+        assert_eq!(snapshot.seq, 2);
+
+        let restored = build();
+        restored.restore(&snapshot).unwrap();
+        let again = restored.snapshot();
+        assert_ne!(again.id, snapshot.id, "every snapshot gets its own id");
+        assert_eq!(
+            Snapshot {
+                id: snapshot.id.clone(),
+                ..again
+            },
+            snapshot
+        );
+        restored.event(&go).unwrap();
+        assert_eq!(restored.last_transition().map(|r| r.seq), Some(3));
+    }
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_recover_unknown_state() {
+        let (open, recovered) = (State::new("open"), State::new("recovered"));
+        let build = |recovery| {
+            StateMachineBuilder::new("ticket", &open)
+                .add_event(
+                    open.clone(),
+                    Event::new("close"),
+                    State::new("closed"),
+                    None,
+                )
+                .add_event(recovered.clone(), Event::new("triage"), open.clone(), None)
+                .on_unknown_state(recovery)
+                .build()
+        };
+        let stranded = Snapshot {
+            id: "s1".to_string(),
+            machine: "ticket".to_string(),
+            state: State::new("escalated"),
+            seq: 7,
+            deadlines: Vec::new(),
+        };
+
+        let machine = build(Recovery::MapTo(recovered.clone()));
+        machine.event(&Event::new("close")).unwrap();
+        machine.restore(&stranded).unwrap();
+        assert_eq!(machine.current_state(), recovered);
+        assert_eq!(machine.snapshot().seq, 7);
+        let machine = build(Recovery::Reset);
+        machine.event(&Event::new("close")).unwrap();
+        machine.restore(&stranded).unwrap();
+        assert_eq!(machine.current_state(), open);
+        assert!(build(Recovery::Fail).restore(&stranded).is_err());
+        assert!(build(Recovery::MapTo(State::new("gone")))
+            .restore(&stranded)
+            .is_err());
+    }
+}
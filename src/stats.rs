@@ -0,0 +1,348 @@
+use crate::clock;
+use crate::{Outcome, State, StateMachine, StateMachineBuilder};
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, SystemTime};
+
+/// Number of buckets of a statistics window
+const BUCKETS: u32 = 10;
+
+/// Time spent by the machine in a state
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DwellStats {
+    /// number of times the state was entered
+    pub visits: u64,
+    /// total time spent in the state, including the current visit
+    pub total: Duration,
+    /// time spent in the state since it was last entered, if it is the current state
+    pub current: Option<Duration>,
+}
+
+impl DwellStats {
+    /// Average time spent in the state per visit
+    /// # Returns
+    /// `None` if the state was never entered
+    pub fn average(&self) -> Option<Duration> {
+        let visits = u32::try_from(self.visits).ok().filter(|v| *v > 0)?;
+        Some(self.total / visits)
+    }
+}
+
+/// Counts of the transitions taken by the machine
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TransitionStats {
+    /// number of transitions taken
+    pub transitions: u64,
+    /// number of transitions whose action failed or panicked
+    pub failed: u64,
+    /// total time spent in actions
+    pub total: Duration,
+    /// longest time spent in an action
+    pub max: Duration,
+}
+
+impl TransitionStats {
+    /// Average time spent in an action per transition
+    /// # Returns
+    /// `None` if no transition was taken
+    pub fn average(&self) -> Option<Duration> {
+        let transitions = u32::try_from(self.transitions).ok().filter(|t| *t > 0)?;
+        Some(self.total / transitions)
+    }
+
+    fn add(&mut self, duration: Duration, failed: bool) {
+        self.transitions += 1;
+        self.failed += u64::from(failed);
+        self.total += duration;
+        self.max = self.max.max(duration);
+    }
+
+    fn merge(&mut self, other: &TransitionStats) {
+        self.transitions += other.transitions;
+        self.failed += other.failed;
+        self.total += other.total;
+        self.max = self.max.max(other.max);
+    }
+}
+
+/// Counts the transitions since the machine was built or its statistics were reset,
+/// and optionally over a sliding window
+pub(crate) struct Counters {
+    total: TransitionStats,
+    window: Option<Window>,
+}
+
+/// A sliding window, as a ring of buckets each covering a tenth of the window
+struct Window {
+    bucket: Duration,
+    /// (index of the bucket since the epoch, its counts), oldest first
+    buckets: VecDeque<(u128, TransitionStats)>,
+}
+
+impl Window {
+    fn index(&self, now: SystemTime) -> u128 {
+        clock::elapsed(SystemTime::UNIX_EPOCH, now).as_nanos() / self.bucket.as_nanos().max(1)
+    }
+
+    /// Drop the buckets that left the window
+    fn prune(&mut self, index: u128) {
+        while self
+            .buckets
+            .front()
+            .is_some_and(|(oldest, _)| oldest + u128::from(BUCKETS) <= index)
+        {
+            self.buckets.pop_front();
+        }
+    }
+}
+
+impl Counters {
+    pub(crate) fn new(window: Option<Duration>) -> Self {
+        Self {
+            total: TransitionStats::default(),
+            window: window.map(|window| Window {
+                bucket: window / BUCKETS,
+                buckets: VecDeque::new(),
+            }),
+        }
+    }
+
+    pub(crate) fn add(&mut self, now: SystemTime, duration: Duration, outcome: &Outcome) {
+        let failed = !outcome.is_ok();
+        self.total.add(duration, failed);
+        let Some(ref mut window) = self.window else {
+            return;
+        };
+        let index = window.index(now);
+        window.prune(index);
+        match window.buckets.back_mut() {
+            Some((last, stats)) if *last == index => stats.add(duration, failed),
+            _ => {
+                let mut stats = TransitionStats::default();
+                stats.add(duration, failed);
+                window.buckets.push_back((index, stats));
+            }
+        }
+    }
+
+    fn window(&mut self, now: SystemTime) -> Option<TransitionStats> {
+        let window = self.window.as_mut()?;
+        window.prune(window.index(now));
+        let mut stats = TransitionStats::default();
+        for (_, bucket) in &window.buckets {
+            stats.merge(bucket);
+        }
+        Some(stats)
+    }
+
+    fn reset(&mut self) {
+        self.total = TransitionStats::default();
+        if let Some(ref mut window) = self.window {
+            window.buckets.clear();
+        }
+    }
+}
+
+/// Tracks the dwell time of every state
+#[derive(Clone)]
+pub(crate) struct Dwell {
+    stats: HashMap<State, DwellStats>,
+    current: State,
+    entered: SystemTime,
+    /// whether the current visit was reported as stuck
+    alerted: bool,
+    /// whether the time box of the current visit expired
+    escalated: bool,
+}
+
+impl Dwell {
+    pub(crate) fn new(initial: &State, now: SystemTime) -> Self {
+        let mut dwell = Self {
+            stats: HashMap::new(),
+            current: initial.clone(),
+            entered: now,
+            alerted: false,
+            escalated: false,
+        };
+        dwell.stats.entry(initial.clone()).or_default().visits = 1;
+        dwell
+    }
+
+    /// Forget the statistics, the current visit counts from `now`
+    pub(crate) fn reset(&mut self, now: SystemTime) {
+        *self = Self::new(&self.current, now);
+    }
+
+    /// Leave the current state and enter `state`
+    pub(crate) fn enter(&mut self, state: &State, now: SystemTime) {
+        let spent = clock::elapsed(self.entered, now);
+        self.stats.entry(self.current.clone()).or_default().total += spent;
+        self.stats.entry(state.clone()).or_default().visits += 1;
+        self.current = state.clone();
+        self.entered = now;
+        self.alerted = false;
+        self.escalated = false;
+    }
+
+    /// Mark the current visit as reported stuck
+    /// # Returns
+    /// `false` if it was already reported
+    pub(crate) fn alert(&mut self) -> bool {
+        !std::mem::replace(&mut self.alerted, true)
+    }
+
+    /// Mark the current visit as escalated
+    /// # Returns
+    /// `false` if it was already escalated
+    pub(crate) fn escalate(&mut self) -> bool {
+        !std::mem::replace(&mut self.escalated, true)
+    }
+
+    /// Time spent in the current state
+    pub(crate) fn current(&self, now: SystemTime) -> Duration {
+        clock::elapsed(self.entered, now)
+    }
+
+    /// Get the statistics of all visited states, including the ongoing visit
+    pub(crate) fn stats(&self, now: SystemTime) -> HashMap<State, DwellStats> {
+        let mut stats = self.stats.clone();
+        if let Some(current) = stats.get_mut(&self.current) {
+            let spent = self.current(now);
+            current.total += spent;
+            current.current = Some(spent);
+        }
+        stats
+    }
+}
+
+impl<Err> StateMachine<Err> {
+    /// Get the counts of the transitions since the machine was built or [`StateMachine::reset_stats`]
+    /// # Panics
+    /// If the lock is poisoned
+    pub fn transition_stats(&self) -> TransitionStats {
+        self.counters.lock().expect("failed to get lock").total
+    }
+
+    /// Get the counts of the transitions over the recent window, see [`StateMachineBuilder::stats_window`]
+    /// # Returns
+    /// `None` if no window is set
+    /// # Panics
+    /// If the lock is poisoned
+    pub fn window_stats(&self) -> Option<TransitionStats> {
+        self.counters
+            .lock()
+            .expect("failed to get lock")
+            .window(self.clock.now())
+    }
+
+    /// Forget the transition counts and the dwell times, e.g. at the start of a reporting period
+    /// The current visit of the current state counts from now
+    /// # Panics
+    /// If the lock is poisoned
+    pub fn reset_stats(&self) {
+        self.counters.lock().expect("failed to get lock").reset();
+        self.dwell
+            .lock()
+            .expect("failed to get lock")
+            .reset(self.clock.now());
+    }
+}
+
+impl<Err> StateMachineBuilder<Err> {
+    #[must_use]
+    /// Also count the transitions over the last `window`, see [`StateMachine::window_stats`]
+    /// The window slides by a tenth of its length
+    pub fn stats_window(mut self, window: Duration) -> Self {
+        self.stats_window = Some(window);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Event, ManualClock, StateMachineBuilder};
+    use std::sync::Arc;
+    #[cfg(feature = "tracing")]
+    use tracing_test::traced_test;
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_dwell_stats() {
+        let idle = State::new("idle");
+        let review = State::new("review");
+        let start = Event::new("start");
+        let done = Event::new("done");
+        let clock = Arc::new(ManualClock::default());
+        let machine = StateMachineBuilder::new("orders", &idle)
+            .add_event(idle.clone(), start.clone(), review.clone(), None)
+            .add_event(review.clone(), done.clone(), idle.clone(), None)
+            .with_clock(clock.clone())
+            .build();
+
+        clock.advance(Duration::from_secs(5));
+        machine.event(&start).unwrap();
+        clock.advance(Duration::from_secs(10));
+        machine.event(&done).unwrap();
+        clock.advance(Duration::from_secs(1));
+        machine.event(&start).unwrap();
+        clock.advance(Duration::from_secs(20));
+
+        let stats = machine.dwell_stats();
+        assert_eq!(
+            stats[&idle],
+            DwellStats {
+                visits: 2,
+                total: Duration::from_secs(6),
+                current: None,
+            }
+        );
+        assert_eq!(
+            stats[&review],
+            DwellStats {
+                visits: 2,
+                total: Duration::from_secs(30),
+                current: Some(Duration::from_secs(20)),
+            }
+        );
+        assert_eq!(stats[&review].average(), Some(Duration::from_secs(15)));
+        assert_eq!(machine.time_in_state(), Duration::from_secs(20));
+    }
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_window_stats() {
+        let idle = State::new("idle");
+        let tick = Event::new("tick");
+        let fail = Event::new("fail");
+        let clock = Arc::new(ManualClock::default());
+        let machine = StateMachineBuilder::new("ticker", &idle)
+            .add_event(idle.clone(), tick.clone(), idle.clone(), None)
+            .add_event(
+                idle.clone(),
+                fail.clone(),
+                idle.clone(),
+                Some(Box::new(|| {
+                    Err(crate::error::message("broken".to_string()))
+                })),
+            )
+            .stats_window(Duration::from_secs(60))
+            .with_clock(clock.clone())
+            .build();
+
+        machine.event(&tick).unwrap();
+        machine.event(&fail).unwrap_err();
+        clock.advance(Duration::from_secs(30));
+        machine.event(&tick).unwrap();
+        assert_eq!(machine.window_stats().map(|s| s.transitions), Some(3));
+        clock.advance(Duration::from_secs(40));
+        let recent = machine.window_stats().unwrap();
+        assert_eq!((recent.transitions, recent.failed), (1, 0));
+        let total = machine.transition_stats();
+        assert_eq!((total.transitions, total.failed), (3, 1));
+
+        machine.reset_stats();
+        assert_eq!(machine.transition_stats(), TransitionStats::default());
+        assert_eq!(machine.window_stats(), Some(TransitionStats::default()));
+        assert_eq!(machine.dwell_stats()[&idle].total, Duration::ZERO);
+    }
+}
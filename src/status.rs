@@ -0,0 +1,172 @@
+use crate::{audit, json, EventBus, Health, StateMachine, TransitionStats};
+
+/// Identifier of the status document format, bumped on incompatible changes
+pub const STATUS_SCHEMA: &str = "state-machine.status.v1";
+
+impl<Err> StateMachine<Err> {
+    /// Export the runtime status of the machine as a single JSON document, e.g. for support tooling
+    ///
+    /// ```text
+    /// {
+    /// "schema": "state-machine.status.v1",
+    /// "machine": "<name of the machine>",
+    /// "generated_at": "<RFC 3339 UTC timestamp>",
+    /// "definition": "<fingerprint of the definition, 16 hex digits>",
+    /// "state": "<current state>",
+    /// "time_in_state_ms": 1500,
+    /// "queue_depth": 0,
+    /// "last_error": null | "<message>",
+    /// "stuck": false,
+    /// "stats": {"transitions": 12, "failed": 1, "total_us": 340, "max_us": 90},
+    /// "window_stats": null | {"transitions": 2, "failed": 0, "total_us": 40, "max_us": 30},
+    /// "transitions": [
+    /// {"seq": 1, "timestamp": "<RFC 3339>", "from": "<state>", "event": "<event>", "to": "<state>",
+    ///  "duration_us": 12, "outcome": "<as in the audit document>", "error": null | "<message>"}
+    /// ]
+    /// }
+    /// ```
+    /// `definition` is [`Definition::fingerprint`](crate::Definition::fingerprint).
+    /// `queue_depth` is the number of events queued on a bus, see [`EventBus::status_json`].
+    /// `stats` and `window_stats` are [`StateMachine::transition_stats`] and [`StateMachine::window_stats`].
+    /// `transitions` are taken from the history, or the last transition if the history is not enabled.
+    /// # Arguments
+    /// * `last` - the maximum number of transitions, the most recent ones are kept
+    /// # Panics
+    /// If the lock is poisoned
+    pub fn status_json(&self, last: usize) -> String {
+        self.status(&self.health(), last)
+    }
+
+    fn status(&self, health: &Health, last: usize) -> String {
+        let mut records = self.history();
+        if records.is_empty() {
+            records.extend(self.last_transition());
+        }
+        let skip = records.len().saturating_sub(last);
+        let optional =
+            |value: Option<String>| value.map_or_else(|| "null".to_string(), |s| json::string(&s));
+
+        let mut out = String::new();
+        let mut line = |text: String| {
+            out.push_str(&text);
+            out.push('\n');
+        };
+        line("{".to_string());
+        line(format!("\"schema\": {},", json::string(STATUS_SCHEMA)));
+        line(format!("\"machine\": {},", json::string(&self.name)));
+        line(format!(
+            "\"generated_at\": {},",
+            json::string(&json::timestamp(self.clock.now()))
+        ));
+        line(format!(
+            "\"definition\": \"{:016x}\",",
+            self.definition().fingerprint()
+        ));
+        line(format!(
+            "\"state\": {},",
+            json::string(&health.state.to_string())
+        ));
+        line(format!(
+            "\"time_in_state_ms\": {},",
+            health.time_in_state.as_millis()
+        ));
+        line(format!("\"queue_depth\": {},", health.queue_depth));
+        line(format!(
+            "\"last_error\": {},",
+            optional(health.last_error.clone())
+        ));
+        line(format!("\"stuck\": {},", health.stuck));
+        line(format!("\"stats\": {},", stats(&self.transition_stats())));
+        line(format!(
+            "\"window_stats\": {},",
+            self.window_stats()
+                .map_or_else(|| "null".to_string(), |s| stats(&s))
+        ));
+        line("\"transitions\": [".to_string());
+        let count = records.len() - skip;
+        for (i, record) in records.iter().skip(skip).enumerate() {
+            let (outcome, error, _) = audit::outcome_fields(&record.outcome);
+            let separator = if i + 1 < count { "," } else { "" };
+            line(format!(
+                "{{\"seq\": {}, \"timestamp\": {}, \"from\": {}, \"event\": {}, \"to\": {}, \"duration_us\": {}, \"outcome\": \"{outcome}\", \"error\": {error}}}{separator}",
+                record.seq,
+                json::string(&json::timestamp(record.timestamp)),
+                json::string(&record.from.to_string()),
+                json::string(&record.event.to_string()),
+                json::string(&record.to.to_string()),
+                record.duration.as_micros(),
+            ));
+        }
+        line("]".to_string());
+        line("}".to_string());
+        out
+    }
+}
+
+fn stats(stats: &TransitionStats) -> String {
+    format!(
+        "{{\"transitions\": {}, \"failed\": {}, \"total_us\": {}, \"max_us\": {}}}",
+        stats.transitions,
+        stats.failed,
+        stats.total.as_micros(),
+        stats.max.as_micros()
+    )
+}
+
+impl<Err> EventBus<Err>
+where
+    Err: From<crate::Error> + std::fmt::Display + 'static,
+{
+    /// Export the runtime status of a registered machine, with the number of events queued for it,
+    /// see [`StateMachine::status_json`]
+    /// # Returns
+    /// `None` if no machine with that name is registered
+    /// # Panics
+    /// If the lock is poisoned
+    pub fn status_json(&self, name: &str, last: usize) -> Option<String> {
+        let health = self.health(name)?;
+        Some(self.machine(name)?.status(&health, last))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Event, EventBus, State, StateMachineBuilder};
+    #[cfg(feature = "tracing")]
+    use tracing_test::traced_test;
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_status_json() {
+        let idle = State::new("idle");
+        let tick = Event::new("tick");
+        let mut bus: EventBus = EventBus::new();
+        bus.register(
+            StateMachineBuilder::new("ticker", &idle)
+                .add_event(idle.clone(), tick.clone(), idle.clone(), None)
+                .with_history(10)
+                .build(),
+        );
+        bus.post("ticker", tick.clone());
+        bus.post("ticker", tick.clone());
+        bus.step().unwrap();
+        bus.step().unwrap();
+        bus.post("ticker", tick);
+
+        let status = bus.status_json("ticker", 1).unwrap();
+        let lines: Vec<&str> = status.lines().collect();
+        assert_eq!(lines[1], "\"schema\": \"state-machine.status.v1\",");
+        assert_eq!(lines[2], "\"machine\": \"ticker\",");
+        let fingerprint = bus.machine("ticker").unwrap().definition().fingerprint();
+        assert_eq!(lines[4], format!("\"definition\": \"{fingerprint:016x}\","));
+        assert_eq!(lines[5], "\"state\": \"idle\",");
+        assert_eq!(lines[7], "\"queue_depth\": 1,");
+        assert_eq!(lines[8], "\"last_error\": null,");
+        assert!(lines[10].starts_with("\"stats\": {\"transitions\": 2, \"failed\": 0,"));
+        assert_eq!(lines[11], "\"window_stats\": null,");
+        assert!(lines[13].starts_with("{\"seq\": 2,"));
+        assert!(lines[13].ends_with("\"outcome\": \"ok\", \"error\": null}"));
+        assert_eq!(lines[14..], ["]", "}"]);
+        assert!(bus.status_json("unknown", 1).is_none());
+    }
+}
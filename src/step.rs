@@ -0,0 +1,220 @@
+//! The pure transition function of a [`Definition`]
+
+use crate::{Definition, Error, Event, Result, State, StateMachineError};
+use std::borrow::Borrow;
+
+/// Compute the state after an event, without locks, actions or observers
+///
+/// The candidate transitions are tried in the same order as [`StateMachine::event`](crate::StateMachine::event).
+/// Definitions do not carry guard implementations, guarded transitions are assumed to be rejected,
+/// use [`step_with_guards`] to decide them.
+/// # Errors
+/// If no transition is found for the event in the state
+pub fn step(definition: &Definition, state: &State, event: &Event) -> Result<State> {
+    step_with_guards(definition, state, event, |_| false)
+}
+
+/// Compute the state after an event, deciding the guards by name
+/// # Arguments
+/// * `definition` - the definition of the machine
+/// * `state` - the state before the event
+/// * `event` - the event
+/// * `guard` - returns whether the guard with the given name passes
+/// # Errors
+/// If no transition is found for the event in the state
+pub fn step_with_guards(
+    definition: &Definition,
+    state: &State,
+    event: &Event,
+    guard: impl Fn(&str) -> bool,
+) -> Result<State> {
+    definition
+        .candidates(state, event)
+        .into_iter()
+        .find(|t| t.guard.as_deref().is_none_or(&guard))
+        .map(|t| t.to.clone())
+        .ok_or_else(|| {
+            Error::from(StateMachineError::NoTransition {
+                state: state.clone(),
+                event: event.clone(),
+                dead_end: !definition
+                    .transitions()
+                    .iter()
+                    .any(|t| t.from.as_ref().is_none_or(|from| from == state)),
+            })
+        })
+}
+
+/// What [`Scan`] does with an event that is not handled in the current state
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ScanPolicy {
+    /// yield the error and end the iteration
+    #[default]
+    Stop,
+    /// yield the error and continue in the same state
+    Continue,
+    /// ignore the event and continue in the same state
+    Skip,
+}
+
+/// Runs events through a definition, see [`Definition::scan`]
+#[derive(Debug, Clone)]
+pub struct Scan<'a> {
+    definition: &'a Definition,
+    state: State,
+    policy: ScanPolicy,
+}
+
+impl<'a> Scan<'a> {
+    #[must_use]
+    /// Choose what to do with unhandled events, the default is [`ScanPolicy::Stop`]
+    pub fn on_error(mut self, policy: ScanPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Map events to the states after each of them
+    pub fn events<I>(self, events: I) -> States<'a, I::IntoIter>
+    where
+        I: IntoIterator,
+        I::Item: Borrow<Event>,
+    {
+        States {
+            scan: self,
+            events: events.into_iter(),
+            done: false,
+        }
+    }
+}
+
+/// Iterator over the states after each event, see [`Scan::events`]
+#[derive(Debug, Clone)]
+pub struct States<'a, I> {
+    scan: Scan<'a>,
+    events: I,
+    done: bool,
+}
+
+impl<I> Iterator for States<'_, I>
+where
+    I: Iterator,
+    I::Item: Borrow<Event>,
+{
+    type Item = Result<State>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        loop {
+            let event = self.events.next()?;
+            match step(self.scan.definition, &self.scan.state, event.borrow()) {
+                Ok(state) => {
+                    self.scan.state = state.clone();
+                    return Some(Ok(state));
+                }
+                Err(e) => match self.scan.policy {
+                    ScanPolicy::Stop => {
+                        self.done = true;
+                        return Some(Err(e));
+                    }
+                    ScanPolicy::Continue => return Some(Err(e)),
+                    ScanPolicy::Skip => {}
+                },
+            }
+        }
+    }
+}
+
+impl Definition {
+    /// Run events through the definition with [`step`], e.g. to process recorded event logs
+    pub fn scan(&self, initial: &State) -> Scan<'_> {
+        Scan {
+            definition: self,
+            state: initial.clone(),
+            policy: ScanPolicy::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::StateMachineBuilder;
+    #[cfg(feature = "tracing")]
+    use tracing_test::traced_test;
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_step_matches_machine() {
+        let (a, b, c) = (State::new("a"), State::new("b"), State::new("c"));
+        let events = [Event::new("x"), Event::new("y"), Event::new("z")];
+        let [x, y, z] = events.clone();
+        let builder = StateMachineBuilder::new("model", &a)
+            .add_event(a.clone(), x, b.clone(), None)
+            .add_event(b.clone(), y.clone(), c.clone(), None)
+            .add_event(b.clone(), y.clone(), a.clone(), None)
+            .with_guard("open", || true)
+            .add_any_state_event(z.clone(), a.clone(), None)
+            .add_event(c.clone(), z, c.clone(), None);
+        let definition = builder.definition();
+        let machine = builder.build();
+
+        // every sequence of 4 events leads to the same state in the model and in the machine
+        for n in 0..81 {
+            machine.reset();
+            let mut state = definition.initial_state().clone();
+            let mut n = n;
+            for _ in 0..4 {
+                let event = &events[n % 3];
+                n /= 3;
+                let expected = step_with_guards(&definition, &state, event, |g| g == "open");
+                assert_eq!(machine.event(event).is_ok(), expected.is_ok());
+                if let Ok(next) = expected {
+                    state = next;
+                }
+                assert_eq!(machine.current_state(), state);
+            }
+        }
+
+        assert_eq!(step(&definition, &b, &y).unwrap(), c);
+        assert_eq!(
+            step(&definition, &a, &y).unwrap_err().to_string(),
+            "no transition found for event y in state a"
+        );
+    }
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_scan_policies() {
+        let (a, b) = (State::new("a"), State::new("b"));
+        let (go, back, noise) = (Event::new("go"), Event::new("back"), Event::new("noise"));
+        let definition = StateMachineBuilder::new("scan", &a)
+            .add_event(a.clone(), go.clone(), b.clone(), None)
+            .add_event(b.clone(), back.clone(), a.clone(), None)
+            .definition();
+        let log = vec![go.clone(), noise, back, go];
+        let ok = |states: Vec<Result<State>>| -> Vec<Option<State>> {
+            states.into_iter().map(Result::ok).collect()
+        };
+
+        let stopped = definition.scan(&a).events(&log).collect();
+        assert_eq!(ok(stopped), vec![Some(b.clone()), None]);
+        let continued = definition
+            .scan(&a)
+            .on_error(ScanPolicy::Continue)
+            .events(&log)
+            .collect();
+        assert_eq!(
+            ok(continued),
+            vec![Some(b.clone()), None, Some(a.clone()), Some(b.clone())]
+        );
+        let skipped: Vec<State> = definition
+            .scan(&a)
+            .on_error(ScanPolicy::Skip)
+            .events(log)
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(skipped, vec![b.clone(), a, b]);
+    }
+}
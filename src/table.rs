@@ -0,0 +1,417 @@
+use crate::{Action, Event, State};
+use std::any::Any;
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+
+/// Where a transition was declared, in order of precedence
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Scope {
+    /// declared on the state itself
+    State,
+    /// declared for any state with `add_any_state_event`
+    AnyState,
+}
+
+/// The data passed along with an event, see [`StateMachine::event_with`](crate::StateMachine::event_with)
+pub(crate) type Payload<'a> = Option<&'a (dyn Any + 'static)>;
+
+/// An action downcasting the payload to the type it expects
+pub(crate) type AnyAction<Err> = Box<dyn Fn(Payload) -> Result<(), Err>>;
+
+/// The action of a transition
+pub(crate) enum Run<Err> {
+    /// added with `add_event`, ignoring the payload
+    Plain(Action<Err>),
+    /// added with `add_event_with`, getting the payload
+    WithPayload(AnyAction<Err>),
+}
+
+impl<Err> Run<Err> {
+    pub(crate) fn call(&self, payload: Payload) -> Result<(), Err> {
+        match self {
+            Run::Plain(action) => action(),
+            Run::WithPayload(action) => action(payload),
+        }
+    }
+}
+
+/// A named condition that must hold for a transition to fire
+pub(crate) struct Guard {
+    pub(crate) name: String,
+    pub(crate) check: Box<dyn Fn() -> bool>,
+}
+
+pub(crate) struct Transition<Err> {
+    /// `None` for transitions declared for any state
+    pub(crate) from: Option<State>,
+    pub(crate) trigger: Event,
+    pub(crate) new_state: State,
+    pub(crate) action: Option<Run<Err>>,
+    pub(crate) guard: Option<Guard>,
+    /// explicit priority, overriding the default precedence
+    pub(crate) priority: Option<i32>,
+    /// number of distinct principals that must send the event before it fires
+    pub(crate) approvals: Option<usize>,
+}
+
+impl<Err> Transition<Err> {
+    pub(crate) fn scope(&self) -> Scope {
+        if self.from.is_some() {
+            Scope::State
+        } else {
+            Scope::AnyState
+        }
+    }
+
+    /// Check the guard, transitions without a guard always pass
+    pub(crate) fn allowed(&self) -> bool {
+        self.guard.as_ref().is_none_or(|guard| (guard.check)())
+    }
+
+    pub(crate) fn guard_name(&self) -> Option<&str> {
+        self.guard.as_ref().map(|guard| guard.name.as_str())
+    }
+}
+
+/// Sort key of a candidate transition, lower keys are tried first:
+/// highest priority first, then by scope, then by depth (the state before its parent, grandparent...),
+/// then guarded before unguarded, then guarded ones in registration order
+/// and unguarded ones in reverse registration order, so adding the same transition again replaces it
+pub(crate) fn precedence(
+    priority: Option<i32>,
+    scope: Scope,
+    depth: usize,
+    guarded: bool,
+    index: usize,
+) -> impl Ord {
+    (
+        Reverse(priority.unwrap_or(0)),
+        scope,
+        depth,
+        !guarded,
+        if guarded { index } else { usize::MAX - index },
+    )
+}
+
+/// Number of events of a state up to which its transitions are found by a linear scan
+const SMALL_FAN_OUT: usize = 8;
+
+/// The indexes of the transitions of one state (or of the any-state transitions) by event
+///
+/// Most states handle a handful of events, comparing them is then faster than hashing the event.
+/// The map switches to a hash map once it handles more than [`SMALL_FAN_OUT`] events.
+enum ByEvent {
+    Small(Vec<(Event, Vec<usize>)>),
+    Large(HashMap<Event, Vec<usize>>),
+}
+
+impl Default for ByEvent {
+    fn default() -> Self {
+        Self::Small(Vec::new())
+    }
+}
+
+impl ByEvent {
+    fn get(&self, event: &Event) -> Option<&Vec<usize>> {
+        match self {
+            Self::Small(events) => events
+                .iter()
+                .find(|(e, _)| e == event)
+                .map(|(_, indexes)| indexes),
+            Self::Large(events) => events.get(event),
+        }
+    }
+
+    fn push(&mut self, event: Event, index: usize) {
+        match self {
+            Self::Small(events) => {
+                if let Some((_, indexes)) = events.iter_mut().find(|(e, _)| *e == event) {
+                    indexes.push(index);
+                } else if events.len() < SMALL_FAN_OUT {
+                    events.push((event, vec![index]));
+                } else {
+                    let mut large: HashMap<_, _> = events.drain(..).collect();
+                    large.insert(event, vec![index]);
+                    *self = Self::Large(large);
+                }
+            }
+            Self::Large(events) => events.entry(event).or_default().push(index),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        match self {
+            Self::Small(events) => events.is_empty(),
+            Self::Large(events) => events.is_empty(),
+        }
+    }
+
+    fn events(&self) -> Vec<&Event> {
+        match self {
+            Self::Small(events) => events.iter().map(|(event, _)| event).collect(),
+            Self::Large(events) => events.keys().collect(),
+        }
+    }
+}
+
+/// All transitions of a machine, indexed by state and event
+///
+/// Transitions are stored in registration order, the indexes refer to that order.
+pub(crate) struct TransitionTable<Err> {
+    transitions: Vec<Transition<Err>>,
+    by_state: HashMap<State, ByEvent>,
+    any_state: ByEvent,
+    /// the parent of every substate
+    parents: HashMap<State, State>,
+    /// the candidates of the states with own or inherited transitions, in the order they are tried,
+    /// see [`TransitionTable::resolve`]
+    resolved: HashMap<State, ByEvent>,
+}
+
+impl<Err> Default for TransitionTable<Err> {
+    fn default() -> Self {
+        Self {
+            transitions: Vec::new(),
+            by_state: HashMap::new(),
+            any_state: ByEvent::default(),
+            parents: HashMap::new(),
+            resolved: HashMap::new(),
+        }
+    }
+}
+
+impl<Err> TransitionTable<Err> {
+    pub(crate) fn add(&mut self, transition: Transition<Err>) {
+        let index = self.transitions.len();
+        let event = transition.trigger.clone();
+        match transition.from {
+            Some(ref from) => self
+                .by_state
+                .entry(from.clone())
+                .or_default()
+                .push(event, index),
+            None => self.any_state.push(event, index),
+        }
+        self.transitions.push(transition);
+    }
+
+    /// Make `state` a substate of `parent`
+    /// # Panics
+    /// If `parent` is `state` or one of its substates
+    #[cfg(feature = "unstable")]
+    pub(crate) fn set_parent(&mut self, state: &State, parent: &State) {
+        assert!(
+            self.lineage(parent).all(|ancestor| ancestor != state),
+            "state {parent} cannot be the parent of {state}, it is one of its substates"
+        );
+        self.parents.insert(state.clone(), parent.clone());
+    }
+
+    /// The state followed by its parent, grandparent...
+    pub(crate) fn lineage<'a>(&'a self, state: &'a State) -> impl Iterator<Item = &'a State> {
+        std::iter::successors(Some(state), |state| self.parents.get(*state))
+    }
+
+    /// The most recently added transition
+    pub(crate) fn last_mut(&mut self) -> Option<&mut Transition<Err>> {
+        self.transitions.last_mut()
+    }
+
+    /// All transitions, in registration order
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &Transition<Err>> {
+        self.transitions.iter()
+    }
+
+    /// Sort the candidates of every (state, event) once all transitions are added, see [`TransitionTable::candidates`]
+    pub(crate) fn resolve(&mut self) {
+        let states: HashSet<&State> = self.by_state.keys().chain(self.parents.keys()).collect();
+        let mut resolved = HashMap::new();
+        for state in states {
+            let mut by_event = ByEvent::default();
+            let mut seen = HashSet::new();
+            let events = self
+                .lineage(state)
+                .filter_map(|s| self.by_state.get(s))
+                .chain(std::iter::once(&self.any_state))
+                .flat_map(ByEvent::events);
+            for event in events {
+                if seen.insert(event) {
+                    for i in self.sorted(Some(state), event) {
+                        by_event.push(event.clone(), i);
+                    }
+                }
+            }
+            resolved.insert(state.clone(), by_event);
+        }
+        let mut any_state = ByEvent::default();
+        for event in self.any_state.events() {
+            for i in self.sorted(None, event) {
+                any_state.push(event.clone(), i);
+            }
+        }
+        self.resolved = resolved;
+        self.any_state = any_state;
+    }
+
+    /// The indexes of the transitions that could handle `event` in `state`, sorted by [`precedence`],
+    /// only the any-state transitions without a state
+    fn sorted(&self, state: Option<&State>, event: &Event) -> Vec<usize> {
+        let lineage = state
+            .into_iter()
+            .flat_map(|state| self.lineage(state))
+            .filter_map(|s| self.by_state.get(s)?.get(event))
+            .enumerate();
+        let any = self.any_state.get(event).map(|indexes| (0, indexes));
+        let mut indexes: Vec<(usize, usize)> = lineage
+            .chain(any)
+            .flat_map(|(depth, indexes)| indexes.iter().map(move |i| (depth, *i)))
+            .collect();
+        indexes.sort_by_key(|(depth, i)| {
+            let t = &self.transitions[*i];
+            precedence(t.priority, t.scope(), *depth, t.guard.is_some(), *i)
+        });
+        indexes.into_iter().map(|(_, i)| i).collect()
+    }
+
+    /// The candidates computed on every call, for a table that may not be resolved yet, e.g. the table of a builder
+    pub(crate) fn unresolved_candidates<'a>(
+        &'a self,
+        state: &State,
+        event: &Event,
+    ) -> impl Iterator<Item = &'a Transition<Err>> {
+        self.sorted(Some(state), event)
+            .into_iter()
+            .map(|i| &self.transitions[i])
+    }
+
+    /// The transitions that could handle `event` in `state`, in the order they are tried:
+    /// highest priority first, then by scope (state before any state),
+    /// then by depth (the state itself, then its parent, grandparent...),
+    /// then guarded before unguarded, then guarded ones in registration order
+    /// and unguarded ones last added first
+    ///
+    /// The order is computed once by [`TransitionTable::resolve`], when the machine is built.
+    pub(crate) fn candidates<'a>(
+        &'a self,
+        state: &State,
+        event: &Event,
+    ) -> impl ExactSizeIterator<Item = &'a Transition<Err>> + Clone {
+        let indexes = match self.resolved.get(state) {
+            Some(by_event) => by_event.get(event),
+            None => self.any_state.get(event),
+        };
+        indexes
+            .map_or(&[][..], Vec::as_slice)
+            .iter()
+            .map(|i| &self.transitions[*i])
+    }
+
+    /// Check if transitions are declared on `state` itself or its parents, ignoring the any-state transitions
+    pub(crate) fn has_own_transitions(&self, state: &State) -> bool {
+        self.lineage(state).any(|s| self.by_state.contains_key(s))
+    }
+
+    /// Check if any event is handled in `state`, including by the any-state transitions
+    pub(crate) fn has_transitions(&self, state: &State) -> bool {
+        self.has_own_transitions(state) || !self.any_state.is_empty()
+    }
+
+    /// The transitions that can fire in `state`, including the inherited and any-state transitions, in registration order
+    pub(crate) fn leaving<'a>(
+        &'a self,
+        state: &'a State,
+    ) -> impl Iterator<Item = &'a Transition<Err>> {
+        self.transitions.iter().filter(move |t| {
+            t.from
+                .as_ref()
+                .is_none_or(|from| self.lineage(state).any(|s| s == from))
+        })
+    }
+
+    /// The events handled in `state`, including by the any-state transitions, in registration order
+    pub(crate) fn events_in<'a>(&'a self, state: &'a State) -> impl Iterator<Item = &'a Event> {
+        let mut seen = HashSet::new();
+        self.leaving(state)
+            .map(|t| &t.trigger)
+            .filter(move |event| seen.insert(*event))
+    }
+
+    /// The states reachable from `state` in one transition, in registration order
+    pub(crate) fn successors<'a>(&'a self, state: &'a State) -> impl Iterator<Item = &'a State> {
+        let mut seen = HashSet::new();
+        self.leaving(state)
+            .map(|t| &t.new_state)
+            .filter(move |to| seen.insert(*to))
+    }
+
+    /// All states mentioned by the transitions, plus the given initial state
+    pub(crate) fn states(&self, initial: &State) -> Vec<State> {
+        let mut seen = HashSet::new();
+        let mut states = Vec::new();
+        let all = std::iter::once(initial).chain(
+            self.transitions
+                .iter()
+                .flat_map(|t| t.from.iter().chain(std::iter::once(&t.new_state))),
+        );
+        for state in all {
+            if seen.insert(state) {
+                states.push(state.clone());
+            }
+        }
+        states
+    }
+
+    /// All events handled by some transition, in registration order
+    pub(crate) fn events(&self) -> Vec<Event> {
+        let mut seen = HashSet::new();
+        self.transitions
+            .iter()
+            .filter(|t| seen.insert(&t.trigger))
+            .map(|t| t.trigger.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "tracing")]
+    use tracing_test::traced_test;
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_fan_out_representation() {
+        let hub = State::new("hub");
+        let mut table: TransitionTable<crate::Error> = TransitionTable::default();
+        let mut add = |from: Option<&State>, event: &str| {
+            table.add(Transition {
+                from: from.cloned(),
+                trigger: Event::new(event),
+                new_state: State::new(event),
+                action: None,
+                guard: None,
+                priority: None,
+                approvals: None,
+            });
+        };
+        for i in 0..=SMALL_FAN_OUT {
+            add(Some(&hub), &format!("e{i}"));
+        }
+        add(Some(&hub), "e0");
+        add(None, "e1");
+        table.resolve();
+        assert!(matches!(table.by_state[&hub], ByEvent::Large(_)));
+        assert!(matches!(table.any_state, ByEvent::Small(_)));
+
+        for i in 0..=SMALL_FAN_OUT {
+            let event = Event::new(format!("e{i}"));
+            let targets: Vec<State> = table
+                .candidates(&hub, &event)
+                .map(|t| t.new_state.clone())
+                .collect();
+            let expected = if i <= 1 { 2 } else { 1 };
+            assert_eq!(targets.len(), expected, "event {event}");
+            assert_eq!(targets[0], State::new(event.to_string()));
+        }
+    }
+}
@@ -0,0 +1,220 @@
+//! Parameterized definitions, one template yields machines differing only in constants
+
+use crate::{Error, Result, StateMachine, StateMachineBuilder, StateMachineError};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+
+/// The value of a template parameter
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Param {
+    Duration(Duration),
+    Number(i64),
+    Text(String),
+}
+
+impl Param {
+    fn kind(&self) -> &'static str {
+        match self {
+            Param::Duration(_) => "duration",
+            Param::Number(_) => "number",
+            Param::Text(_) => "text",
+        }
+    }
+}
+
+impl From<Duration> for Param {
+    fn from(value: Duration) -> Self {
+        Param::Duration(value)
+    }
+}
+
+impl From<i64> for Param {
+    fn from(value: i64) -> Self {
+        Param::Number(value)
+    }
+}
+
+impl From<&str> for Param {
+    fn from(value: &str) -> Self {
+        Param::Text(value.to_string())
+    }
+}
+
+impl From<String> for Param {
+    fn from(value: String) -> Self {
+        Param::Text(value)
+    }
+}
+
+/// The parameters of an instance of a [`Template`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Params {
+    values: BTreeMap<String, Param>,
+}
+
+impl Params {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    /// Set a parameter
+    pub fn set(mut self, name: impl Into<String>, value: impl Into<Param>) -> Self {
+        self.values.insert(name.into(), value.into());
+        self
+    }
+
+    /// Get a duration parameter
+    /// # Panics
+    /// If the template declares no duration parameter with that name
+    pub fn duration(&self, name: &str) -> Duration {
+        match self.values.get(name) {
+            Some(Param::Duration(value)) => *value,
+            _ => panic!("no duration parameter {name}"),
+        }
+    }
+
+    /// Get a number parameter
+    /// # Panics
+    /// If the template declares no number parameter with that name
+    pub fn number(&self, name: &str) -> i64 {
+        match self.values.get(name) {
+            Some(Param::Number(value)) => *value,
+            _ => panic!("no number parameter {name}"),
+        }
+    }
+
+    /// Get a text parameter
+    /// # Panics
+    /// If the template declares no text parameter with that name
+    pub fn text(&self, name: &str) -> &str {
+        match self.values.get(name) {
+            Some(Param::Text(value)) => value,
+            _ => panic!("no text parameter {name}"),
+        }
+    }
+}
+
+/// Creates the builder of an instance from its parameters
+type Build<Err> = Box<dyn Fn(&Params) -> StateMachineBuilder<Err>>;
+
+/// A definition with typed parameters, resolved when a machine is instantiated
+///
+/// The build function gets the parameters of the instance and uses them for timeouts,
+/// guard thresholds or names, instead of closures capturing constants.
+pub struct Template<Err = Error> {
+    name: String,
+    defaults: Params,
+    build: Build<Err>,
+}
+
+impl<Err> Template<Err>
+where
+    Err: From<Error> + fmt::Display + 'static,
+{
+    /// Create a template
+    /// # Arguments
+    /// * `name` - the name of the template, used in errors
+    /// * `build` - creates the builder of an instance from its parameters
+    pub fn new(
+        name: impl Into<String>,
+        build: impl Fn(&Params) -> StateMachineBuilder<Err> + 'static,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            defaults: Params::new(),
+            build: Box::new(build),
+        }
+    }
+
+    #[must_use]
+    /// Declare a parameter, its default value also sets its type
+    pub fn param(mut self, name: impl Into<String>, default: impl Into<Param>) -> Self {
+        self.defaults = self.defaults.set(name, default);
+        self
+    }
+
+    /// Build a machine, the parameters not set in `params` keep their default value
+    /// # Errors
+    /// If a parameter is not declared by the template or has another type than its default value
+    pub fn instantiate(&self, params: &Params) -> Result<StateMachine<Err>> {
+        let mut resolved = self.defaults.clone();
+        for (name, value) in &params.values {
+            let Some(default) = self.defaults.values.get(name) else {
+                return Err(Error::from(StateMachineError::UnknownParameter {
+                    template: self.name.clone(),
+                    name: name.clone(),
+                }));
+            };
+            if default.kind() != value.kind() {
+                return Err(Error::from(StateMachineError::ParameterType {
+                    template: self.name.clone(),
+                    name: name.clone(),
+                    expected: default.kind(),
+                    got: value.kind(),
+                }));
+            }
+            resolved.values.insert(name.clone(), value.clone());
+        }
+        Ok((self.build)(&resolved).build())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Event, State};
+    #[cfg(feature = "tracing")]
+    use tracing_test::traced_test;
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_template_instances() {
+        let (open, closed) = (State::new("open"), State::new("closed"));
+        let template = Template::new("door", move |params| {
+            let limit = params.number("max_openings");
+            let openings = std::cell::Cell::new(0);
+            StateMachineBuilder::new(params.text("name"), &closed)
+                .add_event(closed.clone(), Event::new("open"), open.clone(), None)
+                .with_guard("below_limit", move || {
+                    openings.set(openings.get() + 1);
+                    openings.get() <= limit
+                })
+                .add_event(open.clone(), Event::new("close"), closed.clone(), None)
+                .max_dwell(open.clone(), params.duration("timeout"))
+        })
+        .param("name", "door")
+        .param("max_openings", 1)
+        .param("timeout", Duration::from_secs(30));
+
+        let front = template
+            .instantiate(&Params::new().set("name", "front").set("max_openings", 2))
+            .unwrap();
+        let back = template.instantiate(&Params::new()).unwrap();
+        assert_eq!((front.name(), back.name()), ("front", "door"));
+        for _ in 0..2 {
+            front.event(&Event::new("open")).unwrap();
+            front.event(&Event::new("close")).unwrap();
+        }
+        back.event(&Event::new("open")).unwrap();
+        back.event(&Event::new("close")).unwrap();
+        assert!(back.event(&Event::new("open")).is_err());
+
+        assert_eq!(
+            template
+                .instantiate(&Params::new().set("timeout", 30))
+                .err()
+                .map(|e| e.to_string()),
+            Some("parameter timeout of template door is a duration, got a number".to_string())
+        );
+        assert_eq!(
+            template
+                .instantiate(&Params::new().set("colour", "red"))
+                .err()
+                .map(|e| e.to_string()),
+            Some("template door has no parameter colour".to_string())
+        );
+    }
+}
@@ -0,0 +1,466 @@
+use crate::{Event, Observer, State, TransitionInfo, TransitionRecord};
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+/// Matches transitions of a trace
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Pattern {
+    /// any transition
+    Any,
+    /// a transition triggered by the event
+    Event(Event),
+    /// a transition entering the state, self transitions included
+    Enter(State),
+    /// a transition leaving the state, self transitions included
+    Leave(State),
+}
+
+impl Pattern {
+    /// Match transitions triggered by an event
+    pub fn event(name: impl Into<String>) -> Self {
+        Pattern::Event(Event::new(name))
+    }
+
+    /// Match transitions entering a state
+    pub fn enter(name: impl Into<String>) -> Self {
+        Pattern::Enter(State::new(name))
+    }
+
+    /// Match transitions leaving a state
+    pub fn leave(name: impl Into<String>) -> Self {
+        Pattern::Leave(State::new(name))
+    }
+
+    fn matches(&self, from: &State, event: &Event, to: &State) -> bool {
+        match self {
+            Pattern::Any => true,
+            Pattern::Event(e) => e == event,
+            Pattern::Enter(s) => s == to,
+            Pattern::Leave(s) => s == from,
+        }
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Pattern::Any => write!(f, "any transition"),
+            Pattern::Event(e) => write!(f, "`{e}`"),
+            Pattern::Enter(s) => write!(f, "entering {s}"),
+            Pattern::Leave(s) => write!(f, "leaving {s}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Kind {
+    Never(Pattern),
+    Always(Pattern),
+    Eventually(Pattern),
+    AfterNever(Pattern, Pattern),
+    AfterNeverWithout(Pattern, Pattern, Pattern),
+    AfterEventually(Pattern, Pattern),
+}
+
+/// A temporal property over a trace of transitions
+///
+/// Properties are checked offline on recorded histories with [`Property::check`],
+/// or online while the machine runs with [`Assertions`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Property {
+    name: Option<String>,
+    kind: Kind,
+}
+
+/// Properties that only apply after a transition matched, see [`Property::after`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct After {
+    trigger: Pattern,
+}
+
+impl After {
+    /// After the trigger, `p` never occurs
+    pub fn never(self, p: Pattern) -> Property {
+        Property::new(Kind::AfterNever(self.trigger, p))
+    }
+
+    /// After the trigger, `p` only occurs if `required` occurred since the trigger or since the last `p`
+    pub fn never_without(self, p: Pattern, required: Pattern) -> Property {
+        Property::new(Kind::AfterNeverWithout(self.trigger, p, required))
+    }
+
+    /// Every trigger is eventually followed by `p`, checked when the trace ends
+    pub fn eventually(self, p: Pattern) -> Property {
+        Property::new(Kind::AfterEventually(self.trigger, p))
+    }
+}
+
+impl Property {
+    fn new(kind: Kind) -> Self {
+        Self { name: None, kind }
+    }
+
+    /// `p` never occurs
+    pub fn never(p: Pattern) -> Self {
+        Self::new(Kind::Never(p))
+    }
+
+    /// Every transition matches `p`
+    pub fn always(p: Pattern) -> Self {
+        Self::new(Kind::Always(p))
+    }
+
+    /// `p` occurs at least once, checked when the trace ends
+    pub fn eventually(p: Pattern) -> Self {
+        Self::new(Kind::Eventually(p))
+    }
+
+    /// Start a property that applies once `trigger` occurred
+    pub fn after(trigger: Pattern) -> After {
+        After { trigger }
+    }
+
+    #[must_use]
+    /// Name the property, the name is used in violations instead of the description
+    pub fn named(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Start checking the property on a new trace
+    pub fn checker(&self) -> Checker {
+        Checker {
+            property: self.clone(),
+            position: 0,
+            armed: false,
+            permitted: false,
+            pending: None,
+            seen: false,
+        }
+    }
+
+    /// Check the property on a recorded trace
+    /// Records of transitions that were not taken (rejected by a guard, duplicate deliveries) are skipped
+    /// # Errors
+    /// The first violation of the property
+    pub fn check(&self, trace: &[TransitionRecord]) -> Result<(), TemporalViolation> {
+        let mut checker = self.checker();
+        for record in trace {
+            if record.outcome.is_taken() {
+                checker.step(&record.from, &record.event, &record.to)?;
+            }
+        }
+        checker.finish()
+    }
+}
+
+impl fmt::Display for Property {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ref name) = self.name {
+            return write!(f, "{name}");
+        }
+        match self.kind {
+            Kind::Never(ref p) => write!(f, "never {p}"),
+            Kind::Always(ref p) => write!(f, "always {p}"),
+            Kind::Eventually(ref p) => write!(f, "eventually {p}"),
+            Kind::AfterNever(ref a, ref p) => write!(f, "after {a}, never {p}"),
+            Kind::AfterNeverWithout(ref a, ref p, ref r) => {
+                write!(f, "after {a}, never {p} without {r}")
+            }
+            Kind::AfterEventually(ref a, ref p) => write!(f, "after {a}, eventually {p}"),
+        }
+    }
+}
+
+/// A trace that does not satisfy a property
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TemporalViolation {
+    /// the violated property
+    pub property: String,
+    /// position in the trace of the violating transition, `None` if detected at the end of the trace
+    pub position: Option<usize>,
+    /// the event of the violating transition, if any
+    pub event: Option<Event>,
+}
+
+impl fmt::Display for TemporalViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.position, &self.event) {
+            (Some(position), Some(event)) => write!(
+                f,
+                "property `{}` violated by `{event}` at transition {position}",
+                self.property
+            ),
+            _ => write!(f, "property `{}` violated at end of trace", self.property),
+        }
+    }
+}
+
+impl std::error::Error for TemporalViolation {}
+
+/// Incremental evaluation of a property, one transition at a time
+#[derive(Debug, Clone)]
+pub struct Checker {
+    property: Property,
+    position: usize,
+    /// the trigger of an `after` property occurred
+    armed: bool,
+    /// the required pattern occurred since the trigger or the last match
+    permitted: bool,
+    /// position of the oldest trigger still waiting for its response
+    pending: Option<usize>,
+    /// the pattern of `eventually` occurred
+    seen: bool,
+}
+
+impl Checker {
+    /// Feed the next transition of the trace
+    /// # Errors
+    /// If the transition violates the property
+    pub fn step(
+        &mut self,
+        from: &State,
+        event: &Event,
+        to: &State,
+    ) -> Result<(), TemporalViolation> {
+        let position = self.position;
+        self.position += 1;
+        let m = |p: &Pattern| p.matches(from, event, to);
+        let violated = match self.property.kind {
+            Kind::Never(ref p) => m(p),
+            Kind::Always(ref p) => !m(p),
+            Kind::Eventually(ref p) => {
+                self.seen |= m(p);
+                false
+            }
+            Kind::AfterNever(ref a, ref p) => {
+                let violated = self.armed && m(p);
+                self.armed |= m(a);
+                violated
+            }
+            Kind::AfterNeverWithout(ref a, ref p, ref r) => {
+                let mut violated = false;
+                if self.armed && m(p) {
+                    violated = !self.permitted;
+                    self.permitted = false;
+                }
+                if self.armed && m(r) {
+                    self.permitted = true;
+                }
+                self.armed |= m(a);
+                violated
+            }
+            Kind::AfterEventually(ref a, ref p) => {
+                if m(p) {
+                    self.pending = None;
+                }
+                if m(a) && self.pending.is_none() {
+                    self.pending = Some(position);
+                }
+                false
+            }
+        };
+        if violated {
+            Err(TemporalViolation {
+                property: self.property.to_string(),
+                position: Some(position),
+                event: Some(event.clone()),
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Signal the end of the trace
+    /// # Errors
+    /// If a transition that had to occur did not
+    pub fn finish(&self) -> Result<(), TemporalViolation> {
+        let violated = match self.property.kind {
+            Kind::Eventually(_) => !self.seen,
+            Kind::AfterEventually(..) => self.pending.is_some(),
+            _ => false,
+        };
+        if violated {
+            Err(TemporalViolation {
+                property: self.property.to_string(),
+                position: None,
+                event: None,
+            })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Checks temporal properties online, as an observer of a machine
+///
+/// Clones share the same checkers, keep one to query the violations.
+#[derive(Clone, Default)]
+pub struct Assertions {
+    inner: Rc<RefCell<AssertionsInner>>,
+}
+
+#[derive(Default)]
+struct AssertionsInner {
+    checkers: Vec<Checker>,
+    violations: Vec<TemporalViolation>,
+}
+
+impl Assertions {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    /// Add a property to check
+    pub fn with(self, property: Property) -> Self {
+        self.inner.borrow_mut().checkers.push(property.checker());
+        self
+    }
+
+    /// Get the violations detected so far
+    pub fn violations(&self) -> Vec<TemporalViolation> {
+        self.inner.borrow().violations.clone()
+    }
+
+    /// Get the violations, including the properties not satisfied at the end of the trace
+    pub fn finish(&self) -> Vec<TemporalViolation> {
+        let inner = self.inner.borrow();
+        let mut violations = inner.violations.clone();
+        violations.extend(inner.checkers.iter().filter_map(|c| c.finish().err()));
+        violations
+    }
+
+    fn observe(&self, t: &TransitionInfo) {
+        let mut inner = self.inner.borrow_mut();
+        let inner = &mut *inner;
+        for checker in &mut inner.checkers {
+            if let Err(violation) = checker.step(t.from, t.event, t.to) {
+                inner.violations.push(violation);
+            }
+        }
+    }
+}
+
+impl<Err> Observer<Err> for Assertions {
+    fn on_transition(&self, transition: &TransitionInfo) {
+        self.observe(transition);
+    }
+
+    fn on_action_failed(&self, transition: &TransitionInfo, _error: &Err) {
+        // rolled back transitions are not taken, they are not observed
+        self.observe(transition);
+    }
+}
+
+/// Assert that a recorded trace satisfies all properties, for tests
+/// # Panics
+/// If a property is violated, listing all violations
+pub fn assert_trace(trace: &[TransitionRecord], properties: &[Property]) {
+    let violations: Vec<String> = properties
+        .iter()
+        .filter_map(|p| p.check(trace).err())
+        .map(|v| v.to_string())
+        .collect();
+    assert!(violations.is_empty(), "{}", violations.join("\n"));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::StateMachineBuilder;
+    #[cfg(feature = "tracing")]
+    use tracing_test::traced_test;
+
+    fn refund_property() -> Property {
+        Property::after(Pattern::event("pay"))
+            .never_without(Pattern::event("refund"), Pattern::event("request_refund"))
+    }
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_offline_check() {
+        let open = State::new("open");
+        let paid = State::new("paid");
+        let machine = StateMachineBuilder::new("order", &open)
+            .add_event(open.clone(), Event::new("pay"), paid.clone(), None)
+            .add_event(
+                paid.clone(),
+                Event::new("request_refund"),
+                paid.clone(),
+                None,
+            )
+            .add_event(paid.clone(), Event::new("refund"), paid.clone(), None)
+            .with_history(10)
+            .build();
+        for e in ["pay", "request_refund", "refund"] {
+            machine.event(&Event::new(e)).unwrap();
+        }
+        let trace = machine.history();
+        assert_trace(
+            &trace,
+            &[
+                refund_property(),
+                Property::never(Pattern::enter("closed")),
+                Property::eventually(Pattern::enter("paid")),
+            ],
+        );
+
+        machine.event(&Event::new("refund")).unwrap();
+        let violation = refund_property().check(&machine.history()).unwrap_err();
+        assert_eq!(violation.position, Some(3));
+        assert_eq!(
+            violation.to_string(),
+            "property `after `pay`, never `refund` without `request_refund`` violated by `refund` at transition 3"
+        );
+    }
+
+    #[test]
+    fn test_checker() {
+        let (a, b) = (State::new("a"), State::new("b"));
+        let (e1, e2) = (Event::new("e1"), Event::new("e2"));
+        let mut checker = Property::after(Pattern::event("e1"))
+            .eventually(Pattern::enter("a"))
+            .named("e1 returns to a")
+            .checker();
+        checker.step(&a, &e2, &a).unwrap();
+        assert!(checker.finish().is_ok());
+        checker.step(&a, &e1, &b).unwrap();
+        assert_eq!(
+            checker.finish().unwrap_err().to_string(),
+            "property `e1 returns to a` violated at end of trace"
+        );
+        checker.step(&b, &e2, &a).unwrap();
+        assert!(checker.finish().is_ok());
+
+        let mut checker = Property::always(Pattern::leave("a")).checker();
+        checker.step(&a, &e1, &b).unwrap();
+        assert!(checker.step(&b, &e1, &b).is_err());
+    }
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_online_assertions() {
+        let open = State::new("open");
+        let paid = State::new("paid");
+        let assertions = Assertions::new()
+            .with(refund_property())
+            .with(Property::after(Pattern::event("pay")).never(Pattern::event("pay")));
+        let machine = StateMachineBuilder::new("order", &open)
+            .add_event(open.clone(), Event::new("pay"), paid.clone(), None)
+            .add_any_state_event(Event::new("pay"), paid.clone(), None)
+            .add_event(paid.clone(), Event::new("refund"), paid.clone(), None)
+            .add_observer(Box::new(assertions.clone()))
+            .build();
+        machine.event(&Event::new("pay")).unwrap();
+        assert!(assertions.violations().is_empty());
+        machine.event(&Event::new("refund")).unwrap();
+        machine.event(&Event::new("pay")).unwrap();
+        let violations = assertions.finish();
+        assert_eq!(violations.len(), 2);
+        assert_eq!(violations[0].event, Some(Event::new("refund")));
+        assert_eq!(violations[1].position, Some(2));
+    }
+}
@@ -0,0 +1,240 @@
+//! Rate limits on the events a machine handles, to protect the systems its actions call
+
+use crate::trace::debug;
+use crate::{Error, Event, State, StateMachine, StateMachineBuilder, StateMachineError};
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+/// What a throttled machine does with an event over the limit
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Overflow {
+    /// fail the event
+    #[default]
+    Reject,
+    /// schedule the event as a deadline for when the limit allows it, see [`StateMachine::fire_due`]
+    ///
+    /// Deadlines carry no data, events with a payload are rejected instead.
+    Defer,
+}
+
+/// A token bucket: `rate` events per `per`, with bursts of up to `burst` events
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Throttle {
+    rate: u32,
+    per: Duration,
+    burst: u32,
+    overflow: Overflow,
+}
+
+impl Throttle {
+    /// Allow `rate` events per `per`, rejecting the others
+    /// The burst is `rate`, i.e. a full period of events can be handled at once.
+    #[must_use]
+    pub fn new(rate: u32, per: Duration) -> Self {
+        Self {
+            rate,
+            per,
+            burst: rate,
+            overflow: Overflow::Reject,
+        }
+    }
+
+    #[must_use]
+    /// Allow bursts of up to `burst` events
+    pub fn burst(mut self, burst: u32) -> Self {
+        self.burst = burst;
+        self
+    }
+
+    #[must_use]
+    /// Choose what to do with events over the limit
+    pub fn on_overflow(mut self, overflow: Overflow) -> Self {
+        self.overflow = overflow;
+        self
+    }
+}
+
+/// The tokens left in a bucket
+struct Bucket {
+    throttle: Throttle,
+    tokens: f64,
+    refilled: Option<SystemTime>,
+}
+
+impl Bucket {
+    fn new(throttle: Throttle) -> Self {
+        Self {
+            throttle,
+            tokens: f64::from(throttle.burst),
+            refilled: None,
+        }
+    }
+
+    /// Add the tokens earned since the last refill
+    /// # Returns
+    /// How long until the next token, `None` if a token is available
+    fn refill(&mut self, now: SystemTime) -> Option<Duration> {
+        let per_token = self.throttle.per.as_secs_f64() / f64::from(self.throttle.rate.max(1));
+        if let Some(elapsed) = self.refilled.and_then(|at| now.duration_since(at).ok()) {
+            self.tokens = (self.tokens + elapsed.as_secs_f64() / per_token)
+                .min(f64::from(self.throttle.burst));
+        }
+        self.refilled = Some(now);
+        (self.tokens < 1.0).then(|| {
+            Duration::from_secs_f64((1.0 - self.tokens) * per_token).max(Duration::from_nanos(1))
+        })
+    }
+}
+
+/// The buckets of a machine: one for the machine and one per throttled state
+#[derive(Default)]
+pub(crate) struct Throttles {
+    machine: Option<Bucket>,
+    states: HashMap<State, Bucket>,
+}
+
+impl Throttles {
+    /// Fill every bucket, forgetting the events handled so far
+    pub(crate) fn reset(&mut self) {
+        for bucket in self.machine.iter_mut().chain(self.states.values_mut()) {
+            *bucket = Bucket::new(bucket.throttle);
+        }
+    }
+
+    /// Take a token from the buckets that apply in `state`, only if all of them have one
+    /// # Returns
+    /// The wait and the policy of the bucket that refused, `None` if the event can be handled
+    fn admit(&mut self, state: &State, now: SystemTime) -> Option<(Duration, Overflow)> {
+        let mut buckets: Vec<&mut Bucket> = self.states.get_mut(state).into_iter().collect();
+        buckets.extend(self.machine.as_mut());
+        let mut refused = None;
+        for bucket in &mut buckets {
+            if let Some(wait) = bucket.refill(now) {
+                refused = refused.or(Some((wait, bucket.throttle.overflow)));
+            }
+        }
+        if refused.is_none() {
+            for bucket in buckets {
+                bucket.tokens -= 1.0;
+            }
+        }
+        refused
+    }
+}
+
+impl<Err> StateMachine<Err> {
+    /// Check the throttles before handling an event in `state`
+    /// # Returns
+    /// `false` if the event was deferred
+    /// # Errors
+    /// If the event is over the limit of a rejecting throttle,
+    /// or of a deferring one and it has a payload, which would be lost
+    /// # Panics
+    /// If the lock is poisoned
+    pub(crate) fn admit(&self, state: &State, event: &Event, payload: bool) -> Result<bool, Error> {
+        let now = self.clock.now();
+        let refused = self
+            .throttles
+            .lock()
+            .expect("failed to get lock")
+            .admit(state, now);
+        match refused {
+            None => Ok(true),
+            Some((wait, Overflow::Defer)) if !payload => {
+                debug!(
+                    "{}: throttled, deferring {event} by {wait:?}",
+                    self.log_name()
+                );
+                self.schedule_at(now + wait, event.clone());
+                Ok(false)
+            }
+            Some((wait, _)) => {
+                let error = StateMachineError::Throttled {
+                    state: state.clone(),
+                    event: event.clone(),
+                    retry_in: wait,
+                };
+                debug!("{}: {error}", self.log_name());
+                Err(Error::from(error))
+            }
+        }
+    }
+}
+
+impl<Err> StateMachineBuilder<Err> {
+    #[must_use]
+    /// Limit the rate of all the events handled by the machine
+    pub fn throttle(mut self, throttle: Throttle) -> Self {
+        self.throttles.machine = Some(Bucket::new(throttle));
+        self
+    }
+
+    #[must_use]
+    /// Limit the rate of the events handled in `state`, on top of the limit of the machine
+    pub fn throttle_state(mut self, state: &State, throttle: Throttle) -> Self {
+        self.throttles
+            .states
+            .insert(state.clone(), Bucket::new(throttle));
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Clock, ManualClock};
+    use std::sync::Arc;
+    #[cfg(feature = "tracing")]
+    use tracing_test::traced_test;
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_throttle() {
+        let (idle, busy) = (State::new("idle"), State::new("busy"));
+        let (ping, work) = (Event::new("ping"), Event::new("work"));
+        let clock = Arc::new(ManualClock::default());
+        let machine = StateMachineBuilder::new("api", &idle)
+            .add_event(idle.clone(), ping.clone(), idle.clone(), None)
+            .add_event(idle.clone(), work.clone(), busy.clone(), None)
+            .add_event(busy.clone(), ping.clone(), busy.clone(), None)
+            .throttle_state(&idle, Throttle::new(2, Duration::from_secs(1)))
+            .throttle_state(
+                &busy,
+                Throttle::new(1, Duration::from_secs(1)).on_overflow(Overflow::Defer),
+            )
+            .with_clock(clock.clone())
+            .build();
+
+        machine.event(&ping).unwrap();
+        machine.event(&ping).unwrap();
+        let rejected = machine.event(&work).unwrap_err().to_string();
+        assert!(rejected.contains("throttled"), "{rejected}");
+        assert_eq!(machine.current_state(), idle);
+        clock.advance(Duration::from_millis(500));
+        machine.event(&work).unwrap();
+        assert_eq!(machine.current_state(), busy);
+
+        // busy has its own bucket, the second ping waits for the next token
+        machine.event(&ping).unwrap();
+        machine.event(&ping).unwrap();
+        let deferred = machine.deadlines();
+        assert_eq!(deferred.len(), 1);
+        assert_eq!(deferred[0].at, clock.now() + Duration::from_secs(1));
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(machine.fire_due().unwrap(), 1);
+        assert!(machine.deadlines().is_empty());
+
+        // a deadline cannot carry the payload, the event is rejected instead of losing it
+        clock.advance(Duration::from_secs(1));
+        machine.event_with(&ping, &42).unwrap();
+        let rejected = machine.event_with(&ping, &43).unwrap_err();
+        assert!(matches!(
+            rejected.downcast_ref::<StateMachineError>(),
+            Some(StateMachineError::Throttled { .. })
+        ));
+        assert!(machine.deadlines().is_empty());
+        // a restore fills the buckets again
+        machine.restore(&machine.snapshot()).unwrap();
+        machine.event_with(&ping, &44).unwrap();
+    }
+}
@@ -0,0 +1,25 @@
+//! Logging macros, forwarded to `tracing` when the feature is enabled
+
+#[cfg(feature = "tracing")]
+pub(crate) use tracing::{debug, error};
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        if false {
+            let _ = format!($($arg)*);
+        }
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! error {
+    ($($arg:tt)*) => {
+        if false {
+            let _ = format!($($arg)*);
+        }
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) use {debug, error};
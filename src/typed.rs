@@ -0,0 +1,198 @@
+//! Machines whose states and events are user-defined enums
+
+use crate::{Action, Error, Event, State, StateMachine, StateMachineBuilder};
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+use std::marker::PhantomData;
+
+/// Name a state or an event of a typed machine by its `Debug` representation, e.g. `Idle`
+fn label(value: &impl fmt::Debug) -> String {
+    format!("{value:?}")
+}
+
+/// Builds a [`TypedMachine`], see [`StateMachineBuilder`] for the options
+pub struct TypedBuilder<S, E, Err = Error> {
+    builder: StateMachineBuilder<Err>,
+    states: HashMap<State, S>,
+    events: PhantomData<E>,
+}
+
+impl<S, E> TypedBuilder<S, E>
+where
+    S: Eq + Hash + Clone + fmt::Debug,
+    E: Eq + Hash + Clone + fmt::Debug,
+{
+    #[must_use]
+    pub fn new(name: impl Into<String>, initial_state: S) -> Self {
+        Self::with_error_type(name, initial_state)
+    }
+}
+
+impl<S, E, Err> TypedBuilder<S, E, Err>
+where
+    S: Eq + Hash + Clone + fmt::Debug,
+    E: Eq + Hash + Clone + fmt::Debug,
+{
+    /// Create a builder for a typed machine whose actions return `Err`, see [`StateMachineBuilder::with_error_type`]
+    #[must_use]
+    pub fn with_error_type(name: impl Into<String>, initial_state: S) -> Self {
+        let state = State::new(label(&initial_state));
+        Self {
+            builder: StateMachineBuilder::with_error_type(name, &state),
+            states: HashMap::from([(state, initial_state)]),
+            events: PhantomData,
+        }
+    }
+
+    fn state(&mut self, state: S) -> State {
+        let named = State::new(label(&state));
+        self.states.insert(named.clone(), state);
+        named
+    }
+
+    #[must_use]
+    /// See [`StateMachineBuilder::add_event`]
+    pub fn add_event(
+        mut self,
+        old_state: S,
+        event: E,
+        new_state: S,
+        action: Option<Action<Err>>,
+    ) -> Self {
+        let (old_state, new_state) = (self.state(old_state), self.state(new_state));
+        self.builder =
+            self.builder
+                .add_event(old_state, Event::new(label(&event)), new_state, action);
+        self
+    }
+
+    #[must_use]
+    /// See [`StateMachineBuilder::add_any_state_event`]
+    pub fn add_any_state_event(
+        mut self,
+        event: E,
+        new_state: S,
+        action: Option<Action<Err>>,
+    ) -> Self {
+        let new_state = self.state(new_state);
+        self.builder =
+            self.builder
+                .add_any_state_event(Event::new(label(&event)), new_state, action);
+        self
+    }
+
+    #[must_use]
+    /// Set the other options of the machine on the untyped builder, e.g. guards or the history
+    pub fn configure(
+        mut self,
+        configure: impl FnOnce(StateMachineBuilder<Err>) -> StateMachineBuilder<Err>,
+    ) -> Self {
+        self.builder = configure(self.builder);
+        self
+    }
+
+    #[must_use]
+    pub fn build(self) -> TypedMachine<S, E, Err> {
+        TypedMachine {
+            machine: self.builder.build(),
+            states: self.states,
+            events: PhantomData,
+        }
+    }
+}
+
+/// A state machine over user-defined enums, wrapping a [`StateMachine`] over their names
+///
+/// The states and events are named by their `Debug` representation,
+/// so they are best plain enums without fields.
+pub struct TypedMachine<S, E, Err = Error> {
+    machine: StateMachine<Err>,
+    states: HashMap<State, S>,
+    events: PhantomData<E>,
+}
+
+impl<S, E, Err> TypedMachine<S, E, Err>
+where
+    S: Clone,
+    E: fmt::Debug,
+    Err: From<Error> + fmt::Display + 'static,
+{
+    /// See [`StateMachine::event`]
+    /// # Errors
+    /// As [`StateMachine::event`]
+    pub fn event(&self, event: &E) -> Result<(), Err> {
+        self.machine.event(&Event::new(label(event)))
+    }
+
+    /// Get the current state
+    /// # Returns
+    /// `None` if the machine is in a state the typed builder did not declare, e.g. an error state set with `configure`
+    /// or a state entered through [`TypedMachine::machine`], see [`StateMachine::current_state`] for its name
+    pub fn current_state(&self) -> Option<S> {
+        self.states.get(&self.machine.current_state()).cloned()
+    }
+
+    /// Get the untyped machine, for the other operations
+    pub fn machine(&self) -> &StateMachine<Err> {
+        &self.machine
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "tracing")]
+    use tracing_test::traced_test;
+
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+    enum Light {
+        Off,
+        On,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+    enum Switch {
+        Flip,
+        Cut,
+    }
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_typed_machine() {
+        let machine = TypedBuilder::new("lamp", Light::Off)
+            .add_event(Light::Off, Switch::Flip, Light::On, None)
+            .add_event(Light::On, Switch::Flip, Light::Off, None)
+            .add_any_state_event(Switch::Cut, Light::Off, None)
+            .configure(|builder| builder.with_history(10))
+            .build();
+
+        machine.event(&Switch::Flip).unwrap();
+        assert_eq!(machine.current_state(), Some(Light::On));
+        machine.event(&Switch::Cut).unwrap();
+        assert_eq!(machine.current_state(), Some(Light::Off));
+        assert_eq!(machine.machine().current_state(), State::new("Off"));
+        assert_eq!(machine.machine().history().len(), 2);
+    }
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_undeclared_state() {
+        let broken = State::new("Broken");
+        let machine: TypedMachine<Light, Switch> = TypedBuilder::new("lamp", Light::Off)
+            .add_event(
+                Light::Off,
+                Switch::Flip,
+                Light::On,
+                Some(Box::new(|| {
+                    Err(crate::error::message("bulb blown".to_string()))
+                })),
+            )
+            .configure(|builder| builder.error_state(&broken, 1))
+            .build();
+
+        assert!(machine.event(&Switch::Flip).is_err());
+        assert_eq!(machine.current_state(), None);
+        assert_eq!(machine.machine().current_state(), broken);
+    }
+}
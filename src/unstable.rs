@@ -0,0 +1,13 @@
+//! Experimental APIs, enabled by the `unstable` feature
+//!
+//! They are exempt from semver: they may change or disappear in any release.
+//! A stabilized API moves to the root of the crate, with the same types and behavior,
+//! so code written against this module only needs its imports updated.
+//!
+//! - async machines, whose actions are futures awaited outside of the state lock
+//! - concurrency limits on those actions
+//! - composite states, [`StateMachineBuilder::with_parent`](crate::StateMachineBuilder::with_parent)
+//!   and [`StateMachine::is_in`](crate::StateMachine::is_in), which are methods and stay where they are
+
+pub use crate::async_machine::{ActionFuture, AsyncAction, AsyncBuilder, AsyncStateMachine};
+pub use crate::limit::ConcurrencyLimit;
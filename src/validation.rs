@@ -0,0 +1,192 @@
+use crate::table::{Scope, Transition};
+use crate::{Event, InvalidEntryPoint, MustFollow, State, StateMachine};
+use std::fmt;
+
+/// A transition as listed in a validation report
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Candidate {
+    /// the state after the transition
+    pub to: State,
+    /// where the transition was declared
+    pub scope: Scope,
+    /// name of the guard, if any
+    pub guard: Option<String>,
+    /// effective priority, 0 unless overridden
+    pub priority: i32,
+}
+
+impl<Err> From<&Transition<Err>> for Candidate {
+    fn from(t: &Transition<Err>) -> Self {
+        Self {
+            to: t.new_state.clone(),
+            scope: t.scope(),
+            guard: t.guard_name().map(str::to_string),
+            priority: t.priority.unwrap_or(0),
+        }
+    }
+}
+
+/// Several transitions that can handle the same event in the same state
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    pub state: State,
+    pub event: Event,
+    /// the competing transitions, in the order they are tried
+    pub candidates: Vec<Candidate>,
+}
+
+impl Conflict {
+    /// The candidates that can never fire, because an unguarded candidate is tried before them
+    pub fn shadowed(&self) -> &[Candidate] {
+        self.candidates
+            .iter()
+            .position(|c| c.guard.is_none())
+            .map_or(&[], |i| &self.candidates[i + 1..])
+    }
+}
+
+/// Result of checking a state machine definition
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Validation {
+    /// the (state, event) pairs handled by more than one transition
+    pub conflicts: Vec<Conflict>,
+    /// the states that handle no event at all, the machine cannot leave them
+    pub dead_ends: Vec<State>,
+    /// the entry points starting in a state that is not a state of the machine
+    pub invalid_entry_points: Vec<InvalidEntryPoint>,
+    /// the ordering constraints that a path of the machine may break, flagged at runtime by [`StateMachine::ordering_violations`]
+    pub unordered: Vec<MustFollow>,
+}
+
+impl fmt::Display for Validation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for conflict in &self.conflicts {
+            writeln!(f, "state {}, event {}:", conflict.state, conflict.event)?;
+            let shadowed = conflict.candidates.len() - conflict.shadowed().len();
+            for (i, c) in conflict.candidates.iter().enumerate() {
+                let scope = match c.scope {
+                    Scope::State => "state",
+                    Scope::AnyState => "any state",
+                };
+                write!(f, "  {}. -> {} ({scope}", i + 1, c.to)?;
+                if let Some(ref guard) = c.guard {
+                    write!(f, ", guard {guard}")?;
+                }
+                write!(f, ", priority {})", c.priority)?;
+                if i >= shadowed {
+                    write!(f, " shadowed")?;
+                }
+                writeln!(f)?;
+            }
+        }
+        for state in &self.dead_ends {
+            writeln!(f, "state {state} has no transitions")?;
+        }
+        for entry in &self.invalid_entry_points {
+            writeln!(
+                f,
+                "entry point {} starts in unknown state {}",
+                entry.name, entry.state
+            )?;
+        }
+        for constraint in &self.unordered {
+            writeln!(f, "{constraint}, but may fire before it")?;
+        }
+        Ok(())
+    }
+}
+
+impl<Err> StateMachine<Err> {
+    /// Check the definition of the state machine
+    /// # Returns
+    /// A report listing, among others, the conflicting transitions in the order they are tried
+    /// and the states without transitions, which are either final states or modeling errors
+    pub fn validate(&self) -> Validation {
+        let mut conflicts = Vec::new();
+        let mut dead_ends = Vec::new();
+        let states = self.table.states(&self.initial_state);
+        let invalid_entry_points = self
+            .entry_points
+            .iter()
+            .filter(|(_, state)| !states.contains(state))
+            .map(|(name, state)| InvalidEntryPoint {
+                name: name.clone(),
+                state: state.clone(),
+            })
+            .collect();
+        for state in states {
+            if !self.table.has_transitions(&state) {
+                dead_ends.push(state);
+                continue;
+            }
+            for event in self.table.events() {
+                let candidates = self.table.candidates(&state, &event);
+                if candidates.len() > 1 {
+                    conflicts.push(Conflict {
+                        state: state.clone(),
+                        event,
+                        candidates: candidates.map(Candidate::from).collect(),
+                    });
+                }
+            }
+        }
+        let unordered = self
+            .ordering
+            .iter()
+            .filter(|constraint| self.may_violate(constraint))
+            .cloned()
+            .collect();
+        Validation {
+            conflicts,
+            dead_ends,
+            invalid_entry_points,
+            unordered,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::StateMachineBuilder;
+    #[cfg(feature = "tracing")]
+    use tracing_test::traced_test;
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_validation_reports_precedence() {
+        let idle = State::new("idle");
+        let running = State::new("running");
+        let fast = State::new("fast");
+        let stopped = State::new("stopped");
+        let go = Event::new("go");
+        let stop = Event::new("stop");
+        let machine = StateMachineBuilder::new("test", &idle)
+            .add_event(idle.clone(), go.clone(), running.clone(), None)
+            .add_event(idle.clone(), go.clone(), fast.clone(), None)
+            .with_guard("turbo", || false)
+            .add_any_state_event(stop.clone(), stopped.clone(), None)
+            .add_event(running.clone(), stop.clone(), idle.clone(), None)
+            .build();
+
+        let validation = machine.validate();
+        assert_eq!(validation.conflicts.len(), 2);
+        let go_conflict = &validation.conflicts[0];
+        assert_eq!((&go_conflict.state, &go_conflict.event), (&idle, &go));
+        assert_eq!(go_conflict.candidates[0].guard.as_deref(), Some("turbo"));
+        assert_eq!(go_conflict.candidates[1].to, running);
+        assert!(go_conflict.shadowed().is_empty());
+        let stop_conflict = &validation.conflicts[1];
+        assert_eq!(stop_conflict.state, running);
+        assert_eq!(stop_conflict.shadowed().len(), 1);
+        assert_eq!(
+            validation.to_string(),
+            "state idle, event go:\n  \
+             1. -> fast (state, guard turbo, priority 0)\n  \
+             2. -> running (state, priority 0)\n\
+             state running, event stop:\n  \
+             1. -> idle (state, priority 0)\n  \
+             2. -> stopped (any state, priority 0) shadowed\n"
+        );
+    }
+}
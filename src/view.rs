@@ -0,0 +1,119 @@
+use crate::{
+    Definition, DwellStats, Error, Event, Explanation, Health, State, StateMachine,
+    TransitionRecord, Validation,
+};
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::Duration;
+
+/// Read-only access to a shared machine
+///
+/// A view exposes the state, the history and the introspection of a machine,
+/// but no way to dispatch events, reset or restore it.
+/// Clones share the same machine.
+pub struct ViewHandle<Err = Error> {
+    machine: Rc<StateMachine<Err>>,
+}
+
+impl<Err> Clone for ViewHandle<Err> {
+    fn clone(&self) -> Self {
+        Self {
+            machine: self.machine.clone(),
+        }
+    }
+}
+
+impl<Err> ViewHandle<Err> {
+    /// Create a view of a shared machine
+    pub fn new(machine: Rc<StateMachine<Err>>) -> Self {
+        Self { machine }
+    }
+
+    /// See [`StateMachine::name`]
+    pub fn name(&self) -> &str {
+        self.machine.name()
+    }
+
+    /// See [`StateMachine::current_state`]
+    pub fn current_state(&self) -> State {
+        self.machine.current_state()
+    }
+
+    /// See [`StateMachine::history`]
+    pub fn history(&self) -> Vec<TransitionRecord> {
+        self.machine.history()
+    }
+
+    /// See [`StateMachine::last_transition`]
+    pub fn last_transition(&self) -> Option<TransitionRecord> {
+        self.machine.last_transition()
+    }
+
+    /// See [`StateMachine::time_in_state`]
+    pub fn time_in_state(&self) -> Duration {
+        self.machine.time_in_state()
+    }
+
+    /// See [`StateMachine::dwell_stats`]
+    pub fn dwell_stats(&self) -> HashMap<State, DwellStats> {
+        self.machine.dwell_stats()
+    }
+
+    /// See [`StateMachine::health`]
+    pub fn health(&self) -> Health {
+        self.machine.health()
+    }
+
+    /// See [`StateMachine::definition`]
+    pub fn definition(&self) -> Definition {
+        self.machine.definition()
+    }
+
+    /// See [`StateMachine::validate`]
+    pub fn validate(&self) -> Validation {
+        self.machine.validate()
+    }
+
+    /// See [`StateMachine::explain`]
+    pub fn explain(&self, event: &Event) -> Explanation {
+        self.machine.explain(event)
+    }
+}
+
+impl<Err> StateMachine<Err> {
+    /// Get a read-only view of a shared machine
+    pub fn view(self: &Rc<Self>) -> ViewHandle<Err> {
+        ViewHandle::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::StateMachineBuilder;
+    #[cfg(feature = "tracing")]
+    use tracing_test::traced_test;
+
+    #[cfg_attr(feature = "tracing", traced_test)]
+    #[test]
+    fn test_view_follows_machine() {
+        let off = State::new("off");
+        let on = State::new("on");
+        let toggle = Event::new("toggle");
+        let machine = Rc::new(
+            StateMachineBuilder::new("switch", &off)
+                .add_event(off.clone(), toggle.clone(), on.clone(), None)
+                .with_history(10)
+                .build(),
+        );
+        let view = machine.view();
+        let clone = view.clone();
+        assert_eq!(view.current_state(), off);
+
+        machine.event(&toggle).unwrap();
+        assert_eq!(clone.current_state(), on);
+        assert_eq!(clone.history().len(), 1);
+        assert_eq!(view.name(), "switch");
+        assert_eq!(view.definition(), machine.definition());
+    }
+}